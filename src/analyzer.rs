@@ -1,60 +1,2279 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
     ffi::OsStr,
+    fmt::Write as _,
     fs::{DirEntry, File, FileType},
     io::{BufWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
 
-use crate::{CompilationEngine, StreamTokenizer};
+use crate::{
+    ast::{Class, Statement},
+    brace_style::BraceStyle,
+    build_cache::BuildCache,
+    codegen::MATH_CLASS,
+    comments::{CommentStats, Layout},
+    compilation_report::{CompilationReport, FileReport},
+    dead_code,
+    diagnostic::{utf16_column, TabPolicy},
+    diff::{diff_report, OutputDiff},
+    format::format_class,
+    grammar,
+    ignore::Ignore,
+    json,
+    listener::{ParseEvent, ParseListener, SexpListener, TokensListener},
+    manifest::{HashingWriter, Manifest},
+    marker_line::skip_marker_line,
+    minify_names,
+    newline::{Newline, NewlineWriter},
+    project::{map_parallel, ParsedTree, Project},
+    rename, CompilationEngine, ControlFlowGraph, Diagnostic, Features, Parser, Severity, SourceSet,
+    SpannedToken, StreamTokenizer, SymbolIndex, SymbolRef, Token,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Emit {
+    /// Control flow graph of every subroutine as GraphViz DOT
+    Cfg,
+    /// Control flow graph of every subroutine as JSON
+    CfgJson,
+    /// The grammar the parser accepts as EBNF, no sources are read
+    Grammar,
+    /// Every definition and reference of the project as one JSON index
+    Symbols,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Report {
+    /// Subroutines unreachable from `Main.main` and unused fields, as a table
+    DeadCode,
+    /// The dead code report as JSON
+    DeadCodeJson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ParseFormat {
+    /// The nested xml of the course
+    Xml,
+    /// One `id<TAB>parent<TAB>kind<TAB>value` row per node, ids in pre-order
+    Records,
+    /// The syntax tree as GraphViz DOT
+    Dot,
+}
+
+// rewrites of the sources `fmt` can apply before formatting
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Transform {
+    /// Rename variables and subroutines private to their class to short
+    /// generated names, `v0`, `f0`, ...
+    MinifyNames,
+}
+
+// the outputs parse_outputs writes from a single parse of each source
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ParseOutput {
+    /// The xml parse tree, as `<name>.xml`
+    Xml,
+    /// The tokens as xml, as `<name>T.xml`
+    Tokens,
+    /// The parse tree as an s-expression, as `<name>.sexp`
+    Sexp,
+}
+
+impl ParseOutput {
+    fn suffix(&self) -> &'static str {
+        match self {
+            Self::Xml => ".xml",
+            Self::Tokens => "T.xml",
+            Self::Sexp => ".sexp",
+        }
+    }
+}
+
+impl ParseFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Xml => "xml",
+            Self::Records => "records",
+            Self::Dot => "dot",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Xml => "xml",
+            Self::Records => "tsv",
+            Self::Dot => "dot",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum DiagnosticFormat {
+    /// `file:line:col: severity[code]: message` and the source line
+    Human,
+    /// One JSON object per line
+    Json,
+    /// Only the `file:line:col: severity[code]: message` line
+    Short,
+}
+
+impl DiagnosticFormat {
+    // `source` is the text of `file` the diagnostic's span points into, human
+    // output shows the line it is on with `tabs` expanded
+    pub fn render(
+        &self,
+        diagnostic: &Diagnostic,
+        file: &Path,
+        source: &str,
+        tabs: TabPolicy,
+    ) -> String {
+        match self {
+            Self::Human => {
+                let snippet = diagnostic.snippet(source, tabs);
+                match snippet.is_empty() {
+                    true => diagnostic.in_file(file),
+                    false => format!("{}\n{}", diagnostic.in_file(file), snippet),
+                }
+            }
+            Self::Short => diagnostic.in_file(file),
+            // the column counts characters, the byte offsets and utf-16
+            // column are there for tools that count otherwise
+            Self::Json => format!(
+                "{{\"file\":{},\"line\":{},\"column\":{},\"column_utf16\":{},\"offset\":{},\"end_offset\":{},\"severity\":\"{}\",\"code\":\"{}\",\"message\":{}}}",
+                json::string(&file.display().to_string()),
+                diagnostic.span.line,
+                diagnostic.span.column,
+                match diagnostic.span.line {
+                    0 => 0,
+                    _ => utf16_column(source, diagnostic.span.start),
+                },
+                diagnostic.span.start,
+                diagnostic.span.end,
+                diagnostic.severity,
+                diagnostic.code,
+                json::string(&diagnostic.message)
+            ),
+        }
+    }
+}
+
+// renders diagnostics as they are found, up to the error limit of the
+// options. past it errors and the notes that follow them are only counted
+pub struct DiagnosticSink {
+    format: DiagnosticFormat,
+    tabs: TabPolicy,
+    max_errors: usize,
+    errors: usize,
+    hidden: usize,
+    hiding: bool,
+}
+
+impl DiagnosticSink {
+    pub fn new(options: &AnalyzerOptions) -> Self {
+        Self {
+            format: options.diagnostics,
+            tabs: options.tabs,
+            max_errors: options.max_errors,
+            errors: 0,
+            hidden: 0,
+            hiding: false,
+        }
+    }
+
+    // what to print for `diagnostic`, nothing once it is past the limit
+    pub fn emit(&mut self, diagnostic: &Diagnostic, file: &Path, source: &str) -> Option<String> {
+        match diagnostic.severity {
+            Severity::Error => {
+                self.errors += 1;
+                self.hiding = self.max_errors > 0 && self.errors > self.max_errors;
+                if self.hiding {
+                    self.hidden += 1;
+                }
+            }
+            Severity::Note if self.hiding => {}
+            _ => self.hiding = false,
+        }
+
+        match self.hiding {
+            true => None,
+            false => Some(self.format.render(diagnostic, file, source, self.tabs)),
+        }
+    }
+
+    // the line counting the errors left out, if any were
+    pub fn finish(&self) -> Option<String> {
+        match self.hidden {
+            0 => None,
+            1 => Some("and 1 more error".to_owned()),
+            n => Some(format!("and {} more errors", n)),
+        }
+    }
+}
+
+// the diagnostics of a whole run, printed at once rather than as they are
+// found: the errors, then the warnings, then the notes on their own, each
+// in source order once sorted, and a count of them last. a note stays with
+// the diagnostic before it
+pub struct DiagnosticReport {
+    options: AnalyzerOptions,
+    // the sources the diagnostics are in, in the order they were added
+    sources: Vec<(PathBuf, String)>,
+    // by the index of their source
+    diagnostics: Vec<(usize, Diagnostic)>,
+}
+
+impl DiagnosticReport {
+    pub fn new(options: &AnalyzerOptions) -> Self {
+        Self {
+            options: options.clone(),
+            sources: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, file: &Path, source: &str, diagnostic: Diagnostic) {
+        let index = match self.sources.iter().position(|(path, _)| path == file) {
+            Some(index) => index,
+            None => {
+                self.sources.push((file.to_path_buf(), source.to_owned()));
+                self.sources.len() - 1
+            }
+        };
+        self.diagnostics.push((index, diagnostic));
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|(_, d)| d.severity == severity)
+            .count()
+    }
+
+    // by source, the order they were added in, then line and column. the
+    // ones without a position come first in their source
+    pub fn sort_by_position(&mut self) {
+        let mut groups = self.groups();
+        groups.sort_by_key(|group| {
+            let (source, lead) = &group[0];
+            (*source, lead.span.line, lead.span.column)
+        });
+        self.diagnostics = groups.concat();
+    }
+
+    // each diagnostic with the notes after it
+    fn groups(&self) -> Vec<Vec<(usize, Diagnostic)>> {
+        let mut groups: Vec<Vec<(usize, Diagnostic)>> = Vec::new();
+        for (source, diagnostic) in &self.diagnostics {
+            match groups.last_mut() {
+                Some(group) if diagnostic.severity == Severity::Note && group[0].0 == *source => {
+                    group.push((*source, diagnostic.clone()))
+                }
+                _ => groups.push(vec![(*source, diagnostic.clone())]),
+            }
+        }
+        groups
+    }
+
+    // the errors past the error limit of the options are counted rather
+    // than shown. as json there is only a line per diagnostic, so the output
+    // stays one value a line
+    pub fn render(&self) -> String {
+        let json = self.options.diagnostics == DiagnosticFormat::Json;
+        let groups = self.groups();
+        let mut sections = Vec::new();
+        for severity in [Severity::Error, Severity::Warning, Severity::Note] {
+            // only the errors have a limit
+            let mut sink = DiagnosticSink::new(&self.options);
+            let mut lines = Vec::new();
+            for (source, diagnostic) in groups
+                .iter()
+                .filter(|group| group[0].1.severity == severity)
+                .flatten()
+            {
+                let (file, text) = &self.sources[*source];
+                lines.extend(sink.emit(diagnostic, file, text));
+            }
+            if severity == Severity::Error {
+                lines.extend(sink.finish());
+            }
+            if !lines.is_empty() {
+                sections.push(lines.join("\n"));
+            }
+        }
+        if json {
+            return sections.join("\n");
+        }
+
+        let plural = |n: usize, what: &str| match n {
+            1 => format!("1 {}", what),
+            n => format!("{} {}s", n, what),
+        };
+        let mut summary = format!(
+            "{}, {}",
+            plural(self.errors(), "error"),
+            plural(self.warnings(), "warning")
+        );
+        match self.count(Severity::Note) {
+            0 => {}
+            notes => write!(summary, ", {}", plural(notes, "note")).unwrap(),
+        }
+        sections.push(summary);
+        sections.join("\n\n")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnalyzerOptions {
+    // error recovery stops once this many errors were reported for a file,
+    // and no more than this many are printed. 0 is no limit
+    pub max_errors: usize,
+    // write a manifest.json with the size and hash of every output
+    pub manifest: bool,
+    // leave the generation time out of the manifest
+    pub manifest_timestamp: bool,
+    // check that braces, parentheses and brackets pair up before parsing
+    pub check_balance: bool,
+    // also report method calls and indexing on int, char and boolean values
+    pub strict: bool,
+    // also check the calls between the classes of the program, that the
+    // class and subroutine exist, are of the kind the call needs and get the
+    // right number of arguments
+    pub check_calls: bool,
+    // warn about statics, fields, parameters and locals declared with one of
+    // these names
+    pub reserved_identifiers: BTreeSet<String>,
+    // skip a first line starting with `#!` or `<?xml` with a warning, rather
+    // than fail on it
+    pub skip_marker_line: bool,
+    // the codes of the warnings check leaves out
+    pub allow: Vec<String>,
+    // where check wants the opening braces, not checked when unset
+    pub brace_style: Option<BraceStyle>,
+    // add the source range of every non-terminal to the xml as `start` and
+    // `end` byte offsets
+    pub spans: bool,
+    // where outputs are written, next to the sources or the working
+    // directory when unset
+    pub out_dir: Option<PathBuf>,
+    // also read the sources in subdirectories
+    pub recursive: bool,
+    // analyze warns about a directory without sources instead of failing
+    pub allow_empty: bool,
+    // the line ending of the xml and vm files written
+    pub newline: Newline,
+    // files handled at the same time by the per file stages
+    pub jobs: usize,
+    // also spread the subroutines of each class over threads when compiling
+    // to vm code
+    pub parallel_subroutines: bool,
+    // put a `// <file>:<line>` comment before every function and statement
+    // of the vm code, for the vm emulator's debugger
+    pub vm_debug: bool,
+    // the class whose `multiply` and `divide` the vm code calls for `*` and
+    // `/`, `Math` unless the course renamed it
+    pub math_class: String,
+    // compare every output against the file already there and print the
+    // differences instead of writing anything
+    pub diff: bool,
+    // how parse writes the tree
+    pub format: ParseFormat,
+    // start the xml tree and the tokens with a declaration and escape every
+    // `&`, `<` and `>`, see CompileOptions. records are written from the tree
+    // without it
+    pub well_formed_xml: bool,
+    // write the xml tree with one tag per line and no blank lines, see
+    // CompileOptions
+    pub normalize: bool,
+    // write the tree of every source to Output.{ext} one after the other, as
+    // `-s` always has, rather than to a file named after the source.
+    // archived sources get their own either way
+    pub shared_output: bool,
+    // print how long parse spent tokenizing and in the engine for each file
+    pub profile: bool,
+    pub diagnostics: DiagnosticFormat,
+    // how tabs are shown in the source lines of human diagnostics
+    pub tabs: TabPolicy,
+    // the file parse keeps the hashes of sources and outputs in, sources
+    // unchanged since their output was written are skipped. unset is no cache
+    pub cache: Option<PathBuf>,
+    // where the report of what parse took for every source is written as
+    // json. analyze only runs the semantic checks when it is set, to time
+    // them, what they find is left to check
+    pub report_file: Option<PathBuf>,
+    // stop at the first output of parse_outputs that can't be written
+    // rather than write the others
+    pub fail_fast: bool,
+    // accept `this.f()` as the call `f()` in the current class rather than
+    // report it
+    pub allow_this_receiver: bool,
+    // the extensions of the files read from directories, matched ignoring
+    // case
+    pub extensions: Vec<String>,
+    // patterns of files left out of directories, after those of the
+    // directory's .jackignore
+    pub exclude: Vec<String>,
+    // print the files skipped while looking for sources, and how many were
+    // looked at
+    pub verbose: bool,
+}
+
+impl Default for AnalyzerOptions {
+    fn default() -> Self {
+        Self {
+            max_errors: 20,
+            manifest: false,
+            manifest_timestamp: true,
+            check_balance: false,
+            strict: false,
+            check_calls: false,
+            reserved_identifiers: BTreeSet::new(),
+            skip_marker_line: true,
+            allow: Vec::new(),
+            brace_style: None,
+            spans: false,
+            out_dir: None,
+            recursive: false,
+            allow_empty: false,
+            newline: Newline::Lf,
+            jobs: 1,
+            parallel_subroutines: false,
+            vm_debug: false,
+            math_class: String::from(MATH_CLASS),
+            diff: false,
+            format: ParseFormat::Xml,
+            well_formed_xml: false,
+            normalize: false,
+            shared_output: false,
+            profile: false,
+            diagnostics: DiagnosticFormat::Human,
+            tabs: TabPolicy::One,
+            cache: None,
+            report_file: None,
+            fail_fast: false,
+            allow_this_receiver: false,
+            extensions: vec![String::from("jack")],
+            exclude: Vec::new(),
+            verbose: false,
+        }
+    }
+}
+
+// the time `parse` spent on one source file in each phase. the engine's
+// share includes writing the tree out in memory, the semantic checks are
+// only run, and timed, for the report file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PhaseTimes {
+    pub tokenize: Duration,
+    pub engine: Duration,
+    pub semantic: Duration,
+}
 
 pub struct Analyzer;
 
 impl Analyzer {
-    pub fn analyze(source: &PathBuf) -> Result<()> {
-        let files = Self::read_source_files(source)?;
+    // writes the parse tree of every source to `<stem>.{ext}`, and reports
+    // what each one took
+    pub fn analyze(source: &PathBuf, options: &AnalyzerOptions) -> Result<CompilationReport> {
+        let found = Self::discover(source, options)?;
+        let files = found.files;
+        let extension = options.format.extension();
+        let mut manifest = Self::manifest(options, options.format.name());
+        let output = Self::output_path(options, Path::new(&format!("Output.{}", extension)))?;
+        let mut planned = Vec::new();
+        let mut profile = Vec::new();
+        let mut report = CompilationReport {
+            files_considered: found.considered,
+            files_selected: files.len(),
+            ..Default::default()
+        };
 
+        let project = Self::read_project(&files, options)?;
+        let mut sink = DiagnosticSink::new(options);
+        // nothing is written when diffing, so nothing is skipped either
+        let mut cache = match (&options.cache, options.diff) {
+            (Some(path), false) => Some(BuildCache::load(path, Self::cache_settings(options))?),
+            _ => None,
+        };
         for file in files {
-            // instatiate a new Tokenizer
-            // let mut tokenizer = NaiveTokenizer::new(&file);
-            let mut tokenizer = StreamTokenizer::new(&file);
-
-            // create a output file
-            let output_file = File::create("Output.xml")?;
-            let mut writer = BufWriter::new(output_file);
-
-            // use compilation engine to compile tokens from the tokenizer
-            let mut engine = CompilationEngine::new(&mut writer, &mut tokenizer);
-            match engine.compile() {
-                Ok(_) => {}
-                Err(e) => eprintln!("{:?}", e),
+            let name = file.display().to_string();
+            let source = project.source(&name).unwrap_or_default();
+
+            // create a output file, named after the source unless every
+            // tree goes to Output.{ext}
+            let output = match options.shared_output && !Self::is_gzipped(&file) {
+                true => output.clone(),
+                false => Self::output_path(
+                    options,
+                    &file.with_file_name(format!("{}.{}", source_stem(&file), extension)),
+                )?,
             };
 
+            if let Some(written) = cache.as_ref().and_then(|c| c.fresh(&file, source, &output)) {
+                let mut writer = HashingWriter::new(std::io::sink());
+                writer.write_all(&written)?;
+                manifest.record(&output, &file, writer)?;
+                report.files.push(FileReport {
+                    file: file.clone(),
+                    source_bytes: source.len(),
+                    cached: true,
+                    ..Default::default()
+                });
+                continue;
+            }
+
+            let mut parsed = project.tree(&name)?;
+            for diagnostic in &parsed.diagnostics {
+                if let Some(rendered) = sink.emit(diagnostic, &file, source) {
+                    eprintln!("{}", rendered);
+                }
+            }
+            let Some(tree) = parsed.tree.take() else {
+                continue;
+            };
+            if options.report_file.is_some() {
+                let start = Instant::now();
+                project.check_source(&name);
+                parsed.times.semantic = start.elapsed();
+            }
+            let mut file_report = Self::file_report(&file, source, &parsed);
+            profile.push((file.clone(), parsed.times));
+            let failed = parsed
+                .diagnostics
+                .iter()
+                .any(|d| d.severity == Severity::Error);
+
+            if options.diff {
+                report.files.push(file_report);
+                // the sources sharing an output overwrite each other
+                planned.retain(|(path, _)| *path != output);
+                planned.push((output, tree));
+                continue;
+            }
+
+            let start = Instant::now();
+            let mut writer = Self::create_output(&output, options)?;
+            writer.write_all(tree.as_bytes())?;
+
             // save compilation output into output file
-            writer.flush()?;
+            manifest.record(&output, &file, writer.into_inner())?;
+            file_report.emit = start.elapsed();
+            report.files.push(file_report);
+
+            // a source with errors is parsed again next time so they are
+            // reported again
+            if let (Some(cache), false) = (&mut cache, failed) {
+                cache.record(&file, source, &output, &options.newline.apply(&tree));
+            }
+        }
+
+        if let Some(cache) = &cache {
+            cache.save()?;
+        }
+        if let Some(more) = sink.finish() {
+            eprintln!("{}", more);
+        }
+        if options.profile {
+            eprint!("{}", Self::profile_report(&profile));
+        }
+        if options.diff {
+            Self::print_diffs(&planned)?;
+            return Ok(report);
+        }
+        if options.manifest {
+            manifest.write(Self::output_dir(&output))?;
+        }
+        Ok(report)
+    }
+
+    // what parsing, checking and writing out the tree of every source takes,
+    // for the report of the subcommands that don't parse. nothing is printed
+    // or written, and the cache isn't looked at
+    pub fn measure(source: &PathBuf, options: &AnalyzerOptions) -> Result<CompilationReport> {
+        let found = Self::find_sources(source, options)?;
+        let project = Self::read_project(&found.files, options)?;
+        let mut report = CompilationReport {
+            files_considered: found.considered,
+            files_selected: found.files.len(),
+            ..Default::default()
+        };
+        for file in found.files {
+            let name = file.display().to_string();
+            let source = project.source(&name).unwrap_or_default();
+            let parsed = project.tree(&name)?;
+            let mut file_report = Self::file_report(&file, source, &parsed);
+
+            let start = Instant::now();
+            project.check_source(&name);
+            file_report.semantic = start.elapsed();
+
+            if let Some(tree) = &parsed.tree {
+                let start = Instant::now();
+                let mut writer = NewlineWriter::new(std::io::sink(), options.newline);
+                writer.write_all(tree.as_bytes())?;
+                writer.flush()?;
+                file_report.emit = start.elapsed();
+            }
+            report.files.push(file_report);
+        }
+        Ok(report)
+    }
+
+    // the sizes and parse time of a source, the other phases are timed by
+    // the caller
+    fn file_report(file: &Path, source: &str, parsed: &ParsedTree) -> FileReport {
+        FileReport {
+            file: file.to_path_buf(),
+            source_bytes: source.len(),
+            tokens: parsed.tokens,
+            tokens_consumed: parsed.tokens_consumed,
+            bytes_scanned: parsed.bytes_scanned,
+            parse: parsed.times.tokenize + parsed.times.engine,
+            semantic: parsed.times.semantic,
+            peak_buffer: parsed.peak_buffer,
+            ..Default::default()
+        }
+    }
+
+    // every source tokenized and parsed once, with each of `outputs` written
+    // from that parse next to it or into the output directory. an output
+    // that can't be created or written is returned with its error and the
+    // others are still written, unless the options fail fast, then it is
+    // the error
+    pub fn parse_outputs(
+        source: &PathBuf,
+        outputs: &[ParseOutput],
+        options: &AnalyzerOptions,
+    ) -> Result<Vec<(PathBuf, anyhow::Error)>> {
+        let files = Self::source_files(source, options)?;
+        let project = Self::read_project(&files, options)?;
+        let mut sink = DiagnosticSink::new(options);
+        let mut failed = Vec::new();
+        let mut fail = |path: &Path, e: anyhow::Error| {
+            let e = e.context(path.display().to_string());
+            match options.fail_fast {
+                true => Err(e),
+                false => {
+                    failed.push((path.to_path_buf(), e));
+                    Ok(())
+                }
+            }
+        };
+
+        for file in &files {
+            let name = file.display().to_string();
+            let mut xml = None;
+            let mut listeners: Vec<Box<dyn ParseListener>> = Vec::new();
+            let mut listened = Vec::new();
+            for output in outputs {
+                let path = file.with_file_name(format!("{}{}", source_stem(file), output.suffix()));
+                let path = Self::output_path(options, &path)?;
+                if *output == ParseOutput::Xml {
+                    xml = Some(path);
+                    continue;
+                }
+                let created = match File::create(&path) {
+                    Ok(created) => NewlineWriter::new(BufWriter::new(created), options.newline),
+                    Err(e) => {
+                        fail(&path, e.into())?;
+                        continue;
+                    }
+                };
+                listeners.push(match output {
+                    ParseOutput::Tokens => {
+                        Box::new(TokensListener::new(created).well_formed(options.well_formed_xml))
+                    }
+                    _ => Box::new(SexpListener::new(created)),
+                });
+                listened.push(path);
+            }
+
+            let (parsed, errors) = project.tree_with(&name, listeners)?;
+            let source = project.source(&name).unwrap_or_default();
+            for diagnostic in &parsed.diagnostics {
+                if let Some(rendered) = sink.emit(diagnostic, file, source) {
+                    eprintln!("{}", rendered);
+                }
+            }
+            for (path, error) in listened.iter().zip(errors) {
+                if let Some(e) = error {
+                    fail(path, e.into())?;
+                }
+            }
+            if let (Some(path), Some(tree)) = (xml, &parsed.tree) {
+                if let Err(e) = std::fs::write(&path, options.newline.apply(tree)) {
+                    fail(&path, e.into())?;
+                }
+            }
+        }
+
+        if let Some(more) = sink.finish() {
+            eprintln!("{}", more);
+        }
+        Ok(failed)
+    }
+
+    // one row per file with the time of each phase in milliseconds, and
+    // their sums
+    pub fn profile_report(profile: &[(PathBuf, PhaseTimes)]) -> String {
+        let names: Vec<String> = profile
+            .iter()
+            .map(|(file, _)| file.display().to_string())
+            .collect();
+        let width = names.iter().map(String::len).chain([5]).max().unwrap();
+        let ms = |d: Duration| format!("{:.3}", d.as_secs_f64() * 1000.0);
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{:<width$}  {:>12}  {:>12}",
+            "file", "tokenize ms", "engine ms"
+        )
+        .unwrap();
+        let mut total = PhaseTimes::default();
+        for (name, (_, times)) in names.iter().zip(profile) {
+            writeln!(
+                out,
+                "{:<width$}  {:>12}  {:>12}",
+                name,
+                ms(times.tokenize),
+                ms(times.engine)
+            )
+            .unwrap();
+            total.tokenize += times.tokenize;
+            total.engine += times.engine;
+        }
+        writeln!(
+            out,
+            "{:<width$}  {:>12}  {:>12}",
+            "total",
+            ms(total.tokenize),
+            ms(total.engine)
+        )
+        .unwrap();
+        out
+    }
+
+    // the sources in memory, keyed by the file names diagnostics and
+    // errors show
+    fn read_project(files: &[PathBuf], options: &AnalyzerOptions) -> Result<Project> {
+        let texts = Self::map_files(files, options.jobs, |file| Self::read_source(file))?;
+        let names = files.iter().map(|file| file.display().to_string());
+        Ok(Project::from_sources(names.zip(texts)).with_options(options.clone()))
+    }
+
+    // how each planned output differs from the file it would replace
+    pub fn diff_outputs(planned: &[(PathBuf, String)]) -> Result<Vec<OutputDiff>> {
+        planned
+            .iter()
+            .map(|(path, output)| OutputDiff::of(path, output))
+            .collect()
+    }
+
+    fn print_diffs(planned: &[(PathBuf, String)]) -> Result<()> {
+        print!("{}", diff_report(&Self::diff_outputs(planned)?));
+        Ok(())
+    }
+
+    // the options that change what parse writes for a source, a cache
+    // written with others is started over
+    fn cache_settings(options: &AnalyzerOptions) -> String {
+        format!(
+            "format={} spans={} skip_marker_line={} well_formed_xml={} normalize={} newline={}",
+            options.format.name(),
+            options.spans,
+            options.skip_marker_line,
+            options.well_formed_xml,
+            options.normalize,
+            options.newline.name()
+        )
+    }
+
+    fn manifest(options: &AnalyzerOptions, mode: &str) -> Manifest {
+        let mut manifest = Manifest::new(options.manifest_timestamp);
+        manifest.option("max_errors", options.max_errors);
+        manifest.option("mode", json::string(mode));
+        manifest
+    }
+
+    // `name` inside the output directory when one is set, creating it unless
+    // the outputs are only diffed
+    fn output_path(options: &AnalyzerOptions, name: &Path) -> Result<PathBuf> {
+        match &options.out_dir {
+            Some(dir) => {
+                if !options.diff {
+                    std::fs::create_dir_all(dir).with_context(|| format!("{}", dir.display()))?;
+                }
+                Ok(dir.join(name.file_name().unwrap_or(name.as_os_str())))
+            }
+            None => Ok(name.to_path_buf()),
+        }
+    }
+
+    fn output_dir(artifact: &Path) -> &Path {
+        match artifact.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        }
+    }
+
+    // parses every source file and runs the semantic checks and lints over it,
+    // nothing is written to disk
+    pub fn check(
+        source: &PathBuf,
+        options: &AnalyzerOptions,
+    ) -> Result<Vec<(PathBuf, Diagnostic)>> {
+        let files = Self::source_files(source, options)?;
+        let project = Self::read_project(&files, options)?;
+        Ok(project
+            .check()?
+            .into_iter()
+            .map(|(name, diagnostic)| (PathBuf::from(name), diagnostic))
+            .collect())
+    }
+
+    // the tokens of every source file, as the course's `T.xml` files or well
+    // formed documents
+    pub fn tokenize(source: &PathBuf, options: &AnalyzerOptions) -> Result<Vec<(PathBuf, String)>> {
+        let files = Self::source_files(source, options)?;
+        let outputs = Self::map_files(&files, options.jobs, |file| {
+            let mut out = Vec::new();
+            let mut listener = TokensListener::new(&mut out).well_formed(options.well_formed_xml);
+            for token in StreamTokenizer::from_source(&Self::load_source(file, options)?) {
+                let token = token.with_context(|| format!("{}", file.display()))?;
+                if token == Token::Eof {
+                    break;
+                }
+                listener.event(&ParseEvent::Token(token))?;
+            }
+            listener.finish()?;
+            Ok(String::from_utf8(out)?)
+        })?;
+
+        Ok(files.into_iter().zip(outputs).collect())
+    }
+
+    // every source file parsed and written back out in the canonical layout
+    pub fn format(source: &PathBuf, options: &AnalyzerOptions) -> Result<Vec<(PathBuf, String)>> {
+        let files = Self::source_files(source, options)?;
+        let outputs = Self::map_files(&files, options.jobs, |file| {
+            let text = Self::load_source(file, options)?;
+            let class = Project::parse_class(&text, options)
+                .with_context(|| format!("{}", file.display()))?;
+            Ok(format_class(&class))
+        })?;
+
+        Ok(files.into_iter().zip(outputs).collect())
+    }
+
+    // the sources of the whole project rewritten by `transform`, then formatted
+    pub fn transform(
+        source: &PathBuf,
+        transform: Transform,
+        options: &AnalyzerOptions,
+    ) -> Result<Vec<(PathBuf, String)>> {
+        let project = Self::read_source_set(source, options)?;
+        let edits = match transform {
+            Transform::MinifyNames => minify_names(&project)?,
+        };
+
+        let mut rewritten = project.files().to_vec();
+        for (file, text) in project.apply(&edits) {
+            if let Some(entry) = rewritten.iter_mut().find(|(f, _)| *f == file) {
+                entry.1 = text;
+            }
+        }
+        rewritten
+            .into_iter()
+            .map(|(file, text)| {
+                let class = Project::parse_class(&text, options)
+                    .with_context(|| format!("{}", file.display()))?;
+                Ok((file, format_class(&class)))
+            })
+            .collect()
+    }
+
+    // token, declaration and statement counts of every source file, then how
+    // much each class is commented
+    pub fn stats(source: &PathBuf, options: &AnalyzerOptions) -> Result<String> {
+        let files = Self::source_files(source, options)?;
+        let rows = Self::map_files(&files, options.jobs, |file| {
+            let text = Self::load_source(file, options)?;
+            // tokenized once, for the counts, the comments and the parse
+            let mut tokens = StreamTokenizer::from_source(&text)
+                .collect_stats(true)
+                .into_spanned();
+            let scanned: Vec<Result<SpannedToken>> = tokens.by_ref().collect();
+            let lexed = tokens.tokenizer().stats().unwrap_or_default();
+            let read = scanned.iter().map_while(|t| t.as_ref().ok()).cloned();
+            let layout = Layout::from_tokens(&text, read.collect());
+            let class = Parser::new(scanned.into_iter())
+                .allow_this_receiver(options.allow_this_receiver)
+                .parse_class()
+                .with_context(|| format!("{}", file.display()))?;
+
+            let variables: usize = class.class_vars.iter().map(|dec| dec.names.len()).sum();
+            let statements: usize = class
+                .subroutines
+                .iter()
+                .map(|s| Self::count_statements(&s.statements))
+                .sum();
+            let counts = [
+                lexed.tokens(),
+                variables,
+                class.subroutines.len(),
+                statements,
+                lexed.lines,
+                lexed.blank_lines,
+                lexed.keywords,
+                lexed.symbols,
+                lexed.identifiers,
+                lexed.int_consts,
+                lexed.string_consts,
+            ];
+            let comments = CommentStats::of(&layout, &class);
+            Ok((counts, Features::of(&class), class.name.name, comments))
+        })?;
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{:>8}  {:>10}  {:>11}  {:>10}  {:>8}  {:>8}  {:>8}  {:>8}  {:>11}  {:>8}  {:>8}  file  features",
+            "tokens",
+            "class vars",
+            "subroutines",
+            "statements",
+            "lines",
+            "blank",
+            "keywords",
+            "symbols",
+            "identifiers",
+            "integers",
+            "strings"
+        )
+        .unwrap();
+        for (file, (counts, features, _, _)) in files.iter().zip(&rows) {
+            let [tokens, variables, subroutines, statements, lines, blank, keywords, symbols, identifiers, integers, strings] =
+                counts;
+            writeln!(
+                out,
+                "{:>8}  {:>10}  {:>11}  {:>10}  {:>8}  {:>8}  {:>8}  {:>8}  {:>11}  {:>8}  {:>8}  {}  {}",
+                tokens,
+                variables,
+                subroutines,
+                statements,
+                lines,
+                blank,
+                keywords,
+                symbols,
+                identifiers,
+                integers,
+                strings,
+                file.display(),
+                features.to_json()
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "\n{:>8}  {:>8}  {:>8}  {:>12}  {:>12}  class  file",
+            "line", "block", "doc", "comment/code", "undocumented"
+        )
+        .unwrap();
+        for (file, (_, _, class, comments)) in files.iter().zip(&rows) {
+            writeln!(
+                out,
+                "{:>8}  {:>8}  {:>8}  {:>12.2}  {:>12}  {}  {}",
+                comments.line_comments,
+                comments.block_comments,
+                comments.doc_comments,
+                comments.ratio(),
+                comments.undocumented,
+                class,
+                file.display()
+            )
+            .unwrap();
+        }
+        Ok(out)
+    }
+
+    fn count_statements(statements: &[Statement]) -> usize {
+        statements
+            .iter()
+            .map(|statement| {
+                1 + match statement {
+                    Statement::If {
+                        then_branch,
+                        else_branch,
+                        ..
+                    } => {
+                        Self::count_statements(then_branch)
+                            + else_branch.as_deref().map_or(0, Self::count_statements)
+                    }
+                    Statement::While { body, .. } => Self::count_statements(body),
+                    _ => 0,
+                }
+            })
+            .sum()
+    }
+
+    // writes each output as `<stem><suffix>` into the output directory, or
+    // prints them all when there is none
+    pub fn write_outputs(
+        outputs: &[(PathBuf, String)],
+        suffix: &str,
+        options: &AnalyzerOptions,
+    ) -> Result<()> {
+        if options.diff {
+            return Self::print_diffs(&Self::planned_outputs(outputs, suffix, options)?);
+        }
+        if options.out_dir.is_none() {
+            for (_, output) in outputs {
+                print!("{}", output);
+            }
+            return Ok(());
+        }
+
+        for (file, output) in outputs {
+            let stem = source_stem(file);
+            let path = Self::output_path(options, Path::new(&format!("{}{}", stem, suffix)))?;
+            std::fs::write(&path, options.newline.apply(output))
+                .with_context(|| format!("{}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    // where write_outputs would put each output when diffing, next to its
+    // source without an output directory
+    fn planned_outputs(
+        outputs: &[(PathBuf, String)],
+        suffix: &str,
+        options: &AnalyzerOptions,
+    ) -> Result<Vec<(PathBuf, String)>> {
+        outputs
+            .iter()
+            .map(|(file, output)| {
+                let name = file.with_file_name(format!("{}{}", source_stem(file), suffix));
+                Ok((Self::output_path(options, &name)?, output.clone()))
+            })
+            .collect()
+    }
+
+    // writes an alternative view of every source file instead of the xml
+    pub fn emit(source: &PathBuf, emit: Emit, options: &AnalyzerOptions) -> Result<String> {
+        // what is written for each class, the views of the whole project
+        // are returned right away
+        let view: fn(&Class) -> String = match emit {
+            Emit::Grammar => return Ok(grammar::grammar_to_ebnf()),
+            Emit::Symbols => {
+                let project = Self::read_source_set(source, options)?;
+                return Ok(SymbolIndex::build(&project)?.to_json());
+            }
+            Emit::Cfg => |class| {
+                class
+                    .subroutines
+                    .iter()
+                    .map(|subroutine| ControlFlowGraph::build(subroutine).to_dot())
+                    .collect()
+            },
+            Emit::CfgJson => |class| {
+                let graphs: Vec<String> = class
+                    .subroutines
+                    .iter()
+                    .map(|subroutine| ControlFlowGraph::build(subroutine).to_json())
+                    .collect();
+                format!(
+                    "{{\"class\":{},\"subroutines\":[{}]}}\n",
+                    json::string(&class.name.name),
+                    graphs.join(",")
+                )
+            },
+        };
+
+        let mut out = String::new();
+        for file in Self::read_source_files(source, options)? {
+            let text = Self::load_source(&file, options)?;
+            let class = Project::parse_class(&text, options)
+                .with_context(|| format!("{}", file.display()))?;
+            out.push_str(&view(&class));
+        }
+
+        Ok(out)
+    }
+
+    // project wide reports over every source file
+    pub fn report(
+        source: &PathBuf,
+        report: Report,
+        ignore_constructors: bool,
+        options: &AnalyzerOptions,
+    ) -> Result<String> {
+        let project = Self::read_source_set(source, options)?;
+        let dead = dead_code(&project, ignore_constructors)?;
+        let out = match report {
+            Report::DeadCode => dead.to_table(),
+            Report::DeadCodeJson => dead.to_json(),
+        };
+
+        Ok(out)
+    }
+
+    // vm mode, compiles every class to vm code. each output is paired with
+    // its source file, in file name order
+    pub fn compile_vm(
+        source: &PathBuf,
+        options: &AnalyzerOptions,
+    ) -> Result<Vec<(PathBuf, String)>> {
+        let mut files = Self::source_files(source, options)?;
+        files.sort();
+
+        // the project orders its sources by name, which can differ from the
+        // order of the paths
+        let project = Self::read_project(&files, options)?;
+        let names: Vec<&String> = project.sources().keys().collect();
+        let mut outputs = Vec::new();
+        for (name, (vm, skipped)) in names.iter().zip(project.compile_vm()?) {
+            if let Some(warning) = skipped {
+                let text = project.source(name).unwrap_or_default();
+                Self::warn(&warning, Path::new(name), text, options);
+            }
+            outputs.push(Some(vm));
+        }
+        Ok(files
+            .into_iter()
+            .map(|file| {
+                let name = file.display().to_string();
+                let i = names.binary_search(&&name).expect("every file is a source");
+                let vm = outputs[i].take().expect("every source is compiled once");
+                (file, vm)
+            })
+            .collect())
+    }
+
+    // writes one `.vm` per class, or all of them concatenated into `single`.
+    // functions are already named `Class.sub` so nothing needs renaming
+    pub fn write_vm(
+        source: &PathBuf,
+        single: Option<&PathBuf>,
+        options: &AnalyzerOptions,
+    ) -> Result<()> {
+        if options.diff {
+            print!("{}", diff_report(&Self::diff_vm(source, single, options)?));
+            return Ok(());
+        }
+
+        let outputs = Self::compile_vm(source, options)?;
+        let mut manifest =
+            Self::manifest(options, if single.is_some() { "single-vm" } else { "vm" });
+
+        let mut dir = PathBuf::from(".");
+        match single {
+            Some(path) => {
+                let mut writer = Self::create_output(path, options)?;
+                for (_, vm) in &outputs {
+                    writer.write_all(vm.as_bytes())?;
+                }
+                manifest.record(path, source, writer.into_inner())?;
+                dir = Self::output_dir(path).to_path_buf();
+            }
+            None => {
+                for (file, vm) in &outputs {
+                    let path = Self::vm_path(file, options)?;
+                    let mut writer = Self::create_output(&path, options)?;
+                    writer.write_all(vm.as_bytes())?;
+                    manifest.record(&path, file, writer.into_inner())?;
+                    dir = Self::output_dir(&path).to_path_buf();
+                }
+            }
+        }
+
+        if options.manifest {
+            manifest.write(&dir)?;
         }
         Ok(())
     }
 
-    fn read_source_files(source: &PathBuf) -> std::io::Result<Vec<PathBuf>> {
-        if source.is_dir() {
-            let mut files: Vec<PathBuf> = Vec::new();
-            for entry in std::fs::read_dir(source)? {
-                let entry = entry?;
-                if Self::is_jack_file(&entry) {
-                    files.push(entry.path())
+    // an output file with the line endings of the options, hashed as written
+    fn create_output(
+        path: &Path,
+        options: &AnalyzerOptions,
+    ) -> Result<NewlineWriter<HashingWriter<BufWriter<File>>>> {
+        let file = File::create(path).with_context(|| format!("{}", path.display()))?;
+        Ok(NewlineWriter::new(
+            HashingWriter::new(BufWriter::new(file)),
+            options.newline,
+        ))
+    }
+
+    // the vm file of a source, next to it unless there is an output directory
+    fn vm_path(file: &Path, options: &AnalyzerOptions) -> Result<PathBuf> {
+        Self::output_path(
+            options,
+            &file.with_file_name(format!("{}.vm", source_stem(file))),
+        )
+    }
+
+    // what write_vm would change, without writing anything
+    pub fn diff_vm(
+        source: &PathBuf,
+        single: Option<&PathBuf>,
+        options: &AnalyzerOptions,
+    ) -> Result<Vec<OutputDiff>> {
+        let outputs = Self::compile_vm(source, options)?;
+        let planned = match single {
+            Some(path) => vec![(
+                path.clone(),
+                outputs.into_iter().map(|(_, vm)| vm).collect(),
+            )],
+            None => outputs
+                .into_iter()
+                .map(|(file, vm)| Ok((Self::vm_path(&file, options)?, vm)))
+                .collect::<Result<_>>()?,
+        };
+        Self::diff_outputs(&planned)
+    }
+
+    // applies a `Class.old=new` style rename to the sources in place and
+    // returns the files it changed
+    pub fn rename(source: &PathBuf, spec: &str, options: &AnalyzerOptions) -> Result<Vec<PathBuf>> {
+        let (path, new_name) = spec
+            .split_once('=')
+            .with_context(|| format!("expected `target=new_name`, found `{}`", spec))?;
+        let project = Self::read_source_set(source, options)?;
+        let target = project.resolve(path)?;
+        let edits = rename(&project, &target, new_name)?;
+
+        // jack wants every class in a file of the same name. a file already
+        // there is never overwritten, checked before anything is written
+        let mut moves = BTreeMap::new();
+        if let SymbolRef::Class(old) = &target {
+            for (file, _) in project.files() {
+                if file.file_stem().and_then(OsStr::to_str) == Some(old.as_str()) {
+                    let extension = file.extension().and_then(OsStr::to_str);
+                    let renamed = file.with_file_name(format!(
+                        "{}.{}",
+                        new_name,
+                        extension.unwrap_or("jack")
+                    ));
+                    // only a change of case on a filesystem ignoring it
+                    let same = renamed.exists()
+                        && std::fs::canonicalize(&renamed)? == std::fs::canonicalize(file)?;
+                    ensure!(
+                        !renamed.exists() || same,
+                        "can't rename {} to {}, it already exists",
+                        file.display(),
+                        renamed.display()
+                    );
+                    moves.insert(file.clone(), renamed);
                 }
             }
+        }
 
-            Ok(files)
-        } else {
-            Ok(vec![source.to_path_buf()])
+        let mut changed = Vec::new();
+        for (file, text) in project.apply(&edits) {
+            std::fs::write(&file, text)?;
+            match moves.get(&file) {
+                Some(renamed) => {
+                    std::fs::rename(&file, renamed)?;
+                    changed.push(renamed.clone());
+                }
+                None => changed.push(file),
+            }
         }
+
+        Ok(changed)
     }
 
-    fn is_jack_file(entry: &DirEntry) -> bool {
+    // the sources under `source` as the options find them, without printing
+    // anything
+    pub(crate) fn read_source_files(
+        source: &PathBuf,
+        options: &AnalyzerOptions,
+    ) -> Result<Vec<PathBuf>> {
+        Ok(Self::find_sources(source, options)?.files)
+    }
+
+    fn source_files(source: &PathBuf, options: &AnalyzerOptions) -> Result<Vec<PathBuf>> {
+        Ok(Self::discover(source, options)?.files)
+    }
+
+    // the sources under `source`, with what was skipped printed when the
+    // options are verbose. a directory without any is an error unless the
+    // options allow it, then only a warning
+    fn discover(source: &PathBuf, options: &AnalyzerOptions) -> Result<Discovery> {
+        let found = Self::find_sources(source, options)?;
+        if options.verbose {
+            let wanted = Self::wanted_extensions(options);
+            for file in &found.skipped {
+                eprintln!("skipping {}, not a {} file", file.display(), wanted);
+            }
+            for (file, pattern) in &found.excluded {
+                eprintln!("excluding {}, matched `{}`", file.display(), pattern);
+            }
+            eprintln!(
+                "{}: {} of {} files selected",
+                source.display(),
+                found.files.len(),
+                found.considered
+            );
+        }
+        if found.files.is_empty() {
+            let message = Self::no_sources_message(source, options)?;
+            if !options.allow_empty {
+                bail!(message);
+            }
+            eprintln!("warning: {}", message);
+        }
+        Ok(found)
+    }
+
+    // the sources under `source` and what was left out, without printing
+    fn find_sources(source: &PathBuf, options: &AnalyzerOptions) -> Result<Discovery> {
+        let ignore = Self::ignore(source, &options.exclude)?;
+        let mut found = Discovery::default();
+        Self::collect_source_files(
+            source,
+            options.recursive,
+            &options.extensions,
+            &ignore,
+            &mut found,
+        )?;
+        Ok(found)
+    }
+
+    // why a directory gave no sources. the hint to recurse is only given when
+    // a subdirectory holds some
+    fn no_sources_message(source: &Path, options: &AnalyzerOptions) -> std::io::Result<String> {
+        let wanted = Self::wanted_extensions(options);
+        let mut message = format!("no {} files found under {}", wanted, source.display());
+        if options.recursive {
+            return Ok(message);
+        }
+
+        let ignore = Self::ignore(source, &options.exclude)?;
+        let mut nested = Discovery::default();
+        for entry in std::fs::read_dir(source)? {
+            let path = entry?.path();
+            if path.is_dir() && ignore.excluded(&path, true).is_none() {
+                Self::collect_source_files(
+                    &path,
+                    false,
+                    &options.extensions,
+                    &ignore,
+                    &mut nested,
+                )?;
+            }
+        }
+        if !nested.files.is_empty() {
+            message.push_str(" (searched non-recursively; try --recursive)");
+        }
+        Ok(message)
+    }
+
+    // `.jack`, or `.jack or .jck` for several extensions
+    fn wanted_extensions(options: &AnalyzerOptions) -> String {
+        let wanted: Vec<String> = options
+            .extensions
+            .iter()
+            .map(|extension| format!(".{}", extension.trim_start_matches('.')))
+            .collect();
+        wanted.join(" or ")
+    }
+
+    // the patterns leaving files out of a directory, a file named on its own
+    // is always read
+    fn ignore(source: &Path, exclude: &[String]) -> std::io::Result<Ignore> {
+        match source.is_dir() {
+            true => Ignore::load(source, exclude),
+            false => Ok(Ignore::default()),
+        }
+    }
+
+    fn collect_source_files(
+        source: &PathBuf,
+        recursive: bool,
+        extensions: &[String],
+        ignore: &Ignore,
+        found: &mut Discovery,
+    ) -> std::io::Result<()> {
+        if !source.is_dir() {
+            found.considered += 1;
+            found.files.push(source.to_path_buf());
+            return Ok(());
+        }
+
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let excluded = ignore.excluded(&entry.path(), file_type.is_dir());
+            if file_type.is_dir() {
+                match (recursive, excluded) {
+                    (false, _) => {}
+                    (true, None) => {
+                        Self::collect_source_files(&entry.path(), true, extensions, ignore, found)?
+                    }
+                    (true, Some(pattern)) => {
+                        found.excluded.push((entry.path(), pattern.to_owned()))
+                    }
+                }
+                continue;
+            }
+            found.considered += 1;
+            match (Self::is_jack_file(&entry, extensions), excluded) {
+                (true, None) => found.files.push(entry.path()),
+                (true, Some(pattern)) => found.excluded.push((entry.path(), pattern.to_owned())),
+                (false, _) => found.skipped.push(entry.path()),
+            }
+        }
+        Ok(())
+    }
+
+    // runs `f` over every file on up to `jobs` threads, the results are in
+    // the order of `files`
+    fn map_files<R: Send>(
+        files: &[PathBuf],
+        jobs: usize,
+        f: impl Fn(&PathBuf) -> Result<R> + Sync,
+    ) -> Result<Vec<R>> {
+        map_parallel(files, jobs, f)
+    }
+
+    // the extension is matched ignoring case, `Main.JACK` is a source too
+    fn is_jack_file(entry: &DirEntry, extensions: &[String]) -> bool {
+        let path = entry.path();
+        let extension = path.extension().and_then(OsStr::to_str).unwrap_or_default();
         FileType::is_file(&entry.file_type().unwrap())
-            && entry.path().extension().and_then(OsStr::to_str) == Some("jack")
+            && (extensions.iter().any(|wanted| {
+                wanted
+                    .trim_start_matches('.')
+                    .eq_ignore_ascii_case(extension)
+            }) || cfg!(feature = "gzip") && Self::is_gzipped(&path))
+    }
+
+    pub(crate) fn is_gzipped(file: &Path) -> bool {
+        file.to_str().is_some_and(|name| name.ends_with(".jack.gz"))
+    }
+
+    // the text of a source as the subcommands compile it, with a marker first
+    // line blanked when the options skip it and the warning about it printed
+    pub fn load_source(file: &Path, options: &AnalyzerOptions) -> Result<String> {
+        let text = Self::read_source(file)?;
+        if !options.skip_marker_line {
+            return Ok(text);
+        }
+        let (blanked, skipped) = skip_marker_line(&text);
+        if let Some(warning) = skipped {
+            Self::warn(&warning, file, &text, options);
+        }
+        Ok(blanked.into_owned())
+    }
+
+    // the sources to rename or look up symbols in, edited as they are on disk
+    // so their marker lines are only warned about, and left out of parsing
+    fn read_source_set(source: &PathBuf, options: &AnalyzerOptions) -> Result<SourceSet> {
+        let project =
+            SourceSet::read(source, options)?.keep_marker_lines(!options.skip_marker_line);
+        if options.skip_marker_line {
+            for (file, text) in project.files() {
+                if let (_, Some(warning)) = skip_marker_line(text) {
+                    Self::warn(&warning, file, text, options);
+                }
+            }
+        }
+        Ok(project)
+    }
+
+    // prints a warning found outside of check, unless its code is allowed
+    fn warn(warning: &Diagnostic, file: &Path, source: &str, options: &AnalyzerOptions) {
+        if !options.allow.iter().any(|code| code == warning.code) {
+            let rendered = options
+                .diagnostics
+                .render(warning, file, source, options.tabs);
+            eprintln!("{}", rendered);
+        }
+    }
+
+    // the text of a source file, decompressed first when it is gzipped
+    pub fn read_source(file: &Path) -> Result<String> {
+        if !Self::is_gzipped(file) {
+            return std::fs::read_to_string(file).with_context(|| format!("{}", file.display()));
+        }
+
+        #[cfg(feature = "gzip")]
+        {
+            let data = std::fs::read(file).with_context(|| format!("{}", file.display()))?;
+            let text = crate::gzip::decompress(&data)
+                .and_then(|data| Ok(String::from_utf8(data)?))
+                .with_context(|| format!("{}", file.display()))?;
+            Ok(text)
+        }
+        #[cfg(not(feature = "gzip"))]
+        anyhow::bail!(
+            "{}: reading gzipped sources needs the `gzip` feature",
+            file.display()
+        )
+    }
+}
+
+// the file name without `.jack` or `.jack.gz`, what outputs are named after
+pub(crate) fn source_stem(file: &Path) -> &str {
+    let name = file.file_name().and_then(OsStr::to_str).unwrap_or("Output");
+    name.strip_suffix(".jack.gz")
+        .or_else(|| name.strip_suffix(".jack"))
+        .or_else(|| file.file_stem().and_then(OsStr::to_str))
+        .unwrap_or("Output")
+}
+
+// what source discovery looked at: every file it came across, how many,
+// the ones of them that aren't sources and the sources and directories an
+// ignore pattern left out, with that pattern
+#[derive(Debug, Clone, Default)]
+struct Discovery {
+    files: Vec<PathBuf>,
+    considered: usize,
+    skipped: Vec<PathBuf>,
+    excluded: Vec<(PathBuf, String)>,
+}
+
+// tokenizes `src` once and compiles the xml from those same tokens, for tests
+// that look at both
+pub fn analyze_source(src: &str) -> Result<(Vec<Token>, String)> {
+    let tokens = StreamTokenizer::from_source(src).collect::<Result<Vec<_>>>()?;
+
+    let mut xml = Vec::new();
+    let mut replay = tokens.iter().cloned().map(Ok);
+    CompilationEngine::new(&mut xml, &mut replay).compile()?;
+
+    Ok((tokens, String::from_utf8(xml)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Diagnostic, DiffStatus, Newline, Project, Span, Token};
+
+    use std::path::{Path, PathBuf};
+
+    use super::{
+        analyze_source, Analyzer, AnalyzerOptions, DiagnosticFormat, DiagnosticReport,
+        DiagnosticSink, PhaseTimes,
+    };
+
+    #[test]
+    fn analyze_source_returns_matching_views() {
+        let (tokens, xml) = analyze_source(
+            "class Point { field int x, y; method int sum(Point other) {
+                return x + other.getX(); } }",
+        )
+        .unwrap();
+
+        let identifiers = tokens
+            .iter()
+            .filter(|t| matches!(t, Token::Identifier(_)))
+            .count();
+        assert_eq!(identifiers, 9);
+        assert_eq!(xml.matches("<identifier>").count(), identifiers);
+    }
+
+    #[test]
+    fn renaming_a_class_keeps_a_file_already_there() {
+        let dir = std::env::temp_dir().join(format!("jack_rename_taken_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let point = "class Point { function int one() { return 1; } }";
+        std::fs::write(dir.join("Point.jack"), point).unwrap();
+        std::fs::write(dir.join("Game.jack"), "// not part of the rename").unwrap();
+
+        let options = AnalyzerOptions::default();
+        let err = Analyzer::rename(&dir.join("Point.jack"), "Point=Game", &options).unwrap_err();
+        let point_after = std::fs::read_to_string(dir.join("Point.jack")).unwrap();
+        let game_after = std::fs::read_to_string(dir.join("Game.jack")).unwrap();
+
+        std::fs::remove_file(dir.join("Game.jack")).unwrap();
+        let renamed = Analyzer::rename(&dir.join("Point.jack"), "Point=Game", &options).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(
+            err.to_string().ends_with("Game.jack, it already exists"),
+            "{}",
+            err
+        );
+        assert_eq!(point_after, point);
+        assert_eq!(game_after, "// not part of the rename");
+        assert_eq!(renamed, [dir.join("Game.jack")]);
+    }
+
+    #[test]
+    fn single_vm_contains_every_class() {
+        let dir = std::env::temp_dir().join(format!("jack_single_vm_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("Main.jack"),
+            "class Main { function void main() { do Point.new(); return; } }",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("Point.jack"),
+            "class Point { field int x; constructor Point new() { return this; }
+            method int getX() { return x; } }",
+        )
+        .unwrap();
+
+        let single = dir.join("out.vm");
+        Analyzer::write_vm(&dir, Some(&single), &AnalyzerOptions::default()).unwrap();
+        let got = std::fs::read_to_string(&single).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let functions: Vec<&str> = got.lines().filter(|l| l.starts_with("function")).collect();
+        assert_eq!(
+            functions,
+            [
+                "function Main.main 0",
+                "function Point.new 0",
+                "function Point.getX 0"
+            ]
+        );
+    }
+
+    #[test]
+    fn newline_style_only_changes_the_line_endings() {
+        let dir = std::env::temp_dir().join(format!("jack_newline_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("Main.jack"),
+            "class Main { function void main() { do Output.printInt(1 + 2); return; } }",
+        )
+        .unwrap();
+
+        let mut written = Vec::new();
+        for newline in [Newline::Lf, Newline::Crlf] {
+            let out = dir.join(newline.name());
+            std::fs::create_dir_all(&out).unwrap();
+            let options = AnalyzerOptions {
+                out_dir: Some(out.clone()),
+                newline,
+                ..Default::default()
+            };
+            Analyzer::write_vm(&dir.join("Main.jack"), None, &options).unwrap();
+            Analyzer::analyze(&dir.join("Main.jack"), &options).unwrap();
+            written.push([
+                std::fs::read(out.join("Main.vm")).unwrap(),
+                std::fs::read(out.join("Main.xml")).unwrap(),
+            ]);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        for (lf, crlf) in written[0].iter().zip(&written[1]) {
+            assert!(!lf.contains(&b'\r'));
+            let lf = String::from_utf8(lf.clone()).unwrap();
+            assert_eq!(
+                String::from_utf8(crlf.clone()).unwrap(),
+                lf.replace('\n', "\r\n")
+            );
+        }
+    }
+
+    #[test]
+    fn manifest_is_deterministic_without_timestamp() {
+        let dir = std::env::temp_dir().join(format!("jack_manifest_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("Main.jack"),
+            "class Main { function int main() { return 7; } }",
+        )
+        .unwrap();
+
+        let options = AnalyzerOptions {
+            manifest: true,
+            manifest_timestamp: false,
+            ..Default::default()
+        };
+        let mut manifests = Vec::new();
+        for _ in 0..2 {
+            Analyzer::write_vm(&dir, None, &options).unwrap();
+            manifests.push(std::fs::read_to_string(dir.join("manifest.json")).unwrap());
+        }
+        let vm = std::fs::read(dir.join("Main.vm")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(manifests[0], manifests[1]);
+        assert!(!manifests[0].contains("generated_at"), "{}", manifests[0]);
+        assert!(
+            manifests[0].contains(&format!("\"bytes\":{},", vm.len())),
+            "{}",
+            manifests[0]
+        );
+        assert!(
+            manifests[0].ends_with("\"options\":{\"max_errors\":20,\"mode\":\"vm\"}}\n"),
+            "{}",
+            manifests[0]
+        );
+    }
+
+    #[test]
+    fn a_directory_without_sources_is_an_error() {
+        let dir = std::env::temp_dir().join(format!("jack_empty_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("empty")).unwrap();
+        std::fs::create_dir_all(dir.join("nested/src")).unwrap();
+        std::fs::write(dir.join("nested/src/Main.jack"), "class Main { }").unwrap();
+        let analyze = |path: &str, allow_empty| {
+            Analyzer::analyze(
+                &dir.join(path),
+                &AnalyzerOptions {
+                    out_dir: Some(dir.join("out")),
+                    allow_empty,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let empty = analyze("empty", false);
+        let nested = analyze("nested", false);
+        let allowed = analyze("empty", true);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            empty.unwrap_err().to_string(),
+            format!("no .jack files found under {}", dir.join("empty").display())
+        );
+        assert_eq!(
+            nested.unwrap_err().to_string(),
+            format!(
+                "no .jack files found under {} (searched non-recursively; try --recursive)",
+                dir.join("nested").display()
+            )
+        );
+        assert_eq!(allowed.unwrap().files_selected, 0);
+    }
+
+    #[test]
+    fn extensions_match_ignoring_case() {
+        let dir = std::env::temp_dir().join(format!("jack_extensions_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        for name in [
+            "Main.JACK",
+            "Screen.Jack",
+            "Old.jck",
+            "notes.txt",
+            "lib/A.jack",
+        ] {
+            std::fs::write(dir.join(name), "class A { }").unwrap();
+        }
+        let found = |extensions: &[&str], recursive| {
+            let options = AnalyzerOptions {
+                extensions: extensions.iter().map(|e| e.to_string()).collect(),
+                recursive,
+                ..Default::default()
+            };
+            let found = Analyzer::discover(&dir, &options).unwrap();
+            let mut names: Vec<String> = found
+                .files
+                .iter()
+                .map(|file| file.strip_prefix(&dir).unwrap().display().to_string())
+                .collect();
+            names.sort();
+            (names, found.considered, found.skipped.len())
+        };
+
+        let jack = found(&["jack"], false);
+        let both = found(&["jack", ".JCK"], true);
+        let report = Analyzer::analyze(
+            &dir,
+            &AnalyzerOptions {
+                out_dir: Some(dir.join("out")),
+                ..Default::default()
+            },
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(jack, (vec!["Main.JACK".into(), "Screen.Jack".into()], 4, 2));
+        assert_eq!(
+            both,
+            (
+                vec![
+                    "Main.JACK".into(),
+                    "Old.jck".into(),
+                    "Screen.Jack".into(),
+                    "lib/A.jack".into()
+                ],
+                5,
+                1
+            )
+        );
+        let report = report.unwrap();
+        assert_eq!((report.files_considered, report.files_selected), (4, 2));
+        assert!(report
+            .to_json()
+            .starts_with("{\"files_considered\":4,\"files_selected\":2,"));
+    }
+
+    #[test]
+    fn ignore_patterns_leave_sources_out() {
+        let dir = std::env::temp_dir().join(format!("jack_ignore_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("vendor/games")).unwrap();
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        for name in [
+            "Main.jack",
+            "Old_Main.jack",
+            "Old_Keep.jack",
+            "vendor/games/Pong.jack",
+            "lib/A.jack",
+            "lib/B.jack",
+        ] {
+            std::fs::write(dir.join(name), "class A { }").unwrap();
+        }
+        std::fs::write(
+            dir.join(".jackignore"),
+            "Old_*.jack\n!Old_Keep.jack\nvendor/\n",
+        )
+        .unwrap();
+        let found = |exclude: &[&str]| {
+            let options = AnalyzerOptions {
+                exclude: exclude.iter().map(|e| e.to_string()).collect(),
+                recursive: true,
+                ..Default::default()
+            };
+            let found = Analyzer::discover(&dir, &options).unwrap();
+            let mut names: Vec<String> = found
+                .files
+                .iter()
+                .map(|file| file.strip_prefix(&dir).unwrap().display().to_string())
+                .collect();
+            names.sort();
+            let excluded: Vec<String> = found
+                .excluded
+                .iter()
+                .map(|(_, pattern)| pattern.clone())
+                .collect();
+            (names, excluded)
+        };
+
+        let ignored = found(&[]);
+        let excluded = found(&["lib/B.jack"]);
+        let read = |exclude: &str| {
+            let options = AnalyzerOptions {
+                exclude: vec![exclude.to_owned()],
+                ..Default::default()
+            };
+            Analyzer::read_source_files(&dir, &options).unwrap()
+        };
+        let read = read("Old_Keep.jack");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let kept: Vec<String> = ["Main.jack", "Old_Keep.jack", "lib/A.jack", "lib/B.jack"]
+            .map(String::from)
+            .into();
+        assert_eq!(ignored.0, kept);
+        assert_eq!(ignored.1.len(), 2);
+        assert!(ignored.1.contains(&"Old_*.jack".to_owned()));
+        // the directory itself, nothing under it is looked at
+        assert!(ignored.1.contains(&"vendor/".to_owned()));
+        assert_eq!(excluded.0, kept[..3]);
+        assert_eq!(excluded.1.len(), 3);
+        assert!(excluded.1.contains(&"lib/B.jack".to_owned()));
+        assert_eq!(read.len(), 1);
+    }
+
+    #[test]
+    fn stats_put_the_lexical_counts_with_the_parse_ones() {
+        let dir = std::env::temp_dir().join(format!("jack_stats_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("Main.jack");
+        std::fs::write(
+            &source,
+            "// Main\nclass Main {\n\n    /** entry */\n    function void main() {\n        \
+             do Output.printString(\"hi\");\n        return;\n    }\n}\n",
+        )
+        .unwrap();
+        let stats = Analyzer::stats(&source, &AnalyzerOptions::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let stats = stats.unwrap();
+        let rows: Vec<Vec<&str>> = stats
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(rows.len(), 5, "{}", stats);
+        assert_eq!(
+            rows[0][..5],
+            ["tokens", "class", "vars", "subroutines", "statements"]
+        );
+        assert_eq!(
+            rows[1][..11],
+            ["21", "0", "1", "2", "9", "1", "5", "11", "4", "0", "1"],
+            "{}",
+            stats
+        );
+        assert_eq!(rows[4][..5], ["1", "0", "1", "0.33", "0"], "{}", stats);
+    }
+
+    #[test]
+    fn report_counts_every_phase() {
+        let dir = std::env::temp_dir().join(format!("jack_report_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("Main.jack");
+        std::fs::write(
+            &source,
+            "class Main { field int x;
+                method int f(int a) { var int i; let i = a;
+                    while (i < 10) { let i = i + 1; } return i + x; }
+                function void main() { do Output.printInt(1); return; } }",
+        )
+        .unwrap();
+        let options = AnalyzerOptions {
+            out_dir: Some(dir.clone()),
+            report_file: Some(dir.join("report.json")),
+            ..Default::default()
+        };
+        let report = Analyzer::analyze(&source, &options).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report.files.len(), 1);
+        let file = &report.files[0];
+        assert_eq!(file.file, source);
+        assert!(file.source_bytes > 100, "{:?}", file);
+        assert!(file.tokens > 50, "{:?}", file);
+        assert_eq!(file.tokens_consumed, file.tokens, "{:?}", file);
+        assert_eq!(file.bytes_scanned, file.source_bytes, "{:?}", file);
+        assert!(!file.parse.is_zero(), "{:?}", file);
+        assert!(!file.semantic.is_zero(), "{:?}", file);
+        assert!(!file.emit.is_zero(), "{:?}", file);
+        // every subroutine is held back until it compiled cleanly
+        assert!(file.peak_buffer > 100, "{:?}", file);
+
+        let json = report.to_json();
+        for field in [
+            "\"source_bytes\":",
+            "\"tokens\":",
+            "\"tokens_consumed\":",
+            "\"bytes_scanned\":",
+            "\"parse_ns\":",
+            "\"semantic_ns\":",
+            "\"emit_ns\":",
+            "\"peak_buffer_bytes\":",
+            "\"cached\":false",
+        ] {
+            assert!(json.contains(field), "{}", json);
+        }
+    }
+
+    #[test]
+    fn cache_skips_unchanged_sources() {
+        let dir = std::env::temp_dir().join(format!("jack_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("Main.jack");
+        std::fs::write(&source, "class Main { function int f() { return 1; } }").unwrap();
+        let options = AnalyzerOptions {
+            cache: Some(dir.join("jack.cache")),
+            out_dir: Some(dir.join("out")),
+            ..Default::default()
+        };
+        let output = dir.join("out").join("Main.xml");
+        // an output the run rewrites gets a new modification time
+        let backdate = || {
+            let old = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+            let file = std::fs::File::options().write(true).open(&output).unwrap();
+            file.set_modified(old).unwrap();
+            old
+        };
+        let modified = || std::fs::metadata(&output).unwrap().modified().unwrap();
+
+        Analyzer::analyze(&source, &options).unwrap();
+        let old = backdate();
+        let report = Analyzer::analyze(&source, &options).unwrap();
+        let skipped = modified() == old;
+        assert_eq!(report.files.len(), 1);
+        assert!(report.files[0].cached);
+        assert!(report.to_json().contains("\"cached\":true"));
+
+        std::fs::write(&source, "class Main { function int f() { return 2; } }").unwrap();
+        Analyzer::analyze(&source, &options).unwrap();
+        let rewritten = modified() != old;
+        let xml = std::fs::read_to_string(&output).unwrap();
+        let cache = std::fs::read_to_string(dir.join("jack.cache")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(skipped);
+        assert!(rewritten);
+        assert!(
+            xml.contains("<integerConstant> 2 </integerConstant>"),
+            "{}",
+            xml
+        );
+        assert!(
+            cache.starts_with(
+                "jack-cache 1 format=xml spans=false skip_marker_line=true well_formed_xml=false \
+                 normalize=false newline=lf\n"
+            ),
+            "{}",
+            cache
+        );
+        assert_eq!(cache.lines().count(), 2, "{}", cache);
+    }
+
+    #[test]
+    fn cache_skips_every_unchanged_source_of_a_directory() {
+        let dir = std::env::temp_dir().join(format!("jack_cache_dir_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["A", "B", "C"] {
+            std::fs::write(
+                dir.join(format!("{}.jack", name)),
+                format!("class {} {{ function int f() {{ return 1; }} }}", name),
+            )
+            .unwrap();
+        }
+        let options = AnalyzerOptions {
+            cache: Some(dir.join("jack.cache")),
+            out_dir: Some(dir.join("out")),
+            ..Default::default()
+        };
+
+        let first = Analyzer::analyze(&dir, &options).unwrap();
+        let second = Analyzer::analyze(&dir, &options).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(first.files.len(), 3);
+        assert!(first.files.iter().all(|file| !file.cached));
+        assert_eq!(second.files.len(), 3);
+        assert!(second.files.iter().all(|file| file.cached), "{:?}", second);
+    }
+
+    #[test]
+    fn diff_reports_only_the_changed_output() {
+        let dir = std::env::temp_dir().join(format!("jack_diff_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, value) in [("A", 1), ("B", 2), ("C", 3)] {
+            std::fs::write(
+                dir.join(format!("{}.jack", name)),
+                format!(
+                    "class {} {{ function int f() {{ return {}; }} }}",
+                    name, value
+                ),
+            )
+            .unwrap();
+        }
+        Analyzer::write_vm(&dir, None, &AnalyzerOptions::default()).unwrap();
+        std::fs::write(
+            dir.join("B.jack"),
+            "class B { function int f() { return 20; } }",
+        )
+        .unwrap();
+        std::fs::write(dir.join("D.jack"), "class D { }").unwrap();
+
+        let options = AnalyzerOptions {
+            diff: true,
+            ..Default::default()
+        };
+        let diffs = Analyzer::diff_vm(&dir, None, &options);
+        Analyzer::write_vm(&dir, None, &options).unwrap();
+        let b = std::fs::read_to_string(dir.join("B.vm"));
+        let d = dir.join("D.vm").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let got: Vec<(String, DiffStatus)> = diffs
+            .unwrap()
+            .iter()
+            .map(|diff| {
+                let name = diff
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned();
+                assert_eq!(diff.diff.is_empty(), diff.status != DiffStatus::Changed);
+                if diff.status == DiffStatus::Changed {
+                    assert!(diff
+                        .diff
+                        .contains("\n-push constant 2\n+push constant 20\n"));
+                }
+                (name, diff.status)
+            })
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("A.vm".to_owned(), DiffStatus::Unchanged),
+                ("B.vm".to_owned(), DiffStatus::Changed),
+                ("C.vm".to_owned(), DiffStatus::Unchanged),
+                ("D.vm".to_owned(), DiffStatus::New),
+            ]
+        );
+        // nothing was written
+        assert!(b.unwrap().contains("push constant 2\n"));
+        assert!(!d);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzipped_sources_are_read() {
+        let dir = std::env::temp_dir().join(format!("jack_gzip_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("gz")).unwrap();
+        std::fs::create_dir_all(dir.join("plain")).unwrap();
+        std::fs::write(
+            dir.join("gz/Square.jack.gz"),
+            include_bytes!("../tests/fixtures/Square.jack.gz"),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("plain/Square.jack"),
+            include_str!("../tests/fixtures/Square.jack"),
+        )
+        .unwrap();
+
+        let xml = |source: &str, out: &str| {
+            let options = AnalyzerOptions {
+                out_dir: Some(dir.join(out)),
+                ..Default::default()
+            };
+            Analyzer::analyze(&dir.join(source), &options).unwrap();
+            std::fs::read_to_string(dir.join(out).join("Square.xml"))
+        };
+        let got = xml("gz", "out_gz");
+        let expected = xml("plain", "out_plain");
+        let vm = Analyzer::compile_vm(&dir.join("gz"), &AnalyzerOptions::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let got = got.unwrap();
+        assert!(
+            got.contains("<identifier> drawRectangle </identifier>"),
+            "{}",
+            got
+        );
+        assert_eq!(got, expected.unwrap());
+        assert!(vm.unwrap()[0].1.contains("call Screen.drawRectangle 4"));
+    }
+
+    #[test]
+    fn profile_has_a_row_per_file_and_both_phases() {
+        let options = AnalyzerOptions {
+            profile: true,
+            ..Default::default()
+        };
+        let project = Project::from_sources(["A", "Longer"].map(|name| {
+            (
+                format!("{}.jack", name),
+                format!("class {} {{ function void f() {{ return; }} }}", name),
+            )
+        }))
+        .with_options(options);
+        let profile: Vec<(PathBuf, PhaseTimes)> = project
+            .sources()
+            .keys()
+            .map(|name| {
+                let parsed = project.tree(name).unwrap();
+                assert!(parsed.tree.unwrap().contains("<returnStatement>"));
+                (PathBuf::from(name), parsed.times)
+            })
+            .collect();
+
+        let report = Analyzer::profile_report(&profile);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", report);
+        assert!(lines[0].contains("tokenize ms") && lines[0].contains("engine ms"));
+        assert!(lines[1].starts_with("A.jack "), "{}", report);
+        assert!(lines[2].starts_with("Longer.jack "), "{}", report);
+        assert!(lines[3].starts_with("total "), "{}", report);
+        // the columns line up
+        assert!(
+            lines.iter().all(|l| l.len() == lines[0].len()),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn sink_stops_at_the_error_limit() {
+        let at = |column| Span {
+            start: column - 1,
+            end: column,
+            line: 1,
+            column,
+        };
+        let diagnostics = [
+            Diagnostic::error("J103", "first", at(1)),
+            Diagnostic::warning("J108", "warned", at(2)),
+            Diagnostic::error("J107", "second", at(3)),
+            Diagnostic::note("J107", "of the second", at(4)),
+            Diagnostic::error("J107", "third", at(5)),
+            Diagnostic::note("J107", "of the third", at(6)),
+            Diagnostic::warning("J108", "still warned", at(7)),
+            Diagnostic::error("J103", "fourth", at(8)),
+        ];
+        let shown = |max_errors| {
+            let mut sink = DiagnosticSink::new(&AnalyzerOptions {
+                max_errors,
+                diagnostics: DiagnosticFormat::Short,
+                ..Default::default()
+            });
+            let mut lines: Vec<String> = diagnostics
+                .iter()
+                .filter_map(|d| sink.emit(d, Path::new("A.jack"), "class A { }"))
+                .collect();
+            lines.extend(sink.finish());
+            lines
+        };
+
+        assert_eq!(
+            shown(2),
+            [
+                "A.jack:1:1: error[J103]: first",
+                "A.jack:1:2: warning[J108]: warned",
+                "A.jack:1:3: error[J107]: second",
+                "A.jack:1:4: note[J107]: of the second",
+                "A.jack:1:7: warning[J108]: still warned",
+                "and 2 more errors",
+            ]
+        );
+        assert_eq!(shown(3).last().unwrap(), "and 1 more error");
+        assert_eq!(shown(0).len(), diagnostics.len());
+    }
+
+    #[test]
+    fn reports_group_by_severity_in_source_order() {
+        let at = |line| Span {
+            start: 0,
+            end: 1,
+            line,
+            column: 1,
+        };
+        let mut report = DiagnosticReport::new(&AnalyzerOptions {
+            diagnostics: DiagnosticFormat::Short,
+            ..Default::default()
+        });
+        for (file, diagnostic) in [
+            ("B.jack", Diagnostic::warning("J108", "b warned", at(2))),
+            ("A.jack", Diagnostic::error("J107", "a second", at(5))),
+            ("A.jack", Diagnostic::note("J107", "of a second", at(1))),
+            ("A.jack", Diagnostic::warning("J108", "a warned", at(3))),
+            ("A.jack", Diagnostic::error("J103", "a first", at(4))),
+            ("B.jack", Diagnostic::error("J103", "b first", at(1))),
+        ] {
+            report.add(Path::new(file), "class A { }", diagnostic);
+        }
+        report.sort_by_position();
+
+        assert_eq!((report.errors(), report.warnings()), (3, 2));
+        assert_eq!(
+            report.render(),
+            "B.jack:1:1: error[J103]: b first\n\
+             A.jack:4:1: error[J103]: a first\n\
+             A.jack:5:1: error[J107]: a second\n\
+             A.jack:1:1: note[J107]: of a second\n\
+             \n\
+             B.jack:2:1: warning[J108]: b warned\n\
+             A.jack:3:1: warning[J108]: a warned\n\
+             \n\
+             3 errors, 2 warnings, 1 note"
+        );
     }
 }
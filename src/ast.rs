@@ -0,0 +1,165 @@
+use std::fmt;
+
+use crate::{
+    lexical_elements::{Keywords, Symbols},
+    token::Span,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JackType {
+    Int,
+    Char,
+    Boolean,
+    Void,
+    ClassName(String),
+}
+
+impl fmt::Display for JackType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = match self {
+            Self::Int => "int",
+            Self::Char => "char",
+            Self::Boolean => "boolean",
+            Self::Void => "void",
+            Self::ClassName(name) => name,
+        };
+
+        write!(f, "{}", v)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Class {
+    pub name: Ident,
+    pub class_vars: Vec<ClassVarDec>,
+    pub subroutines: Vec<SubroutineDec>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassVarKind {
+    Static,
+    Field,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassVarDec {
+    pub kind: ClassVarKind,
+    pub var_type: JackType,
+    pub type_span: Span,
+    pub names: Vec<Ident>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubroutineKind {
+    Constructor,
+    Function,
+    Method,
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub var_type: JackType,
+    pub type_span: Span,
+    pub name: Ident,
+}
+
+#[derive(Debug, Clone)]
+pub struct VarDec {
+    pub var_type: JackType,
+    pub type_span: Span,
+    pub names: Vec<Ident>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct SubroutineDec {
+    pub kind: SubroutineKind,
+    pub return_type: JackType,
+    pub return_type_span: Span,
+    pub name: Ident,
+    pub params: Vec<Parameter>,
+    pub locals: Vec<VarDec>,
+    pub statements: Vec<Statement>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum Statement {
+    Let {
+        target: Ident,
+        index: Option<Expression>,
+        value: Expression,
+        span: Span,
+    },
+    If {
+        condition: Expression,
+        then_branch: Vec<Statement>,
+        else_branch: Option<Vec<Statement>>,
+        span: Span,
+    },
+    While {
+        condition: Expression,
+        body: Vec<Statement>,
+        span: Span,
+    },
+    Do {
+        call: SubroutineCall,
+        span: Span,
+    },
+    Return {
+        value: Option<Expression>,
+        span: Span,
+    },
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Let { span, .. }
+            | Self::If { span, .. }
+            | Self::While { span, .. }
+            | Self::Do { span, .. }
+            | Self::Return { span, .. } => *span,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubroutineCall {
+    pub receiver: Option<Ident>,
+    pub name: Ident,
+    pub args: Vec<Expression>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExpressionKind {
+    IntConst(i16),
+    StringConst(String),
+    // one of true, false, null or this
+    KeywordConst(Keywords),
+    Var(Ident),
+    Index(Ident, Box<Expression>),
+    Call(SubroutineCall),
+    // a parenthesised sub expression, kept so the tree can be written back out
+    Paren(Box<Expression>),
+    // `-` or `~` applied to a term
+    Unary(Symbols, Box<Expression>),
+    // jack has no operator precedence, chains of operators are left associative
+    Binary(Symbols, Box<Expression>, Box<Expression>),
+}
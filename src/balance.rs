@@ -0,0 +1,212 @@
+use anyhow::Result;
+
+use crate::{
+    diagnostic::{Diagnostic, Severity},
+    lexical_elements::{Keywords, Symbols},
+    token::Span,
+    SpannedToken, Token,
+};
+
+pub const UNBALANCED_DELIMITER: &str = "J005";
+
+// a quick pass over the tokens before parsing, so a missing or stray brace is
+// reported where it was opened instead of wherever the grammar gives up.
+// lex errors end the pass, the parser reports those itself
+pub fn check_balance(tokens: impl Iterator<Item = Result<SpannedToken>>) -> Result<()> {
+    let tokens: Vec<_> = tokens.collect();
+    match unbalanced(&tokens).into_iter().next() {
+        Some(diagnostic) => Err(diagnostic.into()),
+        None => Ok(()),
+    }
+}
+
+// everything wrong with the delimiters of `tokens`. with nothing parsed a
+// delimiter left open is an error, not a note to an end of input error
+pub(crate) fn unbalanced<'t>(
+    tokens: impl IntoIterator<Item = &'t Result<SpannedToken>>,
+) -> Vec<Diagnostic> {
+    let mut brackets = BracketTracker::new();
+    for token in tokens {
+        match token {
+            Ok(token) => brackets.token(&token.token, token.span),
+            // what is open past it isn't known
+            Err(_) => return brackets.diagnostics,
+        }
+    }
+    let mut diagnostics = brackets.finish();
+    for diagnostic in &mut diagnostics {
+        diagnostic.severity = Severity::Error;
+    }
+    diagnostics
+}
+
+// follows the delimiters as they are read and keeps going after a mismatch,
+// reporting every one of them instead of only the first
+#[derive(Debug, Default)]
+pub struct BracketTracker {
+    open: Vec<(Symbols, Span)>,
+    diagnostics: Vec<Diagnostic>,
+    // the `}` that just closed a subroutine body and the `{` it closed,
+    // until the token after it shows whether it was stray
+    closed_body: Option<(Span, (Symbols, Span))>,
+}
+
+impl BracketTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // every token read, which also tells a stray `}` in a subroutine from
+    // the one closing it: a statement right after the body closed means it
+    // wasn't closed there
+    pub fn token(&mut self, token: &Token, span: Span) {
+        if let Some((brace, body)) = self.closed_body.take() {
+            if starts_statement(token) {
+                let message = format!(
+                    "stray `}}`: the statements after it still belong to the body opened at {}",
+                    body.1
+                );
+                self.diagnostics
+                    .push(Diagnostic::error(UNBALANCED_DELIMITER, message, brace));
+                self.open.push(body);
+            }
+        }
+        if let Token::Symbol(symbol) = token {
+            self.symbol(symbol, span);
+        }
+    }
+
+    pub fn symbol(&mut self, symbol: &Symbols, span: Span) {
+        if symbol.is_opening() {
+            self.open.push((symbol.clone(), span));
+            return;
+        }
+        if !symbol.is_closing() {
+            return;
+        }
+
+        let Some((innermost, opened)) = self.open.last() else {
+            let message = format!("found `{}` but nothing is open", symbol);
+            self.diagnostics
+                .push(Diagnostic::error(UNBALANCED_DELIMITER, message, span));
+            return;
+        };
+        if innermost.closing_partner().as_ref() == Some(symbol) {
+            let closed = self.open.pop();
+            // back in the class body after a subroutine's
+            if *symbol == Symbols::CloseCurlyBrace && self.open.len() == 1 {
+                self.closed_body = closed.map(|body| (span, body));
+            }
+            return;
+        }
+
+        let message = format!(
+            "found `{}` but the nearest unclosed delimiter is `{}` opened at {}",
+            symbol, innermost, opened
+        );
+        self.diagnostics
+            .push(Diagnostic::error(UNBALANCED_DELIMITER, message, span));
+
+        // when a delimiter further out matches, the ones above it were left
+        // open, the innermost is already named. without a match the closing
+        // one is stray and dropped
+        let partner = symbol.opening_partner();
+        if let Some(i) = self
+            .open
+            .iter()
+            .rposition(|(s, _)| Some(s) == partner.as_ref())
+        {
+            let mut unclosed = self.open.split_off(i + 1);
+            unclosed.pop();
+            self.open.pop();
+            self.never_closed(unclosed);
+        }
+    }
+
+    // the delimiters open, a closing one that matches one further out
+    // closes everything above it too
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    // everything found, with a note for each delimiter still open at the end
+    pub fn finish(mut self) -> Vec<Diagnostic> {
+        let open = std::mem::take(&mut self.open);
+        self.never_closed(open);
+        self.diagnostics
+    }
+
+    fn never_closed(&mut self, open: Vec<(Symbols, Span)>) {
+        for (symbol, span) in open {
+            let message = format!("`{}` opened at {} is never closed", symbol, span);
+            self.diagnostics
+                .push(Diagnostic::note(UNBALANCED_DELIMITER, message, span));
+        }
+    }
+}
+
+fn starts_statement(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Keyword(
+            Keywords::Var
+                | Keywords::Let
+                | Keywords::Do
+                | Keywords::If
+                | Keywords::Else
+                | Keywords::While
+                | Keywords::Return
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Diagnostic, StreamTokenizer};
+
+    use super::check_balance;
+
+    fn check(src: &str) -> Option<String> {
+        let tokens = StreamTokenizer::from_source(src).into_spanned();
+        check_balance(tokens)
+            .err()
+            .map(|e| e.downcast::<Diagnostic>().unwrap().message)
+    }
+
+    #[test]
+    fn balanced_source() {
+        let src = "class Main { function void main() { let a[1] = (2); return; } }";
+        assert_eq!(check(src), None);
+    }
+
+    #[test]
+    fn missing_close_brace() {
+        let src =
+            "class Main {\n  function void main() {\n    while (true) {\n      return;\n  }\n}";
+        assert_eq!(
+            check(src).as_deref(),
+            Some("`{` opened at 1:12 is never closed")
+        );
+
+        let src = "class Main { function void main() { do f(1; return; } }";
+        assert_eq!(
+            check(src).as_deref(),
+            Some("found `}` but the nearest unclosed delimiter is `(` opened at 1:41")
+        );
+    }
+
+    #[test]
+    fn stray_close_brace() {
+        let src = "class Main {\n  function void main() { return; }\n  }\n}";
+        assert_eq!(check(src).as_deref(), Some("found `}` but nothing is open"));
+    }
+
+    #[test]
+    fn stray_close_brace_in_a_subroutine_is_reported_where_it_is() {
+        let src = "class Main {\n  function void main() {\n    let a = 1; }\n    return;\n  }\n}";
+        assert_eq!(
+            check(src).as_deref(),
+            Some("stray `}`: the statements after it still belong to the body opened at 2:24")
+        );
+    }
+}
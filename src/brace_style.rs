@@ -0,0 +1,108 @@
+use crate::{
+    diagnostic::Diagnostic, lexical_elements::Symbols, SpannedToken, StreamTokenizer, Token,
+};
+
+pub const BRACE_STYLE: &str = "J109";
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum BraceStyle {
+    /// `{` ends the line of the declaration or statement it opens
+    Same,
+    /// `{` starts the line after it
+    Next,
+}
+
+// a warning for every `{` not placed the way `style` wants, going by the
+// line of the token before it. every `{` in jack opens a class, subroutine
+// or statement body. the scan stops at a lex error, the parser reports those
+pub fn check_brace_style(source: &str, style: BraceStyle) -> Vec<Diagnostic> {
+    let tokens: Vec<SpannedToken> = StreamTokenizer::from_source(source)
+        .into_spanned()
+        .map_while(Result::ok)
+        .collect();
+
+    let mut diagnostics = Vec::new();
+    for pair in tokens.windows(2) {
+        let (before, brace) = (&pair[0], &pair[1]);
+        if !matches!(brace.token, Token::Symbol(Symbols::OpenCurlyBrace)) {
+            continue;
+        }
+
+        let same_line = before.span.line == brace.span.line;
+        let message = match style {
+            BraceStyle::Same if !same_line => {
+                format!("`{{` should end line {}", before.span.line)
+            }
+            BraceStyle::Next if same_line => format!(
+                "`{{` should be on its own line after line {}",
+                before.span.line
+            ),
+            _ => continue,
+        };
+        diagnostics.push(Diagnostic::warning(BRACE_STYLE, message, brace.span));
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_brace_style, BraceStyle, BRACE_STYLE};
+
+    const SAME: &str = "class Main {
+    function void f() {
+        if (true) {
+            return;
+        } else {
+            return;
+        }
+    }
+}";
+
+    const NEXT: &str = "class Main
+{
+    function void f()
+    {
+        while (true)
+        {
+            return;
+        }
+        return;
+    }
+}";
+
+    fn lines(source: &str, style: BraceStyle) -> Vec<(usize, String)> {
+        check_brace_style(source, style)
+            .into_iter()
+            .map(|d| {
+                assert_eq!(d.code, BRACE_STYLE);
+                (d.span.line, d.message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_line_style() {
+        assert!(lines(SAME, BraceStyle::Same).is_empty());
+
+        assert_eq!(
+            lines(NEXT, BraceStyle::Same),
+            [
+                (2, "`{` should end line 1".to_owned()),
+                (4, "`{` should end line 3".to_owned()),
+                (6, "`{` should end line 5".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn next_line_style() {
+        assert!(lines(NEXT, BraceStyle::Next).is_empty());
+
+        let got = lines(SAME, BraceStyle::Next);
+        assert_eq!(got.len(), 4, "{:?}", got);
+        assert_eq!(
+            got[3],
+            (5, "`{` should be on its own line after line 5".to_owned())
+        );
+    }
+}
@@ -0,0 +1,424 @@
+use std::{
+    io::{BufRead, Cursor},
+    iter::FusedIterator,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    lexical_elements::{KeywordSet, Symbols},
+    token::{Span, SpannedToken},
+    StreamTokenizer, TabPolicy, Token, TokenSource,
+};
+
+// the same tokens as StreamTokenizer, read from `reader` as they are needed.
+//
+// only a window from the current position to just past the next token is
+// kept in memory, however long its line. the window is refilled until
+// something follows the token, so an identifier or number can't go on in
+// the next read. a block comment has to be held whole
+pub struct BufReadTokenizer<R: BufRead> {
+    reader: R,
+    window: String,
+    // the start of a character split between two reads
+    partial: Vec<u8>,
+    eof: bool,
+    started: bool,
+    failed: bool,
+    current_index: usize,
+    line: usize,
+    column: usize,
+    recover_from_lex_errors: bool,
+    after_minus: bool,
+    keywords: KeywordSet,
+    tabs: TabPolicy,
+    emit_eof: bool,
+    eof_emitted: bool,
+    #[cfg(test)]
+    peak_window: usize,
+}
+
+impl<R: BufRead> BufReadTokenizer<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            window: String::new(),
+            partial: Vec::new(),
+            eof: false,
+            started: false,
+            failed: false,
+            current_index: 0,
+            line: 1,
+            column: 1,
+            recover_from_lex_errors: false,
+            after_minus: false,
+            keywords: KeywordSet::base(),
+            tabs: TabPolicy::One,
+            emit_eof: false,
+            eof_emitted: false,
+            #[cfg(test)]
+            peak_window: 0,
+        }
+    }
+
+    pub fn recover_from_lex_errors(mut self, recover: bool) -> Self {
+        self.recover_from_lex_errors = recover;
+        self
+    }
+
+    pub fn keywords(mut self, keywords: KeywordSet) -> Self {
+        self.keywords = keywords;
+        self
+    }
+
+    pub fn tabs(mut self, tabs: TabPolicy) -> Self {
+        self.tabs = tabs;
+        self
+    }
+
+    // end with a `Token::Eof` before the iterator runs out
+    pub fn emit_eof(mut self, emit: bool) -> Self {
+        self.emit_eof = emit;
+        self
+    }
+
+    pub fn into_spanned(self) -> BufReadSpannedTokens<R> {
+        BufReadSpannedTokens(self)
+    }
+
+    // appends the next chunk of the reader to the window
+    fn read_more(&mut self) -> Result<()> {
+        let chunk = self.reader.fill_buf()?;
+        if chunk.is_empty() {
+            if !self.partial.is_empty() {
+                bail!("stream did not contain valid UTF-8");
+            }
+            self.eof = true;
+            return Ok(());
+        }
+
+        self.partial.extend_from_slice(chunk);
+        let length = chunk.len();
+        self.reader.consume(length);
+
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => bail!("stream did not contain valid UTF-8"),
+        };
+        let text = std::str::from_utf8(&self.partial[..valid]).expect("checked above");
+        self.window.push_str(text);
+        self.partial.drain(..valid);
+        #[cfg(test)]
+        {
+            self.peak_window = self.peak_window.max(self.window.len());
+        }
+
+        if !self.started && self.window.len() >= '\u{FEFF}'.len_utf8() {
+            self.started = true;
+            // skipped but counted, like StreamTokenizer does
+            if let Some(rest) = self.window.strip_prefix('\u{FEFF}') {
+                self.window = rest.to_owned();
+                self.current_index = '\u{FEFF}'.len_utf8();
+            }
+        }
+        Ok(())
+    }
+
+    // whether the window holds the trivia before the next token and all of
+    // the token, with something after it to show where it ends
+    fn holds_next_token(&self) -> bool {
+        let (skipped, unclosed) = StreamTokenizer::skip_trivia(&self.window);
+        let rest = &self.window[skipped..];
+        if unclosed.is_some() || rest.is_empty() {
+            return false;
+        }
+        let token = StreamTokenizer::tokenize_following(
+            rest,
+            self.recover_from_lex_errors,
+            self.after_minus,
+            &self.keywords,
+        );
+        match token {
+            Ok((_, bytes_read)) => bytes_read < rest.len(),
+            Err(_) => !StreamTokenizer::runs_to_end(rest),
+        }
+    }
+
+    fn chomp(&mut self, num_bytes: usize) {
+        for ch in self.window[..num_bytes].chars() {
+            match ch {
+                '\n' => {
+                    self.line += 1;
+                    self.column = 1;
+                }
+                '\t' => self.column = self.tabs.advance(self.column - 1, '\t') + 1,
+                _ => self.column += 1,
+            }
+        }
+
+        self.window.drain(..num_bytes);
+        self.current_index += num_bytes;
+    }
+
+    fn next_spanned(&mut self) -> Option<Result<SpannedToken>> {
+        if self.failed {
+            return None;
+        }
+
+        let token = self.read_token();
+        if !matches!(token, Some(Ok(_))) {
+            self.failed = true;
+        }
+        token
+    }
+
+    fn read_token(&mut self) -> Option<Result<SpannedToken>> {
+        while !self.eof && !self.holds_next_token() {
+            // at least as much again as the window holds before looking
+            // again, a long comment or string is scanned a few times only
+            let wanted = (self.window.len() * 2).max(1);
+            while !self.eof && self.window.len() < wanted {
+                if let Err(e) = self.read_more() {
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        let (skipped, unclosed) = StreamTokenizer::skip_trivia(&self.window);
+        if let Some(comment) = unclosed {
+            // only seen once the whole input is in the window
+            self.chomp(comment);
+            let span = Span {
+                start: self.current_index,
+                end: self.current_index + self.window.len(),
+                line: self.line,
+                column: self.column,
+            };
+            self.chomp(self.window.len());
+            return Some(Err(StreamTokenizer::unexpected_eof("block comment", span)));
+        }
+        self.chomp(skipped);
+        let (start, line, column) = (self.current_index, self.line, self.column);
+        if self.window.is_empty() {
+            if !self.emit_eof || self.eof_emitted {
+                return None;
+            }
+            self.eof_emitted = true;
+            return Some(Ok(SpannedToken {
+                token: Token::Eof,
+                span: Span {
+                    start,
+                    end: start,
+                    line,
+                    column,
+                },
+                blank_lines_before: 0,
+            }));
+        }
+
+        let token = StreamTokenizer::tokenize_following(
+            &self.window,
+            self.recover_from_lex_errors,
+            self.after_minus,
+            &self.keywords,
+        );
+        let token = match token {
+            Err(_) if StreamTokenizer::runs_to_end(&self.window) => {
+                let span = Span {
+                    start,
+                    end: start + self.window.len(),
+                    line,
+                    column,
+                };
+                return Some(Err(StreamTokenizer::unexpected_eof(
+                    "string constant",
+                    span,
+                )));
+            }
+            token => token.with_context(|| format!("at {}:{}", line, column)),
+        };
+        let (token, bytes_read) = match token {
+            Ok(token) => token,
+            Err(e) => return Some(Err(e)),
+        };
+        self.chomp(bytes_read);
+        self.after_minus = matches!(token, Token::Symbol(Symbols::Minus));
+
+        Some(Ok(SpannedToken {
+            token,
+            span: Span {
+                start,
+                end: self.current_index,
+                line,
+                column,
+            },
+            blank_lines_before: 0,
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for BufReadTokenizer<R> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_spanned()
+            .map(|token| token.map(|spanned| spanned.token))
+    }
+}
+
+impl<R: BufRead> FusedIterator for BufReadTokenizer<R> {}
+
+pub struct BufReadSpannedTokens<R: BufRead>(BufReadTokenizer<R>);
+
+impl<R: BufRead> FusedIterator for BufReadSpannedTokens<R> {}
+
+impl<R: BufRead> Iterator for BufReadSpannedTokens<R> {
+    type Item = Result<SpannedToken>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_spanned()
+    }
+}
+
+impl TokenSource for BufReadTokenizer<Cursor<Vec<u8>>> {
+    const NAME: &'static str = "BufReadTokenizer";
+
+    fn from_source(source: &str) -> Self {
+        BufReadTokenizer::new(Cursor::new(source.as_bytes().to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Cursor};
+
+    use crate::{token::SpannedToken, StreamTokenizer, TabPolicy};
+
+    use super::BufReadTokenizer;
+
+    fn spanned(tokens: impl Iterator<Item = anyhow::Result<SpannedToken>>) -> Vec<String> {
+        tokens
+            .map(|token| match token {
+                Ok(t) => format!("{} {}..{} {}", t.token, t.span.start, t.span.end, t.span),
+                Err(e) => format!("error: {:#}", e),
+            })
+            .collect()
+    }
+
+    // a few hundred tokens far apart, with comments and strings longer than
+    // the reader's buffer and multi byte characters in both
+    fn large_source() -> String {
+        let mut src = String::from("\u{FEFF}class Big {\n");
+        for i in 0..50 {
+            src.push_str(&format!("  /* {} é {} */\n", i, "ső ".repeat(100 + 5 * i)));
+            src.push_str(&format!(
+                "  function void f{}() {{ do Output.printString(\"{}\"); return; }}\n",
+                i,
+                "ab€".repeat(i)
+            ));
+            src.push_str(&format!("  // {}\n", "x".repeat(i * 40)));
+        }
+        src.push('}');
+        src
+    }
+
+    #[test]
+    fn matches_the_stream_tokenizer_on_large_input() {
+        let src = large_source();
+        assert!(src.len() > 64 * 1024, "{} bytes", src.len());
+
+        let expected = spanned(StreamTokenizer::from_source(&src).into_spanned());
+        for capacity in [1, 7, 64, 8192] {
+            let reader = BufReader::with_capacity(capacity, src.as_bytes());
+            let got = spanned(BufReadTokenizer::new(reader).into_spanned());
+            assert_eq!(got, expected, "buffer of {} bytes", capacity);
+        }
+    }
+
+    #[test]
+    fn one_long_line_is_read_a_token_at_a_time() {
+        let mut src = String::from("class Main { function void main() {");
+        for i in 0..2000 {
+            src.push_str(&format!(" let x{} = x{} + 12345; /* {} */", i, i, i));
+        }
+        src.push_str(" return; } }");
+
+        let mut tokens = BufReadTokenizer::new(BufReader::with_capacity(16, src.as_bytes()));
+        let got = spanned(std::iter::from_fn(|| tokens.next_spanned()));
+        assert_eq!(
+            got,
+            spanned(StreamTokenizer::from_source(&src).into_spanned())
+        );
+        assert!(tokens.peak_window < 64, "{} bytes", tokens.peak_window);
+    }
+
+    #[test]
+    fn tokens_straddling_refills() {
+        let src = "let identifier_longer_than_the_buffer = \"string over refills\"; /**/ x";
+        let reader = BufReader::with_capacity(3, Cursor::new(src));
+
+        let got = spanned(BufReadTokenizer::new(reader).into_spanned());
+        assert_eq!(
+            got,
+            spanned(StreamTokenizer::from_source(src).into_spanned())
+        );
+    }
+
+    #[test]
+    fn tabs_match_the_stream_tokenizer() {
+        let src = "class\tMain {\n\tfield  int\ta;\n  x\t\t}";
+        for tabs in [TabPolicy::One, TabPolicy::Stop(4), TabPolicy::Stop(8)] {
+            let reader = BufReader::with_capacity(3, src.as_bytes());
+            let got = spanned(BufReadTokenizer::new(reader).tabs(tabs).into_spanned());
+            assert_eq!(
+                got,
+                spanned(StreamTokenizer::from_source(src).tabs(tabs).into_spanned()),
+                "{:?}",
+                tabs
+            );
+        }
+    }
+
+    #[test]
+    fn stops_after_an_error() {
+        let reader = BufReader::with_capacity(2, "a # b".as_bytes());
+
+        let got: Vec<bool> = BufReadTokenizer::new(reader).map(|t| t.is_ok()).collect();
+        assert_eq!(got, [true, false]);
+    }
+
+    #[test]
+    fn eof_matches_the_stream_tokenizer() {
+        let src = "class Main { }\n   ";
+        let reader = BufReader::with_capacity(4, src.as_bytes());
+
+        let got = spanned(BufReadTokenizer::new(reader).emit_eof(true).into_spanned());
+        assert_eq!(
+            got,
+            spanned(
+                StreamTokenizer::from_source(src)
+                    .emit_eof(true)
+                    .into_spanned()
+            )
+        );
+        assert_eq!(got.last().unwrap(), "<EOF> 18..18 2:4");
+    }
+
+    #[test]
+    fn input_ending_inside_a_token_matches_the_stream_tokenizer() {
+        for src in ["a \"open", "a /* open\nstill open", "x \"open\n\""] {
+            let reader = BufReader::with_capacity(2, src.as_bytes());
+            let mut tokens = BufReadTokenizer::new(reader).into_spanned();
+            let got = spanned(tokens.by_ref());
+            assert_eq!(
+                got,
+                spanned(StreamTokenizer::from_source(src).into_spanned())
+            );
+            assert!(got[1].starts_with("error: "), "{:?}", got);
+            for _ in 0..3 {
+                assert!(tokens.next().is_none());
+            }
+        }
+    }
+}
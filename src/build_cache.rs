@@ -0,0 +1,178 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::sha256::Sha256;
+
+const HEADER: &str = "jack-cache 1";
+// how long after it was modified a source's time is trusted
+const RACY_NANOS: u128 = 2_000_000_000;
+
+struct Entry {
+    source_sha256: String,
+    // nanoseconds since the epoch. a source modified at the same time is
+    // taken as unchanged without hashing it, otherwise its hash decides. 0
+    // when unknown or too recent to trust
+    mtime: u128,
+    output_sha256: String,
+}
+
+// what `parse --cache` wrote for every source on earlier runs, by source and
+// the output written for it. the file has a header line with the settings
+// the outputs were written with, then one
+// `source sha256<TAB>mtime<TAB>output sha256<TAB>output<TAB>source` line per
+// source. a cache written with other settings is started over
+pub struct BuildCache {
+    path: PathBuf,
+    settings: String,
+    entries: BTreeMap<(PathBuf, PathBuf), Entry>,
+}
+
+impl BuildCache {
+    pub fn load(path: &Path, settings: String) -> Result<Self> {
+        let mut cache = Self {
+            path: path.to_path_buf(),
+            settings,
+            entries: BTreeMap::new(),
+        };
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(e).with_context(|| format!("{}", path.display())),
+        };
+
+        let mut lines = text.lines();
+        if lines.next() != Some(&format!("{} {}", HEADER, cache.settings)) {
+            return Ok(cache);
+        }
+        for (number, line) in lines.enumerate() {
+            let fields: Vec<&str> = line.splitn(5, '\t').collect();
+            let [source_sha256, mtime, output_sha256, output, source] = fields[..] else {
+                bail!("{}:{}: not a cache entry", path.display(), number + 2);
+            };
+            let mtime = mtime
+                .parse()
+                .with_context(|| format!("{}:{}: bad mtime", path.display(), number + 2))?;
+            cache.entries.insert(
+                (PathBuf::from(source), PathBuf::from(output)),
+                Entry {
+                    source_sha256: source_sha256.to_owned(),
+                    mtime,
+                    output_sha256: output_sha256.to_owned(),
+                },
+            );
+        }
+        Ok(cache)
+    }
+
+    // the contents of `output` when it still holds what was written for
+    // `source` and `source` is unchanged since, by its modification time or
+    // failing that the hash of its text
+    pub fn fresh(&self, source: &Path, text: &str, output: &Path) -> Option<Vec<u8>> {
+        let entry = self
+            .entries
+            .get(&(source.to_path_buf(), output.to_path_buf()))?;
+        let touched = entry.mtime == 0 || mtime(source) != entry.mtime;
+        if touched && entry.source_sha256 != sha256(text.as_bytes()) {
+            return None;
+        }
+        let written = std::fs::read(output).ok()?;
+        (sha256(&written) == entry.output_sha256).then_some(written)
+    }
+
+    pub fn record(&mut self, source: &Path, text: &str, output: &Path, written: &[u8]) {
+        // the clock the file system stamps with can be coarse, a source
+        // written again right after this could keep the time recorded here
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let mtime = match mtime(source) {
+            recent if now.saturating_sub(recent) < RACY_NANOS => 0,
+            mtime => mtime,
+        };
+        self.entries.insert(
+            (source.to_path_buf(), output.to_path_buf()),
+            Entry {
+                source_sha256: sha256(text.as_bytes()),
+                mtime,
+                output_sha256: sha256(written),
+            },
+        );
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut out = format!("{} {}\n", HEADER, self.settings);
+        for ((source, output), entry) in &self.entries {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}",
+                entry.source_sha256,
+                entry.mtime,
+                entry.output_sha256,
+                output.display(),
+                source.display()
+            )
+            .unwrap();
+        }
+        std::fs::write(&self.path, out).with_context(|| format!("{}", self.path.display()))
+    }
+}
+
+// nanoseconds since the epoch, 0 when the file system doesn't say
+fn mtime(path: &Path) -> u128 {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos())
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::BuildCache;
+
+    #[test]
+    fn an_unchanged_modification_time_skips_hashing() {
+        let dir = std::env::temp_dir().join(format!("jack_build_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (source, output) = (dir.join("Main.jack"), dir.join("Main.xml"));
+        let set_mtime = |secs| {
+            let file = std::fs::File::options().write(true).open(&source).unwrap();
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        };
+        std::fs::write(&source, "class Main { }").unwrap();
+        std::fs::write(&output, "<class>").unwrap();
+        set_mtime(1_000_000);
+
+        let mut cache = BuildCache::load(&dir.join("jack.cache"), String::new()).unwrap();
+        cache.record(&source, "class Main { }", &output, b"<class>");
+        cache.save().unwrap();
+        let cache = BuildCache::load(&dir.join("jack.cache"), String::new()).unwrap();
+        // the text isn't looked at while the time is the same
+        let same_time = cache.fresh(&source, "class Other { }", &output);
+        set_mtime(2_000_000);
+        let touched = cache.fresh(&source, "class Main { }", &output);
+        let changed = cache.fresh(&source, "class Other { }", &output);
+        let elsewhere = cache.fresh(&source, "class Main { }", &dir.join("Other.xml"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(same_time.as_deref(), Some(&b"<class>"[..]));
+        assert_eq!(touched.as_deref(), Some(&b"<class>"[..]));
+        assert_eq!(changed, None);
+        assert_eq!(elsewhere, None);
+    }
+}
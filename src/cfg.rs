@@ -0,0 +1,308 @@
+use std::fmt::Write;
+
+use crate::{
+    ast::{Statement, SubroutineDec},
+    json,
+    token::Span,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    Next,
+    True,
+    False,
+    Loop,
+}
+
+impl EdgeKind {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Next => "next",
+            Self::True => "true",
+            Self::False => "false",
+            Self::Loop => "loop",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BasicBlock {
+    pub id: usize,
+    // spans of the statements in the block, a trailing if/while is the branch
+    pub statements: Vec<Span>,
+    pub unreachable: bool,
+}
+
+impl BasicBlock {
+    pub fn first_line(&self) -> Option<usize> {
+        self.statements.first().map(|s| s.line)
+    }
+}
+
+// basic blocks of a single subroutine, connected by if/while edges
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    pub subroutine: String,
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<(usize, usize, EdgeKind)>,
+}
+
+impl ControlFlowGraph {
+    pub fn build(subroutine: &SubroutineDec) -> Self {
+        let mut cfg = Self {
+            subroutine: subroutine.name.name.clone(),
+            blocks: Vec::new(),
+            edges: Vec::new(),
+        };
+
+        let entry = cfg.new_block();
+        cfg.add_statements(entry, &subroutine.statements);
+        cfg.mark_unreachable();
+        cfg
+    }
+
+    fn new_block(&mut self) -> usize {
+        let id = self.blocks.len();
+        self.blocks.push(BasicBlock {
+            id,
+            ..Default::default()
+        });
+        id
+    }
+
+    // appends `statements` starting at block `current`, returns the block
+    // control falls out of, or None when every path has returned
+    fn add_statements(&mut self, mut current: usize, statements: &[Statement]) -> Option<usize> {
+        let mut falls_through = true;
+
+        for statement in statements {
+            if !falls_through {
+                // anything after a return starts a block nothing jumps to
+                current = self.new_block();
+                falls_through = true;
+            }
+
+            match statement {
+                Statement::Let { span, .. } | Statement::Do { span, .. } => {
+                    self.blocks[current].statements.push(*span);
+                }
+                Statement::Return { span, .. } => {
+                    self.blocks[current].statements.push(*span);
+                    falls_through = false;
+                }
+                Statement::If {
+                    then_branch,
+                    else_branch,
+                    span,
+                    ..
+                } => {
+                    self.blocks[current].statements.push(*span);
+                    let join = self.new_block();
+
+                    let then_block = self.new_block();
+                    self.edges.push((current, then_block, EdgeKind::True));
+                    if let Some(end) = self.add_statements(then_block, then_branch) {
+                        self.edges.push((end, join, EdgeKind::Next));
+                    }
+
+                    match else_branch {
+                        Some(else_branch) => {
+                            let else_block = self.new_block();
+                            self.edges.push((current, else_block, EdgeKind::False));
+                            if let Some(end) = self.add_statements(else_block, else_branch) {
+                                self.edges.push((end, join, EdgeKind::Next));
+                            }
+                        }
+                        None => self.edges.push((current, join, EdgeKind::False)),
+                    }
+
+                    current = join;
+                }
+                Statement::While { body, span, .. } => {
+                    let header = if self.blocks[current].statements.is_empty() {
+                        current
+                    } else {
+                        let header = self.new_block();
+                        self.edges.push((current, header, EdgeKind::Next));
+                        header
+                    };
+                    self.blocks[header].statements.push(*span);
+
+                    let body_block = self.new_block();
+                    self.edges.push((header, body_block, EdgeKind::True));
+                    if let Some(end) = self.add_statements(body_block, body) {
+                        self.edges.push((end, header, EdgeKind::Loop));
+                    }
+
+                    let exit = self.new_block();
+                    self.edges.push((header, exit, EdgeKind::False));
+                    current = exit;
+                }
+            }
+        }
+
+        if falls_through {
+            Some(current)
+        } else {
+            None
+        }
+    }
+
+    fn mark_unreachable(&mut self) {
+        let mut reached = vec![false; self.blocks.len()];
+        let mut pending = vec![0];
+        while let Some(block) = pending.pop() {
+            if reached[block] {
+                continue;
+            }
+            reached[block] = true;
+            for (from, to, _) in &self.edges {
+                if *from == block {
+                    pending.push(*to);
+                }
+            }
+        }
+
+        for block in &mut self.blocks {
+            block.unreachable = !reached[block.id];
+        }
+    }
+
+    fn block_label(block: &BasicBlock) -> String {
+        match block.first_line() {
+            Some(line) => format!("B{} (line {})", block.id, line),
+            None => format!("B{} (empty)", block.id),
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph {} {{", json::string(&self.subroutine)).unwrap();
+        for block in &self.blocks {
+            let style = if block.unreachable {
+                ", style=dashed"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "  B{} [shape=box, label={}{}];",
+                block.id,
+                json::string(&Self::block_label(block)),
+                style
+            )
+            .unwrap();
+        }
+        for (from, to, kind) in &self.edges {
+            writeln!(out, "  B{} -> B{} [label=\"{}\"];", from, to, kind.label()).unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let blocks: Vec<String> = self
+            .blocks
+            .iter()
+            .map(|block| {
+                let line = block
+                    .first_line()
+                    .map(|l| l.to_string())
+                    .unwrap_or("null".to_owned());
+                format!(
+                    "{{\"id\":{},\"line\":{},\"statements\":{},\"unreachable\":{}}}",
+                    block.id,
+                    line,
+                    block.statements.len(),
+                    block.unreachable
+                )
+            })
+            .collect();
+        let edges: Vec<String> = self
+            .edges
+            .iter()
+            .map(|(from, to, kind)| {
+                format!(
+                    "{{\"from\":{},\"to\":{},\"kind\":\"{}\"}}",
+                    from,
+                    to,
+                    kind.label()
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"subroutine\":{},\"blocks\":[{}],\"edges\":[{}]}}",
+            json::string(&self.subroutine),
+            blocks.join(","),
+            edges.join(",")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+
+    use super::ControlFlowGraph;
+
+    fn build(src: &str) -> ControlFlowGraph {
+        let class = Parser::from_source(src).parse_class().unwrap();
+        ControlFlowGraph::build(&class.subroutines[0])
+    }
+
+    #[test]
+    fn straight_line_code_is_one_block() {
+        let cfg = build("class A { function void f() { let x = 1; do g(); return; } }");
+
+        assert_eq!(cfg.blocks.len(), 1);
+        assert_eq!(cfg.edges.len(), 0);
+        assert_eq!(cfg.blocks[0].statements.len(), 3);
+    }
+
+    #[test]
+    fn nested_control_flow() {
+        let src = "class A { function void f() {
+            let x = 0;
+            while (x < 10) {
+                if (x = 5) { let y = 1; } else { let y = 2; }
+                let x = x + 1;
+            }
+            return;
+        } }";
+        let cfg = build(src);
+
+        // entry, while header, while body (ending in the if), if join, then,
+        // else and the while exit
+        assert_eq!(cfg.blocks.len(), 7, "{:#?}", cfg);
+        // entry->header, header->body, body->then, then->join, body->else,
+        // else->join, join->header, header->exit
+        assert_eq!(cfg.edges.len(), 8, "{:#?}", cfg);
+        assert!(cfg.blocks.iter().all(|b| !b.unreachable));
+        assert_eq!(cfg.blocks[0].first_line(), Some(2));
+    }
+
+    #[test]
+    fn statements_after_return_are_unreachable() {
+        let src = "class A { function int f() {
+            if (true) { return 1; } else { return 2; }
+            let x = 1;
+            return x;
+        } }";
+        let cfg = build(src);
+
+        let unreachable: Vec<_> = cfg.blocks.iter().filter(|b| b.unreachable).collect();
+        assert_eq!(unreachable.len(), 1, "{:#?}", cfg);
+        assert_eq!(unreachable[0].first_line(), Some(3));
+    }
+
+    #[test]
+    fn dot_output_is_a_digraph() {
+        let cfg = build("class A { function void f() { while (true) { do g(); } return; } }");
+        let dot = cfg.to_dot();
+
+        assert!(dot.starts_with("digraph \"f\" {"), "{}", dot);
+        assert!(dot.contains("B0 -> B1 [label=\"true\"]"), "{}", dot);
+        assert!(dot.trim_end().ends_with('}'));
+    }
+}
@@ -0,0 +1,175 @@
+use std::{borrow::Cow, collections::BTreeSet};
+
+use crate::{
+    balance::unbalanced,
+    checker::{NOT_AN_ARRAY, NOT_AN_OBJECT},
+    codegen::UNDECLARED_VARIABLE,
+    diagnostic::{CompileError, Diagnostic, Severity},
+    marker_line::skip_marker_line,
+    Checker, CodeGenerator, Parser, StreamTokenizer,
+};
+
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    // run the brace-balance pre-check before parsing
+    pub check_balance: bool,
+    // the checker's passes: let targets, keyword casing and unreachable code
+    pub lints: bool,
+    // the errors vm code generation finds, undeclared variables and calls on
+    // values that aren't objects
+    pub codegen: bool,
+    // the checker's type checks, method calls and indexing on int, char and
+    // boolean variables
+    pub strict: bool,
+    // names the lints warn about variables being declared with
+    pub reserved_identifiers: BTreeSet<String>,
+    // skip a first line starting with `#!` or `<?xml` with a warning
+    pub skip_marker_line: bool,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        Self {
+            check_balance: true,
+            lints: true,
+            codegen: true,
+            strict: false,
+            reserved_identifiers: BTreeSet::new(),
+            skip_marker_line: true,
+        }
+    }
+}
+
+// checks the source of one class and returns everything found, nothing is
+// read from or written to disk. parsing stops at the first syntax error, in
+// which case that error is the only diagnostic
+pub fn check_source(source: &str, options: &CheckOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let source = match options.skip_marker_line {
+        true => {
+            let (text, skipped) = skip_marker_line(source);
+            diagnostics.extend(skipped);
+            text
+        }
+        false => Cow::Borrowed(source),
+    };
+
+    // read once, for the balance check and the parser both
+    let tokens: Vec<_> = StreamTokenizer::from_source(&source)
+        .into_spanned()
+        .collect();
+    if options.check_balance {
+        if let Some(diagnostic) = unbalanced(&tokens).into_iter().next() {
+            diagnostics.push(diagnostic);
+            return diagnostics;
+        }
+    }
+
+    let class = match Parser::new(tokens.into_iter()).parse_class() {
+        Ok(class) => class,
+        Err(e) => {
+            diagnostics.push(to_diagnostic(e));
+            return diagnostics;
+        }
+    };
+
+    if options.lints {
+        diagnostics.extend(Checker::check_reserving(
+            &class,
+            false,
+            &options.reserved_identifiers,
+        ));
+    }
+    if options.strict {
+        // the rest of check_strict is what the lints already found
+        diagnostics.extend(
+            Checker::check_strict(&class)
+                .into_iter()
+                .filter(|d| [NOT_AN_OBJECT, NOT_AN_ARRAY, UNDECLARED_VARIABLE].contains(&d.code)),
+        );
+    }
+    if options.codegen {
+        if let Err(e) = CodeGenerator::generate(&class) {
+            let diagnostic = to_diagnostic(e);
+            // the strict checks already report the receiver codegen stops at,
+            // and the lints warn about the string it can't write
+            let reported = diagnostics
+                .iter_mut()
+                .find(|d| d.span == diagnostic.span && d.message == diagnostic.message);
+            match reported {
+                Some(reported) => reported.severity = Severity::Error,
+                None => diagnostics.push(diagnostic),
+            }
+        }
+    }
+    diagnostics
+}
+
+fn to_diagnostic(e: anyhow::Error) -> Diagnostic {
+    CompileError::from(e).diagnostic
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::SYNTAX_ERROR;
+
+    use super::{check_source, CheckOptions};
+
+    #[test]
+    fn clean_and_undeclared() {
+        let clean = "class Main { function void main() { return; } }";
+        assert!(check_source(clean, &CheckOptions::default()).is_empty());
+
+        let undeclared = "class Main { function void main() { let x = 1; return; } }";
+        let diagnostics = check_source(undeclared, &CheckOptions::default());
+        assert_eq!(diagnostics[0].code, "J103");
+    }
+
+    #[test]
+    fn lex_errors_become_diagnostics() {
+        let got = check_source(
+            "class Main { function void main() { let a = #; return; } }",
+            &CheckOptions::default(),
+        );
+
+        assert_eq!(got.len(), 1, "{:#?}", got);
+        assert_eq!(got[0].code, SYNTAX_ERROR);
+    }
+
+    #[test]
+    fn passes_can_be_turned_off() {
+        let src = "class Main { function void main() { return; let Null = 1; } }";
+
+        let codes = |options: &CheckOptions| -> Vec<&str> {
+            check_source(src, options).iter().map(|d| d.code).collect()
+        };
+        assert_eq!(
+            codes(&CheckOptions::default()),
+            ["J101", "J103", "J102", "J201"]
+        );
+        assert_eq!(
+            codes(&CheckOptions {
+                lints: false,
+                ..Default::default()
+            }),
+            ["J201"]
+        );
+    }
+
+    #[test]
+    fn strict_checks_replace_the_codegen_error() {
+        let src = "class Main { function void main() { var int x; do x.foo(); return; } }";
+        let strict = CheckOptions {
+            strict: true,
+            ..Default::default()
+        };
+
+        let codes: Vec<&str> = check_source(src, &CheckOptions::default())
+            .iter()
+            .map(|d| d.code)
+            .collect();
+        assert_eq!(codes, ["J202"]);
+        let codes: Vec<&str> = check_source(src, &strict).iter().map(|d| d.code).collect();
+        assert_eq!(codes, ["J105"]);
+    }
+}
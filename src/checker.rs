@@ -0,0 +1,809 @@
+use std::{collections::BTreeSet, str::FromStr};
+
+use crate::{
+    ast::{
+        Class, Expression, ExpressionKind, Ident, JackType, Statement, SubroutineCall,
+        SubroutineDec, SubroutineKind,
+    },
+    cfg::ControlFlowGraph,
+    codegen::undeclared_variable,
+    diagnostic::Diagnostic,
+    lexical_elements::Keywords,
+    symbol_table::{SymbolKind, SymbolTable},
+    token::Span,
+};
+
+pub const KEYWORD_CASING: &str = "J101";
+pub const UNREACHABLE_CODE: &str = "J102";
+pub const UNDECLARED_TARGET: &str = "J103";
+pub const NOT_ASSIGNABLE: &str = "J104";
+pub const NOT_AN_OBJECT: &str = "J105";
+pub const NOT_AN_ARRAY: &str = "J106";
+pub const SHADOWED_CLASS_VARIABLE: &str = "J108";
+pub const CONSTRUCTOR_RETURN_TYPE: &str = "J110";
+pub const RESERVED_IDENTIFIER: &str = "J116";
+pub const UNSUPPORTED_CHARACTER: &str = "J118";
+
+pub const OS_CLASSES: [&str; 8] = [
+    "Math", "String", "Array", "Output", "Screen", "Keyboard", "Memory", "Sys",
+];
+
+// semantic checks and lints run by `--check` over a parsed class
+pub struct Checker {
+    table: SymbolTable,
+    diagnostics: Vec<Diagnostic>,
+    class_name: String,
+    subroutine_names: Vec<String>,
+    // also run the type checks, which only look at declared types
+    strict: bool,
+    // names variables may not be declared with, a warning
+    reserved: BTreeSet<String>,
+}
+
+impl Checker {
+    pub fn check(class: &Class) -> Vec<Diagnostic> {
+        Self::run(class, false, &BTreeSet::new())
+    }
+
+    // like check, plus calls and indexing on variables of builtin types,
+    // reads of undeclared variables and characters outside the hack set in
+    // strings are errors
+    pub fn check_strict(class: &Class) -> Vec<Diagnostic> {
+        Self::run(class, true, &BTreeSet::new())
+    }
+
+    // like check or check_strict, and warns about every static, field,
+    // parameter and local declared with one of the `reserved` names
+    pub fn check_reserving(
+        class: &Class,
+        strict: bool,
+        reserved: &BTreeSet<String>,
+    ) -> Vec<Diagnostic> {
+        Self::run(class, strict, reserved)
+    }
+
+    fn run(class: &Class, strict: bool, reserved: &BTreeSet<String>) -> Vec<Diagnostic> {
+        let mut checker = Self {
+            table: SymbolTable::new(),
+            diagnostics: Vec::new(),
+            class_name: class.name.name.clone(),
+            subroutine_names: class
+                .subroutines
+                .iter()
+                .map(|s| s.name.name.clone())
+                .collect(),
+            strict,
+            reserved: reserved.clone(),
+        };
+        checker.check_class(class);
+        checker.diagnostics
+    }
+
+    fn check_class(&mut self, class: &Class) {
+        self.check_declaration(&class.name);
+
+        for dec in &class.class_vars {
+            for name in &dec.names {
+                self.check_declaration(name);
+                self.define(name, &dec.var_type, dec.kind.into());
+            }
+        }
+
+        for subroutine in &class.subroutines {
+            self.check_declaration(&subroutine.name);
+            self.check_return_type(subroutine);
+            self.table.start_subroutine();
+
+            for param in &subroutine.params {
+                self.check_declaration(&param.name);
+                self.define(&param.name, &param.var_type, SymbolKind::Arg);
+            }
+
+            for dec in &subroutine.locals {
+                for name in &dec.names {
+                    self.check_declaration(name);
+                    self.define(name, &dec.var_type, SymbolKind::Var);
+                }
+            }
+
+            self.check_statements(&subroutine.statements);
+            self.check_reachability(&ControlFlowGraph::build(subroutine));
+        }
+    }
+
+    // defines `ident`, a name declared twice in a scope is reported with a
+    // note at the first one and a parameter or local hiding a class variable
+    // or with a reserved name is warned about
+    fn define(&mut self, ident: &Ident, var_type: &JackType, kind: SymbolKind) {
+        if self.reserved.contains(&ident.name) {
+            self.diagnostics.push(Diagnostic::warning(
+                RESERVED_IDENTIFIER,
+                format!(
+                    "{} `{}` is declared with a reserved name",
+                    Self::kind_name(kind),
+                    ident.name
+                ),
+                ident.span,
+            ));
+        }
+
+        let hidden = match self.table.lookup(&ident.name) {
+            Some(symbol) if matches!(symbol.kind, SymbolKind::Static | SymbolKind::Field) => {
+                Some((symbol.kind, symbol.span))
+            }
+            _ => None,
+        };
+
+        if let Err(diagnostic) = self
+            .table
+            .define(&ident.name, var_type.clone(), kind, ident.span)
+        {
+            let first = self
+                .table
+                .lookup(&ident.name)
+                .map_or(Span::default(), |s| s.span);
+            let code = diagnostic.code;
+            self.diagnostics.push(diagnostic);
+            self.diagnostics.push(Diagnostic::note(
+                code,
+                format!("`{}` is first declared here", ident.name),
+                first,
+            ));
+            return;
+        }
+
+        if let (Some((hidden_kind, hidden_span)), SymbolKind::Arg | SymbolKind::Var) =
+            (hidden, kind)
+        {
+            self.diagnostics.push(Diagnostic::warning(
+                SHADOWED_CLASS_VARIABLE,
+                format!(
+                    "{} `{}` shadows the {} `{}` declared at {}",
+                    Self::kind_name(kind),
+                    ident.name,
+                    Self::kind_name(hidden_kind),
+                    ident.name,
+                    hidden_span
+                ),
+                ident.span,
+            ));
+        }
+    }
+
+    // a constructor returns the object it builds, so its return type is the
+    // enclosing class
+    fn check_return_type(&mut self, subroutine: &SubroutineDec) {
+        if subroutine.kind != SubroutineKind::Constructor
+            || matches!(&subroutine.return_type, JackType::ClassName(name) if *name == self.class_name)
+        {
+            return;
+        }
+        self.diagnostics.push(Diagnostic::error(
+            CONSTRUCTOR_RETURN_TYPE,
+            format!(
+                "constructor `{}` returns `{}`, a constructor of `{}` must return `{}`",
+                subroutine.name.name, subroutine.return_type, self.class_name, self.class_name
+            ),
+            subroutine.return_type_span,
+        ));
+    }
+
+    fn kind_name(kind: SymbolKind) -> &'static str {
+        match kind {
+            SymbolKind::Static => "static",
+            SymbolKind::Field => "field",
+            SymbolKind::Arg => "parameter",
+            SymbolKind::Var => "local",
+        }
+    }
+
+    fn check_reachability(&mut self, cfg: &ControlFlowGraph) {
+        for block in cfg.blocks.iter().filter(|b| b.unreachable) {
+            if let Some(span) = block.statements.first() {
+                self.diagnostics.push(Diagnostic::warning(
+                    UNREACHABLE_CODE,
+                    format!("unreachable code in `{}`", cfg.subroutine),
+                    *span,
+                ));
+            }
+        }
+    }
+
+    fn check_statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            match statement {
+                Statement::Let {
+                    target,
+                    index,
+                    value,
+                    ..
+                } => {
+                    self.check_use(target, false);
+                    self.check_assignable(target);
+                    if let Some(index) = index {
+                        self.check_builtin_type(target, NOT_AN_ARRAY, "index");
+                        self.check_expression(index);
+                    }
+                    self.check_expression(value);
+                }
+                Statement::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                    ..
+                } => {
+                    self.check_expression(condition);
+                    self.check_statements(then_branch);
+                    if let Some(else_branch) = else_branch {
+                        self.check_statements(else_branch);
+                    }
+                }
+                Statement::While {
+                    condition, body, ..
+                } => {
+                    self.check_expression(condition);
+                    self.check_statements(body);
+                }
+                Statement::Do { call, .. } => self.check_call(call),
+                Statement::Return { value, .. } => {
+                    if let Some(value) = value {
+                        self.check_expression(value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Var(ident) => {
+                self.check_use(ident, true);
+                self.check_declared(ident);
+            }
+            ExpressionKind::Index(ident, index) => {
+                self.check_use(ident, false);
+                self.check_declared(ident);
+                self.check_builtin_type(ident, NOT_AN_ARRAY, "index");
+                self.check_expression(index);
+            }
+            ExpressionKind::Call(call) => self.check_call(call),
+            ExpressionKind::Paren(inner) | ExpressionKind::Unary(_, inner) => {
+                self.check_expression(inner)
+            }
+            ExpressionKind::Binary(_, lhs, rhs) => {
+                self.check_expression(lhs);
+                self.check_expression(rhs);
+            }
+            ExpressionKind::StringConst(value) => {
+                if let Some((message, span)) = unsupported_character(value, expression.span) {
+                    self.diagnostics.push(match self.strict {
+                        true => Diagnostic::error(UNSUPPORTED_CHARACTER, message, span),
+                        false => Diagnostic::warning(UNSUPPORTED_CHARACTER, message, span),
+                    });
+                }
+            }
+            ExpressionKind::IntConst(_) | ExpressionKind::KeywordConst(_) => {}
+        }
+    }
+
+    fn check_call(&mut self, call: &SubroutineCall) {
+        if let Some(receiver) = &call.receiver {
+            self.check_use(receiver, false);
+            self.check_builtin_type(receiver, NOT_AN_OBJECT, "call method on");
+        }
+        // a subroutine of this class was already flagged where it's declared
+        let declared = call.receiver.is_none() && self.subroutine_names.contains(&call.name.name);
+        if let (false, Some(keyword)) = (declared, Self::keyword_in_other_casing(&call.name.name)) {
+            self.diagnostics.push(Diagnostic::warning(
+                KEYWORD_CASING,
+                format!(
+                    "`{}` is called but differs from the keyword `{}` only by case",
+                    call.name.name, keyword
+                ),
+                call.name.span,
+            ));
+        }
+        for arg in &call.args {
+            self.check_expression(arg);
+        }
+    }
+
+    fn check_declaration(&mut self, ident: &Ident) {
+        if let Some(keyword) = Self::keyword_in_other_casing(&ident.name) {
+            self.diagnostics.push(Diagnostic::warning(
+                KEYWORD_CASING,
+                format!(
+                    "`{}` is declared as an identifier but differs from the keyword `{}` only by case",
+                    ident.name, keyword
+                ),
+                ident.span,
+            ));
+        }
+    }
+
+    // strict mode only, a variable read in an expression has to be declared.
+    // a call's receiver isn't one of these, undeclared it names a class
+    fn check_declared(&mut self, ident: &Ident) {
+        if !self.strict || self.table.lookup(&ident.name).is_some() {
+            return;
+        }
+        self.diagnostics.push(undeclared_variable("use of", ident));
+    }
+
+    // strict mode only, `action` needs an object but `ident` is declared with
+    // one of the builtin types. undeclared names are left to the other checks
+    fn check_builtin_type(&mut self, ident: &Ident, code: &'static str, action: &str) {
+        if !self.strict {
+            return;
+        }
+        let Some(symbol) = self.table.lookup(&ident.name) else {
+            return;
+        };
+        if matches!(symbol.var_type, JackType::ClassName(_)) {
+            return;
+        }
+
+        let message = format!("cannot {} value of type {}", action, symbol.var_type);
+        self.diagnostics
+            .push(Diagnostic::error(code, message, ident.span));
+    }
+
+    // a let target has to be a variable of one of the four kinds
+    fn check_assignable(&mut self, target: &Ident) {
+        if self.table.lookup(&target.name).is_some() {
+            return;
+        }
+
+        let what = if target.name == self.class_name || OS_CLASSES.contains(&target.name.as_str()) {
+            "a class"
+        } else if self.subroutine_names.contains(&target.name) {
+            "a subroutine"
+        } else {
+            self.diagnostics.push(Diagnostic::error(
+                UNDECLARED_TARGET,
+                format!("cannot assign to `{}`, it is not declared", target.name),
+                target.span,
+            ));
+            return;
+        };
+        self.diagnostics.push(Diagnostic::error(
+            NOT_ASSIGNABLE,
+            format!("cannot assign to `{}`, it is {}", target.name, what),
+            target.span,
+        ));
+    }
+
+    // `as_term` is set when the identifier stands alone as a term, where a
+    // keyword constant would also be valid
+    fn check_use(&mut self, ident: &Ident, as_term: bool) {
+        if self.table.lookup(&ident.name).is_some() {
+            return;
+        }
+
+        if let Some(keyword) = Self::keyword_in_other_casing(&ident.name) {
+            let is_keyword_constant = matches!(
+                keyword,
+                Keywords::True | Keywords::False | Keywords::Null | Keywords::This
+            );
+            let message = if as_term && is_keyword_constant {
+                format!(
+                    "`{}` is not declared, did you mean the keyword `{}`?",
+                    ident.name, keyword
+                )
+            } else {
+                format!(
+                    "`{}` is not declared and differs from the keyword `{}` only by case",
+                    ident.name, keyword
+                )
+            };
+            self.diagnostics
+                .push(Diagnostic::warning(KEYWORD_CASING, message, ident.span));
+        }
+    }
+
+    fn keyword_in_other_casing(name: &str) -> Option<Keywords> {
+        if OS_CLASSES.contains(&name) {
+            return None;
+        }
+
+        let lowercase = name.to_lowercase();
+        if lowercase == name {
+            return None;
+        }
+
+        Keywords::from_str(&lowercase).ok()
+    }
+}
+
+// the first character of the string constant `value` at `span` that
+// String.appendChar can't take, only printable ascii is in the hack
+// character set. the message and the span of the character
+pub(crate) fn unsupported_character(value: &str, span: Span) -> Option<(String, Span)> {
+    let (n, (offset, c)) = value
+        .char_indices()
+        .enumerate()
+        .find(|(_, (_, c))| !matches!(c, ' '..='~'))?;
+    let mut message = format!(
+        "`{}` (U+{:04X}) at column {} of the string is not in the Hack character set",
+        c.escape_debug(),
+        c as u32,
+        n + 1
+    );
+    if matches!(c, '\u{2018}' | '\u{2019}' | '\u{201C}' | '\u{201D}') {
+        message.push_str(", it is a smart quote, did you paste from a document?");
+    }
+    // past the opening quote
+    let start = span.start + 1 + offset;
+    let span = Span {
+        start,
+        end: start + c.len_utf8(),
+        line: span.line,
+        column: span.column + 1 + n,
+    };
+    Some((message, span))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        diagnostic::{Diagnostic, Severity},
+        parser::Parser,
+    };
+
+    use super::{
+        Checker, CONSTRUCTOR_RETURN_TYPE, KEYWORD_CASING, NOT_AN_ARRAY, NOT_AN_OBJECT,
+        NOT_ASSIGNABLE, RESERVED_IDENTIFIER, SHADOWED_CLASS_VARIABLE, UNDECLARED_TARGET,
+        UNREACHABLE_CODE, UNSUPPORTED_CHARACTER,
+    };
+
+    fn check(src: &str) -> Vec<Diagnostic> {
+        let class = Parser::from_source(src).parse_class().unwrap();
+        Checker::check(&class)
+    }
+
+    #[test]
+    fn unreachable_code_after_return() {
+        let src = "class Main { function void f() { return; do f(); } }";
+
+        let got = check(src);
+        assert_eq!(got.len(), 1, "{:?}", got);
+        assert_eq!(got[0].code, UNREACHABLE_CODE);
+        assert_eq!(got[0].span.column, 42);
+    }
+
+    #[test]
+    fn keyword_casing_at_declaration() {
+        let src = "class Main { field int While; function void f() { return; } }";
+
+        let got = check(src);
+        assert_eq!(got.len(), 1, "{:?}", got);
+        assert_eq!(got[0].code, KEYWORD_CASING);
+        assert!(got[0].message.contains("`while`"), "{}", got[0].message);
+        assert_eq!(got[0].span.column, 24);
+    }
+
+    #[test]
+    fn keyword_casing_at_undeclared_use_suggests_keyword() {
+        let src = "class Main { function void f() { if (True) { return; } return; } }";
+
+        let got = check(src);
+        assert_eq!(got.len(), 1, "{:?}", got);
+        assert!(
+            got[0].message.contains("did you mean the keyword `true`?"),
+            "{}",
+            got[0].message
+        );
+    }
+
+    #[test]
+    fn keyword_casing_ignores_declared_uses() {
+        let src = "class Main { function void f(int Do) { let Do = Do + 1; return; } }";
+
+        let got = check(src);
+        assert_eq!(got.len(), 1, "only the declaration is flagged: {:?}", got);
+    }
+
+    #[test]
+    fn keyword_casing_at_call_names() {
+        let src = "class Main { function void f() { do While(); do Game.Return(); return; } }";
+
+        let got = check(src);
+        assert_eq!(got.len(), 2, "{:?}", got);
+        assert!(got.iter().all(|d| d.code == KEYWORD_CASING), "{:?}", got);
+        assert!(got[0].message.contains("`while`"), "{}", got[0].message);
+        assert_eq!(got[0].span.column, 37);
+        assert!(got[1].message.contains("`return`"), "{}", got[1].message);
+
+        // only the declaration of a subroutine of the class is flagged
+        let src =
+            "class Main { function void Do() { return; } function void f() { do Do(); return; } }";
+        assert_eq!(check(src).len(), 1);
+    }
+
+    #[test]
+    fn keyword_casing_ignores_os_classes() {
+        let src = "class Main { function void f() { var String s; var Array a;
+            let s = String.new(1); let a = Array.new(2); return; } }";
+
+        let got = check(src);
+        assert!(got.is_empty(), "{:?}", got);
+    }
+
+    #[test]
+    fn let_targets_must_be_variables() {
+        let src = "class Main { field int x; static int y;
+            method void draw() { return; }
+            method void f(int a) { var int b;
+            let x = 1; let y = 2; let a = 3; let b[0] = 4;
+            let Main = 5; let Math = 6; let draw = 7; let c = 8; return; } }";
+
+        let got: Vec<(&str, String)> = check(src)
+            .into_iter()
+            .map(|d| (d.code, d.message))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                (
+                    NOT_ASSIGNABLE,
+                    "cannot assign to `Main`, it is a class".to_owned()
+                ),
+                (
+                    NOT_ASSIGNABLE,
+                    "cannot assign to `Math`, it is a class".to_owned()
+                ),
+                (
+                    NOT_ASSIGNABLE,
+                    "cannot assign to `draw`, it is a subroutine".to_owned()
+                ),
+                (
+                    UNDECLARED_TARGET,
+                    "cannot assign to `c`, it is not declared".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn constructors_return_their_class() {
+        let src = "class Square {
+            constructor Square new() { return this; }
+            constructor void blank() { return; }
+            constructor int size() { return 1; }
+            constructor Point corner() { return this; }
+            function Point origin() { return null; } }";
+
+        let got: Vec<(&str, usize, String)> = check(src)
+            .into_iter()
+            .map(|d| (d.code, d.span.line, d.message))
+            .collect();
+        let error = |line, message: &str| (CONSTRUCTOR_RETURN_TYPE, line, message.to_owned());
+        assert_eq!(
+            got,
+            [
+                error(
+                    3,
+                    "constructor `blank` returns `void`, a constructor of `Square` must return `Square`"
+                ),
+                error(
+                    4,
+                    "constructor `size` returns `int`, a constructor of `Square` must return `Square`"
+                ),
+                error(
+                    5,
+                    "constructor `corner` returns `Point`, a constructor of `Square` must return `Square`"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn locals_shadow_subroutine_names() {
+        let src = "class Main { function void draw() { var int draw; let draw = 1; return; } }";
+
+        assert!(check(src).is_empty());
+    }
+
+    #[test]
+    fn strict_mode_rejects_methods_on_builtin_types() {
+        let src = "class Main { function void f() { var int x; var Point p;
+            do x.foo(); do p.foo(); do Point.new(); return; } }";
+        let class = Parser::from_source(src).parse_class().unwrap();
+
+        assert!(Checker::check(&class).is_empty());
+        let got = Checker::check_strict(&class);
+        assert_eq!(got.len(), 1, "{:?}", got);
+        assert_eq!(got[0].code, NOT_AN_OBJECT);
+        assert_eq!(got[0].message, "cannot call method on value of type int");
+    }
+
+    #[test]
+    fn strict_mode_reports_every_read_of_an_undeclared_variable() {
+        let src = "class Main { function void f() { var int y;
+            let y = z + 1; let y = a[z]; do Output.printInt(y); return; } }";
+        let class = Parser::from_source(src).parse_class().unwrap();
+
+        let got: Vec<String> = Checker::check_strict(&class)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            got,
+            [
+                "2:21: error[J201]: use of undeclared variable `z`",
+                "2:36: error[J201]: use of undeclared variable `a`",
+                "2:38: error[J201]: use of undeclared variable `z`",
+            ]
+        );
+        assert!(Checker::check(&class).is_empty());
+    }
+
+    #[test]
+    fn strict_mode_rejects_indexing_builtin_types() {
+        let src = "class Main { function void f(boolean b) { var Array a;
+            let a[0] = b[1]; let b[2] = a[3]; return; } }";
+        let class = Parser::from_source(src).parse_class().unwrap();
+
+        let got: Vec<(&str, String)> = Checker::check_strict(&class)
+            .into_iter()
+            .map(|d| (d.code, d.message))
+            .collect();
+        let message = "cannot index value of type boolean".to_owned();
+        assert_eq!(
+            got,
+            [(NOT_AN_ARRAY, message.clone()), (NOT_AN_ARRAY, message)]
+        );
+    }
+
+    #[test]
+    fn duplicate_variables_point_at_both_and_checking_goes_on() {
+        let src = "class Main { field int x; static int x;
+            function void f() { var int a; var boolean a;
+                let y = 1; return; } }";
+
+        let got: Vec<String> = check(src).iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            got,
+            [
+                "1:38: error[J119]: variable 'x' already declared in this scope",
+                "1:24: note[J119]: `x` is first declared here",
+                "2:56: error[J119]: variable 'a' already declared in this scope",
+                "2:41: note[J119]: `a` is first declared here",
+                "3:21: error[J103]: cannot assign to `y`, it is not declared",
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_parameters_point_at_both() {
+        let src = "class Main { method void move(int x, int x) { let x = 1; return; } }";
+
+        let got: Vec<String> = check(src).iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            got,
+            [
+                "1:42: error[J107]: parameter `x` is declared twice, first at 1:35",
+                "1:35: note[J107]: `x` is first declared here",
+            ]
+        );
+    }
+
+    #[test]
+    fn parameter_shadowing_a_field_is_a_warning() {
+        let src = "class Main { field int x;
+            method void f(int x) { let x = 1; return; } }";
+
+        let got = check(src);
+        assert_eq!(got.len(), 1, "{:?}", got);
+        assert_eq!(
+            (got[0].code, got[0].severity),
+            (SHADOWED_CLASS_VARIABLE, Severity::Warning)
+        );
+        assert_eq!(
+            got[0].message,
+            "parameter `x` shadows the field `x` declared at 1:24"
+        );
+        assert_eq!((got[0].span.line, got[0].span.column), (2, 31));
+    }
+
+    #[test]
+    fn local_shadowing_a_static_is_a_warning() {
+        let src = "class Main { static int count;
+            function void f(int n) { var int count; let count = n; return; } }";
+
+        let got: Vec<(&str, String)> = check(src)
+            .into_iter()
+            .map(|d| (d.code, d.message))
+            .collect();
+        assert_eq!(
+            got,
+            [(
+                SHADOWED_CLASS_VARIABLE,
+                "local `count` shadows the static `count` declared at 1:25".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn reserved_names_are_warned_about() {
+        let src = "class Main { field int Array;
+            function void f(int Sys) { var int Math, x; let x = 1; return; } }";
+        let class = Parser::from_source(src).parse_class().unwrap();
+        assert!(Checker::check(&class).is_empty());
+
+        let reserved = ["Math", "Array", "Sys"].map(str::to_owned).into();
+        let got: Vec<(&str, Severity, String, usize)> =
+            Checker::check_reserving(&class, false, &reserved)
+                .into_iter()
+                .map(|d| (d.code, d.severity, d.message, d.span.column))
+                .collect();
+        assert_eq!(
+            got,
+            [
+                (
+                    RESERVED_IDENTIFIER,
+                    Severity::Warning,
+                    "field `Array` is declared with a reserved name".to_owned(),
+                    24
+                ),
+                (
+                    RESERVED_IDENTIFIER,
+                    Severity::Warning,
+                    "parameter `Sys` is declared with a reserved name".to_owned(),
+                    33
+                ),
+                (
+                    RESERVED_IDENTIFIER,
+                    Severity::Warning,
+                    "local `Math` is declared with a reserved name".to_owned(),
+                    48
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn strings_outside_the_hack_character_set() {
+        let string = |value: &str| {
+            let src = format!(
+                "class Main {{ function void main() {{ do Output.printString(\"{}\"); return; }} }}",
+                value
+            );
+            let class = Parser::from_source(&src).parse_class().unwrap();
+            let found: Vec<(Severity, String, usize)> = Checker::check(&class)
+                .into_iter()
+                .map(|d| (d.severity, d.message, d.span.column))
+                .collect();
+            let strict: Vec<_> = Checker::check_strict(&class)
+                .into_iter()
+                .map(|d| (d.code, d.severity))
+                .collect();
+            (found, strict)
+        };
+
+        assert_eq!(string("Hello, World! ~{}"), (vec![], vec![]));
+
+        let (found, strict) = string("it\u{2019}s");
+        assert_eq!(
+            found,
+            [(
+                Severity::Warning,
+                "`\u{2019}` (U+2019) at column 3 of the string is not in the Hack character set, it is a smart quote, did you paste from a document?"
+                    .to_owned(),
+                62
+            )]
+        );
+        assert_eq!(strict, [(UNSUPPORTED_CHARACTER, Severity::Error)]);
+
+        let (found, _) = string("a\tb\tc");
+        assert_eq!(
+            found,
+            [(
+                Severity::Warning,
+                "`\\t` (U+0009) at column 2 of the string is not in the Hack character set"
+                    .to_owned(),
+                61
+            )]
+        );
+    }
+}
@@ -0,0 +1,936 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    ast::{
+        Class, Expression, ExpressionKind, Ident, JackType, Statement, SubroutineCall,
+        SubroutineDec, SubroutineKind,
+    },
+    checker::{unsupported_character, UNSUPPORTED_CHARACTER},
+    diagnostic::Diagnostic,
+    lexical_elements::{Keywords, Symbols},
+    rename::check_identifier,
+    symbol_table::{SymbolKind, SymbolTable},
+};
+
+pub const UNDECLARED_VARIABLE: &str = "J201";
+pub const INVALID_RECEIVER: &str = "J202";
+pub const INVALID_LABEL: &str = "J203";
+
+// the class `*` and `/` call `multiply` and `divide` of unless told otherwise
+pub const MATH_CLASS: &str = "Math";
+
+// how the labels of ifs and whiles are named. numbering restarts in every
+// function whichever style is used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LabelStyle {
+    // what the course's own compiler emits, `IF_TRUE0`
+    #[default]
+    Course,
+    // unique in the whole program, `Main.main$if_true.0`
+    Qualified,
+    // for the older vm emulator, which only takes uppercase letters, digits,
+    // `_`, `.` and `:`, `MAIN.MAIN:IF_TRUE0`
+    Legacy,
+}
+
+impl LabelStyle {
+    fn label(&self, class: &str, function: &str, kind: &str, n: usize) -> String {
+        match self {
+            LabelStyle::Course => format!("{}{}", kind, n),
+            LabelStyle::Qualified => {
+                format!("{}.{}${}.{}", class, function, kind.to_lowercase(), n)
+            }
+            LabelStyle::Legacy => format!("{}.{}:{}{}", class, function, kind, n).to_uppercase(),
+        }
+    }
+
+    fn allows(&self, c: char) -> bool {
+        match self {
+            LabelStyle::Course => c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_',
+            LabelStyle::Qualified => c.is_ascii_alphanumeric() || "_.:$".contains(c),
+            LabelStyle::Legacy => c.is_ascii_uppercase() || c.is_ascii_digit() || "_.:".contains(c),
+        }
+    }
+
+    // the name each subroutine's labels are made from, where it isn't its
+    // own. legacy labels are uppercased so `foo` and `Foo` would share
+    // theirs, every one after the first gets a `_<n>` suffix no other
+    // subroutine has
+    fn renamed(&self, class: &Class) -> BTreeMap<String, String> {
+        let mut renamed = BTreeMap::new();
+        if *self != LabelStyle::Legacy {
+            return renamed;
+        }
+        let mut taken: BTreeSet<String> = class
+            .subroutines
+            .iter()
+            .map(|subroutine| subroutine.name.name.to_uppercase())
+            .collect();
+        let mut seen = BTreeSet::new();
+        for subroutine in &class.subroutines {
+            let name = &subroutine.name.name;
+            let upper = name.to_uppercase();
+            if seen.insert(upper.clone()) {
+                continue;
+            }
+            let unique = (1..)
+                .map(|n| format!("{}_{}", upper, n))
+                .find(|candidate| !taken.contains(candidate))
+                .expect("some suffix is free");
+            taken.insert(unique.clone());
+            renamed.insert(name.clone(), unique);
+        }
+        renamed
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            LabelStyle::Course => "course",
+            LabelStyle::Qualified => "qualified",
+            LabelStyle::Legacy => "legacy",
+        }
+    }
+}
+
+// translates a parsed class into hack vm code, one `function` per subroutine
+pub struct CodeGenerator<'a> {
+    class: &'a Class,
+    table: SymbolTable,
+    out: String,
+    labels: LabelStyle,
+    // subroutines whose labels are named otherwise, see LabelStyle::renamed
+    label_names: BTreeMap<String, String>,
+    // the subroutine being written, labels are named after it
+    function: Option<Ident>,
+    // running label numbers, restarted for every subroutine
+    if_count: usize,
+    while_count: usize,
+    // the file named in the `// <file>:<line>` comments, none without them
+    source: Option<&'a str>,
+    // the line of the last of those comments
+    line: usize,
+    // the class whose `multiply` and `divide` do `*` and `/`
+    math_class: &'a str,
+}
+
+// how a class is translated, everything but the class itself
+#[derive(Debug, Clone, Copy)]
+pub struct CodegenOptions<'a> {
+    pub labels: LabelStyle,
+    // the subroutines are spread over this many threads. they only share
+    // the class scope, which is filled in before any of them starts and not
+    // written after
+    pub jobs: usize,
+    // a `// <source>:<line>` comment before every function and every
+    // statement on a new line, how the vm emulator maps the code back to
+    // the jack it came from
+    pub source: Option<&'a str>,
+    // `*` and `/` call `multiply` and `divide` of this class, for courses
+    // renaming the math library
+    pub math_class: &'a str,
+}
+
+impl Default for CodegenOptions<'_> {
+    fn default() -> Self {
+        Self {
+            labels: LabelStyle::default(),
+            jobs: 1,
+            source: None,
+            math_class: MATH_CLASS,
+        }
+    }
+}
+
+impl<'a> CodeGenerator<'a> {
+    pub fn generate(class: &'a Class) -> Result<String> {
+        Self::generate_with(class, &CodegenOptions::default())
+    }
+
+    pub fn generate_with(class: &'a Class, options: &CodegenOptions<'a>) -> Result<String> {
+        check_identifier(options.math_class).context("invalid math class")?;
+        let subroutines = &class.subroutines;
+        let jobs = options.jobs.clamp(1, subroutines.len().max(1));
+        let base = Self::for_class(class, options)?;
+        if jobs == 1 {
+            let mut generator = base;
+            for subroutine in subroutines {
+                generator.write_subroutine(subroutine)?;
+            }
+            return Ok(generator.out);
+        }
+
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<Option<Result<String>>> = subroutines.iter().map(|_| None).collect();
+        std::thread::scope(|scope| {
+            let (base, next) = (&base, &next);
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    scope.spawn(move || {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(subroutine) = subroutines.get(i) else {
+                                break done;
+                            };
+                            let mut generator = base.fork();
+                            let result = generator
+                                .write_subroutine(subroutine)
+                                .map(|()| generator.out);
+                            done.push((i, result));
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                for (i, result) in worker.join().expect("worker thread panicked") {
+                    results[i] = Some(result);
+                }
+            }
+        });
+
+        // in subroutine order, so the first error is the one a sequential
+        // run stops at
+        results.into_iter().flatten().collect()
+    }
+
+    fn for_class(class: &'a Class, options: &CodegenOptions<'a>) -> Result<Self> {
+        let mut generator = Self {
+            class,
+            table: SymbolTable::new(),
+            out: String::new(),
+            labels: options.labels,
+            label_names: options.labels.renamed(class),
+            function: None,
+            if_count: 0,
+            while_count: 0,
+            source: options.source,
+            line: 0,
+            math_class: options.math_class,
+        };
+        for dec in &class.class_vars {
+            for name in &dec.names {
+                generator.table.define(
+                    &name.name,
+                    dec.var_type.clone(),
+                    dec.kind.into(),
+                    name.span,
+                )?;
+            }
+        }
+        Ok(generator)
+    }
+
+    // a generator with the same class scope and nothing written yet
+    fn fork(&self) -> Self {
+        Self {
+            class: self.class,
+            table: self.table.clone(),
+            out: String::new(),
+            labels: self.labels,
+            label_names: self.label_names.clone(),
+            function: None,
+            if_count: 0,
+            while_count: 0,
+            source: self.source,
+            line: 0,
+            math_class: self.math_class,
+        }
+    }
+
+    fn emit(&mut self, line: impl AsRef<str>) {
+        self.out.push_str(line.as_ref());
+        self.out.push('\n');
+    }
+
+    // the `// <source>:<line>` comment when positions are written and the
+    // last one was for another line
+    fn emit_position(&mut self, line: usize) {
+        if let Some(source) = self.source {
+            if line != self.line {
+                self.line = line;
+                self.emit(format!("// {}:{}", source, line));
+            }
+        }
+    }
+
+    fn label(&self, kind: &str, n: usize) -> Result<String> {
+        let function = self
+            .function
+            .as_ref()
+            .expect("labels are only made in subroutines");
+        let name = self
+            .label_names
+            .get(&function.name)
+            .unwrap_or(&function.name);
+        let label = self.labels.label(&self.class.name.name, name, kind, n);
+        if !label.chars().all(|c| self.labels.allows(c)) {
+            return Err(Diagnostic::error(
+                INVALID_LABEL,
+                format!(
+                    "label `{}` is not valid in the {} label style",
+                    label,
+                    self.labels.name()
+                ),
+                function.span,
+            )
+            .into());
+        }
+        Ok(label)
+    }
+
+    fn write_subroutine(&mut self, subroutine: &SubroutineDec) -> Result<()> {
+        self.table.start_subroutine();
+        self.function = Some(subroutine.name.clone());
+        self.if_count = 0;
+        self.while_count = 0;
+
+        if subroutine.kind == SubroutineKind::Method {
+            let this_type = JackType::ClassName(self.class.name.name.clone());
+            self.table
+                .define("this", this_type, SymbolKind::Arg, subroutine.name.span)?;
+        }
+        for param in &subroutine.params {
+            self.table.define(
+                &param.name.name,
+                param.var_type.clone(),
+                SymbolKind::Arg,
+                param.name.span,
+            )?;
+        }
+        for dec in &subroutine.locals {
+            for name in &dec.names {
+                self.table
+                    .define(&name.name, dec.var_type.clone(), SymbolKind::Var, name.span)?;
+            }
+        }
+
+        let locals = self.table.var_count(SymbolKind::Var);
+        self.line = 0;
+        self.emit_position(subroutine.span.line);
+        self.emit(format!(
+            "function {}.{} {}",
+            self.class.name.name, subroutine.name.name, locals
+        ));
+        match subroutine.kind {
+            SubroutineKind::Constructor => {
+                let fields = self.table.var_count(SymbolKind::Field);
+                self.emit(format!("push constant {}", fields));
+                self.emit("call Memory.alloc 1");
+                self.emit("pop pointer 0");
+            }
+            SubroutineKind::Method => {
+                self.emit("push argument 0");
+                self.emit("pop pointer 0");
+            }
+            SubroutineKind::Function => {}
+        }
+
+        self.write_statements(&subroutine.statements)
+    }
+
+    fn write_statements(&mut self, statements: &[Statement]) -> Result<()> {
+        for statement in statements {
+            self.emit_position(statement.span().line);
+            self.write_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn write_statement(&mut self, statement: &Statement) -> Result<()> {
+        match statement {
+            Statement::Let {
+                target,
+                index: None,
+                value,
+                ..
+            } => {
+                self.write_expression(value)?;
+                let (segment, index) = self.variable(target)?;
+                self.emit(format!("pop {} {}", segment, index));
+            }
+            Statement::Let {
+                target,
+                index: Some(index),
+                value,
+                ..
+            } => {
+                let (segment, base) = self.variable(target)?;
+                self.emit(format!("push {} {}", segment, base));
+                self.write_expression(index)?;
+                self.emit("add");
+                self.write_expression(value)?;
+                self.emit("pop temp 0");
+                self.emit("pop pointer 1");
+                self.emit("push temp 0");
+                self.emit("pop that 0");
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let n = self.if_count;
+                self.if_count += 1;
+                let if_true = self.label("IF_TRUE", n)?;
+                let if_false = self.label("IF_FALSE", n)?;
+
+                self.write_expression(condition)?;
+                self.emit(format!("if-goto {}", if_true));
+                self.emit(format!("goto {}", if_false));
+                self.emit(format!("label {}", if_true));
+                self.write_statements(then_branch)?;
+                match else_branch {
+                    Some(else_branch) => {
+                        let if_end = self.label("IF_END", n)?;
+                        self.emit(format!("goto {}", if_end));
+                        self.emit(format!("label {}", if_false));
+                        self.write_statements(else_branch)?;
+                        self.emit(format!("label {}", if_end));
+                    }
+                    None => self.emit(format!("label {}", if_false)),
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                let n = self.while_count;
+                self.while_count += 1;
+                let while_exp = self.label("WHILE_EXP", n)?;
+                let while_end = self.label("WHILE_END", n)?;
+
+                self.emit(format!("label {}", while_exp));
+                self.write_expression(condition)?;
+                self.emit("not");
+                self.emit(format!("if-goto {}", while_end));
+                self.write_statements(body)?;
+                self.emit(format!("goto {}", while_exp));
+                self.emit(format!("label {}", while_end));
+            }
+            Statement::Do { call, .. } => {
+                self.write_call(call)?;
+                // the value of a do call is thrown away
+                self.emit("pop temp 0");
+            }
+            Statement::Return { value, .. } => {
+                match value {
+                    Some(value) => self.write_expression(value)?,
+                    None => self.emit("push constant 0"),
+                }
+                self.emit("return");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_expression(&mut self, expression: &Expression) -> Result<()> {
+        match &expression.kind {
+            // constants are 0 to 32767, 32768 wraps around to -32768 and
+            // negating that leaves it there
+            ExpressionKind::IntConst(i16::MIN) => {
+                self.emit("push constant 32767");
+                self.emit("push constant 1");
+                self.emit("add");
+                self.emit("neg");
+            }
+            ExpressionKind::IntConst(i) => self.emit(format!("push constant {}", i)),
+            ExpressionKind::StringConst(s) => {
+                if let Some((message, span)) = unsupported_character(s, expression.span) {
+                    return Err(Diagnostic::error(UNSUPPORTED_CHARACTER, message, span).into());
+                }
+                self.emit(format!("push constant {}", s.chars().count()));
+                self.emit("call String.new 1");
+                for c in s.chars() {
+                    self.emit(format!("push constant {}", c as u32));
+                    self.emit("call String.appendChar 2");
+                }
+            }
+            ExpressionKind::KeywordConst(keyword) => match keyword {
+                Keywords::True => {
+                    self.emit("push constant 0");
+                    self.emit("not");
+                }
+                Keywords::This => self.emit("push pointer 0"),
+                _ => self.emit("push constant 0"),
+            },
+            ExpressionKind::Var(ident) => self.write_push_variable(ident)?,
+            ExpressionKind::Index(ident, index) => {
+                self.write_push_variable(ident)?;
+                self.write_expression(index)?;
+                self.emit("add");
+                self.emit("pop pointer 1");
+                self.emit("push that 0");
+            }
+            ExpressionKind::Call(call) => self.write_call(call)?,
+            ExpressionKind::Paren(inner) => self.write_expression(inner)?,
+            ExpressionKind::Unary(op, operand) => {
+                self.write_expression(operand)?;
+                match op {
+                    Symbols::Minus => self.emit("neg"),
+                    _ => self.emit("not"),
+                }
+            }
+            ExpressionKind::Binary(op, lhs, rhs) => {
+                self.write_expression(lhs)?;
+                self.write_expression(rhs)?;
+                let command = match op {
+                    Symbols::Plus => "add",
+                    Symbols::Minus => "sub",
+                    Symbols::Asterik => "multiply",
+                    Symbols::BackSlash => "divide",
+                    Symbols::Ampersand => "and",
+                    Symbols::VerticalBar => "or",
+                    Symbols::LessThan => "lt",
+                    Symbols::GreaterThan => "gt",
+                    _ => "eq",
+                };
+                match op {
+                    Symbols::Asterik | Symbols::BackSlash => {
+                        self.emit(format!("call {}.{} 2", self.math_class, command))
+                    }
+                    _ => self.emit(command),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_call(&mut self, call: &SubroutineCall) -> Result<()> {
+        let mut args = call.args.len();
+        let target = match &call.receiver {
+            // a variable receiver is passed along as the method's `this`
+            Some(receiver) => match self.table.lookup(&receiver.name) {
+                Some(symbol) => {
+                    let class = match &symbol.var_type {
+                        JackType::ClassName(name) => name.clone(),
+                        other => {
+                            return Err(Diagnostic::error(
+                                INVALID_RECEIVER,
+                                format!("cannot call method on value of type {}", other),
+                                receiver.span,
+                            )
+                            .into())
+                        }
+                    };
+                    self.write_push_variable(receiver)?;
+                    args += 1;
+                    class
+                }
+                None => receiver.name.clone(),
+            },
+            // an unqualified call is a method on the current object unless
+            // the class declares it as a function or constructor
+            None => {
+                let is_method = !self
+                    .class
+                    .subroutines
+                    .iter()
+                    .any(|s| s.name.name == call.name.name && s.kind != SubroutineKind::Method);
+                if is_method {
+                    self.emit("push pointer 0");
+                    args += 1;
+                }
+                self.class.name.name.clone()
+            }
+        };
+
+        for arg in &call.args {
+            self.write_expression(arg)?;
+        }
+        self.emit(format!("call {}.{} {}", target, call.name.name, args));
+        Ok(())
+    }
+
+    // pushes a variable read in an expression. a receiver that isn't
+    // declared was already taken for a class name, so anything else not in
+    // the table is a use of an undeclared variable
+    fn write_push_variable(&mut self, ident: &Ident) -> Result<()> {
+        let (segment, index) = self
+            .segment(ident)
+            .ok_or_else(|| undeclared_variable("use of", ident))?;
+        self.emit(format!("push {} {}", segment, index));
+        Ok(())
+    }
+
+    // the segment and index of a let target
+    fn variable(&self, ident: &Ident) -> Result<(&'static str, u16)> {
+        let variable = self
+            .segment(ident)
+            .ok_or_else(|| undeclared_variable("assignment to", ident))?;
+        Ok(variable)
+    }
+
+    fn segment(&self, ident: &Ident) -> Option<(&'static str, u16)> {
+        let symbol = self.table.lookup(&ident.name)?;
+        let segment = match symbol.kind {
+            SymbolKind::Static => "static",
+            SymbolKind::Field => "this",
+            SymbolKind::Arg => "argument",
+            SymbolKind::Var => "local",
+        };
+        Some((segment, symbol.index))
+    }
+}
+
+// the error of a variable that isn't declared, `what` is "use of" for a read
+// and "assignment to" for a let target
+pub(crate) fn undeclared_variable(what: &str, ident: &Ident) -> Diagnostic {
+    Diagnostic::error(
+        UNDECLARED_VARIABLE,
+        format!("{} undeclared variable `{}`", what, ident.name),
+        ident.span,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parser::Parser, vm_interp::VmInterpreter};
+
+    use std::collections::BTreeSet;
+
+    use super::{CodeGenerator, CodegenOptions, LabelStyle};
+
+    fn generate(src: &str) -> String {
+        let class = Parser::from_source(src).parse_class().unwrap();
+        CodeGenerator::generate(&class).unwrap()
+    }
+
+    fn labelled(labels: LabelStyle) -> CodegenOptions<'static> {
+        CodegenOptions {
+            labels,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn constructor_allocates_fields() {
+        let got = generate(
+            "class Point { field int x, y; static int count;
+            constructor Point new(int ax) { let x = ax; return this; } }",
+        );
+
+        assert_eq!(
+            got,
+            "function Point.new 0\npush constant 2\ncall Memory.alloc 1\npop pointer 0\n\
+             push argument 0\npop this 0\npush pointer 0\nreturn\n"
+        );
+    }
+
+    #[test]
+    fn math_class_can_be_renamed() {
+        let class =
+            Parser::from_source("class A { function int f(int a, int b) { return a * b / 2; } }")
+                .parse_class()
+                .unwrap();
+
+        let options = CodegenOptions {
+            math_class: "MyMath",
+            ..Default::default()
+        };
+        let got = CodeGenerator::generate_with(&class, &options).unwrap();
+        assert!(got.contains("call MyMath.multiply 2\n"), "{}", got);
+        assert!(got.contains("call MyMath.divide 2\n"), "{}", got);
+        assert!(!got.replace("MyMath.", "").contains("Math."), "{}", got);
+        assert!(
+            generate("class A { function int f(int a) { return a * a; } }")
+                .contains("call Math.multiply 2\n")
+        );
+
+        for bad in ["a b", "", "1Math", "class"] {
+            let options = CodegenOptions {
+                math_class: bad,
+                ..Default::default()
+            };
+            let err = CodeGenerator::generate_with(&class, &options).unwrap_err();
+            assert!(
+                format!("{:#}", err).starts_with("invalid math class: "),
+                "{:#}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn generated_code_runs() {
+        let vm = generate(
+            "class Main {
+                function int main() { var int a; let a = Main.twice(5); return a - 1; }
+                function int twice(int n) { return n + n; }
+            }",
+        );
+
+        let got = VmInterpreter::new(&vm).unwrap().run("Main.main").unwrap();
+        assert_eq!(got, 9, "{}", vm);
+    }
+
+    #[test]
+    fn undeclared_variable_is_an_error() {
+        let class = Parser::from_source("class Main { function void f() { let x = 1; return; } }")
+            .parse_class()
+            .unwrap();
+
+        let err = CodeGenerator::generate(&class).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:38: error[J201]: assignment to undeclared variable `x`"
+        );
+    }
+
+    #[test]
+    fn undeclared_variable_in_an_expression_is_reported_where_used() {
+        let class = Parser::from_source(
+            "class Main { function void f() { var int y; let y = z + 1; return z[y]; } }",
+        )
+        .parse_class()
+        .unwrap();
+
+        let err = CodeGenerator::generate(&class).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:53: error[J201]: use of undeclared variable `z`"
+        );
+    }
+
+    #[test]
+    fn smallest_int_constant() {
+        let got = generate("class Main { function int f() { return -32768; } }");
+        assert_eq!(
+            got,
+            "function Main.f 0\npush constant 32767\npush constant 1\nadd\nneg\nreturn\n"
+        );
+
+        let got = VmInterpreter::new(&got).unwrap().run("Main.f").unwrap();
+        assert_eq!(got, -32768);
+    }
+
+    #[test]
+    fn parallel_output_matches_sequential() {
+        let mut src = String::from("class Many { field int x; static int s;\n");
+        // stays below the tokenizer's cap
+        for i in 0..12 {
+            src.push_str(&format!(
+                "method int m{0}(int a) {{ var int b; let b = a + x; \
+                 while (b < {0}) {{ if (b) {{ let s = s + 1; }} let b = b + 1; }} return b; }}\n",
+                i
+            ));
+        }
+        src.push('}');
+        let class = Parser::from_source(&src).parse_class().unwrap();
+
+        let sequential = CodeGenerator::generate(&class).unwrap();
+        for jobs in [1, 2, 7, 32] {
+            let parallel = CodeGenerator::generate_with(
+                &class,
+                &CodegenOptions {
+                    jobs,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(parallel, sequential, "{} jobs", jobs);
+        }
+    }
+
+    #[test]
+    fn parallel_errors_match_sequential() {
+        let class = Parser::from_source(
+            "class Main { function void f() { return; }
+            function void g() { let y = 1; return; }
+            function void h() { let z = 1; return; } }",
+        )
+        .parse_class()
+        .unwrap();
+
+        let sequential = CodeGenerator::generate(&class).unwrap_err();
+        let options = CodegenOptions {
+            jobs: 3,
+            ..Default::default()
+        };
+        let parallel = CodeGenerator::generate_with(&class, &options).unwrap_err();
+        assert_eq!(parallel.to_string(), sequential.to_string());
+    }
+
+    fn labels(src: &str, style: LabelStyle) -> Vec<String> {
+        let class = Parser::from_source(src).parse_class().unwrap();
+        let vm = CodeGenerator::generate_with(&class, &labelled(style)).unwrap();
+        vm.lines()
+            .filter_map(|line| line.strip_prefix("label "))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    const BRANCHES: &str = "class Main {
+        function void main() {
+            var int i;
+            if (i) { let i = 1; } else { let i = 2; }
+            while (i) { if (i) { let i = 0; } }
+            return;
+        }
+        function void other() { while (true) { } return; }
+    }";
+
+    #[test]
+    fn label_styles() {
+        let cases = [
+            (
+                LabelStyle::Course,
+                [
+                    "IF_TRUE0",
+                    "IF_FALSE0",
+                    "IF_END0",
+                    "WHILE_EXP0",
+                    "IF_TRUE1",
+                    "IF_FALSE1",
+                    "WHILE_END0",
+                    "WHILE_EXP0",
+                    "WHILE_END0",
+                ],
+            ),
+            (
+                LabelStyle::Qualified,
+                [
+                    "Main.main$if_true.0",
+                    "Main.main$if_false.0",
+                    "Main.main$if_end.0",
+                    "Main.main$while_exp.0",
+                    "Main.main$if_true.1",
+                    "Main.main$if_false.1",
+                    "Main.main$while_end.0",
+                    "Main.other$while_exp.0",
+                    "Main.other$while_end.0",
+                ],
+            ),
+            (
+                LabelStyle::Legacy,
+                [
+                    "MAIN.MAIN:IF_TRUE0",
+                    "MAIN.MAIN:IF_FALSE0",
+                    "MAIN.MAIN:IF_END0",
+                    "MAIN.MAIN:WHILE_EXP0",
+                    "MAIN.MAIN:IF_TRUE1",
+                    "MAIN.MAIN:IF_FALSE1",
+                    "MAIN.MAIN:WHILE_END0",
+                    "MAIN.OTHER:WHILE_EXP0",
+                    "MAIN.OTHER:WHILE_END0",
+                ],
+            ),
+        ];
+
+        for (style, expected) in cases {
+            let got = labels(BRANCHES, style);
+            assert_eq!(got, expected, "{:?}", style);
+
+            // course labels repeat across functions, the others are unique
+            // in the whole class
+            let unique: BTreeSet<&String> = got.iter().collect();
+            let expected = if style == LabelStyle::Course { 7 } else { 9 };
+            assert_eq!(unique.len(), expected, "{:?}", style);
+        }
+    }
+
+    #[test]
+    fn legacy_labels_of_names_differing_in_case_stay_apart() {
+        let src = "class Main {
+            function void foo() { while (true) { } return; }
+            function void FOO_1() { while (true) { } return; }
+            function void Foo() { while (true) { } return; }
+        }";
+
+        let got = labels(src, LabelStyle::Legacy);
+        assert_eq!(
+            got,
+            [
+                "MAIN.FOO:WHILE_EXP0",
+                "MAIN.FOO:WHILE_END0",
+                "MAIN.FOO_1:WHILE_EXP0",
+                "MAIN.FOO_1:WHILE_END0",
+                "MAIN.FOO_2:WHILE_EXP0",
+                "MAIN.FOO_2:WHILE_END0",
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_outside_the_style_are_rejected() {
+        let class =
+            Parser::from_source("class Main { function void año() { while (true) { } return; } }")
+                .parse_class()
+                .unwrap();
+
+        assert!(CodeGenerator::generate_with(&class, &labelled(LabelStyle::Course)).is_ok());
+        let err = CodeGenerator::generate_with(&class, &labelled(LabelStyle::Legacy)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:28: error[J203]: label `MAIN.AÑO:WHILE_EXP0` is not valid in the legacy label style"
+        );
+        assert!(CodeGenerator::generate_with(&class, &labelled(LabelStyle::Qualified)).is_err());
+    }
+
+    #[test]
+    fn positions_come_before_functions_and_statements() {
+        let src = "class Main {
+    function void main() {
+        do Main.f(1); do Main.f(2);
+        return;
+    }
+
+    function void f(int a) { return; }
+}";
+        let class = Parser::from_source(src).parse_class().unwrap();
+        let options = CodegenOptions {
+            source: Some("Main.jack"),
+            ..Default::default()
+        };
+        let vm = CodeGenerator::generate_with(&class, &options).unwrap();
+        let positions: Vec<(&str, Option<&str>)> = vm
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.starts_with("//"))
+            .map(|(i, line)| (line, vm.lines().nth(i + 1)))
+            .collect();
+        assert_eq!(
+            positions,
+            [
+                ("// Main.jack:2", Some("function Main.main 0")),
+                ("// Main.jack:3", Some("push constant 1")),
+                ("// Main.jack:4", Some("push constant 0")),
+                ("// Main.jack:7", Some("function Main.f 0")),
+            ]
+        );
+
+        // the code around them is unchanged, and the same on several threads
+        let plain: Vec<&str> = vm.lines().filter(|l| !l.starts_with("//")).collect();
+        assert_eq!(
+            plain,
+            CodeGenerator::generate(&class)
+                .unwrap()
+                .lines()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            CodeGenerator::generate_with(&class, &CodegenOptions { jobs: 2, ..options }).unwrap(),
+            vm
+        );
+    }
+
+    #[test]
+    fn strings_the_platform_cant_show_are_errors() {
+        let class = Parser::from_source(
+            "class Main { function void main() { do Output.printString(\"café\"); return; } }",
+        )
+        .parse_class()
+        .unwrap();
+        let err = CodeGenerator::generate(&class).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:63: error[J118]: `é` (U+00E9) at column 4 of the string is not in the Hack character set"
+        );
+    }
+}
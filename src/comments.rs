@@ -0,0 +1,233 @@
+use std::collections::BTreeSet;
+
+use crate::{ast::Class, SpannedToken, StreamTokenizer};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CommentKind {
+    Line,
+    Block,
+    // a block comment opened with `/**`
+    Doc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Comment {
+    pub kind: CommentKind,
+    // byte offsets
+    pub start: usize,
+    pub end: usize,
+    // the lines it starts and ends on, counted from 1
+    pub line: usize,
+    pub end_line: usize,
+}
+
+// the tokens of a source and the comments between them. the scan stops at a
+// lex error like the brace style one, the parser reports those
+pub(crate) struct Layout {
+    tokens: Vec<SpannedToken>,
+    comments: Vec<Comment>,
+}
+
+impl Layout {
+    #[cfg(test)]
+    pub fn of(source: &str) -> Self {
+        let tokens = StreamTokenizer::from_source(source)
+            .into_spanned()
+            .map_while(Result::ok)
+            .collect();
+        Self::from_tokens(source, tokens)
+    }
+
+    // the comments around `tokens`, already read from `source`
+    pub fn from_tokens(source: &str, tokens: Vec<SpannedToken>) -> Self {
+        let mut comments = Vec::new();
+        let mut after = 0;
+        for token in &tokens {
+            Self::scan(source, after..token.span.start, &mut comments);
+            after = token.span.end;
+        }
+        Self::scan(source, after..source.len(), &mut comments);
+
+        Self { tokens, comments }
+    }
+
+    // the comments in the `gap` between two tokens of `source`
+    pub fn scan(source: &str, gap: std::ops::Range<usize>, out: &mut Vec<Comment>) {
+        let mut at = gap.start;
+        while at < gap.end {
+            at += StreamTokenizer::leading_whitespace(&source[at..gap.end]);
+            let (len, _) = StreamTokenizer::skip_comments(&source[at..gap.end]);
+            if len == 0 {
+                return;
+            }
+            let text = &source[at..at + len];
+            let kind = if text.starts_with("//") {
+                CommentKind::Line
+            } else if text.starts_with("/**") && text != "/**/" {
+                CommentKind::Doc
+            } else {
+                CommentKind::Block
+            };
+            let line = source[..at].matches('\n').count() + 1;
+            // a line comment's newline is the end of its line, not part of
+            // the next one
+            let end_line = line + text.trim_end_matches('\n').matches('\n').count();
+            out.push(Comment {
+                kind,
+                start: at,
+                end: at + len,
+                line,
+                end_line,
+            });
+            at += len;
+        }
+    }
+
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    // the comments on the lines between the token before `offset` and
+    // `offset`, the ones that lead whatever starts there. a comment on the
+    // line of the token before trails that token instead
+    pub fn leading_comments(&self, offset: usize) -> &[Comment] {
+        let before = self.tokens.iter().rev().find(|t| t.span.end <= offset);
+        let (after, line) = before.map_or((0, 0), |t| (t.span.end, t.span.line));
+        let first = self
+            .comments
+            .partition_point(|c| c.start < after || c.line <= line);
+        let last = self.comments.partition_point(|c| c.start < offset);
+        &self.comments[first.min(last)..last]
+    }
+
+    // the doc comment nearest before `offset` among its leading comments,
+    // blank lines in between don't matter
+    pub fn doc_comment(&self, offset: usize) -> Option<&Comment> {
+        self.leading_comments(offset)
+            .iter()
+            .rev()
+            .find(|c| c.kind == CommentKind::Doc)
+    }
+}
+
+// how much of a source is commented, for grading
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct CommentStats {
+    pub line_comments: usize,
+    pub block_comments: usize,
+    pub doc_comments: usize,
+    // the lines with a comment and the lines with a token, a line may be
+    // counted in both
+    pub comment_lines: usize,
+    pub code_lines: usize,
+    // subroutines without a doc comment leading them
+    pub undocumented: usize,
+}
+
+impl CommentStats {
+    pub fn of(layout: &Layout, class: &Class) -> Self {
+        let mut stats = Self::default();
+        let mut comment_lines = BTreeSet::new();
+        for comment in layout.comments() {
+            match comment.kind {
+                CommentKind::Line => stats.line_comments += 1,
+                CommentKind::Block => stats.block_comments += 1,
+                CommentKind::Doc => stats.doc_comments += 1,
+            }
+            comment_lines.extend(comment.line..=comment.end_line);
+        }
+        stats.comment_lines = comment_lines.len();
+        stats.code_lines = layout
+            .tokens
+            .iter()
+            .map(|t| t.span.line)
+            .collect::<BTreeSet<_>>()
+            .len();
+        stats.undocumented = class
+            .subroutines
+            .iter()
+            .filter(|s| layout.doc_comment(s.span.start).is_none())
+            .count();
+        stats
+    }
+
+    pub fn ratio(&self) -> f64 {
+        self.comment_lines as f64 / self.code_lines.max(1) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Parser;
+
+    use super::{CommentKind, CommentStats, Layout};
+
+    const SOURCE: &str = "// Main.jack
+/* the entry point */
+class Main {
+    field int x; // trailing
+
+    /** documented */
+    method void a() { return; }
+
+    /** documented after a blank line */
+
+    function void b() {
+        // inside
+        return;
+    }
+
+    // only a line comment
+    function void c() { return; } /** trails c */
+    function void d() { let x = \"// not a comment\"; return; }
+}
+";
+
+    #[test]
+    fn comments_are_classified() {
+        let kinds: Vec<(CommentKind, usize)> = Layout::of(SOURCE)
+            .comments()
+            .iter()
+            .map(|c| (c.kind, c.line))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (CommentKind::Line, 1),
+                (CommentKind::Block, 2),
+                (CommentKind::Line, 4),
+                (CommentKind::Doc, 6),
+                (CommentKind::Doc, 9),
+                (CommentKind::Line, 12),
+                (CommentKind::Line, 16),
+                (CommentKind::Doc, 17),
+            ]
+        );
+    }
+
+    #[test]
+    fn doc_comments_lead_their_subroutine() {
+        let class = Parser::from_source(SOURCE).parse_class().unwrap();
+        let layout = Layout::of(SOURCE);
+        let docs: Vec<Option<usize>> = class
+            .subroutines
+            .iter()
+            .map(|s| layout.doc_comment(s.span.start).map(|c| c.line))
+            .collect();
+        assert_eq!(docs, [Some(6), Some(9), None, None]);
+
+        let stats = CommentStats::of(&layout, &class);
+        assert_eq!(
+            stats,
+            CommentStats {
+                line_comments: 4,
+                block_comments: 1,
+                doc_comments: 3,
+                comment_lines: 8,
+                code_lines: 9,
+                undocumented: 2,
+            }
+        );
+        assert_eq!(format!("{:.2}", stats.ratio()), "0.89");
+    }
+}
@@ -0,0 +1,81 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+
+use crate::json;
+
+// what compiling one source took
+#[derive(Debug, Clone, Default)]
+pub struct FileReport {
+    pub file: PathBuf,
+    pub source_bytes: usize,
+    pub tokens: usize,
+    // the tokens the engine compiled and the bytes the tokenizer read, all
+    // of them unless the output was cut short
+    pub tokens_consumed: usize,
+    pub bytes_scanned: usize,
+    // tokenizing and the engine
+    pub parse: Duration,
+    // building the ast and running the semantic checks on it
+    pub semantic: Duration,
+    // writing the output
+    pub emit: Duration,
+    // the most output the engine held back at once, in bytes
+    pub peak_buffer: usize,
+    // skipped as unchanged since the cached output was written, only the
+    // size of the source is known
+    pub cached: bool,
+}
+
+// the sizes and times of every source `parse` compiled, for tracking the
+// compiler's performance
+#[derive(Debug, Clone, Default)]
+pub struct CompilationReport {
+    pub files: Vec<FileReport>,
+    // the files looked at in the source directories and the ones of them
+    // that were sources
+    pub files_considered: usize,
+    pub files_selected: usize,
+}
+
+impl CompilationReport {
+    pub fn extend(&mut self, other: CompilationReport) {
+        self.files.extend(other.files);
+        self.files_considered += other.files_considered;
+        self.files_selected += other.files_selected;
+    }
+
+    // durations are in nanoseconds
+    pub fn to_json(&self) -> String {
+        let files: Vec<String> = self
+            .files
+            .iter()
+            .map(|file| {
+                format!(
+                    "{{\"file\":{},\"source_bytes\":{},\"tokens\":{},\"tokens_consumed\":{},\"bytes_scanned\":{},\"parse_ns\":{},\"semantic_ns\":{},\"emit_ns\":{},\"peak_buffer_bytes\":{},\"cached\":{}}}",
+                    json::string(&file.file.display().to_string()),
+                    file.source_bytes,
+                    file.tokens,
+                    file.tokens_consumed,
+                    file.bytes_scanned,
+                    file.parse.as_nanos(),
+                    file.semantic.as_nanos(),
+                    file.emit.as_nanos(),
+                    file.peak_buffer,
+                    file.cached
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"files_considered\":{},\"files_selected\":{},\"files\":[{}]}}\n",
+            self.files_considered,
+            self.files_selected,
+            files.join(",")
+        )
+    }
+
+    pub fn write(&self, path: &PathBuf) -> Result<()> {
+        std::fs::write(path, self.to_json()).with_context(|| format!("{}", path.display()))
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Class, SubroutineCall, SubroutineDec, SubroutineKind},
+    checker::OS_CLASSES,
+    diagnostic::Diagnostic,
+    resolve::{call_target, uses, var_type, Use},
+};
+
+pub const UNKNOWN_CLASS: &str = "J111";
+pub const UNKNOWN_SUBROUTINE: &str = "J112";
+pub const WRONG_ARGUMENT_COUNT: &str = "J113";
+pub const CALL_KIND_MISMATCH: &str = "J114";
+pub const RECEIVER_SHADOWS_CLASS: &str = "J115";
+
+// what a call needs to know about the subroutine it goes to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Signature {
+    pub kind: SubroutineKind,
+    pub params: usize,
+}
+
+// every class of a program at once, for the checks that look across classes.
+// the signatures of all classes are collected before any call is looked at,
+// so a call may go to a class parsed after the one it is in
+#[derive(Debug, Clone, Default)]
+pub struct CompilationUnit {
+    // by source name
+    classes: Vec<(String, Class)>,
+    // by class name, then subroutine name
+    signatures: HashMap<String, HashMap<String, Signature>>,
+}
+
+impl CompilationUnit {
+    pub fn new(classes: impl IntoIterator<Item = (String, Class)>) -> Self {
+        let classes: Vec<(String, Class)> = classes.into_iter().collect();
+        let signatures = classes
+            .iter()
+            .map(|(_, class)| {
+                let subroutines = class
+                    .subroutines
+                    .iter()
+                    .map(|s| {
+                        let signature = Signature {
+                            kind: s.kind,
+                            params: s.params.len(),
+                        };
+                        (s.name.name.clone(), signature)
+                    })
+                    .collect();
+                (class.name.name.clone(), subroutines)
+            })
+            .collect();
+
+        Self {
+            classes,
+            signatures,
+        }
+    }
+
+    pub fn signature(&self, class: &str, subroutine: &str) -> Option<Signature> {
+        self.signatures.get(class)?.get(subroutine).copied()
+    }
+
+    // calls to a class that doesn't exist, to a subroutine its class doesn't
+    // declare, with the wrong number of arguments, or of the wrong kind: a
+    // method called on a class name, or a function or constructor called
+    // through a variable. a receiver is the variable of that name when there
+    // is one, with a note when a class has the name too. by the source they
+    // are in. the os classes are taken to exist and calls into them aren't
+    // checked, nor are calls on int, char and boolean variables
+    pub fn check_calls(&self) -> Vec<(String, Diagnostic)> {
+        let mut diagnostics = Vec::new();
+        for (name, class) in &self.classes {
+            for subroutine in &class.subroutines {
+                for found in uses(&subroutine.statements) {
+                    if let Use::Call(call) = found {
+                        let mut found = Vec::new();
+                        self.check_call(class, subroutine, call, &mut found);
+                        diagnostics.extend(found.into_iter().map(|d| (name.clone(), d)));
+                    }
+                }
+            }
+        }
+        diagnostics
+    }
+
+    fn check_call(
+        &self,
+        class: &Class,
+        subroutine: &SubroutineDec,
+        call: &SubroutineCall,
+        out: &mut Vec<Diagnostic>,
+    ) {
+        let Some(target) = call_target(class, subroutine, call) else {
+            return;
+        };
+        // the variable the call goes through, if any
+        let variable = call
+            .receiver
+            .as_ref()
+            .filter(|receiver| var_type(class, subroutine, &receiver.name).is_some());
+        if let Some(receiver) = variable {
+            if self.signatures.contains_key(&receiver.name) {
+                out.push(Diagnostic::note(
+                    RECEIVER_SHADOWS_CLASS,
+                    format!(
+                        "`{0}` is a variable and a class, `{0}.{1}` calls `{1}` on the variable",
+                        receiver.name, call.name.name
+                    ),
+                    receiver.span,
+                ));
+            }
+        }
+        if OS_CLASSES.contains(&target.as_str()) {
+            return;
+        }
+
+        let Some(subroutines) = self.signatures.get(&target) else {
+            if let Some(receiver) = &call.receiver {
+                let message = match variable {
+                    Some(_) => format!(
+                        "unknown class `{}`, the type of `{}`",
+                        target, receiver.name
+                    ),
+                    None => format!("unknown class `{}`", target),
+                };
+                out.push(Diagnostic::error(UNKNOWN_CLASS, message, receiver.span));
+            }
+            return;
+        };
+
+        let Some(signature) = subroutines.get(&call.name.name) else {
+            let message = match variable {
+                Some(receiver) => format!(
+                    "`{}` is a `{}`, which has no subroutine `{}`",
+                    receiver.name, target, call.name.name
+                ),
+                None => format!("`{}` has no subroutine `{}`", target, call.name.name),
+            };
+            out.push(Diagnostic::error(
+                UNKNOWN_SUBROUTINE,
+                message,
+                call.name.span,
+            ));
+            return;
+        };
+
+        let kind = match signature.kind {
+            SubroutineKind::Constructor => "constructor",
+            SubroutineKind::Function => "function",
+            SubroutineKind::Method => "method",
+        };
+        let is_method = signature.kind == SubroutineKind::Method;
+        let message = match (&call.receiver, variable) {
+            (Some(_), Some(receiver)) if !is_method => Some(format!(
+                "`{0}.{1}` is a {2}, call it on the class as `{0}.{1}(...)` rather than through `{3}`",
+                target, call.name.name, kind, receiver.name
+            )),
+            (Some(_), None) if is_method => Some(format!(
+                "`{0}.{1}` is a method, it is called on a `{0}` variable rather than on the class",
+                target, call.name.name
+            )),
+            _ => None,
+        };
+        if let Some(message) = message {
+            out.push(Diagnostic::error(
+                CALL_KIND_MISMATCH,
+                message,
+                call.name.span,
+            ));
+        }
+
+        if signature.params != call.args.len() {
+            let plural = |n: usize, what: &str| match n {
+                1 => format!("1 {}", what),
+                n => format!("{} {}s", n, what),
+            };
+            out.push(Diagnostic::error(
+                WRONG_ARGUMENT_COUNT,
+                format!(
+                    "`{}.{}` takes {}, {} given",
+                    target,
+                    call.name.name,
+                    plural(signature.params, "argument"),
+                    call.args.len()
+                ),
+                call.span,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Parser;
+
+    use super::{
+        CompilationUnit, CALL_KIND_MISMATCH, RECEIVER_SHADOWS_CLASS, UNKNOWN_CLASS,
+        UNKNOWN_SUBROUTINE, WRONG_ARGUMENT_COUNT,
+    };
+
+    fn check(files: &[(&str, &str)]) -> Vec<(String, &'static str, String)> {
+        let classes = files.iter().map(|(name, text)| {
+            let class = Parser::from_source(text).parse_class().unwrap();
+            (name.to_string(), class)
+        });
+        CompilationUnit::new(classes)
+            .check_calls()
+            .into_iter()
+            .map(|(name, d)| (name, d.code, d.message))
+            .collect()
+    }
+
+    const BAR: &str = "class Bar { field int x;
+        constructor Bar new(int ax) { let x = ax; return this; }
+        method int get() { return x; }
+        function int twice(int a) { return a + a; } }";
+
+    #[test]
+    fn calls_into_other_classes_are_checked() {
+        let foo = "class Foo { function void main() { var Bar b; var int n;
+            let b = Bar.new(1); let n = b.get() + Bar.twice(n);
+            do Output.printInt(n); do Bar.missing(); return; } }";
+
+        let got = check(&[("Foo.jack", foo), ("Bar.jack", BAR)]);
+        assert_eq!(
+            got,
+            [(
+                "Foo.jack".to_owned(),
+                UNKNOWN_SUBROUTINE,
+                "`Bar` has no subroutine `missing`".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn unknown_classes_and_argument_counts() {
+        let foo = "class Foo { field Baz z;
+            method void f() { var Bar b;
+            let b = Bar.new(); do b.get(1, 2); do Qux.run(); do z.go(); do g(3); return; }
+            method void g(int a) { return; } }";
+
+        let got: Vec<(&str, String)> = check(&[("Foo.jack", foo), ("Bar.jack", BAR)])
+            .into_iter()
+            .map(|(_, code, message)| (code, message))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                (
+                    WRONG_ARGUMENT_COUNT,
+                    "`Bar.new` takes 1 argument, 0 given".to_owned()
+                ),
+                (
+                    WRONG_ARGUMENT_COUNT,
+                    "`Bar.get` takes 0 arguments, 2 given".to_owned()
+                ),
+                (UNKNOWN_CLASS, "unknown class `Qux`".to_owned()),
+                (
+                    UNKNOWN_CLASS,
+                    "unknown class `Baz`, the type of `z`".to_owned()
+                ),
+            ]
+        );
+    }
+
+    fn messages(foo: &str) -> Vec<(&'static str, String)> {
+        check(&[("Foo.jack", foo), ("Bar.jack", BAR)])
+            .into_iter()
+            .map(|(_, code, message)| (code, message))
+            .collect()
+    }
+
+    #[test]
+    fn method_calls_go_by_the_type_of_their_receiver() {
+        let ok = "class Foo { function void f(Bar b) { do b.get(); return; } }";
+        assert!(messages(ok).is_empty(), "{:?}", messages(ok));
+
+        let wrong_class = "class Foo { method void f() { var Foo other; var Bar b;
+            do other.get(); do b.run(); return; } }";
+        assert_eq!(
+            messages(wrong_class),
+            [
+                (
+                    UNKNOWN_SUBROUTINE,
+                    "`other` is a `Foo`, which has no subroutine `get`".to_owned()
+                ),
+                (
+                    UNKNOWN_SUBROUTINE,
+                    "`b` is a `Bar`, which has no subroutine `run`".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn functions_through_variables_and_methods_on_classes() {
+        let foo = "class Foo { function void f() { var Bar b; var int n;
+            let n = b.twice(1); let n = Bar.get(); return; } }";
+        assert_eq!(
+            messages(foo),
+            [
+                (
+                    CALL_KIND_MISMATCH,
+                    "`Bar.twice` is a function, call it on the class as `Bar.twice(...)` rather than through `b`"
+                        .to_owned()
+                ),
+                (
+                    CALL_KIND_MISMATCH,
+                    "`Bar.get` is a method, it is called on a `Bar` variable rather than on the class"
+                        .to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_variable_named_like_a_class_is_the_receiver() {
+        // `Bar` is the local, so `Bar.get()` is a method call on it
+        let foo = "class Foo { function void f() { var Bar Bar;
+            let Bar = Bar.new(1); do Bar.get(); return; } }";
+        let got = messages(foo);
+        assert_eq!(
+            got,
+            [
+                (
+                    RECEIVER_SHADOWS_CLASS,
+                    "`Bar` is a variable and a class, `Bar.new` calls `new` on the variable".to_owned()
+                ),
+                (
+                    CALL_KIND_MISMATCH,
+                    "`Bar.new` is a constructor, call it on the class as `Bar.new(...)` rather than through `Bar`"
+                        .to_owned()
+                ),
+                (
+                    RECEIVER_SHADOWS_CLASS,
+                    "`Bar` is a variable and a class, `Bar.get` calls `get` on the variable".to_owned()
+                ),
+            ]
+        );
+    }
+}
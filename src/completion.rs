@@ -0,0 +1,215 @@
+use anyhow::Result;
+
+use crate::{
+    checker::OS_CLASSES,
+    diagnostic::Diagnostic,
+    parser::{Expected, Parser, UNEXPECTED_EOF},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompletionKind {
+    Keyword,
+    Variable,
+    Subroutine,
+    Class,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionItem {
+    pub label: String,
+    pub kind: CompletionKind,
+}
+
+// the tokens the grammar allows at `offset` (a byte offset into `source`).
+// the source is cut at the offset and parsed, the parser running out of input
+// leaves behind the set of tokens it tried last. a word the cursor is still
+// in the middle of is used as a prefix filter instead of being parsed.
+pub fn completions(source: &str, offset: usize) -> Vec<CompletionItem> {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    let before = &source[..offset];
+    let word_start = before
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let prefix = &before[word_start..];
+
+    let mut parser = Parser::from_source(&before[..word_start]);
+    let err = match parser.parse_class() {
+        Ok(_) => return Vec::new(),
+        Err(err) => err,
+    };
+
+    // a real syntax error before the cursor, nothing sensible to offer
+    match err.downcast_ref::<Diagnostic>() {
+        Some(diagnostic) if diagnostic.code == UNEXPECTED_EOF => {}
+        _ => return Vec::new(),
+    }
+
+    let mut items = Vec::new();
+    for expected in parser.expected() {
+        match expected {
+            Expected::Keyword(keyword) => {
+                push(&mut items, keyword.to_string(), CompletionKind::Keyword)
+            }
+            Expected::ClassName => push_classes(&mut items, parser.class_name()),
+            Expected::VarName => {
+                let mut variables: Vec<_> =
+                    parser.symbols().visible().map(|s| s.name.clone()).collect();
+                variables.sort();
+                for name in variables {
+                    push(&mut items, name, CompletionKind::Variable);
+                }
+                for name in parser.subroutine_names() {
+                    push(&mut items, name.clone(), CompletionKind::Subroutine);
+                }
+                push_classes(&mut items, parser.class_name());
+            }
+            Expected::Symbol(_)
+            | Expected::Identifier
+            | Expected::IntConst
+            | Expected::StringConst => {}
+        }
+    }
+
+    items.retain(|item| item.label.starts_with(prefix));
+    items
+}
+
+// the tokens the parser would accept after all of `src`, in the order it
+// tried them. a complete class expects nothing more, a syntax error before the
+// end is returned as is
+pub fn expected_at_end(src: &str) -> Result<Vec<Expected>> {
+    let mut parser = Parser::from_source(src);
+    let err = match parser.parse_class() {
+        Ok(_) => return Ok(Vec::new()),
+        Err(err) => err,
+    };
+    match err.downcast_ref::<Diagnostic>() {
+        Some(diagnostic) if diagnostic.code == UNEXPECTED_EOF => {}
+        _ => return Err(err),
+    }
+
+    let mut expected = Vec::new();
+    for token in parser.expected() {
+        if !expected.contains(token) {
+            expected.push(token.clone());
+        }
+    }
+    Ok(expected)
+}
+
+fn push_classes(items: &mut Vec<CompletionItem>, class_name: Option<&str>) {
+    if let Some(name) = class_name {
+        push(items, name.to_owned(), CompletionKind::Class);
+    }
+    for name in OS_CLASSES {
+        push(items, name.to_owned(), CompletionKind::Class);
+    }
+}
+
+fn push(items: &mut Vec<CompletionItem>, label: String, kind: CompletionKind) {
+    if !items.iter().any(|item| item.label == label) {
+        items.push(CompletionItem { label, kind });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        lexical_elements::{Keywords, Symbols},
+        parser::Expected,
+    };
+
+    use super::{completions, expected_at_end, CompletionItem, CompletionKind};
+
+    fn labels(items: &[CompletionItem]) -> Vec<&str> {
+        items.iter().map(|item| item.label.as_str()).collect()
+    }
+
+    #[test]
+    fn statement_start_offers_statement_keywords() {
+        let src = "class Main { function void main() { var int x; ";
+
+        let items = completions(src, src.len());
+        let got = labels(&items);
+        for keyword in ["var", "let", "if", "while", "do", "return"] {
+            assert!(got.contains(&keyword), "{:?}", got);
+        }
+        assert!(items.iter().all(|i| i.kind == CompletionKind::Keyword));
+    }
+
+    #[test]
+    fn after_var_offers_types() {
+        let src = "class Main { function void main() { var ";
+
+        let items = completions(src, src.len());
+        let got = labels(&items);
+        assert_eq!(&got[..3], ["int", "char", "boolean"]);
+        assert!(items.contains(&CompletionItem {
+            label: "Main".to_owned(),
+            kind: CompletionKind::Class,
+        }));
+        assert!(!got.contains(&"let"), "{:?}", got);
+    }
+
+    #[test]
+    fn inside_expression_offers_declared_names() {
+        let src = "class Main { field int count;
+            function void main(int n) { var int x; let x = co";
+
+        let items = completions(src, src.len());
+        assert_eq!(
+            items,
+            vec![CompletionItem {
+                label: "count".to_owned(),
+                kind: CompletionKind::Variable,
+            }]
+        );
+
+        let src = "class Main { field int count; function void f() { return; }
+            function void main(int n) { var int x; let x = ";
+        let got = labels(&completions(src, src.len())).join(" ");
+        assert!(
+            got.starts_with("true false null this count n x f main Main"),
+            "{}",
+            got
+        );
+    }
+
+    #[test]
+    fn expected_in_class_body() {
+        let got = expected_at_end("class Main {").unwrap();
+        assert_eq!(
+            got,
+            [
+                Expected::Keyword(Keywords::Static),
+                Expected::Keyword(Keywords::Field),
+                Expected::Keyword(Keywords::Constructor),
+                Expected::Keyword(Keywords::Function),
+                Expected::Keyword(Keywords::Method),
+                Expected::Symbol(Symbols::CloseCurlyBrace),
+            ]
+        );
+
+        // the same after a member
+        let got =
+            expected_at_end("class Main { field int x; function void f() { return; }").unwrap();
+        assert!(
+            got.contains(&Expected::Keyword(Keywords::Method)),
+            "{:?}",
+            got
+        );
+        assert!(
+            !got.contains(&Expected::Keyword(Keywords::Field)),
+            "{:?}",
+            got
+        );
+
+        assert_eq!(expected_at_end("class Main { }").unwrap(), []);
+        assert!(expected_at_end("class Main { let").is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    collections::HashSet,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    ast::{Class, Ident, SubroutineCall, SubroutineKind},
+    json,
+    resolve::{call_target, local_type, uses, Use},
+    token::Span,
+    SourceSet,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeadKind {
+    Subroutine,
+    Field,
+}
+
+impl DeadKind {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Subroutine => "subroutine",
+            Self::Field => "field",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadItem {
+    pub kind: DeadKind,
+    pub class: String,
+    pub name: String,
+    pub file: PathBuf,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeadCodeReport {
+    pub items: Vec<DeadItem>,
+}
+
+// subroutines that can't be reached from `Main.main` and class variables no
+// subroutine of their class uses.
+//
+// reachability is transitive: a subroutine only called from dead subroutines
+// is just as dead as one nobody calls. calls into the os classes are opaque,
+// they never lead back into the project, so only `Main.main` is a root.
+pub fn dead_code(project: &SourceSet, ignore_constructors: bool) -> Result<DeadCodeReport> {
+    let classes = project.parse()?;
+    let has_main = classes.iter().any(|(_, class)| {
+        class.name.name == "Main" && class.subroutines.iter().any(|s| s.name.name == "main")
+    });
+    if !has_main {
+        bail!("no `Main.main` to start the reachability analysis from");
+    }
+
+    let mut reached: HashSet<(String, String)> = HashSet::new();
+    let mut pending = vec![("Main".to_owned(), "main".to_owned())];
+    while let Some(key) = pending.pop() {
+        if !reached.insert(key.clone()) {
+            continue;
+        }
+        let found = classes
+            .iter()
+            .filter(|(_, class)| class.name.name == key.0)
+            .find_map(|(_, class)| {
+                let subroutine = class.subroutines.iter().find(|s| s.name.name == key.1);
+                subroutine.map(|s| (class, s))
+            });
+        let Some((class, subroutine)) = found else {
+            // not one of ours, an os class or a missing subroutine
+            continue;
+        };
+
+        for found in uses(&subroutine.statements) {
+            if let Use::Call(call) = found {
+                if let Some(target) = call_target(class, subroutine, call) {
+                    pending.push((target, call.name.name.clone()));
+                }
+            }
+        }
+    }
+
+    let mut report = DeadCodeReport::default();
+    for (file, class) in &classes {
+        for subroutine in &class.subroutines {
+            let key = (class.name.name.clone(), subroutine.name.name.clone());
+            if reached.contains(&key)
+                || (ignore_constructors && subroutine.kind == SubroutineKind::Constructor)
+            {
+                continue;
+            }
+            report.push(DeadKind::Subroutine, file, class, &subroutine.name);
+        }
+
+        for dec in &class.class_vars {
+            for name in &dec.names {
+                if !is_field_used(class, &name.name) {
+                    report.push(DeadKind::Field, file, class, name);
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn is_field_used(class: &Class, name: &str) -> bool {
+    class.subroutines.iter().any(|subroutine| {
+        local_type(subroutine, name).is_none()
+            && uses(&subroutine.statements).iter().any(|u| match u {
+                Use::Var(ident) => ident.name == name,
+                Use::Call(SubroutineCall {
+                    receiver: Some(receiver),
+                    ..
+                }) => receiver.name == name,
+                Use::Call(_) => false,
+            })
+    })
+}
+
+impl DeadCodeReport {
+    fn push(&mut self, kind: DeadKind, file: &Path, class: &Class, name: &Ident) {
+        self.items.push(DeadItem {
+            kind,
+            class: class.name.name.clone(),
+            name: name.name.clone(),
+            file: file.to_path_buf(),
+            span: name.span,
+        });
+    }
+
+    pub fn to_table(&self) -> String {
+        let rows: Vec<(String, String)> = self
+            .items
+            .iter()
+            .map(|item| {
+                (
+                    format!("{}.{}", item.class, item.name),
+                    format!("{}:{}", item.file.display(), item.span),
+                )
+            })
+            .collect();
+        let width = rows
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("name".len());
+
+        let mut out = String::new();
+        writeln!(out, "{:<10}  {:<width$}  location", "kind", "name").unwrap();
+        for (item, (name, location)) in self.items.iter().zip(&rows) {
+            writeln!(
+                out,
+                "{:<10}  {:<width$}  {}",
+                item.kind.label(),
+                name,
+                location
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let items: Vec<String> = self
+            .items
+            .iter()
+            .map(|item| {
+                format!(
+                    "{{\"kind\":\"{}\",\"class\":{},\"name\":{},\"file\":{},\"line\":{},\"column\":{}}}",
+                    item.kind.label(),
+                    json::string(&item.class),
+                    json::string(&item.name),
+                    json::string(&item.file.display().to_string()),
+                    item.span.line,
+                    item.span.column
+                )
+            })
+            .collect();
+
+        format!("[{}]\n", items.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SourceSet;
+
+    use super::{dead_code, DeadKind};
+
+    fn dead(files: &[(&str, &str)], ignore_constructors: bool) -> Vec<(DeadKind, String)> {
+        let mut set = SourceSet::new();
+        for (file, text) in files {
+            set.add(*file, *text);
+        }
+        dead_code(&set, ignore_constructors)
+            .unwrap()
+            .items
+            .into_iter()
+            .map(|item| (item.kind, format!("{}.{}", item.class, item.name)))
+            .collect()
+    }
+
+    #[test]
+    fn unused_and_transitively_unused_subroutines() {
+        let got = dead(
+            &[(
+                "Main.jack",
+                "class Main {
+                function void main() { do Main.used(); do Output.printInt(1); return; }
+                function void used() { return; }
+                function void unused() { do Main.onlyFromUnused(); return; }
+                function void onlyFromUnused() { return; }
+            }",
+            )],
+            false,
+        );
+
+        assert_eq!(
+            got,
+            [
+                (DeadKind::Subroutine, "Main.unused".to_owned()),
+                (DeadKind::Subroutine, "Main.onlyFromUnused".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn method_calls_through_variables_and_unused_fields() {
+        let files = [
+            (
+                "Main.jack",
+                "class Main { function void main() { var Point p;
+                let p = Point.new(); do p.getX(); return; } }",
+            ),
+            (
+                "Point.jack",
+                "class Point { field int x, y; static int unused;
+                constructor Point new() { let x = 0; return this; }
+                constructor Point origin() { return this; }
+                method int getX() { return x; }
+                method int getY() { var int y; let y = 1; return y; } }",
+            ),
+        ];
+
+        assert_eq!(
+            dead(&files, false),
+            [
+                (DeadKind::Subroutine, "Point.origin".to_owned()),
+                (DeadKind::Subroutine, "Point.getY".to_owned()),
+                (DeadKind::Field, "Point.y".to_owned()),
+                (DeadKind::Field, "Point.unused".to_owned()),
+            ]
+        );
+        assert_eq!(dead(&files, true).len(), 3);
+    }
+}
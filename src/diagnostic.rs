@@ -0,0 +1,317 @@
+use std::{fmt, path::Path};
+
+use crate::{engine::SYNTAX_ERROR, token::Span};
+
+// how tabs are shown when the source line of a diagnostic is printed, and
+// how the tokenizers count them in span columns when given one
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TabPolicy {
+    // one column, like any other character
+    #[default]
+    One,
+    // up to the next multiple of the width
+    Stop(usize),
+}
+
+impl TabPolicy {
+    // the display columns `text` takes up when it starts at `column`, counted
+    // from 0
+    fn width(&self, text: &str, column: usize) -> usize {
+        text.chars().fold(column, |c, ch| self.advance(c, ch)) - column
+    }
+
+    // `line` with its tabs replaced by the spaces they take up
+    fn expand(&self, line: &str) -> String {
+        let mut out = String::with_capacity(line.len());
+        let mut column = 0;
+        for ch in line.chars() {
+            let next = self.advance(column, ch);
+            match ch {
+                '\t' => out.extend(std::iter::repeat_n(' ', next - column)),
+                ch => out.push(ch),
+            }
+            column = next;
+        }
+        out
+    }
+
+    // the column after `ch` at `column`, both counted from 0
+    pub(crate) fn advance(&self, column: usize, ch: char) -> usize {
+        match (self, ch) {
+            (Self::Stop(width), '\t') => {
+                let width = (*width).max(1);
+                (column / width + 1) * width
+            }
+            _ => column + 1,
+        }
+    }
+}
+
+// the byte offset the line holding `offset` starts at
+fn line_start(source: &str, offset: usize) -> usize {
+    source[..offset].rfind('\n').map_or(0, |i| i + 1)
+}
+
+// `offset` moved back onto a character of `source`
+fn char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+// the column of the byte `offset` in utf-16 code units, what the language
+// server protocol counts in. like span columns it counts from 1
+pub fn utf16_column(source: &str, offset: usize) -> usize {
+    let offset = char_boundary(source, offset);
+    source[line_start(source, offset)..offset]
+        .encode_utf16()
+        .count()
+        + 1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Note => "note",
+        };
+
+        write!(f, "{}", v)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            code,
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn note(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Note,
+            code,
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl Diagnostic {
+    // the source line of the diagnostic with carets under its span, two more
+    // lines after the header. empty for diagnostics without a position
+    pub fn snippet(&self, source: &str, tabs: TabPolicy) -> String {
+        if self.span.line == 0 {
+            return String::new();
+        }
+
+        let start = char_boundary(source, self.span.start);
+        let line_start = line_start(source, start);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line = source[line_start..line_end].trim_end_matches('\r');
+        let start = start.min(line_start + line.len());
+        let end = char_boundary(source, self.span.end).clamp(start, line_start + line.len());
+
+        let before = tabs.width(&source[line_start..start], 0);
+        let under = tabs.width(&source[start..end], before).max(1);
+        let number = self.span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        format!(
+            "{} |\n{} | {}\n{} | {}{}",
+            gutter,
+            number,
+            tabs.expand(line),
+            gutter,
+            " ".repeat(before),
+            "^".repeat(under)
+        )
+    }
+
+    // `file:line:col: severity[code]: message`, as compilers usually print it
+    pub fn in_file(&self, file: &Path) -> String {
+        if self.span.line > 0 {
+            format!("{}:{}", file.display(), self)
+        } else {
+            format!("{}: {}", file.display(), self)
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // diagnostics without a known position have a zero line
+        if self.span.line > 0 {
+            write!(f, "{}: ", self.span)?;
+        }
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+// a failed compile as a concrete error type, for callers with error enums of
+// their own rather than anyhow. the diagnostic is what check would report.
+// when the tokenizer stopped the parser, its error is the source, with the
+// reason behind it as that error's source in turn
+#[derive(Debug)]
+pub struct CompileError {
+    pub diagnostic: Diagnostic,
+    cause: Option<anyhow::Error>,
+}
+
+impl From<Diagnostic> for CompileError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self {
+            diagnostic,
+            cause: None,
+        }
+    }
+}
+
+// lex errors aren't diagnostics yet, they only carry their position in the
+// message
+impl From<anyhow::Error> for CompileError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<Diagnostic>() {
+            Ok(diagnostic) => diagnostic.into(),
+            Err(e) => Self {
+                diagnostic: Diagnostic::error(SYNTAX_ERROR, format!("{:#}", e), Span::default()),
+                cause: Some(e),
+            },
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.diagnostic.fmt(f)
+    }
+}
+
+impl std::error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        let cause: &(dyn std::error::Error + Send + Sync + 'static) = self.cause.as_ref()?.as_ref();
+        Some(cause)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Parser;
+
+    use super::{utf16_column, CompileError, Diagnostic, TabPolicy};
+
+    // tabs and characters of two, three and four bytes before the error
+    const SOURCE: &str =
+        "class A {\n\tfunction void f() {\n\t\tlet s = \"é€😀\";\tlet = 1;\n\t}\n}\n";
+
+    fn error() -> Diagnostic {
+        let err = Parser::from_source(SOURCE).parse_class().unwrap_err();
+        err.downcast::<Diagnostic>().unwrap()
+    }
+
+    #[test]
+    fn columns_count_characters_and_offsets_bytes() {
+        let span = error().span;
+
+        // 2 tabs, `let s = "`, 3 characters and `";`, a tab and `let `
+        assert_eq!((span.line, span.column), (3, 22));
+        assert_eq!(&SOURCE[span.start..span.end], "=");
+        assert_eq!(span.start, 58);
+        // the emoji is two utf-16 code units
+        assert_eq!(utf16_column(SOURCE, span.start), 23);
+        assert_eq!(utf16_column(SOURCE, 0), 1);
+    }
+
+    #[test]
+    fn carets_follow_the_tab_policy() {
+        let diagnostic = error();
+
+        assert_eq!(
+            diagnostic.snippet(SOURCE, TabPolicy::One),
+            "  |\n3 |   let s = \"é€😀\"; let = 1;\n  |                      ^"
+        );
+        assert_eq!(
+            diagnostic.snippet(SOURCE, TabPolicy::Stop(4)),
+            "  |\n3 |         let s = \"é€😀\";  let = 1;\n  |                             ^"
+        );
+        let unplaced = Diagnostic::error("J000", "no position", Default::default());
+        assert_eq!(unplaced.snippet(SOURCE, TabPolicy::One), "");
+    }
+
+    #[test]
+    fn compile_errors_chain_to_the_lex_error() {
+        use std::error::Error;
+
+        // what a library with its own error type would do
+        #[derive(Debug)]
+        enum AppError {
+            Compile(CompileError),
+        }
+        impl From<CompileError> for AppError {
+            fn from(e: CompileError) -> Self {
+                Self::Compile(e)
+            }
+        }
+        fn parse(source: &str) -> Result<crate::ast::Class, AppError> {
+            Ok(Parser::from_source(source)
+                .parse_class()
+                .map_err(CompileError::from)?)
+        }
+
+        let AppError::Compile(err) =
+            parse("class Main { function void main() { let a = #; return; } }").unwrap_err();
+        assert_eq!(err.diagnostic.code, "J003");
+        let mut chain = Vec::new();
+        let mut cause = err.source();
+        while let Some(e) = cause {
+            chain.push(e.to_string());
+            cause = e.source();
+        }
+        assert_eq!(
+            chain.last().map(String::as_str),
+            Some("unknown character"),
+            "{:?}",
+            chain
+        );
+
+        // the parser's own errors have no cause
+        let AppError::Compile(err) =
+            parse("class Main { function void main() { let = 1; return; } }").unwrap_err();
+        assert_eq!(err.diagnostic.code, "J001");
+        assert!(err.source().is_none());
+        assert_eq!(err.to_string(), err.diagnostic.to_string());
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    fmt::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+// lines of unchanged text kept around every change
+const CONTEXT: usize = 3;
+
+// past this many edits the changed region is shown as removed and added
+// whole, the search keeps every round of edits in memory
+const MAX_EDITS: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStatus {
+    New,
+    Changed,
+    Unchanged,
+}
+
+impl fmt::Display for DiffStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            DiffStatus::New => "new",
+            DiffStatus::Changed => "changed",
+            DiffStatus::Unchanged => "unchanged",
+        })
+    }
+}
+
+// how an output would change if it was written again. the diff is empty
+// unless the output is `Changed`
+#[derive(Debug, Clone)]
+pub struct OutputDiff {
+    pub path: PathBuf,
+    pub status: DiffStatus,
+    pub diff: String,
+}
+
+impl OutputDiff {
+    // compares `output` against what `path` holds now, a file written with
+    // `\r\n` line endings is the same as one with `\n`
+    pub fn of(path: &Path, output: &str) -> Result<Self> {
+        let old = match std::fs::read(path) {
+            Ok(old) => Some(String::from_utf8_lossy(&old).replace("\r\n", "\n")),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("{}", path.display())),
+        };
+        let (status, diff) = match old {
+            None => (DiffStatus::New, String::new()),
+            Some(old) if old == output.replace("\r\n", "\n") => {
+                (DiffStatus::Unchanged, String::new())
+            }
+            Some(old) => {
+                let name = path.display().to_string();
+                let diff = unified_diff(&old, output, &name, &name);
+                (DiffStatus::Changed, diff)
+            }
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            status,
+            diff,
+        })
+    }
+}
+
+// one `status path` line per output, followed by the diff of the changed ones
+pub fn diff_report(diffs: &[OutputDiff]) -> String {
+    let mut out = String::new();
+    for diff in diffs {
+        writeln!(out, "{:<9}  {}", diff.status, diff.path.display()).unwrap();
+        out.push_str(&diff.diff);
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Keep,
+    Delete,
+    Insert,
+}
+
+// the lines of `old` and `new` as a unified diff with three lines of context,
+// empty when they have the same lines
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let edits = edits(&a, &b);
+
+    let changes: Vec<usize> = (0..edits.len())
+        .filter(|&i| edits[i] != Edit::Keep)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // the line of a and b each edit starts at
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut x, mut y) = (0, 0);
+    for edit in &edits {
+        positions.push((x, y));
+        match edit {
+            Edit::Keep => (x, y) = (x + 1, y + 1),
+            Edit::Delete => x += 1,
+            Edit::Insert => y += 1,
+        }
+    }
+    positions.push((x, y));
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let mut i = 0;
+    while i < changes.len() {
+        // changes with at most twice the context between them share a hunk
+        let first = changes[i];
+        let mut last = first;
+        while i + 1 < changes.len() && changes[i + 1] - last <= 2 * CONTEXT + 1 {
+            i += 1;
+            last = changes[i];
+        }
+        i += 1;
+        let lo = first.saturating_sub(CONTEXT);
+        let hi = (last + 1 + CONTEXT).min(edits.len());
+
+        let (old_start, new_start) = positions[lo];
+        let (old_end, new_end) = positions[hi];
+        writeln!(
+            out,
+            "@@ -{} +{} @@",
+            range(old_start, old_end - old_start),
+            range(new_start, new_end - new_start)
+        )
+        .unwrap();
+        for (edit, &(x, y)) in edits[lo..hi].iter().zip(&positions[lo..hi]) {
+            match edit {
+                Edit::Keep => writeln!(out, " {}", a[x]),
+                Edit::Delete => writeln!(out, "-{}", a[x]),
+                Edit::Insert => writeln!(out, "+{}", b[y]),
+            }
+            .unwrap();
+        }
+    }
+    out
+}
+
+// a hunk header range, an empty range names the line before it
+fn range(start: usize, length: usize) -> String {
+    match length {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, length),
+    }
+}
+
+// the shortest edit script turning a into b, by myers' algorithm. the lines
+// both start and end with are kept without searching
+fn edits(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut edits = vec![Edit::Keep; prefix];
+    match shortest_edits(a_mid, b_mid) {
+        Some(middle) => edits.extend(middle),
+        None => {
+            edits.extend(std::iter::repeat_n(Edit::Delete, a_mid.len()));
+            edits.extend(std::iter::repeat_n(Edit::Insert, b_mid.len()));
+        }
+    }
+    edits.extend(std::iter::repeat_n(Edit::Keep, suffix));
+    edits
+}
+
+fn shortest_edits(a: &[&str], b: &[&str]) -> Option<Vec<Edit>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // the furthest x of the diagonals -d - 1 to d + 1 before each round d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        if d as usize > MAX_EDITS {
+            return None;
+        }
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let down =
+                k == -d || (k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize]);
+            let mut x = match down {
+                true => v[(offset + k + 1) as usize],
+                false => v[(offset + k - 1) as usize] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(offset + k) as usize] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // walks the rounds back from the end, one edit and a run of kept lines
+    // per round
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = match k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(if x == prev_x {
+                Edit::Insert
+            } else {
+                Edit::Delete
+            });
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    edits.reverse();
+    Some(edits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edits, unified_diff, Edit};
+
+    #[test]
+    fn hunks_keep_three_lines_of_context() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\nn\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\nn\no\n";
+
+        assert_eq!(
+            unified_diff(old, new, "old", "new"),
+            "--- old\n+++ new\n\
+             @@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n\
+             @@ -12,3 +12,4 @@\n l\n m\n n\n+o\n"
+        );
+        assert_eq!(
+            unified_diff("x\n", "", "old", "new"),
+            "--- old\n+++ new\n@@ -1 +0,0 @@\n-x\n"
+        );
+        assert_eq!(unified_diff(old, old, "old", "new"), "");
+    }
+
+    #[test]
+    fn edits_are_minimal() {
+        for (a, b, length) in [
+            ("abcabba", "cbabac", 5),
+            ("", "abc", 3),
+            ("abc", "", 3),
+            ("xaxbx", "ab", 3),
+            ("same", "same", 0),
+        ] {
+            let a: Vec<String> = a.chars().map(String::from).collect();
+            let b: Vec<String> = b.chars().map(String::from).collect();
+            let a: Vec<&str> = a.iter().map(String::as_str).collect();
+            let b: Vec<&str> = b.iter().map(String::as_str).collect();
+            let edits = edits(&a, &b);
+
+            // applying the edits to a gives b
+            let (mut x, mut y, mut got) = (0, 0, Vec::new());
+            for edit in &edits {
+                match edit {
+                    Edit::Keep => {
+                        assert_eq!(a[x], b[y]);
+                        got.push(a[x]);
+                        (x, y) = (x + 1, y + 1);
+                    }
+                    Edit::Delete => x += 1,
+                    Edit::Insert => {
+                        got.push(b[y]);
+                        y += 1;
+                    }
+                }
+            }
+            assert_eq!((x, got), (a.len(), b.clone()));
+            let changed = edits.iter().filter(|&&e| e != Edit::Keep).count();
+            assert_eq!(changed, length, "{:?} to {:?}", a, b);
+        }
+    }
+}
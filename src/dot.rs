@@ -0,0 +1,238 @@
+use std::fmt::Write;
+
+use crate::{
+    ast::{
+        Class, ClassVarKind, Expression, ExpressionKind, Statement, SubroutineCall, SubroutineDec,
+        SubroutineKind,
+    },
+    format::operator,
+    json,
+};
+
+// the syntax tree of a class as a graphviz digraph. constructs are drawn as
+// ellipses named like the xml tags, tokens as plain text leaves holding their
+// lexeme
+pub fn ast_to_dot(class: &Class) -> String {
+    let mut graph = DotGraph {
+        out: String::new(),
+        nodes: 0,
+    };
+    writeln!(graph.out, "digraph {} {{", json::string(&class.name.name)).unwrap();
+    graph.class(class);
+    graph.out.push_str("}\n");
+    graph.out
+}
+
+struct DotGraph {
+    out: String,
+    nodes: usize,
+}
+
+impl DotGraph {
+    fn node(&mut self, parent: Option<usize>, label: &str, shape: &str) -> usize {
+        let id = self.nodes;
+        self.nodes += 1;
+        writeln!(
+            self.out,
+            "  N{} [shape={}, label={}];",
+            id,
+            shape,
+            json::string(label)
+        )
+        .unwrap();
+        if let Some(parent) = parent {
+            writeln!(self.out, "  N{} -> N{};", parent, id).unwrap();
+        }
+        id
+    }
+
+    fn construct(&mut self, parent: usize, name: &str) -> usize {
+        self.node(Some(parent), name, "ellipse")
+    }
+
+    fn leaf(&mut self, parent: usize, lexeme: &str) {
+        self.node(Some(parent), lexeme, "plaintext");
+    }
+
+    fn class(&mut self, class: &Class) {
+        let root = self.node(None, "class", "ellipse");
+        self.leaf(root, &class.name.name);
+
+        for dec in &class.class_vars {
+            let id = self.construct(root, "classVarDec");
+            let kind = match dec.kind {
+                ClassVarKind::Static => "static",
+                ClassVarKind::Field => "field",
+            };
+            self.leaf(id, kind);
+            self.leaf(id, &dec.var_type.to_string());
+            for name in &dec.names {
+                self.leaf(id, &name.name);
+            }
+        }
+        for subroutine in &class.subroutines {
+            self.subroutine(root, subroutine);
+        }
+    }
+
+    fn subroutine(&mut self, parent: usize, subroutine: &SubroutineDec) {
+        let id = self.construct(parent, "subroutineDec");
+        let kind = match subroutine.kind {
+            SubroutineKind::Constructor => "constructor",
+            SubroutineKind::Function => "function",
+            SubroutineKind::Method => "method",
+        };
+        self.leaf(id, kind);
+        self.leaf(id, &subroutine.return_type.to_string());
+        self.leaf(id, &subroutine.name.name);
+
+        let params = self.construct(id, "parameterList");
+        for param in &subroutine.params {
+            self.leaf(params, &param.var_type.to_string());
+            self.leaf(params, &param.name.name);
+        }
+
+        let body = self.construct(id, "subroutineBody");
+        for dec in &subroutine.locals {
+            let var_dec = self.construct(body, "varDec");
+            self.leaf(var_dec, &dec.var_type.to_string());
+            for name in &dec.names {
+                self.leaf(var_dec, &name.name);
+            }
+        }
+        self.statements(body, &subroutine.statements);
+    }
+
+    fn statements(&mut self, parent: usize, statements: &[Statement]) {
+        let id = self.construct(parent, "statements");
+        for statement in statements {
+            self.statement(id, statement);
+        }
+    }
+
+    fn statement(&mut self, parent: usize, statement: &Statement) {
+        match statement {
+            Statement::Let {
+                target,
+                index,
+                value,
+                ..
+            } => {
+                let id = self.construct(parent, "letStatement");
+                self.leaf(id, &target.name);
+                if let Some(index) = index {
+                    self.expression(id, index);
+                }
+                self.expression(id, value);
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let id = self.construct(parent, "ifStatement");
+                self.expression(id, condition);
+                self.statements(id, then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statements(id, else_branch);
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                let id = self.construct(parent, "whileStatement");
+                self.expression(id, condition);
+                self.statements(id, body);
+            }
+            Statement::Do { call, .. } => {
+                let id = self.construct(parent, "doStatement");
+                self.call(id, call);
+            }
+            Statement::Return { value, .. } => {
+                let id = self.construct(parent, "returnStatement");
+                if let Some(value) = value {
+                    self.expression(id, value);
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self, parent: usize, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::IntConst(i) => self.leaf(parent, &i.to_string()),
+            ExpressionKind::StringConst(s) => self.leaf(parent, &format!("\"{}\"", s)),
+            ExpressionKind::KeywordConst(keyword) => self.leaf(parent, &keyword.to_string()),
+            ExpressionKind::Var(ident) => self.leaf(parent, &ident.name),
+            ExpressionKind::Index(ident, index) => {
+                let id = self.construct(parent, "index");
+                self.leaf(id, &ident.name);
+                self.expression(id, index);
+            }
+            ExpressionKind::Call(call) => self.call(parent, call),
+            ExpressionKind::Paren(inner) => self.expression(parent, inner),
+            ExpressionKind::Unary(op, operand) => {
+                let id = self.construct(parent, "term");
+                self.leaf(id, operator(op));
+                self.expression(id, operand);
+            }
+            ExpressionKind::Binary(op, lhs, rhs) => {
+                let id = self.construct(parent, "expression");
+                self.expression(id, lhs);
+                self.leaf(id, operator(op));
+                self.expression(id, rhs);
+            }
+        }
+    }
+
+    fn call(&mut self, parent: usize, call: &SubroutineCall) {
+        let id = self.construct(parent, "subroutineCall");
+        let name = match &call.receiver {
+            Some(receiver) => format!("{}.{}", receiver.name, call.name.name),
+            None => call.name.name.clone(),
+        };
+        self.leaf(id, &name);
+
+        let args = self.construct(id, "expressionList");
+        for arg in &call.args {
+            self.expression(args, arg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Parser;
+
+    use super::ast_to_dot;
+
+    #[test]
+    fn dot_output_is_a_digraph() {
+        let class = Parser::from_source(
+            "class Main { field int x; method void f(int a) {
+                let x = -(a + 1); do Output.printString(\"hi\"); return; } }",
+        )
+        .parse_class()
+        .unwrap();
+        let dot = ast_to_dot(&class);
+
+        assert!(dot.starts_with("digraph \"Main\" {\n"), "{}", dot);
+        assert!(
+            dot.contains("  N0 [shape=ellipse, label=\"class\"];"),
+            "{}",
+            dot
+        );
+        assert!(
+            dot.contains("[shape=plaintext, label=\"\\\"hi\\\"\"];"),
+            "{}",
+            dot
+        );
+        assert!(dot.trim_end().ends_with('}'));
+        assert_eq!(dot.matches('{').count(), dot.matches('}').count());
+
+        // one edge into every node but the root
+        let nodes = dot.lines().filter(|l| l.contains("[shape=")).count();
+        let edges = dot.lines().filter(|l| l.contains(" -> ")).count();
+        assert_eq!(edges, nodes - 1);
+    }
+}
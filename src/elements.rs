@@ -1,5 +1,5 @@
 pub mod lexical_elements {
-    use std::str::FromStr;
+    use std::{fmt, str::FromStr};
 
     use anyhow::anyhow;
 
@@ -26,6 +26,8 @@ pub mod lexical_elements {
         Else,
         While,
         Return,
+        // only a keyword with the for loop extension, see KeywordSet
+        For,
     }
 
     impl FromStr for Keywords {
@@ -61,31 +63,34 @@ pub mod lexical_elements {
         }
     }
 
-    impl ToString for Keywords {
-        fn to_string(&self) -> String {
-            match self {
-                Self::Class => "class".to_owned(),
-                Self::Constructor => "constructor".to_owned(),
-                Self::Function => "function".to_owned(),
-                Self::Method => "method".to_owned(),
-                Self::Field => "field".to_owned(),
-                Self::Static => "static".to_owned(),
-                Self::Var => "var".to_owned(),
-                Self::Int => "int".to_owned(),
-                Self::Char => "char".to_owned(),
-                Self::Boolean => "boolean".to_owned(),
-                Self::Void => "void".to_owned(),
-                Self::True => "true".to_owned(),
-                Self::False => "false".to_owned(),
-                Self::Null => "null".to_owned(),
-                Self::This => "this".to_owned(),
-                Self::Let => "let".to_owned(),
-                Self::Do => "do".to_owned(),
-                Self::If => "if".to_owned(),
-                Self::Else => "else".to_owned(),
-                Self::While => "while".to_owned(),
-                Self::Return => "return".to_owned(),
-            }
+    impl fmt::Display for Keywords {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let v = match self {
+                Self::Class => "class",
+                Self::Constructor => "constructor",
+                Self::Function => "function",
+                Self::Method => "method",
+                Self::Field => "field",
+                Self::Static => "static",
+                Self::Var => "var",
+                Self::Int => "int",
+                Self::Char => "char",
+                Self::Boolean => "boolean",
+                Self::Void => "void",
+                Self::True => "true",
+                Self::False => "false",
+                Self::Null => "null",
+                Self::This => "this",
+                Self::Let => "let",
+                Self::Do => "do",
+                Self::If => "if",
+                Self::Else => "else",
+                Self::While => "while",
+                Self::Return => "return",
+                Self::For => "for",
+            };
+
+            write!(f, "{}", v)
         }
     }
 
@@ -95,6 +100,75 @@ pub mod lexical_elements {
         }
     }
 
+    impl Keywords {
+        // int, char and boolean, a class name is the other kind of type
+        pub fn is_type_keyword(&self) -> bool {
+            matches!(self, Self::Int | Self::Char | Self::Boolean)
+        }
+
+        // the statements of the base language, `for` is only one with its
+        // extension, see KeywordSet::statement_keywords
+        pub fn is_statement_keyword(&self) -> bool {
+            matches!(
+                self,
+                Self::Let | Self::If | Self::While | Self::Do | Self::Return
+            )
+        }
+
+        pub fn is_subroutine_keyword(&self) -> bool {
+            matches!(self, Self::Constructor | Self::Function | Self::Method)
+        }
+
+        pub fn is_class_var_keyword(&self) -> bool {
+            matches!(self, Self::Static | Self::Field)
+        }
+
+        pub fn is_keyword_constant(&self) -> bool {
+            matches!(self, Self::True | Self::False | Self::Null | Self::This)
+        }
+    }
+
+    // the words a tokenizer reads as keywords. `Keywords::from_str` is the
+    // base language, words added by extensions are only keywords while the
+    // extension is on so older programs can keep using them as names
+    #[derive(Debug, Clone, Default)]
+    pub struct KeywordSet {
+        for_loops: bool,
+    }
+
+    impl KeywordSet {
+        pub fn base() -> Self {
+            Self::default()
+        }
+
+        pub fn for_loops(mut self, enabled: bool) -> Self {
+            self.for_loops = enabled;
+            self
+        }
+
+        pub fn lookup(&self, word: &str) -> Option<Keywords> {
+            match word {
+                "for" if self.for_loops => Some(Keywords::For),
+                _ => Keywords::from_str(word).ok(),
+            }
+        }
+
+        // the keywords a statement can start with
+        pub fn statement_keywords(&self) -> Vec<Keywords> {
+            let mut keywords = vec![
+                Keywords::Let,
+                Keywords::If,
+                Keywords::While,
+                Keywords::Do,
+                Keywords::Return,
+            ];
+            if self.for_loops {
+                keywords.push(Keywords::For);
+            }
+            keywords
+        }
+    }
+
     #[derive(Debug, Clone)]
     pub enum Symbols {
         OpenCurlyBrace,
@@ -149,29 +223,31 @@ pub mod lexical_elements {
         }
     }
 
-    impl ToString for Symbols {
-        fn to_string(&self) -> String {
-            match self {
-                Self::OpenCurlyBrace => "{".to_owned(),
-                Self::CloseCurlyBrace => "}".to_owned(),
-                Self::OpenBrace => "(".to_owned(),
-                Self::CloseBrace => ")".to_owned(),
-                Self::OpenSquareBrace => "[".to_owned(),
-                Self::CloseSquareBrace => "]".to_owned(),
-                Self::Dot => ".".to_owned(),
-                Self::Comma => ",".to_owned(),
-                Self::SemiColon => ";".to_owned(),
-                Self::Plus => "+".to_owned(),
-                Self::Minus => "-".to_owned(),
-                Self::Asterik => "*".to_owned(),
-                Self::BackSlash => "/".to_owned(),
-                Self::Ampersand => "&".to_owned(),
-                Self::VerticalBar => "|".to_owned(),
-                Self::LessThan => "&lt;".to_owned(),
-                Self::GreaterThan => "&gt;".to_owned(),
-                Self::Equal => "=".to_owned(),
-                Self::Tilde => "~".to_owned(),
-            }
+    impl fmt::Display for Symbols {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let v = match self {
+                Self::OpenCurlyBrace => "{",
+                Self::CloseCurlyBrace => "}",
+                Self::OpenBrace => "(",
+                Self::CloseBrace => ")",
+                Self::OpenSquareBrace => "[",
+                Self::CloseSquareBrace => "]",
+                Self::Dot => ".",
+                Self::Comma => ",",
+                Self::SemiColon => ";",
+                Self::Plus => "+",
+                Self::Minus => "-",
+                Self::Asterik => "*",
+                Self::BackSlash => "/",
+                Self::Ampersand => "&",
+                Self::VerticalBar => "|",
+                Self::LessThan => "&lt;",
+                Self::GreaterThan => "&gt;",
+                Self::Equal => "=",
+                Self::Tilde => "~",
+            };
+
+            write!(f, "{}", v)
         }
     }
 
@@ -180,4 +256,117 @@ pub mod lexical_elements {
             self.to_string() == other.to_string()
         }
     }
+
+    impl Symbols {
+        // the `}`, `)` or `]` closing this symbol, if it opens anything
+        pub fn closing_partner(&self) -> Option<Symbols> {
+            match self {
+                Self::OpenCurlyBrace => Some(Self::CloseCurlyBrace),
+                Self::OpenBrace => Some(Self::CloseBrace),
+                Self::OpenSquareBrace => Some(Self::CloseSquareBrace),
+                _ => None,
+            }
+        }
+
+        // the `{`, `(` or `[` this symbol closes, if it closes anything
+        pub fn opening_partner(&self) -> Option<Symbols> {
+            match self {
+                Self::CloseCurlyBrace => Some(Self::OpenCurlyBrace),
+                Self::CloseBrace => Some(Self::OpenBrace),
+                Self::CloseSquareBrace => Some(Self::OpenSquareBrace),
+                _ => None,
+            }
+        }
+
+        pub fn is_opening(&self) -> bool {
+            self.closing_partner().is_some()
+        }
+
+        pub fn is_closing(&self) -> bool {
+            self.opening_partner().is_some()
+        }
+
+        pub fn is_delimiter(&self) -> bool {
+            self.is_opening() || self.is_closing()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::str::FromStr;
+
+        use super::{Keywords, Symbols};
+
+        // the words of the base language `classify` holds for
+        fn words(classify: fn(&Keywords) -> bool) -> Vec<String> {
+            [
+                "class",
+                "constructor",
+                "function",
+                "method",
+                "field",
+                "static",
+                "var",
+                "int",
+                "char",
+                "boolean",
+                "void",
+                "true",
+                "false",
+                "null",
+                "this",
+                "let",
+                "do",
+                "if",
+                "else",
+                "while",
+                "return",
+            ]
+            .into_iter()
+            .map(|word| Keywords::from_str(word).unwrap())
+            .filter(classify)
+            .map(|keyword| keyword.to_string())
+            .collect()
+        }
+
+        #[test]
+        fn keyword_classes() {
+            assert_eq!(words(Keywords::is_type_keyword), ["int", "char", "boolean"]);
+            assert_eq!(
+                words(Keywords::is_statement_keyword),
+                ["let", "do", "if", "while", "return"]
+            );
+            assert_eq!(
+                words(Keywords::is_subroutine_keyword),
+                ["constructor", "function", "method"]
+            );
+            assert_eq!(words(Keywords::is_class_var_keyword), ["field", "static"]);
+            assert_eq!(
+                words(Keywords::is_keyword_constant),
+                ["true", "false", "null", "this"]
+            );
+            assert!(!Keywords::For.is_statement_keyword());
+        }
+
+        #[test]
+        fn delimiter_partners() {
+            assert_eq!(
+                Symbols::OpenCurlyBrace.closing_partner(),
+                Some(Symbols::CloseCurlyBrace)
+            );
+            assert_eq!(
+                Symbols::CloseSquareBrace.opening_partner(),
+                Some(Symbols::OpenSquareBrace)
+            );
+            assert_eq!(Symbols::CloseBrace.closing_partner(), None);
+            assert!(Symbols::OpenBrace.is_delimiter());
+        }
+
+        #[test]
+        fn operators_are_not_delimiters() {
+            assert_eq!(Symbols::Plus.closing_partner(), None);
+            assert_eq!(Symbols::Plus.opening_partner(), None);
+            assert!(!Symbols::LessThan.is_delimiter());
+        }
+    }
 }
@@ -1,204 +1,940 @@
 use crate::{
-    lexical_elements::{Keywords, Symbols},
-    Token,
+    ast::JackType,
+    balance::BracketTracker,
+    diagnostic::Diagnostic,
+    lexical_elements::{KeywordSet, Keywords, Symbols},
+    listener::{ParseEvent, ParseListener},
+    parser::{UNEXPECTED_EOF, UNEXPECTED_TOKEN},
+    token::Span,
+    Parser, StreamTokenizer, SymbolKind, Token,
 };
 use anyhow::{anyhow, bail, Context, Result};
 use peekmore::{PeekMore, PeekMoreIterator};
 use std::io::Write;
 
+pub const INTERNAL_ERROR: &str = "J000";
+pub const SYNTAX_ERROR: &str = "J003";
+pub const TOO_MANY_ERRORS: &str = "J004";
+
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    // flush the writer after every subroutine so partial output can be
+    // followed while a large file is compiled
+    pub flush_per_subroutine: bool,
+    // what compile_collecting writes in place of a subroutine with errors
+    pub failed_subroutines: FailedSubroutines,
+    // accept a lone `;` as a statement, written as `<emptyStatement/>`
+    pub allow_empty_statements: bool,
+    // accept `this.f()` as the call `f()` and write it without the receiver,
+    // otherwise it is an error suggesting the bare call
+    pub allow_this_receiver: bool,
+    // add `start` and `end` byte offsets to every non-terminal's opening tag,
+    // only when the engine was given the token spans. the output is held back
+    // until the class is done
+    pub span_attributes: bool,
+    // start with an xml declaration and escape every `&`, `<` and `>`, so
+    // the output is a document xml parsers take. otherwise strings and
+    // the `&` symbol are written as they are
+    pub well_formed_xml: bool,
+    // every tag starts its own line and nothing else does: no blank lines
+    // between elements and none at the top. the default follows the
+    // reference files
+    pub normalize: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FailedSubroutines {
+    // an `<invalidSubroutineDec>` naming the subroutine
+    #[default]
+    Placeholder,
+    Omit,
+}
+
+// a variable declared in a class or a subroutine, with its declared type
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledVar {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub var_type: JackType,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompiledSubroutine {
+    pub name: String,
+    pub failed: bool,
+    // unknown when the subroutine failed before it
+    pub return_type: Option<JackType>,
+    // the parameters and then the locals, as far as they were compiled
+    pub vars: Vec<CompiledVar>,
+}
+
+// the source a non-terminal was compiled from, the byte range from its first
+// token to its last
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementSpan {
+    pub tag: &'static str,
+    pub start: usize,
+    pub end: usize,
+}
+
+// a non-terminal while it is written. `at` is where its attributes go in the
+// output, `first` the index of its first token
+struct Element {
+    tag: &'static str,
+    at: usize,
+    first: usize,
+    end: Option<usize>,
+}
+
+// the class variables and subroutines the engine went through, in source
+// order. a subroutine only fails under compile_collecting, compile stops at
+// the first error instead
+#[derive(Debug, Clone, Default)]
+pub struct CompiledClassInfo {
+    pub class_vars: Vec<CompiledVar>,
+    pub subroutines: Vec<CompiledSubroutine>,
+}
+
+impl CompiledClassInfo {
+    // the declared return type of the subroutine called `name`
+    pub fn return_type(&self, name: &str) -> Option<&JackType> {
+        self.subroutines
+            .iter()
+            .find(|s| s.name == name)
+            .and_then(|s| s.return_type.as_ref())
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &str> {
+        self.subroutines
+            .iter()
+            .filter(|s| s.failed)
+            .map(|s| s.name.as_str())
+    }
+}
+
+// the tokens the engine reads, with room for one read token to be put back
+// and read again. peeking looks at the put back token first
+struct TokenStream<I: Iterator<Item = Result<Token>>> {
+    tokens: PeekMoreIterator<I>,
+    // always an `Ok`, held as an item so peeking can hand out a reference
+    pushed: Option<Result<Token>>,
+}
+
+impl<I: Iterator<Item = Result<Token>>> TokenStream<I> {
+    fn new(tokens: I) -> Self {
+        Self {
+            tokens: tokens.peekmore(),
+            pushed: None,
+        }
+    }
+
+    fn next(&mut self) -> Option<Result<Token>> {
+        self.pushed.take().or_else(|| self.tokens.next())
+    }
+
+    fn peek(&mut self) -> Option<&Result<Token>> {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<&Result<Token>> {
+        match (n, self.pushed.is_some()) {
+            (0, true) => self.pushed.as_ref(),
+            (n, true) => self.tokens.peek_nth(n - 1),
+            (n, false) => self.tokens.peek_nth(n),
+        }
+    }
+
+    // makes `token` the next one read. only the token just read may be put
+    // back, and only one at a time
+    fn unget(&mut self, token: Token) {
+        assert!(self.pushed.is_none(), "a token was already put back");
+        self.pushed = Some(Ok(token));
+    }
+}
+
 pub struct CompilationEngine<'a, T: Iterator<Item = Result<Token>>> {
     writer: &'a mut dyn Write,
-    tokenizer: PeekMoreIterator<&'a mut T>,
+    options: CompileOptions,
+    tokenizer: TokenStream<&'a mut T>,
+    has_written: bool,
+    consumed: usize,
+    // error recovery, only used by compile_collecting
+    recover: bool,
+    max_errors: usize,
+    errors: Vec<Diagnostic>,
+    aborted: bool,
+    // output of the subroutine being compiled, held back until it is known
+    // to have compiled cleanly
+    buffer: Option<Vec<u8>>,
+    info: CompiledClassInfo,
+    // the subroutine being compiled, moved into `info` once it is done
+    subroutine: Option<CompiledSubroutine>,
+    // the span of every token the tokenizer yields, in order, when known
+    token_spans: Option<Vec<Span>>,
+    elements: Vec<Element>,
+    // indices into `elements` of the non-terminals not closed yet
+    open: Vec<usize>,
+    // everything written, when held back for the span attributes
+    out: Vec<u8>,
+    // the most bytes held back at once, in the buffer and `out` together
+    peak_buffer: usize,
+    // fed every token read, only when the token spans are known
+    brackets: BracketTracker,
+    // what the tokenizer reads as keywords, for suggestions
+    keywords: KeywordSet,
+    // the class, subroutine and statements being compiled, outermost first,
+    // named in the errors recovery records
+    context: Vec<Scope>,
+    // the tags written and not closed yet, innermost last. output that
+    // doesn't nest is a bug in the engine, it panics in debug builds and
+    // is an internal error otherwise
+    tags: Vec<&'static str>,
+    panic_on_bad_nesting: bool,
+    nesting_error: Option<Diagnostic>,
+    // tests break the nesting on purpose by closing the first tag with a
+    // different name
+    #[cfg(test)]
+    misnest: Option<(&'static str, &'static str)>,
+    // outputs written from the same run, with the error that stopped each
+    listeners: Vec<(&'a mut dyn ParseListener, Option<std::io::Error>)>,
+    // the events of the subroutine in the buffer, given to the listeners
+    // with its output
+    held_events: Vec<ParseEvent>,
+}
+
+#[derive(Debug, Clone)]
+enum Scope {
+    Class(String),
+    Subroutine(String),
+    // the position of a statement in its block, from 1
+    Statement(usize),
 }
 
 impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
     pub fn new<W: Write>(writer: &'a mut W, tokenizer: &'a mut T) -> Self {
-        let peekable = tokenizer.peekmore();
         Self {
             writer,
-            tokenizer: peekable,
+            options: CompileOptions::default(),
+            tokenizer: TokenStream::new(tokenizer),
+            has_written: false,
+            consumed: 0,
+            recover: false,
+            max_errors: 0,
+            errors: Vec::new(),
+            aborted: false,
+            buffer: None,
+            info: CompiledClassInfo::default(),
+            subroutine: None,
+            token_spans: None,
+            elements: Vec::new(),
+            open: Vec::new(),
+            out: Vec::new(),
+            peak_buffer: 0,
+            brackets: BracketTracker::new(),
+            keywords: KeywordSet::base(),
+            context: Vec::new(),
+            tags: Vec::new(),
+            panic_on_bad_nesting: cfg!(debug_assertions),
+            nesting_error: None,
+            #[cfg(test)]
+            misnest: None,
+            listeners: Vec::new(),
+            held_events: Vec::new(),
         }
     }
 
-    fn write_tagged(&mut self, token_name: &str, value: &str) {
+    // the spans of the tokens `tokenizer` is going to yield, so the source
+    // range of each non-terminal can be recorded
+    pub fn with_token_spans(mut self, spans: Vec<Span>) -> Self {
+        self.token_spans = Some(spans);
+        self
+    }
+
+    // the keyword set the tokenizer was built with
+    pub fn with_keywords(mut self, keywords: KeywordSet) -> Self {
+        self.keywords = keywords;
+        self
+    }
+
+    pub fn with_options(mut self, options: CompileOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // another output of the run, given everything the engine writes as
+    // events. the listeners are finished when compiling is done
+    pub fn with_listener(mut self, listener: &'a mut dyn ParseListener) -> Self {
+        self.listeners.push((listener, None));
+        self
+    }
+
+    // the error each listener failed with, in the order they were added
+    pub fn listener_errors(&mut self) -> Vec<Option<std::io::Error>> {
+        self.listeners
+            .iter_mut()
+            .map(|(_, error)| error.take())
+            .collect()
+    }
+
+    pub fn class_info(&self) -> &CompiledClassInfo {
+        &self.info
+    }
+
+    // the tokens compiled so far
+    pub fn tokens_consumed(&self) -> usize {
+        self.consumed
+    }
+
+    // the most output held back at once, for subroutines compiled in
+    // isolation and for the span attributes. zero when everything went
+    // straight to the writer
+    pub fn peak_buffer(&self) -> usize {
+        self.peak_buffer
+    }
+
+    // the non-terminals compiled so far in the order they were opened, empty
+    // without token spans. ones that were left by an error or hold no token
+    // have no span and are skipped
+    pub fn element_spans(&self) -> Vec<ElementSpan> {
+        self.elements
+            .iter()
+            .filter_map(|element| {
+                let (start, end) = self.range(element)?;
+                Some(ElementSpan {
+                    tag: element.tag,
+                    start,
+                    end,
+                })
+            })
+            .collect()
+    }
+
+    fn range(&self, element: &Element) -> Option<(usize, usize)> {
+        let start = self.token_spans.as_ref()?.get(element.first)?.start;
+        Some((start, element.end?))
+    }
+
+    fn write_tagged(&mut self, token_name: &'static str, value: &str) {
         self.write_opening_tag(token_name);
         self.write(value);
         self.write_closing_tag(token_name);
     }
 
+    // a terminal, for the listeners too
+    fn write_token(&mut self, token_name: &'static str, value: &str, token: Token) {
+        self.write_tagged(token_name, value);
+        self.notify(ParseEvent::Token(token));
+    }
+
+    fn notify(&mut self, event: ParseEvent) {
+        if self.listeners.is_empty() {
+            return;
+        }
+        match self.buffer {
+            Some(_) => self.held_events.push(event),
+            None => {
+                for (listener, error) in &mut self.listeners {
+                    if error.is_none() {
+                        *error = listener.event(&event).err();
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish_listeners(&mut self) {
+        for (listener, error) in &mut self.listeners {
+            if error.is_none() {
+                *error = listener.finish().err();
+            }
+        }
+    }
+
     pub fn compile(&mut self) -> Result<()> {
-        self.write_class()?;
+        let result = self.write_class();
+        self.finish_listeners();
+        self.write_held_back()?;
+        result?;
+        match self.check_nesting() {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+
+    // like compile, but a broken statement is recorded and skipped so later
+    // errors are reported too, and a subroutine with errors is replaced as
+    // set in the options. recovery stops once `max_errors` errors were
+    // collected, in which case a final note is appended. 0 is no limit
+    pub fn compile_collecting(&mut self, max_errors: usize) -> Vec<Diagnostic> {
+        self.recover = true;
+        self.max_errors = match max_errors {
+            0 => usize::MAX,
+            n => n,
+        };
+
+        match self.write_class() {
+            Ok(()) => {
+                let nesting = self.check_nesting();
+                self.errors.extend(nesting);
+            }
+            Err(e) if !self.aborted => {
+                let diagnostic = self.to_diagnostic(e);
+                self.errors.push(diagnostic);
+            }
+            Err(_) => {}
+        }
+        if self.token_spans.is_some() && !self.aborted {
+            self.check_brackets();
+        }
+        self.finish_listeners();
+        if let Err(e) = self.write_held_back() {
+            let diagnostic = self.to_diagnostic(e);
+            self.errors.push(diagnostic);
+        }
+
+        std::mem::take(&mut self.errors)
+    }
+
+    // the statements or the expression that is all of the input, outside of
+    // any class, for the repl
+    pub fn compile_fragment(&mut self, fragment: Fragment) -> Result<()> {
+        let result = match fragment {
+            Fragment::Class => self.write_class(),
+            Fragment::Statements => self.write_statements(),
+            Fragment::Expression => self.write_expression(),
+        }
+        .and_then(|()| match self.tokenizer.peek() {
+            None | Some(Ok(Token::Eof)) => Ok(()),
+            Some(Ok(token)) => bail!("expected the end of input, found `{}`", token),
+            Some(Err(_)) => self.next_token().map(|_| ()),
+        });
+        self.finish_listeners();
+        self.write_held_back()?;
+        result?;
+        match self.check_nesting() {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+
+    // the first close that didn't match the tag it closed, or else a tag the
+    // class left open. only meaningful once the class compiled
+    fn check_nesting(&mut self) -> Option<Diagnostic> {
+        if let Some(error) = self.nesting_error.take() {
+            return Some(error);
+        }
+        let open = self.tags.last().copied()?;
+        let message = format!("internal error: `<{}>` is never closed", open);
+        self.nesting_failed(message)
+    }
+
+    fn nesting_failed(&mut self, message: String) -> Option<Diagnostic> {
+        if self.panic_on_bad_nesting {
+            panic!("{}", message);
+        }
+        Some(Diagnostic::error(
+            INTERNAL_ERROR,
+            message,
+            self.last_consumed_span(),
+        ))
+    }
+
+    // an error without a position of its own is put at the token it failed
+    // at, the last one read
+    fn to_diagnostic(&self, e: anyhow::Error) -> Diagnostic {
+        match e.downcast::<Diagnostic>() {
+            Ok(diagnostic) => diagnostic,
+            Err(e) => {
+                Diagnostic::error(SYNTAX_ERROR, format!("{:#}", e), self.last_consumed_span())
+            }
+        }
+    }
+
+    fn record_error(&mut self, e: anyhow::Error) -> Result<()> {
+        let mut diagnostic = self.to_diagnostic(e);
+        if let Some(context) = self.describe_context() {
+            diagnostic.message = format!("in {}: {}", context, diagnostic.message);
+        }
+        self.errors.push(diagnostic);
+
+        if self.errors.len() >= self.max_errors {
+            self.errors.push(Diagnostic::note(
+                TOO_MANY_ERRORS,
+                format!("too many errors, stopping after {}", self.max_errors),
+                Span::default(),
+            ));
+            self.aborted = true;
+            bail!("too many errors");
+        }
+
+        Ok(())
+    }
+
+    // `Foo.bar, statement 3.2` for the second statement in the block of the
+    // third one in `bar`
+    fn describe_context(&self) -> Option<String> {
+        let mut name = Vec::new();
+        let mut statements = Vec::new();
+        for context in &self.context {
+            match context {
+                Scope::Class(class) | Scope::Subroutine(class) => name.push(class.as_str()),
+                Scope::Statement(n) => statements.push(n.to_string()),
+            }
+        }
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut description = name.join(".");
+        if !statements.is_empty() {
+            description.push_str(&format!(", statement {}", statements.join(".")));
+        }
+        Some(description)
+    }
+
+    // reads what is left after the engine stopped only for its delimiters, so
+    // an imbalance is still found past the error it caused
+    fn check_brackets(&mut self) {
+        while let Some(Ok(_)) = self.tokenizer.peek() {
+            let _ = self.next_token();
+        }
+
+        let brackets = std::mem::take(&mut self.brackets);
+        self.errors.extend(brackets.finish());
+    }
+
+    // skips the rest of a subroutine that failed outside its statements, up
+    // to the next subroutine or the `}` closing the class
+    fn skip_to_next_subroutine(&mut self) {
+        loop {
+            let class_end = self.tokenizer.peek_nth(1).is_none();
+            match self.tokenizer.peek() {
+                Some(Ok(Token::Keyword(k))) if k.is_subroutine_keyword() => break,
+                Some(Err(_)) | None => break,
+                Some(Ok(Token::Symbol(Symbols::CloseCurlyBrace))) if class_end => break,
+                Some(Ok(_)) => {
+                    let _ = self.next_token();
+                }
+            }
+        }
+    }
+
+    // compiles one subroutine into the buffer, recording rather than
+    // returning its errors. only fails once too many errors were seen
+    fn write_isolated_subroutine_dec(&mut self, keyword: Keywords) -> Result<()> {
+        let name = match self.tokenizer.peek_nth(2) {
+            Some(Ok(Token::Identifier(name))) => name.clone(),
+            _ => String::from("?"),
+        };
+        let errors = self.errors.len();
+        let elements = self.elements.len();
+        let subroutines = self.info.subroutines.len();
+        let depth = self.context.len();
+        let tags = self.tags.len();
+
+        self.buffer = Some(Vec::new());
+        self.context.push(Scope::Subroutine(name.clone()));
+        let result = self.write_subroutine_dec(keyword);
+        let output = self.buffer.take().unwrap_or_default();
+        if let Err(e) = result {
+            if self.aborted {
+                return Err(e);
+            }
+            self.record_error(e)?;
+            self.skip_to_next_subroutine();
+        }
+        self.context.truncate(depth);
+        self.tags.truncate(tags);
+
+        let failed = self.errors.len() > errors;
+        if !failed {
+            // the elements in it were placed relative to the buffer
+            let base = self.out.len();
+            for element in &mut self.elements[elements..] {
+                element.at += base;
+            }
+            self.write_bytes(&output)?;
+            for event in std::mem::take(&mut self.held_events) {
+                self.notify(event);
+            }
+        } else {
+            self.held_events.clear();
+            self.elements.truncate(elements);
+            self.open.retain(|&i| i < elements);
+            if self.options.failed_subroutines == FailedSubroutines::Placeholder {
+                self.write_tagged("invalidSubroutineDec", &name);
+            }
+        }
+        // a subroutine that failed part way keeps the declarations read
+        // before the error
+        if self.info.subroutines.len() == subroutines {
+            let subroutine = self.subroutine.take().unwrap_or(CompiledSubroutine {
+                name,
+                ..Default::default()
+            });
+            self.info.subroutines.push(subroutine);
+        }
+        self.subroutine = None;
+        if let Some(subroutine) = self.info.subroutines.last_mut() {
+            subroutine.failed = failed;
+        }
         Ok(())
     }
 
-    fn write_opening_tag(&mut self, tag_name: &str) {
-        self.write(&format!("\n<{}> ", tag_name))
+    // skips the rest of a broken statement, up to and including its `;`, or
+    // up to the `}` closing the enclosing block
+    fn skip_to_statement_end(&mut self) {
+        loop {
+            match self.tokenizer.peek() {
+                Some(Ok(Token::Symbol(Symbols::CloseCurlyBrace))) | Some(Err(_)) | None => break,
+                Some(Ok(Token::Symbol(Symbols::SemiColon))) => {
+                    let _ = self.next_token();
+                    break;
+                }
+                Some(Ok(_)) => {
+                    let _ = self.next_token();
+                }
+            }
+        }
     }
 
-    fn write_closing_tag(&mut self, tag_name: &str) {
+    // what goes before an opening tag. the reference files start every tag
+    // on a line of its own after the previous one's, but the very first tag
+    // would otherwise leave a blank line at the top. normalized tags already
+    // end their line
+    fn tag_break(&self) -> &'static str {
+        if self.has_written && !self.options.normalize {
+            "\n"
+        } else {
+            ""
+        }
+    }
+
+    fn write_opening_tag(&mut self, tag_name: &'static str) {
+        self.tags.push(tag_name);
+        self.write(&format!("{}<{}> ", self.tag_break(), tag_name))
+    }
+
+    // an empty non-terminal, its opening and closing tag on lines of their own
+    // like in the reference files
+    fn write_empty_tag(&mut self, tag_name: &'static str) {
+        self.write(&format!(
+            "{}<{}>\n</{}>\n",
+            self.tag_break(),
+            tag_name,
+            tag_name
+        ));
+        self.notify(ParseEvent::Open(tag_name));
+        self.notify(ParseEvent::Close(tag_name));
+    }
+
+    fn write_closing_tag(&mut self, tag_name: &'static str) {
+        let tag_name = self.closing_tag(tag_name);
         self.write(&format!(" </{}>\n", tag_name))
     }
 
+    // the tag to close `tag_name` with, checked against the open ones
+    fn closing_tag(&mut self, tag_name: &'static str) -> &'static str {
+        #[cfg(test)]
+        let tag_name = match self.misnest {
+            Some((tag, instead)) if tag == tag_name => {
+                self.misnest = None;
+                instead
+            }
+            _ => tag_name,
+        };
+        self.close_tag(tag_name);
+        tag_name
+    }
+
+    fn close_tag(&mut self, tag: &'static str) {
+        let message = match self.tags.pop() {
+            Some(open) if open == tag => return,
+            Some(open) => format!("internal error: `</{}>` closes `<{}>`", tag, open),
+            None => format!("internal error: `</{}>` closes nothing", tag),
+        };
+        if self.nesting_error.is_none() {
+            self.nesting_error = self.nesting_failed(message);
+        }
+    }
+
+    // opens a non-terminal, recording where it starts when spans are known
+    fn open_element(&mut self, tag: &'static str) {
+        self.notify(ParseEvent::Open(tag));
+        self.tags.push(tag);
+        self.write(&format!("{}<{}", self.tag_break(), tag));
+        if self.token_spans.is_some() {
+            let at = match &self.buffer {
+                Some(buffer) => buffer.len(),
+                None => self.out.len(),
+            };
+            self.elements.push(Element {
+                tag,
+                at,
+                first: self.consumed,
+                end: None,
+            });
+            self.open.push(self.elements.len() - 1);
+        }
+        self.write(if self.options.normalize { ">\n" } else { "> " });
+    }
+
+    fn close_element(&mut self, tag: &'static str) {
+        let closing = self.closing_tag(tag);
+        // normalized content has already ended its line
+        let space = if self.options.normalize { "" } else { " " };
+        self.write(&format!("{}</{}>\n", space, closing));
+        self.notify(ParseEvent::Close(tag));
+
+        // non-terminals above it on the stack were abandoned by an error
+        while let Some(i) = self.open.pop() {
+            if self.elements[i].tag != tag {
+                continue;
+            }
+            if self.consumed > self.elements[i].first {
+                let last = self.token_spans.as_ref().unwrap().get(self.consumed - 1);
+                self.elements[i].end = last.map(|span| span.end);
+            }
+            break;
+        }
+    }
+
     fn write(&mut self, value: &str) {
-        write!(&mut self.writer, "{}", value).unwrap()
+        self.has_written = true;
+        match &mut self.buffer {
+            Some(buffer) => buffer.extend_from_slice(value.as_bytes()),
+            None if self.options.span_attributes => self.out.extend_from_slice(value.as_bytes()),
+            None => return write!(&mut self.writer, "{}", value).unwrap(),
+        }
+        self.note_held_back();
+    }
+
+    fn note_held_back(&mut self) {
+        let held = self.buffer.as_ref().map_or(0, Vec::len) + self.out.len();
+        self.peak_buffer = self.peak_buffer.max(held);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.options.span_attributes {
+            self.out.extend_from_slice(bytes);
+            self.note_held_back();
+        } else {
+            self.writer.write_all(bytes)?;
+        }
+        Ok(())
+    }
+
+    // writes the output held back for the span attributes, with them added
+    fn write_held_back(&mut self) -> Result<()> {
+        if !self.options.span_attributes {
+            return Ok(());
+        }
+
+        let out = std::mem::take(&mut self.out);
+        let mut written = 0;
+        for element in &self.elements {
+            if let Some((start, end)) = self.range(element) {
+                self.writer.write_all(&out[written..element.at])?;
+                write!(self.writer, " start=\"{}\" end=\"{}\"", start, end)?;
+                written = element.at;
+            }
+        }
+        self.writer.write_all(&out[written..])?;
+        Ok(())
     }
 
     fn write_class(&mut self) -> Result<()> {
-        self.write_opening_tag("class");
+        if self.options.well_formed_xml {
+            self.write(XML_DECLARATION);
+            if self.options.normalize {
+                self.write("\n");
+            }
+        }
+        self.open_element("class");
         self.write_keyword(&Keywords::Class)?;
+        let name = match self.tokenizer.peek() {
+            Some(Ok(Token::Identifier(name))) => name.clone(),
+            _ => String::new(),
+        };
+        self.context = vec![Scope::Class(name.clone())];
         self.write_identifier()?;
         self.write_symbol(Symbols::OpenCurlyBrace)?;
 
+        let mut progress = None;
         loop {
-            if self.write_class_var_dec().is_err() {
+            self.ensure_progress(&mut progress, "classVarDec")?;
+
+            let Some(keyword) = self.peek_keyword().filter(Keywords::is_class_var_keyword) else {
+                break;
+            };
+            let tags = self.tags.len();
+            if self.write_class_var_dec(keyword).is_err() {
+                self.tags.truncate(tags);
                 break;
             }
         }
 
+        let mut progress = None;
         loop {
-            if self.write_subroutine_dec().is_err() {
+            self.ensure_progress(&mut progress, "subroutineDec")?;
+
+            let Some(keyword) = self.peek_keyword().filter(Keywords::is_subroutine_keyword) else {
+                break;
+            };
+            let tags = self.tags.len();
+            if self.recover {
+                self.write_isolated_subroutine_dec(keyword)?;
+            } else if self.write_subroutine_dec(keyword).is_err() {
+                self.tags.truncate(tags);
                 break;
             }
+            if self.options.flush_per_subroutine {
+                self.writer.flush()?;
+            }
         }
 
+        if self.tokenizer.peek().is_none() {
+            let span = self.last_consumed_span();
+            let message = format!(
+                "expected '}}' to close class '{}', reached end of input",
+                name
+            );
+            return Err(Diagnostic::error(UNEXPECTED_EOF, message, span).into());
+        }
         self.write_symbol(Symbols::CloseCurlyBrace)?;
-        self.write_closing_tag("class");
-        Ok(())
+        self.close_element("class");
+        self.expect_end()
     }
 
-    fn write_class_var_dec(&mut self) -> Result<()> {
-        self.write_opening_tag("classVarDec");
-        let is_static = match self.tokenizer.peek() {
-            Some(Ok(Token::Keyword(k))) if k == &Keywords::Static => true,
-            _ => false,
-        };
-
-        if is_static {
-            self.write_keyword(&Keywords::Static)?;
-        } else {
-            let is_field = match self.tokenizer.peek() {
-                Some(Ok(Token::Keyword(k))) if k == &Keywords::Field => true,
-                _ => false,
-            };
-
-            if is_field {
-                self.write_keyword(&Keywords::Field)?;
-            } else {
-                self.write_closing_tag("classVarDec");
-                bail!("Invalid class variable declaration")
+    // a file is exactly one class, anything after it is an error
+    fn expect_end(&mut self) -> Result<()> {
+        match self.tokenizer.peek() {
+            None | Some(Ok(Token::Eof)) => Ok(()),
+            Some(Err(_)) => self.next_token().map(|_| ()),
+            Some(Ok(token)) => {
+                let message = format!("unexpected token after class: `{}`", token.lexeme());
+                let span = match &self.token_spans {
+                    Some(spans) => spans.get(self.consumed).copied().unwrap_or_default(),
+                    None => Span::default(),
+                };
+                Err(Diagnostic::error(UNEXPECTED_TOKEN, message, span).into())
             }
         }
+    }
 
-        self.write_type()?;
-        self.write_var_name()?;
+    // the keyword read next, if that is a keyword
+    fn peek_keyword(&mut self) -> Option<Keywords> {
+        self.tokenizer
+            .peek()
+            .and_then(|t| t.as_ref().ok())
+            .and_then(Token::keyword)
+            .cloned()
+    }
 
+    // `keyword` is the `static` or `field` read next
+    fn write_class_var_dec(&mut self, keyword: Keywords) -> Result<()> {
+        self.open_element("classVarDec");
+        let kind = match keyword {
+            Keywords::Static => SymbolKind::Static,
+            _ => SymbolKind::Field,
+        };
+        self.write_keyword(&keyword)?;
+
+        let var_type = self.write_type()?;
+        let name = self.write_var_name()?;
+        self.declare(name, kind, &var_type);
+
+        let mut progress = None;
         loop {
-            let has_more_param = match self.tokenizer.peek() {
-                Some(Ok(Token::Symbol(s))) if s != &Symbols::SemiColon => true,
-                _ => false,
-            };
+            self.ensure_progress(&mut progress, "classVarDec")?;
+
+            let has_more_param = self
+                .tokenizer
+                .peek()
+                .and_then(|t| t.as_ref().ok())
+                .and_then(Token::symbol)
+                .is_some_and(|s| s != &Symbols::SemiColon);
 
             if !has_more_param {
                 break;
             }
 
             self.write_symbol(Symbols::Comma)?;
-            self.write_var_name()?;
+            let name = self.write_var_name()?;
+            self.declare(name, kind, &var_type);
         }
 
         self.write_symbol(Symbols::SemiColon)?;
-        self.write_closing_tag("classVarDec");
+        self.close_element("classVarDec");
         Ok(())
     }
 
-    fn write_subroutine_dec(&mut self) -> Result<()> {
-        self.write_opening_tag("subroutineDec");
-        let is_constructor = match self.tokenizer.peek() {
-            Some(Ok(Token::Keyword(k))) if k == &Keywords::Constructor => true,
-            _ => false,
-        };
-
-        if is_constructor {
-            self.write_keyword(&Keywords::Constructor)?;
-        } else {
-            let is_method = match self.tokenizer.peek() {
-                Some(Ok(Token::Keyword(k))) if k == &Keywords::Method => true,
-                _ => false,
-            };
-
-            if is_method {
-                self.write_keyword(&Keywords::Method)?;
-            } else {
-                let is_function = match self.tokenizer.peek() {
-                    Some(Ok(Token::Keyword(k))) if k == &Keywords::Function => true,
-                    _ => false,
-                };
-
-                if is_function {
-                    self.write_keyword(&Keywords::Function)?;
-                } else {
-                    self.write_closing_tag("subroutineDec");
-                    bail!("Invalid subroutine")
-                }
-            }
-        }
-
-        if let Some(Ok(t)) = self.tokenizer.peek() {
-            match t {
-                Token::Keyword(k) if k == &Keywords::Void => self.write_keyword(&Keywords::Void)?,
-                _ => self.write_type()?,
-            }
-        }
-
-        self.write_subroutine_name()?;
+    // `keyword` is the `constructor`, `function` or `method` read next
+    fn write_subroutine_dec(&mut self, keyword: Keywords) -> Result<()> {
+        self.open_element("subroutineDec");
+        self.write_keyword(&keyword)?;
+
+        let return_type = self.write_return_type()?;
+        let name = self.write_subroutine_name()?;
+        self.subroutine = Some(CompiledSubroutine {
+            name,
+            failed: false,
+            return_type: Some(return_type),
+            vars: Vec::new(),
+        });
         self.write_symbol(Symbols::OpenBrace)?;
         self.write_parameter_list()?;
         self.write_symbol(Symbols::CloseBrace)?;
         self.write_subroutine_body()?;
-        self.write_closing_tag("subroutineDec");
+        self.close_element("subroutineDec");
+        self.info.subroutines.extend(self.subroutine.take());
         Ok(())
     }
 
     fn write_parameter_list(&mut self) -> Result<()> {
-        self.write_opening_tag("parameterList");
-        if let Some(Ok(Token::Symbol(s))) = self.tokenizer.peek() {
-            if s != &Symbols::CloseBrace {
-                self.write_type()?;
-                self.write_var_name()?;
-            } else {
-                self.write_closing_tag("parameterList");
-                return Ok(());
-            }
+        if matches!(
+            self.tokenizer.peek(),
+            Some(Ok(Token::Symbol(Symbols::CloseBrace)))
+        ) {
+            self.write_empty_tag("parameterList");
+            return Ok(());
         }
 
+        self.open_element("parameterList");
+        let var_type = self.write_type()?;
+        let name = self.write_var_name()?;
+        self.declare(name, SymbolKind::Arg, &var_type);
+
+        let mut progress = None;
         loop {
-            let has_more_param = match self.tokenizer.peek() {
-                Some(Ok(Token::Symbol(s))) if s != &Symbols::CloseBrace => true,
-                _ => false,
-            };
+            self.ensure_progress(&mut progress, "parameterList")?;
+
+            let has_more_param = matches!(self.tokenizer.peek(), Some(Ok(Token::Symbol(s))) if s != &Symbols::CloseBrace);
 
             if !has_more_param {
                 break;
             }
 
             self.write_symbol(Symbols::Comma)?;
-            self.write_type()?;
-            self.write_var_name()?;
+            let var_type = self.write_type()?;
+            let name = self.write_var_name()?;
+            self.declare(name, SymbolKind::Arg, &var_type);
         }
 
-        self.write_closing_tag("parameterList");
+        self.close_element("parameterList");
         Ok(())
     }
 
     fn write_subroutine_body(&mut self) -> Result<()> {
-        self.write_opening_tag("subroutineBody");
+        self.open_element("subroutineBody");
         self.write_symbol(Symbols::OpenCurlyBrace)?;
 
+        let mut progress = None;
         loop {
-            let has_more_var_declaration = match self.tokenizer.peek() {
-                Some(Ok(Token::Keyword(k))) if k == &Keywords::Var => true,
-                _ => false,
-            };
+            self.ensure_progress(&mut progress, "subroutineBody")?;
+
+            let has_more_var_declaration =
+                matches!(self.tokenizer.peek(), Some(Ok(Token::Keyword(k))) if k == &Keywords::Var);
 
             if !has_more_var_declaration {
                 break;
@@ -209,26 +945,27 @@ impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
 
         self.write_statements()?;
         self.write_symbol(Symbols::CloseCurlyBrace)?;
-        self.write_closing_tag("subroutineBody");
+        self.close_element("subroutineBody");
         Ok(())
     }
 
     fn write_var_dec(&mut self) -> Result<()> {
-        self.write_opening_tag("varDec");
+        self.open_element("varDec");
         self.write_keyword(&Keywords::Var)?;
-        self.write_type()?;
+        let var_type = self.write_type()?;
 
+        let mut progress = None;
         loop {
-            let has_more_var_declaration = match self.tokenizer.peek() {
-                Some(Ok(Token::Symbol(s))) if s == &Symbols::SemiColon => false,
-                _ => true,
-            };
+            self.ensure_progress(&mut progress, "varDec")?;
+
+            let has_more_var_declaration = !matches!(self.tokenizer.peek(), Some(Ok(Token::Symbol(s))) if s == &Symbols::SemiColon);
 
             if !has_more_var_declaration {
                 break;
             }
 
-            self.write_var_name()?;
+            let name = self.write_var_name()?;
+            self.declare(name, SymbolKind::Var, &var_type);
 
             match self.tokenizer.peek() {
                 Some(Ok(Token::Symbol(s))) if s == &Symbols::Comma => {
@@ -239,43 +976,125 @@ impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
         }
 
         self.write_symbol(Symbols::SemiColon)?;
-        self.write_closing_tag("varDec");
+        self.close_element("varDec");
 
         Ok(())
     }
 
-    fn write_type(&mut self) -> Result<()> {
-        if let Some(Ok(token)) = self.tokenizer.peek() {
-            match token {
-                Token::Keyword(k) if k == &Keywords::Int => {
-                    self.write_keyword(&Keywords::Int)?;
-                }
-                Token::Keyword(k) if k == &Keywords::Char => {
-                    self.write_keyword(&Keywords::Char)?;
-                }
-                Token::Keyword(k) if k == &Keywords::Boolean => {
-                    self.write_keyword(&Keywords::Boolean)?;
-                }
-                Token::Identifier(_) => self.write_identifier()?,
-                _ => {
-                    return Err(anyhow!("invalid type")).with_context(|| {
-                        format!("type `{}` is not a valid type", token.to_string())
-                    })
+    // `void` or a type, only a subroutine may return nothing
+    fn write_return_type(&mut self) -> Result<JackType> {
+        if let Some(Ok(Token::Keyword(Keywords::Void))) = self.tokenizer.peek() {
+            self.write_keyword(&Keywords::Void)?;
+            return Ok(JackType::Void);
+        }
+        self.write_type()
+    }
+
+    // the type of a variable or parameter
+    fn write_type(&mut self) -> Result<JackType> {
+        let Some(Ok(token)) = self.tokenizer.peek() else {
+            // surfaces the tokenizer error or the end of input
+            self.next_token()?;
+            bail!("expected a type");
+        };
+
+        let var_type = match token {
+            Token::Keyword(k) if k.is_type_keyword() => {
+                let keyword = k.clone();
+                self.write_keyword(&keyword)?;
+                match keyword {
+                    Keywords::Int => JackType::Int,
+                    Keywords::Char => JackType::Char,
+                    _ => JackType::Boolean,
                 }
             }
+            Token::Identifier(_) => JackType::ClassName(self.write_identifier()?),
+            Token::Keyword(Keywords::Void) => bail!("'void' is not a valid variable type"),
+            _ => {
+                return Err(anyhow!("invalid type"))
+                    .with_context(|| format!("type `{}` is not a valid type", token))
+            }
+        };
+
+        Ok(var_type)
+    }
+
+    // class variables go to the class info, the rest to the subroutine being
+    // compiled
+    fn declare(&mut self, name: String, kind: SymbolKind, var_type: &JackType) {
+        let var = CompiledVar {
+            name,
+            kind,
+            var_type: var_type.clone(),
+        };
+        match (kind, &mut self.subroutine) {
+            (SymbolKind::Static | SymbolKind::Field, _) => self.info.class_vars.push(var),
+            (_, Some(subroutine)) => subroutine.vars.push(var),
+            (_, None) => {}
         }
+    }
 
-        Ok(())
+    fn is_statement_start(&mut self) -> bool {
+        match self.tokenizer.peek() {
+            Some(Ok(Token::Keyword(k))) => k.is_statement_keyword(),
+            Some(Ok(Token::Symbol(Symbols::SemiColon))) => self.options.allow_empty_statements,
+            _ => false,
+        }
     }
 
     fn write_statements(&mut self) -> Result<()> {
-        self.write_opening_tag("statements");
+        if !self.is_statement_start() {
+            self.check_statement_typo()?;
+            self.write_empty_tag("statements");
+            return Ok(());
+        }
+
+        self.open_element("statements");
+        let mut progress = None;
+        let mut n = 0;
         loop {
-            if self.write_statement().is_err() {
+            self.ensure_progress(&mut progress, "statements")?;
+
+            if !self.is_statement_start() {
                 break;
             }
+
+            n += 1;
+            let depth = self.context.len();
+            let tags = self.tags.len();
+            self.context.push(Scope::Statement(n));
+            let result = self.write_statement();
+            if let Err(e) = result {
+                // what the statement left open is dropped with it
+                self.tags.truncate(tags);
+                if !self.recover {
+                    self.context.truncate(depth);
+                    break;
+                }
+                let recorded = self.record_error(e);
+                self.context.truncate(depth);
+                recorded?;
+                self.skip_to_statement_end();
+            }
+            self.context.truncate(depth);
+        }
+        self.check_statement_typo()?;
+        self.close_element("statements");
+        Ok(())
+    }
+
+    // statements end at anything that doesn't start one, a misspelt keyword
+    // would only be reported as a missing `}`
+    fn check_statement_typo(&mut self) -> Result<()> {
+        if let Some(Ok(Token::Identifier(word))) = self.tokenizer.peek() {
+            if let Some(keyword) = Self::statement_keyword_like(&self.keywords, word) {
+                bail!(
+                    "`{}` is not valid at this position to be statement, did you mean `{}`?",
+                    word,
+                    keyword
+                );
+            }
         }
-        self.write_closing_tag("statements");
         Ok(())
     }
 
@@ -287,22 +1106,41 @@ impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
                 Token::Keyword(k) if k == &Keywords::While => self.write_while_statement()?,
                 Token::Keyword(k) if k == &Keywords::Do => self.write_do_statement()?,
                 Token::Keyword(k) if k == &Keywords::Return => self.write_return_statement()?,
+                Token::Symbol(Symbols::SemiColon) if self.options.allow_empty_statements => {
+                    self.next_token()?;
+                    self.write(&format!("{}<emptyStatement/>\n", self.tag_break()));
+                    self.notify(ParseEvent::Open("emptyStatement"));
+                    self.notify(ParseEvent::Close("emptyStatement"));
+                }
                 token => {
                     return Err(anyhow!("invalid statement")).with_context(|| {
-                        format!(
-                            "`{}` is not valid at this position to be statement",
-                            token.to_string()
-                        )
+                        format!("`{}` is not valid at this position to be statement", token)
                     })
                 }
             }
+        } else {
+            bail!("expected a statement")
         }
 
-        Ok(())
+        Ok(())
+    }
+
+    // a statement keyword `word` is a typo of, differing by case or by one
+    // character added, dropped, changed or swapped with its neighbour. single
+    // letters are more likely variables missing their `let`
+    fn statement_keyword_like(keywords: &KeywordSet, word: &str) -> Option<Keywords> {
+        if word.chars().count() < 2 {
+            return None;
+        }
+        let word: Vec<char> = word.to_lowercase().chars().collect();
+        keywords.statement_keywords().into_iter().find(|keyword| {
+            let keyword: Vec<char> = keyword.to_string().chars().collect();
+            is_one_edit_apart(&word, &keyword)
+        })
     }
 
     fn write_let_statement(&mut self) -> Result<()> {
-        self.write_opening_tag("letStatement");
+        self.open_element("letStatement");
         self.write_keyword(&Keywords::Let)?;
         self.write_identifier()?;
 
@@ -317,12 +1155,12 @@ impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
         self.write_symbol(Symbols::Equal)?;
         self.write_expression()?;
         self.write_symbol(Symbols::SemiColon)?;
-        self.write_closing_tag("letStatement");
+        self.close_element("letStatement");
         Ok(())
     }
 
     fn write_if_statement(&mut self) -> Result<()> {
-        self.write_opening_tag("ifStatement");
+        self.open_element("ifStatement");
         self.write_keyword(&Keywords::If)?;
         self.write_symbol(Symbols::OpenBrace)?;
         self.write_expression()?;
@@ -334,17 +1172,18 @@ impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
 
         if let Some(Ok(Token::Keyword(k))) = self.tokenizer.peek() {
             if k == &Keywords::Else {
+                self.write_keyword(&Keywords::Else)?;
                 self.write_symbol(Symbols::OpenCurlyBrace)?;
                 self.write_statements()?;
                 self.write_symbol(Symbols::CloseCurlyBrace)?;
             }
         }
-        self.write_closing_tag("ifStatement");
+        self.close_element("ifStatement");
         Ok(())
     }
 
     fn write_while_statement(&mut self) -> Result<()> {
-        self.write_opening_tag("whileStatement");
+        self.open_element("whileStatement");
         self.write_keyword(&Keywords::While)?;
         self.write_symbol(Symbols::OpenBrace)?;
         self.write_expression()?;
@@ -353,30 +1192,31 @@ impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
         self.write_symbol(Symbols::OpenCurlyBrace)?;
         self.write_statements()?;
         self.write_symbol(Symbols::CloseCurlyBrace)?;
-        self.write_closing_tag("whileStatement");
+        self.close_element("whileStatement");
         Ok(())
     }
 
     fn write_do_statement(&mut self) -> Result<()> {
-        self.write_opening_tag("doStatement");
+        self.open_element("doStatement");
         self.write_keyword(&Keywords::Do)?;
         self.write_subroutine_call()?;
         self.write_symbol(Symbols::SemiColon)?;
-        self.write_closing_tag("doStatement");
+        self.close_element("doStatement");
         Ok(())
     }
 
     fn write_return_statement(&mut self) -> Result<()> {
-        self.write_opening_tag("returnStatement");
+        self.open_element("returnStatement");
         self.write_keyword(&Keywords::Return)?;
 
-        if let Some(Ok(Token::Symbol(s))) = self.tokenizer.peek() {
-            if s != &Symbols::SemiColon {
-                self.write_expression()?;
-            }
+        if !matches!(
+            self.tokenizer.peek(),
+            Some(Ok(Token::Symbol(Symbols::SemiColon)))
+        ) {
+            self.write_expression()?;
         }
         self.write_symbol(Symbols::SemiColon)?;
-        self.write_closing_tag("returnStatement");
+        self.close_element("returnStatement");
         Ok(())
     }
 
@@ -384,35 +1224,40 @@ impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
         // (2*3, ade, a.b())
         // ()
         // (2*3)
-        self.write_opening_tag("expressionList");
-        loop {
-            let has_more_expression = match self.tokenizer.peek() {
-                Some(Ok(Token::Symbol(s))) if s == &Symbols::CloseBrace => false,
-                _ => true,
-            };
+        if matches!(
+            self.tokenizer.peek(),
+            Some(Ok(Token::Symbol(Symbols::CloseBrace)))
+        ) {
+            self.write_empty_tag("expressionList");
+            return Ok(());
+        }
 
-            if !has_more_expression {
-                break;
+        // expression (',' expression)*, every comma is followed by an
+        // expression so a leading or doubled one is an error here
+        self.open_element("expressionList");
+        loop {
+            if let Some(Ok(Token::Symbol(Symbols::Comma))) = self.tokenizer.peek() {
+                bail!("expected an expression, found `,`");
             }
-
             self.write_expression()?;
 
             match self.tokenizer.peek() {
-                Some(Ok(Token::Symbol(s))) if s == &Symbols::Comma => {
-                    self.write_symbol(Symbols::Comma)?;
-                }
-                _ => {}
+                Some(Ok(Token::Symbol(Symbols::Comma))) => self.write_symbol(Symbols::Comma)?,
+                _ => break,
             }
         }
-        self.write_closing_tag("expressionList");
+        self.close_element("expressionList");
         Ok(())
     }
 
     fn write_expression(&mut self) -> Result<()> {
-        self.write_opening_tag("expression");
+        self.open_element("expression");
         self.write_term()?;
 
+        let mut progress = None;
         loop {
+            self.ensure_progress(&mut progress, "expression")?;
+
             let token = self.tokenizer.peek();
             let next_operator = match &token {
                 Some(Ok(Token::Symbol(v))) => {
@@ -432,13 +1277,28 @@ impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
             self.write_operator()?;
             self.write_term()?;
         }
-        self.write_closing_tag("expression");
+        self.close_element("expression");
         Ok(())
     }
 
     fn write_subroutine_call(&mut self) -> Result<()> {
         // (Class|varName).subRoutine(?expressionList)
         // subRoutine(?expressionList)
+        if self.at_this_receiver() {
+            if !self.options.allow_this_receiver {
+                let name = match self.tokenizer.peek_nth(2) {
+                    Some(Ok(Token::Identifier(name))) => name.clone(),
+                    _ => String::from("f"),
+                };
+                bail!(
+                    "`this` can't be the receiver of a call, methods of the current class are called bare, as `{}(...)`",
+                    name
+                );
+            }
+            // the `this` and `.`
+            self.next_token()?;
+            self.next_token()?;
+        }
         self.write_identifier()?;
 
         if let Some(Ok(Token::Symbol(s))) = self.tokenizer.peek() {
@@ -454,11 +1314,30 @@ impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
         Ok(())
     }
 
+    // `this.` starting a call, which the grammar doesn't allow
+    fn at_this_receiver(&mut self) -> bool {
+        matches!(
+            self.tokenizer.peek_nth(0),
+            Some(Ok(Token::Keyword(Keywords::This)))
+        ) && matches!(
+            self.tokenizer.peek_nth(1),
+            Some(Ok(Token::Symbol(Symbols::Dot)))
+        )
+    }
+
     fn write_term(&mut self) -> Result<()> {
-        self.write_opening_tag("term");
+        self.open_element("term");
+        if self.at_this_receiver() {
+            self.write_subroutine_call()?;
+            self.close_element("term");
+            return Ok(());
+        }
         let token = self.tokenizer.peek();
         if let Some(Ok(token)) = token {
             match token {
+                Token::IntConst(i16::MIN) => {
+                    bail!("integer constant 32768 is out of range, only -32768 is allowed")
+                }
                 Token::IntConst(_) => self.write_const()?,
                 Token::StringConst(_) => self.write_const()?,
                 Token::Keyword(k) if k == &Keywords::Function => {
@@ -472,25 +1351,41 @@ impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
                 Token::Symbol(s) if s == &Symbols::OpenBrace => {
                     self.write_symbol(Symbols::OpenBrace)?;
                     self.write_expression()?;
-                    self.write_symbol(Symbols::OpenBrace)?;
+                    self.write_symbol(Symbols::CloseBrace)?;
+                }
+                Token::Symbol(s) if s == &Symbols::Minus || s == &Symbols::Tilde => {
+                    let op = s.clone();
+                    self.write_symbol(op)?;
+                    if let Some(Ok(Token::IntConst(i16::MIN))) = self.tokenizer.peek() {
+                        // the 32768 of -32768
+                        self.open_element("term");
+                        self.write_const()?;
+                        self.close_element("term");
+                    } else {
+                        self.write_term()?;
+                    }
                 }
-                Token::Symbol(s) if s == &Symbols::Minus => self.write_symbol(Symbols::Minus)?,
-                Token::Symbol(s) if s == &Symbols::Tilde => self.write_symbol(Symbols::Tilde)?,
                 _ => self.write_subroutine_call()?,
             }
+        } else {
+            // surfaces the tokenizer error or the end of input
+            self.next_token()?;
         }
-        self.write_closing_tag("term");
+        self.close_element("term");
         Ok(())
     }
 
     fn write_term_identifier(&mut self) -> Result<()> {
-        let _ = self.tokenizer.advance_cursor();
-        let next_token = self.tokenizer.peek();
-        if let Some(Ok(token)) = next_token {
-            if token == &Token::Symbol(Symbols::OpenBrace) || token == &Token::Symbol(Symbols::Dot)
-            {
-                return self.write_subroutine_call();
-            }
+        // the identifier is read to see what follows it, then put back for
+        // the call or the variable to read
+        let identifier = self.next_token()?;
+        let is_call = matches!(
+            self.tokenizer.peek(),
+            Some(Ok(Token::Symbol(Symbols::OpenBrace | Symbols::Dot)))
+        );
+        self.unget_token(identifier);
+        if is_call {
+            return self.write_subroutine_call();
         }
 
         self.write_var_name()?;
@@ -510,118 +1405,1569 @@ impl<'a, T: Iterator<Item = Result<Token>>> CompilationEngine<'a, T> {
     }
 
     fn write_const(&mut self) -> Result<()> {
-        if let Some(Ok(token)) = self.tokenizer.next() {
-            match token {
-                Token::IntConst(i) => {
-                    self.write_opening_tag("integerConstant");
-                    self.write(&format!("{}", i));
-                    self.write_closing_tag("integerConstant");
-                }
-                Token::StringConst(s) => {
-                    self.write_opening_tag("stringConstant");
-                    self.write(&format!("{}", s));
-                    self.write_closing_tag("stringConstant");
-                }
-                _ => {}
+        match self.next_token()? {
+            Token::IntConst(i) => {
+                self.write_token(
+                    "integerConstant",
+                    &(i as u16).to_string(),
+                    Token::IntConst(i),
+                );
             }
+            Token::StringConst(s) => {
+                let text = match self.options.well_formed_xml {
+                    true => escape_text(&s),
+                    false => s.clone(),
+                };
+                self.write_token("stringConstant", &text, Token::StringConst(s));
+            }
+            _ => {}
         }
 
         Ok(())
     }
 
-    fn write_var_name(&mut self) -> Result<()> {
+    fn write_var_name(&mut self) -> Result<String> {
         self.write_identifier()
     }
 
-    fn write_subroutine_name(&mut self) -> Result<()> {
+    fn write_subroutine_name(&mut self) -> Result<String> {
         self.write_identifier()
     }
 
     fn write_operator(&mut self) -> Result<()> {
-        let op = self.tokenizer.next().unwrap()?;
-        if let Token::Symbol(op) = &op {
-            if Self::is_operator(&op) {
-                self.write_opening_tag("symbol");
-                self.write(&op.to_string());
-                self.write_closing_tag("symbol");
+        let op = self.next_token()?;
+        if let Token::Symbol(symbol) = &op {
+            if Self::is_operator(symbol) {
+                let text = self.symbol_text(symbol);
+                self.write_token("symbol", &text, op);
                 return Ok(());
             }
         }
 
         Err(anyhow!("Invalid operator"))
-            .with_context(|| format!("operator `{}` is not a valid operator", op.to_string()))
+            .with_context(|| format!("operator `{}` is not a valid operator", op))
     }
 
     fn is_operator(op: &Symbols) -> bool {
-        match op {
+        matches!(
+            op,
             Symbols::Plus
-            | Symbols::Minus
-            | Symbols::Asterik
-            | Symbols::BackSlash
-            | Symbols::Ampersand
-            | Symbols::VerticalBar
-            | Symbols::LessThan
-            | Symbols::GreaterThan
-            | Symbols::Equal => true,
-            _ => false,
-        }
+                | Symbols::Minus
+                | Symbols::Asterik
+                | Symbols::BackSlash
+                | Symbols::Ampersand
+                | Symbols::VerticalBar
+                | Symbols::LessThan
+                | Symbols::GreaterThan
+                | Symbols::Equal
+        )
     }
 
     fn write_keyword_constant(&mut self) -> Result<()> {
-        let token = self.tokenizer.next();
-        if let Some(token) = token {
-            if let Token::Keyword(keyword) = token? {
-                match keyword {
-                    Keywords::True | Keywords::False | Keywords::Null | Keywords::This => {
-                        return Ok(self.write(&keyword.to_string()));
-                    }
-                    _ => {
-                        return Err(anyhow!("Invalid keyword")).with_context(|| {
-                            format!("keyword `{}` is not a valid keyword", keyword.to_string())
-                        })
-                    }
-                }
+        if let Token::Keyword(keyword) = self.next_token()? {
+            if keyword.is_keyword_constant() {
+                self.write(&keyword.to_string());
+                self.notify(ParseEvent::Token(Token::Keyword(keyword)));
+                return Ok(());
             }
+            return Err(anyhow!("Invalid keyword"))
+                .with_context(|| format!("keyword `{}` is not a valid keyword", keyword));
         }
 
         Err(anyhow!(""))
     }
 
-    fn write_identifier(&mut self) -> Result<()> {
-        let token = self.tokenizer.next().unwrap()?;
+    fn next_token(&mut self) -> Result<Token> {
+        match self.tokenizer.next() {
+            Some(token) => {
+                if let (Ok(token), Some(spans)) = (&token, &self.token_spans) {
+                    let span = spans.get(self.consumed).copied().unwrap_or_default();
+                    self.brackets.token(token, span);
+                }
+                self.consumed += 1;
+                token
+            }
+            // the tokenizers end cleanly here, input cut off inside a token
+            // is their error
+            None => Err(Diagnostic::error(
+                UNEXPECTED_EOF,
+                "unexpected end of input",
+                self.last_consumed_span(),
+            )
+            .into()),
+        }
+    }
+
+    // puts back the token next_token just read. the bracket tracker has
+    // already seen it, so it isn't meant for delimiters
+    fn unget_token(&mut self, token: Token) {
+        self.consumed -= 1;
+        self.tokenizer.unget(token);
+    }
+
+    fn last_consumed_span(&self) -> Span {
+        match (&self.token_spans, self.consumed) {
+            (Some(spans), n) if n > 0 => spans.get(n - 1).copied().unwrap_or_default(),
+            _ => Span::default(),
+        }
+    }
+
+    // fails when the loop body ran a full iteration without consuming a
+    // token, which would otherwise spin forever
+    fn ensure_progress(&self, last: &mut Option<usize>, construct: &str) -> Result<()> {
+        if *last == Some(self.consumed) {
+            let message = format!(
+                "internal error: no progress while compiling {} after {} tokens",
+                construct, self.consumed
+            );
+            return Err(Diagnostic::error(INTERNAL_ERROR, message, Span::default()).into());
+        }
+
+        *last = Some(self.consumed);
+        Ok(())
+    }
+
+    fn write_identifier(&mut self) -> Result<String> {
+        let token = self.next_token()?;
         if let Token::Identifier(k) = token {
-            self.write_tagged("identifier", &k);
-            return Ok(());
+            self.write_token("identifier", &k, Token::Identifier(k.clone()));
+            return Ok(k);
         }
 
         Err(anyhow!("invalid token"))
-            .with_context(|| format!("`{}` is not a valid identifier", token.to_string()))
+            .with_context(|| format!("`{}` is not a valid identifier", token))
     }
 
     fn write_keyword(&mut self, keyword: &Keywords) -> Result<()> {
-        let token = self.tokenizer.next().unwrap()?;
+        let token = self.next_token()?;
         if let Token::Keyword(k) = &token {
             if k == keyword {
-                self.write_tagged("keyword", &keyword.to_string());
+                self.write_token("keyword", &keyword.to_string(), token.clone());
                 return Ok(());
             }
         }
 
-        Err(anyhow!("invalid token"))
-            .with_context(|| format!("`{}` is not a valid keyword", token.to_string()))
+        Err(anyhow!("invalid token")).with_context(|| format!("`{}` is not a valid keyword", token))
+    }
+
+    // the display form of a symbol escapes `<` and `>` but not `&`
+    fn symbol_text(&self, symbol: &Symbols) -> String {
+        match symbol {
+            Symbols::Ampersand if self.options.well_formed_xml => String::from("&amp;"),
+            symbol => symbol.to_string(),
+        }
     }
 
     fn write_symbol(&mut self, symbol: Symbols) -> Result<()> {
-        let token = self.tokenizer.next().unwrap()?;
+        let token = self.next_token()?;
         if let Token::Symbol(s) = &token {
             if s == &symbol {
-                self.write_tagged("symbol", &symbol.to_string());
+                let text = self.symbol_text(&symbol);
+                self.write_token("symbol", &text, token.clone());
                 return Ok(());
             }
         }
 
         Err(anyhow!("invalid token"))
-            .context(format!("`{}` is not a valid symbol", &token.to_string()))
-            .context(format!("should print {}", symbol.to_string()))
+            .context(format!("`{}` is not a valid symbol", &token))
+            .context(format!("should print {}", symbol))
+    }
+}
+
+// the xml parse tree of `src`. strings in and out and nothing else, so it
+// also runs where there is no filesystem, like in a browser on
+// wasm32-unknown-unknown
+pub fn compile_source(src: &str) -> Result<String> {
+    let mut tokenizer = StreamTokenizer::from_source(src);
+    let mut xml = Vec::new();
+    let mut engine = CompilationEngine::new(&mut xml, &mut tokenizer);
+    engine.compile()?;
+    let consumed = engine.tokens_consumed();
+    if let Some(error) = check_fully_read(&tokenizer, consumed) {
+        return Err(error.into());
+    }
+    Ok(String::from_utf8(xml)?)
+}
+
+// what a piece of source typed into the repl is, by its first token
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fragment {
+    Class,
+    Statements,
+    Expression,
+}
+
+impl Fragment {
+    pub fn of(src: &str) -> Self {
+        match StreamTokenizer::from_source(src).next() {
+            Some(Ok(Token::Keyword(Keywords::Class))) => Fragment::Class,
+            Some(Ok(Token::Keyword(k))) if k.is_statement_keyword() => Fragment::Statements,
+            _ => Fragment::Expression,
+        }
+    }
+}
+
+// the xml tree of a class, of statements or of an expression on its own,
+// which of them `src` is going by its first token. it is parsed first so an
+// error has the position the engine doesn't know
+pub fn compile_fragment_source(
+    src: &str,
+    listener: Option<&mut dyn ParseListener>,
+) -> Result<String> {
+    let fragment = Fragment::of(src);
+    let mut parser = Parser::from_source(src);
+    match fragment {
+        Fragment::Class => parser.parse_class().map(|_| ()),
+        Fragment::Statements => parser.parse_statements().map(|_| ()),
+        Fragment::Expression => parser.parse_expression().map(|_| ()),
+    }?;
+    parser.expect_end()?;
+
+    let mut tokenizer = StreamTokenizer::from_source(src);
+    let mut xml = Vec::new();
+    let mut engine = CompilationEngine::new(&mut xml, &mut tokenizer);
+    if let Some(listener) = listener {
+        engine = engine.with_listener(listener);
+    }
+    engine.compile_fragment(fragment)?;
+    Ok(String::from_utf8(xml)?)
+}
+
+// an internal error when a compile that succeeded didn't get to the end: the
+// engine left tokens it was given, or the tokenizer stopped with more than
+// whitespace left. either way the output is missing part of the source
+pub(crate) fn check_fully_read(tokenizer: &StreamTokenizer, consumed: usize) -> Option<Diagnostic> {
+    let produced = tokenizer.tokens_produced();
+    if consumed < produced {
+        let message = format!(
+            "internal error: only {} of the {} tokens read were compiled",
+            consumed, produced
+        );
+        return Some(Diagnostic::error(INTERNAL_ERROR, message, Span::default()));
+    }
+    tokenizer.truncation()
+}
+
+// compile_source over `(name, source)` pairs, the results in the same order
+// and each on its own, a source that fails doesn't stop the others
+pub fn compile_batch(sources: &[(String, String)]) -> Vec<(String, Result<String>)> {
+    sources
+        .iter()
+        .map(|(name, source)| (name.clone(), compile_source(source)))
+        .collect()
+}
+
+// the first line of a well formed document
+pub(crate) const XML_DECLARATION: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>";
+
+// `text` as the content of an element
+pub(crate) fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn is_one_edit_apart(a: &[char], b: &[char]) -> bool {
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    let prefix = short.iter().zip(long).take_while(|(x, y)| x == y).count();
+    match long.len() - short.len() {
+        0 if prefix == short.len() => true,
+        0 => {
+            let swapped = prefix + 1 < short.len()
+                && short[prefix] == long[prefix + 1]
+                && short[prefix + 1] == long[prefix]
+                && short[prefix + 2..] == long[prefix + 2..];
+            swapped || short[prefix + 1..] == long[prefix + 1..]
+        }
+        1 => short[prefix..] == long[prefix + 1..],
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use crate::{
+        lexical_elements::KeywordSet, vm_interp::VmInterpreter, CodeGenerator, CompilationEngine,
+        CompileError, Parser, Severity, SexpListener, StreamTokenizer, TokensListener,
+    };
+
+    use crate::{balance::UNBALANCED_DELIMITER, parser::UNEXPECTED_EOF, Diagnostic};
+
+    use super::{
+        compile_fragment_source, compile_source, CompileOptions, CompiledVar, ElementSpan,
+        FailedSubroutines, Fragment, JackType, SymbolKind, TokenStream, INTERNAL_ERROR,
+        TOO_MANY_ERRORS,
+    };
+
+    #[test]
+    fn a_token_put_back_is_read_again() {
+        let src = "let a = b;";
+        let mut stream = TokenStream::new(StreamTokenizer::from_source(src));
+        let first = stream.next().unwrap().unwrap();
+        let second = stream.next().unwrap().unwrap();
+        stream.unget(second.clone());
+        assert_eq!(stream.peek().unwrap().as_ref().unwrap(), &second);
+        assert_eq!(stream.peek_nth(1).unwrap().as_ref().unwrap().lexeme(), "=");
+
+        let mut read = vec![first];
+        while let Some(token) = stream.next() {
+            read.push(token.unwrap());
+        }
+        let all: Vec<_> = StreamTokenizer::from_source(src)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(read, all);
+    }
+
+    #[test]
+    fn tokens_after_the_class_are_an_error() {
+        let src = "class A { } class B { }";
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let err = CompilationEngine::new(&mut out, &mut tokenizer)
+            .compile()
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<Diagnostic>().unwrap().message,
+            "unexpected token after class: `class`"
+        );
+
+        let spanned: Vec<_> = StreamTokenizer::from_source(src)
+            .into_spanned()
+            .map(Result::unwrap)
+            .collect();
+        let spans = spanned.iter().map(|t| t.span).collect();
+        let mut tokens = spanned.into_iter().map(|t| Ok(t.token));
+        let mut out = Vec::new();
+        let got = CompilationEngine::new(&mut out, &mut tokens)
+            .with_token_spans(spans)
+            .compile_collecting(10);
+        assert_eq!(got.len(), 1, "{:?}", got);
+        assert_eq!(
+            got[0].to_string(),
+            "1:13: error[J001]: unexpected token after class: `class`"
+        );
+
+        // the end of input marker some tokenizers give is no trailing token
+        let mut tokenizer = StreamTokenizer::from_source("class A { }").emit_eof(true);
+        let mut out = Vec::new();
+        CompilationEngine::new(&mut out, &mut tokenizer)
+            .compile()
+            .unwrap();
+    }
+
+    #[test]
+    fn compile_source_matches_the_engine() {
+        let src = "class Main { function void main() { do Output.printInt(1 + 2); return; } }";
+        assert_eq!(super::compile_source(src).unwrap(), compile(src));
+
+        let err = super::compile_source("class Main { function void main() { let = 1; } }");
+        assert!(err.is_err());
+    }
+
+    // enough of xml to check the engine's output by: a declaration first,
+    // one root element, tags that nest and only the predefined entities
+    fn well_formed(xml: &str) -> Result<(), String> {
+        let mut rest = xml
+            .strip_prefix("<?xml version=\"1.0\" encoding=\"UTF-8\"?>")
+            .ok_or("no declaration at the start")?;
+        let (mut open, mut roots) = (Vec::new(), 0);
+        while !rest.is_empty() {
+            let text_end = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..text_end];
+            if open.is_empty() && !text.trim().is_empty() {
+                return Err(format!("text `{}` outside the root", text.trim()));
+            }
+            for (i, _) in text.match_indices('&') {
+                let entity = &text[i..text[i..].find(';').map_or(text.len(), |end| i + end + 1)];
+                if !["&amp;", "&lt;", "&gt;", "&quot;", "&apos;"].contains(&entity) {
+                    return Err(format!("bad entity `{}`", entity));
+                }
+            }
+            rest = &rest[text_end..];
+            if rest.is_empty() {
+                break;
+            }
+
+            let end = rest.find('>').ok_or("unclosed tag")?;
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+            let name = tag.trim_start_matches('/').split(' ').next().unwrap();
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(format!("bad tag `<{}>`", tag));
+            }
+            if tag.starts_with('/') {
+                if open.pop() != Some(name) {
+                    return Err(format!("`</{}>` closes nothing open", name));
+                }
+            } else {
+                roots += usize::from(open.is_empty());
+                open.push(name);
+            }
+        }
+        match (roots, open.last()) {
+            (1, None) => Ok(()),
+            (_, Some(name)) => Err(format!("`<{}>` is never closed", name)),
+            (n, None) => Err(format!("{} root elements", n)),
+        }
+    }
+
+    #[test]
+    fn well_formed_xml_is_a_document() {
+        let src = "class Main {
+            function void main() {
+                var String s;
+                let s = \"a < b && 'c' > d\";
+                if ((1 < 2) & (3 > 2)) { do Output.printString(\"<&>\"); }
+                return;
+            }
+        }";
+        let compile = |options: CompileOptions| {
+            let mut tokenizer = StreamTokenizer::from_source(src);
+            let mut out = Vec::new();
+            CompilationEngine::new(&mut out, &mut tokenizer)
+                .with_options(options)
+                .compile()
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let tokens = |well_formed| {
+            let mut tokenizer = StreamTokenizer::from_source(src);
+            let mut out = Vec::new();
+            let mut listener = TokensListener::new(&mut out).well_formed(well_formed);
+            CompilationEngine::new(&mut Vec::new(), &mut tokenizer)
+                .with_listener(&mut listener)
+                .compile()
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let xml = compile(CompileOptions {
+            well_formed_xml: true,
+            ..Default::default()
+        });
+        assert_eq!(well_formed(&xml), Ok(()), "{}", xml);
+        assert!(xml.contains("<stringConstant> &lt;&amp;&gt; </stringConstant>"));
+        assert!(xml.contains("<symbol> &amp; </symbol>"));
+
+        // the reference format leaves the strings as they are
+        let plain = compile(CompileOptions::default());
+        assert!(plain.contains("<stringConstant> <&> </stringConstant>"));
+        assert!(well_formed(&plain).is_err());
+
+        let spans = compile(CompileOptions {
+            well_formed_xml: true,
+            span_attributes: true,
+            ..Default::default()
+        });
+        assert_eq!(well_formed(&spans), Ok(()), "{}", spans);
+
+        let xml = tokens(true);
+        assert_eq!(well_formed(&xml), Ok(()), "{}", xml);
+        assert!(xml.contains("<stringConstant> &lt;&amp;&gt; </stringConstant>"));
+        assert!(xml.contains("<symbol> &amp; </symbol>"));
+        assert!(well_formed(&tokens(false)).is_err());
+    }
+
+    #[test]
+    fn normalized_output_has_one_line_per_tag() {
+        let mut tokenizer =
+            StreamTokenizer::from_source(
+            "class A { field int y; method void f() { var int x; ; return x; } function void g() { } }",
+        );
+        let mut out = Vec::new();
+        CompilationEngine::new(&mut out, &mut tokenizer)
+            .with_options(CompileOptions {
+                normalize: true,
+                allow_empty_statements: true,
+                ..Default::default()
+            })
+            .compile()
+            .unwrap();
+
+        let expected = "\
+<class>
+<keyword> class </keyword>
+<identifier> A </identifier>
+<symbol> { </symbol>
+<classVarDec>
+<keyword> field </keyword>
+<keyword> int </keyword>
+<identifier> y </identifier>
+<symbol> ; </symbol>
+</classVarDec>
+<subroutineDec>
+<keyword> method </keyword>
+<keyword> void </keyword>
+<identifier> f </identifier>
+<symbol> ( </symbol>
+<parameterList>
+</parameterList>
+<symbol> ) </symbol>
+<subroutineBody>
+<symbol> { </symbol>
+<varDec>
+<keyword> var </keyword>
+<keyword> int </keyword>
+<identifier> x </identifier>
+<symbol> ; </symbol>
+</varDec>
+<statements>
+<emptyStatement/>
+<returnStatement>
+<keyword> return </keyword>
+<expression>
+<term>
+<identifier> x </identifier>
+</term>
+</expression>
+<symbol> ; </symbol>
+</returnStatement>
+</statements>
+<symbol> } </symbol>
+</subroutineBody>
+</subroutineDec>
+<subroutineDec>
+<keyword> function </keyword>
+<keyword> void </keyword>
+<identifier> g </identifier>
+<symbol> ( </symbol>
+<parameterList>
+</parameterList>
+<symbol> ) </symbol>
+<subroutineBody>
+<symbol> { </symbol>
+<statements>
+</statements>
+<symbol> } </symbol>
+</subroutineBody>
+</subroutineDec>
+<symbol> } </symbol>
+</class>
+";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn void_is_only_a_return_type() {
+        let xml = compile("class A { function void f() { return; } }");
+        assert!(xml.contains("<keyword> void </keyword>"), "{}", xml);
+
+        for src in [
+            "class A { function void f() { var void x; return; } }",
+            "class A { function void f(void x) { return; } }",
+        ] {
+            let mut tokenizer = StreamTokenizer::from_source(src);
+            let mut out = Vec::new();
+            let got = CompilationEngine::new(&mut out, &mut tokenizer).compile_collecting(20);
+            assert!(
+                got.iter()
+                    .any(|d| d.message.contains("'void' is not a valid variable type")),
+                "{}: {:#?}",
+                src,
+                got
+            );
+        }
+    }
+
+    #[test]
+    fn a_tokenizer_stopping_early_is_an_internal_error() {
+        let src = "class A { } class B { }";
+
+        let mut whole = StreamTokenizer::from_source("class A { }  // done\n");
+        let mut out = Vec::new();
+        let mut engine = CompilationEngine::new(&mut out, &mut whole);
+        engine.compile().unwrap();
+        let consumed = engine.tokens_consumed();
+        assert_eq!((consumed, whole.tokens_produced()), (4, 4));
+        assert!(super::check_fully_read(&whole, consumed).is_none());
+
+        // stopping after the first class would otherwise leave the engine no
+        // way to tell, the output is a complete class `A`
+        let mut cut = StreamTokenizer::from_source(src).stop_after(4);
+        let mut out = Vec::new();
+        let err = CompilationEngine::new(&mut out, &mut cut)
+            .compile()
+            .unwrap_err();
+        let error = CompileError::from(err).diagnostic;
+        assert_eq!(error.code, INTERNAL_ERROR);
+        assert_eq!(
+            error.message,
+            "internal error: the tokenizer stopped after 4 tokens at byte 12 of 23, before `class B { }`"
+        );
+
+        // the parser vm code is generated from fails the same way
+        let cut = StreamTokenizer::from_source(src).stop_after(4);
+        let err = Parser::new(cut.into_spanned()).parse_class().unwrap_err();
+        assert_eq!(CompileError::from(err).diagnostic.code, INTERNAL_ERROR);
+
+        // tokens read but never compiled
+        let mut left = StreamTokenizer::from_source(src);
+        let _ = left.by_ref().take(5).count();
+        let error = super::check_fully_read(&left, 4).unwrap();
+        assert_eq!(
+            error.message,
+            "internal error: only 4 of the 5 tokens read were compiled"
+        );
+    }
+
+    #[test]
+    fn long_sources_are_read_to_the_end() {
+        let mut src = String::from("class Main {\n    function int main() {\n        var int x;\n");
+        for _ in 0..300 {
+            src.push_str("        let x = x + 1;\n");
+        }
+        src.push_str("        return x;\n    }\n}\n");
+
+        let xml = compile_source(&src).unwrap();
+        assert_eq!(xml.matches("<letStatement>").count(), 300);
+        let class = Parser::from_source(&src).parse_class().unwrap();
+        let vm = CodeGenerator::generate(&class).unwrap();
+        let got = VmInterpreter::new(&vm).unwrap().run("Main.main").unwrap();
+        assert_eq!(got, 300);
+    }
+
+    #[test]
+    fn listeners_share_one_parse() {
+        struct Counting<I> {
+            tokens: I,
+            read: usize,
+        }
+        impl<I: Iterator> Iterator for Counting<I> {
+            type Item = I::Item;
+            fn next(&mut self) -> Option<I::Item> {
+                self.read += 1;
+                self.tokens.next()
+            }
+        }
+        struct Broken;
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let src = "class A { function void f() { do g(1 < 2); return; } }";
+        let mut tokenizer = Counting {
+            tokens: StreamTokenizer::from_source(src),
+            read: 0,
+        };
+        let (mut xml, mut tokens, mut sexp) = (Vec::new(), Vec::new(), Vec::new());
+        let mut tokens_listener = TokensListener::new(&mut tokens);
+        let mut broken = SexpListener::new(Broken);
+        let mut sexp_listener = SexpListener::new(&mut sexp);
+        let mut engine = CompilationEngine::new(&mut xml, &mut tokenizer)
+            .with_listener(&mut tokens_listener)
+            .with_listener(&mut broken)
+            .with_listener(&mut sexp_listener);
+        engine.compile().unwrap();
+        let errors: Vec<bool> = engine
+            .listener_errors()
+            .iter()
+            .map(Option::is_some)
+            .collect();
+
+        // every token read once, and the end
+        assert_eq!(
+            tokenizer.read,
+            StreamTokenizer::from_source(src).count() + 1
+        );
+        assert_eq!(errors, [false, true, false]);
+        assert_eq!(
+            String::from_utf8(xml).unwrap(),
+            super::compile_source(src).unwrap()
+        );
+        let tokens = String::from_utf8(tokens).unwrap();
+        // 21 tokens between `<tokens>` and `</tokens>`
+        assert_eq!(tokens.lines().count(), 23);
+        assert!(tokens.contains("\n<symbol> &lt; </symbol>\n"), "{}", tokens);
+        let sexp = String::from_utf8(sexp).unwrap();
+        assert!(
+            sexp.starts_with("(class\n  (keyword \"class\")\n"),
+            "{}",
+            sexp
+        );
+        assert!(sexp.contains("(symbol \"<\")"), "{}", sexp);
+        assert!(sexp.ends_with("  (symbol \"}\"))\n"), "{}", sexp);
+        assert_eq!(sexp.matches('(').count(), sexp.matches(')').count());
+    }
+
+    #[test]
+    fn listeners_leave_out_failed_subroutines() {
+        let src = "class A { function void f() { let = 1; return; }
+            function void g() { return; } }";
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let (mut xml, mut sexp) = (Vec::new(), Vec::new());
+        let mut listener = SexpListener::new(&mut sexp);
+        let mut engine =
+            CompilationEngine::new(&mut xml, &mut tokenizer).with_listener(&mut listener);
+        assert_eq!(engine.compile_collecting(20).len(), 1);
+
+        let sexp = String::from_utf8(sexp).unwrap();
+        assert!(!sexp.contains("\"f\""), "{}", sexp);
+        assert!(sexp.contains("(identifier \"g\")"), "{}", sexp);
+    }
+
+    #[test]
+    fn a_batch_keeps_its_order_and_failures_apart() {
+        let sources = [
+            ("A.jack", "class A { function void f() { return; } }"),
+            ("B.jack", "class B { function void f() { let = 1; } }"),
+            ("C.jack", "class C { }"),
+        ]
+        .map(|(name, src)| (name.to_owned(), src.to_owned()));
+
+        let results = super::compile_batch(&sources);
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["A.jack", "B.jack", "C.jack"]);
+        assert_eq!(results[0].1.as_ref().unwrap(), &compile(&sources[0].1));
+        assert!(results[1].1.is_err());
+        assert_eq!(results[2].1.as_ref().unwrap(), &compile(&sources[2].1));
+    }
+
+    fn compile(src: &str) -> String {
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        CompilationEngine::new(&mut out, &mut tokenizer)
+            .compile()
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn errors_name_the_statement_they_are_in() {
+        let src = "class Foo {
+            function void bar() { let a = 1; let b = 2 return; }
+            method void baz() {
+                while (true) { let a = 1; if (a) { do f(); let = 2; } }
+                return;
+            }
+            function void qux(int) { return; }
+        }";
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let got: Vec<String> = CompilationEngine::new(&mut out, &mut tokenizer)
+            .compile_collecting(5)
+            .into_iter()
+            .map(|d| d.message)
+            .collect();
+
+        assert_eq!(got.len(), 3, "{:#?}", got);
+        assert!(
+            got[0].starts_with("in Foo.bar, statement 2: "),
+            "{}",
+            got[0]
+        );
+        assert!(got[0].contains("should print ;"), "{}", got[0]);
+        assert!(
+            got[1].starts_with("in Foo.baz, statement 1.2.2: "),
+            "{}",
+            got[1]
+        );
+        assert!(got[2].starts_with("in Foo.qux: "), "{}", got[2]);
+    }
+
+    #[test]
+    fn typos_of_statement_keywords_are_suggested() {
+        let error = |src: &str, keywords: KeywordSet| {
+            let mut tokenizer = StreamTokenizer::from_source(src).keywords(keywords.clone());
+            let mut out = Vec::new();
+            let errors = CompilationEngine::new(&mut out, &mut tokenizer)
+                .with_keywords(keywords)
+                .compile_collecting(5);
+            errors[0].message.clone()
+        };
+        let body = |statement: &str| {
+            format!(
+                "class Main {{ function void f() {{ {} return; }} }}",
+                statement
+            )
+        };
+
+        let got = error(&body("Whiel (true) { }"), KeywordSet::base());
+        assert!(got.contains("did you mean `while`?"), "{}", got);
+
+        // `for` is only offered while it is a keyword
+        let got = error(&body("fro (i) { }"), KeywordSet::base());
+        assert!(!got.contains("did you mean"), "{}", got);
+        let got = error(&body("fro (i) { }"), KeywordSet::base().for_loops(true));
+        assert!(got.contains("did you mean `for`?"), "{}", got);
+
+        let got = error(&body("i = 1;"), KeywordSet::base());
+        assert!(!got.contains("did you mean"), "{}", got);
+    }
+
+    #[test]
+    fn unknown_character_in_argument_list_terminates() {
+        let src = "class Main { function void main() { do foo(a, $ b); return; } }";
+        let start = std::time::Instant::now();
+
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let got = CompilationEngine::new(&mut out, &mut tokenizer).compile();
+        assert!(got.is_err(), "{:?} should be an error", got);
+        assert!(start.elapsed().as_millis() < 500);
+    }
+
+    #[test]
+    fn compile_collecting_stops_at_max_errors() {
+        let src = format!(
+            "class Main {{ function void main() {{ {} return; }} }}",
+            "let = 1; ".repeat(30)
+        );
+
+        let mut tokenizer = StreamTokenizer::from_source(&src);
+        let mut out = Vec::new();
+        let got = CompilationEngine::new(&mut out, &mut tokenizer).compile_collecting(5);
+        assert_eq!(got.len(), 6, "{:#?}", got);
+        assert!(got[..5].iter().all(|d| d.severity == Severity::Error));
+        assert_eq!(got[5].code, TOO_MANY_ERRORS);
+    }
+
+    #[test]
+    fn compile_collecting_without_a_limit() {
+        let src = format!(
+            "class Main {{ function void main() {{ {} return; }} }}",
+            "let = 1; ".repeat(30)
+        );
+
+        let mut tokenizer = StreamTokenizer::from_source(&src);
+        let mut out = Vec::new();
+        let got = CompilationEngine::new(&mut out, &mut tokenizer).compile_collecting(0);
+        assert_eq!(got.len(), 30, "{:#?}", got);
+        assert!(got.iter().all(|d| d.code != TOO_MANY_ERRORS));
+    }
+
+    #[test]
+    fn recovered_errors_are_placed_at_the_failing_token() {
+        let src =
+            "class Main {\n  function void main() {\n    let = 1;\n    do = ;\n    return;\n  }\n}";
+        let spans = StreamTokenizer::from_source(src)
+            .into_spanned()
+            .map(|t| t.unwrap().span)
+            .collect();
+
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let got = CompilationEngine::new(&mut out, &mut tokenizer)
+            .with_token_spans(spans)
+            .compile_collecting(20);
+        let positions: Vec<String> = got.iter().map(|d| d.span.to_string()).collect();
+        assert_eq!(positions, ["3:9", "4:8"], "{:#?}", got);
+    }
+
+    #[test]
+    fn compile_collecting_recovers_after_broken_statement() {
+        let src = "class Main { function void main() { let = 1; let x = 2; do = ; return; } }";
+
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let got = CompilationEngine::new(&mut out, &mut tokenizer).compile_collecting(20);
+        assert_eq!(got.len(), 2, "{:#?}", got);
+    }
+
+    #[test]
+    fn output_does_not_start_with_blank_line() {
+        let got = compile("class Main { }");
+
+        let should_start_with =
+            "<class> \n<keyword> class </keyword>\n\n<identifier> Main </identifier>\n";
+        assert!(got.starts_with(should_start_with), "Output was {:?}", got);
+    }
+
+    // counts flushes, the output itself is thrown away
+    #[derive(Default)]
+    struct FlushCounter {
+        flushes: usize,
+    }
+
+    impl Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flush_per_subroutine() {
+        let src = "class Main { function void a() { return; }
+            function void b() { return; } method void c() { return; } }";
+
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut writer = FlushCounter::default();
+        CompilationEngine::new(&mut writer, &mut tokenizer)
+            .with_options(CompileOptions {
+                flush_per_subroutine: true,
+                ..Default::default()
+            })
+            .compile()
+            .unwrap();
+        assert_eq!(writer.flushes, 3);
+
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut writer = FlushCounter::default();
+        CompilationEngine::new(&mut writer, &mut tokenizer)
+            .compile()
+            .unwrap();
+        assert_eq!(writer.flushes, 0);
+    }
+
+    #[test]
+    fn broken_subroutine_does_not_void_the_class() {
+        let src = "class Main { function void a() { return; }
+            method void b(int) { return; }
+            function void c() { return; } }";
+
+        let compile = |failed_subroutines| {
+            let mut tokenizer = StreamTokenizer::from_source(src);
+            let mut out = Vec::new();
+            let mut engine =
+                CompilationEngine::new(&mut out, &mut tokenizer).with_options(CompileOptions {
+                    failed_subroutines,
+                    ..Default::default()
+                });
+            let diagnostics = engine.compile_collecting(20);
+            let failed: Vec<String> = engine.class_info().failed().map(str::to_owned).collect();
+            (String::from_utf8(out).unwrap(), diagnostics, failed)
+        };
+
+        let (xml, diagnostics, failed) = compile(FailedSubroutines::Placeholder);
+        assert_eq!(diagnostics.len(), 1, "{:#?}", diagnostics);
+        assert_eq!(failed, ["b"]);
+        assert!(xml.contains("<identifier> a </identifier>"), "{}", xml);
+        assert!(xml.contains("<identifier> c </identifier>"), "{}", xml);
+        assert!(!xml.contains("<identifier> b </identifier>"), "{}", xml);
+        assert!(xml.contains("<invalidSubroutineDec> b </invalidSubroutineDec>"));
+        assert!(xml.trim_end().ends_with("</class>"), "{}", xml);
+
+        let (xml, diagnostics, _) = compile(FailedSubroutines::Omit);
+        assert_eq!(diagnostics.len(), 1, "{:#?}", diagnostics);
+        assert!(!xml.contains("invalidSubroutineDec"), "{}", xml);
+        assert!(xml.contains("<identifier> c </identifier>"), "{}", xml);
+    }
+
+    #[test]
+    fn declarations_record_their_types() {
+        let src = "class Main { static int count; field char c, d; field boolean done;
+            field Point origin;
+            constructor Main new(Point p, int n) { var Array a, b; var boolean ok; return this; }
+            function void run() { return; }
+            method int bad(int) { return 0; } }";
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let mut engine = CompilationEngine::new(&mut out, &mut tokenizer);
+        engine.compile_collecting(20);
+
+        let vars = |vars: &[CompiledVar]| -> Vec<(String, SymbolKind, String)> {
+            vars.iter()
+                .map(|v| (v.name.clone(), v.kind, v.var_type.to_string()))
+                .collect()
+        };
+        let var = |name: &str, kind, var_type: &str| (name.to_owned(), kind, var_type.to_owned());
+        let info = engine.class_info();
+        assert_eq!(
+            vars(&info.class_vars),
+            [
+                var("count", SymbolKind::Static, "int"),
+                var("c", SymbolKind::Field, "char"),
+                var("d", SymbolKind::Field, "char"),
+                var("done", SymbolKind::Field, "boolean"),
+                var("origin", SymbolKind::Field, "Point"),
+            ]
+        );
+
+        let [new, run, bad] = &info.subroutines[..] else {
+            panic!("{:?}", info.subroutines);
+        };
+        assert_eq!(
+            new.return_type,
+            Some(JackType::ClassName("Main".to_owned()))
+        );
+        assert_eq!(
+            vars(&new.vars),
+            [
+                var("p", SymbolKind::Arg, "Point"),
+                var("n", SymbolKind::Arg, "int"),
+                var("a", SymbolKind::Var, "Array"),
+                var("b", SymbolKind::Var, "Array"),
+                var("ok", SymbolKind::Var, "boolean"),
+            ]
+        );
+        assert_eq!(run.return_type, Some(JackType::Void));
+        assert_eq!(info.return_type("bad"), Some(&JackType::Int));
+        assert!(run.vars.is_empty());
+        // failed in its parameter list, after the return type
+        assert!(bad.failed);
+        assert_eq!(bad.return_type, Some(JackType::Int));
+    }
+
+    #[test]
+    fn this_receiver_is_an_error_unless_allowed() {
+        let compile = |src: &str, allow_this_receiver| {
+            let mut tokenizer = StreamTokenizer::from_source(src);
+            let mut out = Vec::new();
+            let errors: Vec<String> = CompilationEngine::new(&mut out, &mut tokenizer)
+                .with_options(CompileOptions {
+                    allow_this_receiver,
+                    ..Default::default()
+                })
+                .compile_collecting(5)
+                .into_iter()
+                .map(|d| d.message)
+                .collect();
+            (String::from_utf8(out).unwrap(), errors)
+        };
+        let src = "class Main { method void f() {
+            do this.draw(1); let x = this.size() + 1; return; } }";
+
+        let (_, errors) = compile(src, false);
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(
+            errors[0].ends_with(
+                "`this` can't be the receiver of a call, methods of the current class are called bare, as `draw(...)`"
+            ),
+            "{}",
+            errors[0]
+        );
+        assert!(errors[1].ends_with("as `size(...)`"), "{}", errors[1]);
+
+        let (xml, errors) = compile(src, true);
+        assert!(errors.is_empty(), "{:?}", errors);
+        let (bare, _) = compile(
+            "class Main { method void f() {
+            do draw(1); let x = size() + 1; return; } }",
+            false,
+        );
+        assert_eq!(xml, bare);
+        assert_eq!(
+            compile("class Main { method Main f() { return this; } }", false).1,
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn empty_statements_need_the_option() {
+        let src = "class Main { function void main() { while (x) { ; } return; } }";
+
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        CompilationEngine::new(&mut out, &mut tokenizer)
+            .with_options(CompileOptions {
+                allow_empty_statements: true,
+                ..Default::default()
+            })
+            .compile()
+            .unwrap();
+        let xml = String::from_utf8(out).unwrap();
+        assert!(xml.contains("\n<emptyStatement/>\n"), "{}", xml);
+
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let got = CompilationEngine::new(&mut out, &mut tokenizer).compile();
+        assert!(got.is_err(), "{:?} should be an error", got);
+    }
+
+    #[test]
+    fn empty_parameter_list() {
+        let got = compile("class Main { function void main() { return; } }");
+
+        let should_contain =
+            "<symbol> ( </symbol>\n\n<parameterList>\n</parameterList>\n\n<symbol> ) </symbol>";
+        assert!(got.contains(should_contain), "Output was {:?}", got);
+    }
+
+    #[test]
+    fn empty_expression_list() {
+        let got = compile("class Main { function void main() { do f(); return; } }");
+
+        let should_contain =
+            "<symbol> ( </symbol>\n\n<expressionList>\n</expressionList>\n\n<symbol> ) </symbol>";
+        assert!(got.contains(should_contain), "Output was {:?}", got);
+    }
+
+    #[test]
+    fn empty_statements_in_else_branch() {
+        let got =
+            compile("class Main { function void main() { if (x) { return; } else { } return; } }");
+
+        let should_contain = "<keyword> else </keyword>\n\n<symbol> { </symbol>\n\n\
+            <statements>\n</statements>\n\n<symbol> } </symbol>";
+        assert!(got.contains(should_contain), "Output was {:?}", got);
+    }
+
+    #[test]
+    fn declarations_are_only_opened_when_present() {
+        let got = compile("class A { field int x; function void f() { return; } }");
+
+        let expected = "<class> \n\
+            <keyword> class </keyword>\n\n\
+            <identifier> A </identifier>\n\n\
+            <symbol> { </symbol>\n\n\
+            <classVarDec> \n\
+            <keyword> field </keyword>\n\n\
+            <keyword> int </keyword>\n\n\
+            <identifier> x </identifier>\n\n\
+            <symbol> ; </symbol>\n \
+            </classVarDec>\n\n\
+            <subroutineDec> \n\
+            <keyword> function </keyword>\n\n\
+            <keyword> void </keyword>\n\n\
+            <identifier> f </identifier>\n\n\
+            <symbol> ( </symbol>\n\n\
+            <parameterList>\n\
+            </parameterList>\n\n\
+            <symbol> ) </symbol>\n\n\
+            <subroutineBody> \n\
+            <symbol> { </symbol>\n\n\
+            <statements> \n\
+            <returnStatement> \n\
+            <keyword> return </keyword>\n\n\
+            <symbol> ; </symbol>\n \
+            </returnStatement>\n \
+            </statements>\n\n\
+            <symbol> } </symbol>\n \
+            </subroutineBody>\n \
+            </subroutineDec>\n\n\
+            <symbol> } </symbol>\n \
+            </class>\n";
+        assert_eq!(got, expected);
+
+        // a class without fields or subroutines has neither element
+        assert_eq!(
+            compile("class B { }"),
+            "<class> \n<keyword> class </keyword>\n\n<identifier> B </identifier>\n\n\
+             <symbol> { </symbol>\n\n<symbol> } </symbol>\n </class>\n"
+        );
+    }
+
+    #[test]
+    fn parameters_are_written() {
+        let got = compile("class Main { function void f(int a, Point b) { return; } }");
+
+        assert!(
+            got.contains("<identifier> a </identifier>"),
+            "Output was {:?}",
+            got
+        );
+        assert!(
+            got.contains("<identifier> Point </identifier>"),
+            "Output was {:?}",
+            got
+        );
+    }
+
+    #[test]
+    fn unary_and_parenthesized_terms() {
+        let got = compile("class Main { function int f() { return -(x); } }");
+
+        let should_contain = "<keyword> return </keyword>\n\n<expression> \n<term> \n\
+            <symbol> - </symbol>\n\n<term> \n<symbol> ( </symbol>\n\n<expression> \n\
+            <term> \n<identifier> x </identifier>\n </term>\n </expression>\n\n\
+            <symbol> ) </symbol>\n </term>\n </term>\n </expression>";
+        assert!(got.contains(should_contain), "Output was {:?}", got);
+    }
+
+    #[test]
+    fn smallest_int_constant() {
+        let got = compile("class Main { function int f() { return -32768; } }");
+
+        let should_contain = "<symbol> - </symbol>\n\n<term> \n\
+            <integerConstant> 32768 </integerConstant>\n </term>\n </term>";
+        assert!(got.contains(should_contain), "Output was {:?}", got);
+    }
+
+    // the let statement of `src` as one `kind value` line per node, indented
+    // by its depth below the statement
+    fn let_outline(src: &str) -> Vec<String> {
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut records = Vec::new();
+        let mut listener = crate::RecordsListener::new(&mut records);
+        CompilationEngine::new(&mut Vec::new(), &mut tokenizer)
+            .with_listener(&mut listener)
+            .compile()
+            .unwrap();
+        let records = String::from_utf8(records).unwrap();
+        let rows: Vec<Vec<&str>> = records.lines().map(|l| l.split('\t').collect()).collect();
+        let start = rows.iter().position(|r| r[2] == "letStatement").unwrap();
+
+        let mut depths = vec![(rows[start][0], 0)];
+        let mut outline = Vec::new();
+        for row in &rows[start + 1..] {
+            let Some(&(_, depth)) = depths.iter().find(|(id, _)| *id == row[1]) else {
+                break;
+            };
+            depths.push((row[0], depth + 1));
+            outline.push(format!("{}{} {}", "  ".repeat(depth), row[2], row[3]));
+        }
+        outline
+    }
+
+    #[test]
+    fn calls_in_array_index_and_value_of_let() {
+        let got = let_outline(
+            "class Main { function void f() { let a[foo(i, a[j])] = b.bar(a[k] + 1); return; } }",
+        );
+
+        let expected = [
+            "keyword let",
+            "identifier a",
+            "symbol [",
+            "expression ",
+            "  term ",
+            "    identifier foo",
+            "    symbol (",
+            "    expressionList ",
+            "      expression ",
+            "        term ",
+            "          identifier i",
+            "      symbol ,",
+            "      expression ",
+            "        term ",
+            "          identifier a",
+            "          symbol [",
+            "          expression ",
+            "            term ",
+            "              identifier j",
+            "          symbol ]",
+            "    symbol )",
+            "symbol ]",
+            "symbol =",
+            "expression ",
+            "  term ",
+            "    identifier b",
+            "    symbol .",
+            "    identifier bar",
+            "    symbol (",
+            "    expressionList ",
+            "      expression ",
+            "        term ",
+            "          identifier a",
+            "          symbol [",
+            "          expression ",
+            "            term ",
+            "              identifier k",
+            "          symbol ]",
+            "        symbol +",
+            "        term ",
+            "          integerConstant 1",
+            "    symbol )",
+            "symbol ;",
+        ];
+        assert_eq!(got, expected);
+
+        let got =
+            let_outline("class Main { function void f() { let a[foo()] = b.bar(); return; } }");
+        assert_eq!(
+            got,
+            [
+                "keyword let",
+                "identifier a",
+                "symbol [",
+                "expression ",
+                "  term ",
+                "    identifier foo",
+                "    symbol (",
+                "    expressionList ",
+                "    symbol )",
+                "symbol ]",
+                "symbol =",
+                "expression ",
+                "  term ",
+                "    identifier b",
+                "    symbol .",
+                "    identifier bar",
+                "    symbol (",
+                "    expressionList ",
+                "    symbol )",
+                "symbol ;",
+            ]
+        );
+    }
+
+    #[test]
+    fn expression_lists_separate_expressions_with_single_commas() {
+        let calls = |args: &str| {
+            let src = format!(
+                "class Main {{ function void f() {{ do foo({}); return; }} }}",
+                args
+            );
+            let mut out = Vec::new();
+            let mut tokenizer = StreamTokenizer::from_source(&src);
+            let errors: Vec<String> = CompilationEngine::new(&mut out, &mut tokenizer)
+                .compile_collecting(5)
+                .into_iter()
+                .map(|d| d.message)
+                .collect();
+            (String::from_utf8(out).unwrap(), errors)
+        };
+
+        let (xml, errors) = calls("a, b");
+        assert!(errors.is_empty(), "{:?}", errors);
+        let list =
+            &xml[xml.find("<expressionList>").unwrap()..xml.find("</expressionList>").unwrap()];
+        assert_eq!(list.matches("<expression>").count(), 2, "{}", list);
+        assert_eq!(list.matches("<symbol> , </symbol>").count(), 1, "{}", list);
+
+        let (xml, errors) = calls("");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(xml.contains("<expressionList>\n"), "{}", xml);
+
+        for args in [", a", "a,, b"] {
+            let (_, errors) = calls(args);
+            assert_eq!(errors.len(), 1, "{}: {:?}", args, errors);
+            assert!(
+                errors[0].ends_with("expected an expression, found `,`"),
+                "{}: {}",
+                args,
+                errors[0]
+            );
+        }
+    }
+
+    fn compile_with_spans(src: &str, options: CompileOptions) -> (String, Vec<ElementSpan>) {
+        let spans = StreamTokenizer::from_source(src)
+            .into_spanned()
+            .map(|t| t.unwrap().span)
+            .collect();
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let mut engine = CompilationEngine::new(&mut out, &mut tokenizer)
+            .with_options(options)
+            .with_token_spans(spans);
+        engine.compile().unwrap();
+        let elements = engine.element_spans();
+        (String::from_utf8(out).unwrap(), elements)
+    }
+
+    #[test]
+    fn while_statement_span() {
+        let src = "class Main {\n  function void f() {\n    while (x) {\n      let x = x - 1;\n    }\n    return;\n  }\n}\n";
+        let (xml, elements) = compile_with_spans(src, CompileOptions::default());
+
+        let whiles: Vec<&ElementSpan> = elements
+            .iter()
+            .filter(|e| e.tag == "whileStatement")
+            .collect();
+        assert_eq!(whiles.len(), 1);
+        assert_eq!(
+            &src[whiles[0].start..whiles[0].end],
+            "while (x) {\n      let x = x - 1;\n    }"
+        );
+        assert_eq!(elements[0].tag, "class");
+        assert_eq!((elements[0].start, elements[0].end), (0, src.len() - 1));
+        assert!(!xml.contains("start="), "Output was {:?}", xml);
+    }
+
+    #[test]
+    fn span_attributes() {
+        let src = "class Main { function void f() { while (x) { } return; } }";
+        let options = CompileOptions {
+            span_attributes: true,
+            ..Default::default()
+        };
+        let (xml, _) = compile_with_spans(src, options);
+
+        assert!(
+            xml.starts_with("<class start=\"0\" end=\"58\"> "),
+            "{:?}",
+            xml
+        );
+        assert!(
+            xml.contains("\n<whileStatement start=\"33\" end=\"46\"> \n<keyword> while"),
+            "Output was {:?}",
+            xml
+        );
+        // empty non-terminals hold no token and get no attributes
+        assert!(xml.contains("\n<statements>\n</statements>\n"), "{:?}", xml);
+    }
+
+    #[test]
+    fn span_attributes_skip_failed_subroutines() {
+        let src = "class Main { function void f() { let = 1; return; }
+            function void g() { return; } }";
+        let spans = StreamTokenizer::from_source(src)
+            .into_spanned()
+            .map(|t| t.unwrap().span)
+            .collect();
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let mut engine = CompilationEngine::new(&mut out, &mut tokenizer)
+            .with_options(CompileOptions {
+                span_attributes: true,
+                ..Default::default()
+            })
+            .with_token_spans(spans);
+        assert_eq!(engine.compile_collecting(5).len(), 1);
+        let got = String::from_utf8(out).unwrap();
+
+        let g = src.find("function void g").unwrap();
+        let g_end = src.rfind('}').unwrap() - 1;
+        let should_contain = format!("<subroutineDec start=\"{}\" end=\"{}\">", g, g_end);
+        assert!(got.contains(&should_contain), "Output was {:?}", got);
+        assert_eq!(got.matches("<subroutineDec start").count(), 1, "{:?}", got);
+    }
+
+    // the diagnostics of compile_collecting, with the token spans given
+    fn collect(src: &str) -> Vec<String> {
+        let spans = StreamTokenizer::from_source(src)
+            .into_spanned()
+            .map(|t| t.unwrap().span)
+            .collect();
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        CompilationEngine::new(&mut out, &mut tokenizer)
+            .with_token_spans(spans)
+            .compile_collecting(20)
+            .iter()
+            .filter(|d| d.code == UNBALANCED_DELIMITER)
+            .map(|d| d.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn extra_close_brace_in_subroutine() {
+        let got =
+            collect("class Main {\n  function void f() {\n    let a = 1; }\n    return;\n  }\n}\n");
+
+        assert_eq!(
+            got,
+            ["3:16: error[J005]: stray `}`: the statements after it still belong to the body opened at 2:21"],
+            "{:#?}",
+            got
+        );
+    }
+
+    #[test]
+    fn missing_close_paren_in_condition() {
+        let got = collect(
+            "class Main {\n  function void f() {\n    if (x {\n      return;\n    }\n    return;\n  }\n}\n",
+        );
+
+        assert_eq!(
+            got,
+            ["7:3: error[J005]: found `}` but the nearest unclosed delimiter is `(` opened at 3:8"],
+            "{:#?}",
+            got
+        );
+    }
+
+    #[test]
+    fn unclosed_braces_at_end_of_input() {
+        let got =
+            collect("class Main {\n  function void f() {\n    while (true) {\n      return;\n");
+
+        assert_eq!(
+            got,
+            [
+                "1:12: note[J005]: `{` opened at 1:12 is never closed",
+                "2:21: note[J005]: `{` opened at 2:21 is never closed",
+                "3:18: note[J005]: `{` opened at 3:18 is never closed",
+            ],
+            "{:#?}",
+            got
+        );
+    }
+
+    #[test]
+    fn class_without_closing_brace() {
+        let src = "class Foo {\n  function void f() {\n    return;\n  }\n";
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let err = CompilationEngine::new(&mut out, &mut tokenizer)
+            .compile()
+            .unwrap_err();
+
+        let diagnostic = err.downcast::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.code, UNEXPECTED_EOF);
+        assert_eq!(
+            diagnostic.message,
+            "expected '}' to close class 'Foo', reached end of input"
+        );
+    }
+
+    fn misnested(
+        misnest: (&'static str, &'static str),
+        panic: bool,
+        collecting: bool,
+    ) -> Vec<Diagnostic> {
+        let src = "class Main { function void f() { let x = 1; return; } }";
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let mut engine = CompilationEngine::new(&mut out, &mut tokenizer);
+        engine.panic_on_bad_nesting = panic;
+        engine.misnest = Some(misnest);
+        match collecting {
+            true => engine.compile_collecting(10),
+            false => engine
+                .compile()
+                .err()
+                .map(|e| e.downcast::<Diagnostic>().unwrap())
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "`</whileStatement>` closes `<letStatement>`")]
+    fn misnested_tags_panic_in_debug_builds() {
+        misnested(("letStatement", "whileStatement"), true, false);
+    }
+
+    #[test]
+    fn misnested_tags_are_internal_errors_otherwise() {
+        for collecting in [false, true] {
+            let got = misnested(("letStatement", "whileStatement"), false, collecting);
+            assert_eq!(got.len(), 1, "{:?}", got);
+            assert_eq!(got[0].code, INTERNAL_ERROR);
+            assert_eq!(
+                got[0].message,
+                "internal error: `</whileStatement>` closes `<letStatement>`"
+            );
+        }
+
+        // a tag left open when the class is done
+        let mut tokenizer = StreamTokenizer::from_source("class Main { }");
+        let mut out = Vec::new();
+        let mut engine = CompilationEngine::new(&mut out, &mut tokenizer);
+        engine.panic_on_bad_nesting = false;
+        engine.write_opening_tag("dangling");
+        let err = engine.compile().unwrap_err();
+        assert_eq!(
+            err.downcast::<Diagnostic>().unwrap().message,
+            "internal error: `<dangling>` is never closed"
+        );
+    }
+
+    #[test]
+    fn fragments_compile_on_their_own() {
+        assert_eq!(Fragment::of("1 + x"), Fragment::Expression);
+        assert_eq!(Fragment::of("  let a = 1;"), Fragment::Statements);
+        assert_eq!(Fragment::of("class A { }"), Fragment::Class);
+
+        let xml = compile_fragment_source("1 + 2 * x", None).unwrap();
+        assert!(xml.trim_start().starts_with("<expression>"), "{}", xml);
+        assert_eq!(xml.matches("<term>").count(), 3);
+
+        let mut sexp = Vec::new();
+        let mut listener = SexpListener::new(&mut sexp);
+        compile_fragment_source("let a[i] = b;", Some(&mut listener)).unwrap();
+        let sexp = String::from_utf8(sexp).unwrap();
+        assert!(sexp.starts_with("(statements\n  (letStatement"), "{}", sexp);
+
+        // the parser's error, with where it is
+        let err = compile_fragment_source("1 + ;", None).unwrap_err();
+        let diagnostic = err.downcast::<Diagnostic>().unwrap();
+        assert_eq!((diagnostic.span.line, diagnostic.span.column), (1, 5));
+
+        assert!(compile_fragment_source("1 2", None).is_err());
     }
 }
@@ -0,0 +1,298 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    ast::{Class, Expression, ExpressionKind, Statement, SubroutineCall, SubroutineKind},
+    json,
+};
+
+// the parts of the language a class uses beyond what the expressionless
+// programs of project 10 need
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    ArrayIndexing,
+    StringConstants,
+    UnaryOps,
+    CompoundExpressions,
+    MethodCalls,
+    Constructors,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 6] = [
+        Feature::ArrayIndexing,
+        Feature::StringConstants,
+        Feature::UnaryOps,
+        Feature::CompoundExpressions,
+        Feature::MethodCalls,
+        Feature::Constructors,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::ArrayIndexing => "array_indexing",
+            Feature::StringConstants => "string_constants",
+            Feature::UnaryOps => "unary_ops",
+            Feature::CompoundExpressions => "compound_expressions",
+            Feature::MethodCalls => "method_calls",
+            Feature::Constructors => "constructors",
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+// what the calls of the subroutine being scanned are resolved against
+struct Scope<'c> {
+    // the class's variables and the subroutine's parameters and locals
+    vars: BTreeSet<&'c str>,
+    // whether a call without a receiver is a method call on `this`
+    on_this: bool,
+}
+
+// a bitmap of the features found in a class, bit n is Feature::ALL[n]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features(pub u8);
+
+impl Features {
+    pub fn of(class: &Class) -> Self {
+        let mut features = Self::default();
+        let class_vars = class
+            .class_vars
+            .iter()
+            .flat_map(|dec| &dec.names)
+            .map(|name| name.name.as_str());
+        for subroutine in &class.subroutines {
+            if subroutine.kind == SubroutineKind::Constructor {
+                features.insert(Feature::Constructors);
+            }
+            let params = subroutine.params.iter().map(|param| &param.name);
+            let locals = subroutine.locals.iter().flat_map(|dec| &dec.names);
+            let scope = Scope {
+                vars: class_vars
+                    .clone()
+                    .chain(params.chain(locals).map(|name| name.name.as_str()))
+                    .collect(),
+                // a constructor's bare calls are on the object it builds
+                on_this: subroutine.kind != SubroutineKind::Function,
+            };
+            features.statements(&subroutine.statements, &scope);
+        }
+        features
+    }
+
+    pub fn contains(&self, feature: Feature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Feature> + '_ {
+        Feature::ALL.into_iter().filter(|f| self.contains(*f))
+    }
+
+    // the names as a json list, `["unary_ops", "constructors"]`
+    pub fn to_json(&self) -> String {
+        let names: Vec<String> = self.iter().map(|f| json::string(f.name())).collect();
+        format!("[{}]", names.join(", "))
+    }
+
+    fn insert(&mut self, feature: Feature) {
+        self.0 |= feature.bit();
+    }
+
+    fn statements(&mut self, statements: &[Statement], scope: &Scope) {
+        for statement in statements {
+            match statement {
+                Statement::Let { index, value, .. } => {
+                    if let Some(index) = index {
+                        self.insert(Feature::ArrayIndexing);
+                        self.expression(index, scope);
+                    }
+                    self.expression(value, scope);
+                }
+                Statement::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                    ..
+                } => {
+                    self.expression(condition, scope);
+                    self.statements(then_branch, scope);
+                    if let Some(else_branch) = else_branch {
+                        self.statements(else_branch, scope);
+                    }
+                }
+                Statement::While {
+                    condition, body, ..
+                } => {
+                    self.expression(condition, scope);
+                    self.statements(body, scope);
+                }
+                Statement::Do { call, .. } => self.call(call, scope),
+                Statement::Return { value, .. } => {
+                    if let Some(value) = value {
+                        self.expression(value, scope);
+                    }
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression, scope: &Scope) {
+        match &expression.kind {
+            ExpressionKind::Index(_, index) => {
+                self.insert(Feature::ArrayIndexing);
+                self.expression(index, scope);
+            }
+            ExpressionKind::StringConst(_) => self.insert(Feature::StringConstants),
+            ExpressionKind::Unary(_, operand) => {
+                self.insert(Feature::UnaryOps);
+                self.expression(operand, scope);
+            }
+            ExpressionKind::Binary(_, lhs, rhs) => {
+                self.insert(Feature::CompoundExpressions);
+                self.expression(lhs, scope);
+                self.expression(rhs, scope);
+            }
+            ExpressionKind::Paren(inner) => self.expression(inner, scope),
+            ExpressionKind::Call(call) => self.call(call, scope),
+            ExpressionKind::IntConst(_)
+            | ExpressionKind::KeywordConst(_)
+            | ExpressionKind::Var(_) => {}
+        }
+    }
+
+    fn call(&mut self, call: &SubroutineCall, scope: &Scope) {
+        // `Class.f()` is a function call, `a.f()` with `a` a variable and a
+        // bare `f()` on `this` are method calls
+        let method = match &call.receiver {
+            Some(receiver) => scope.vars.contains(receiver.name.as_str()),
+            None => scope.on_this,
+        };
+        if method {
+            self.insert(Feature::MethodCalls);
+        }
+        for arg in &call.args {
+            self.expression(arg, scope);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Parser;
+
+    use super::{Feature, Features};
+
+    fn features(src: &str) -> Vec<Feature> {
+        Features::of(&Parser::from_source(src).parse_class().unwrap())
+            .iter()
+            .collect()
+    }
+
+    #[test]
+    fn expressionless_code_uses_no_expression_features() {
+        // from ExpressionLessSquare, every expression is a single term
+        let got = features(
+            "class Main {
+                static boolean test;
+                function void main() {
+                    var SquareGame game;
+                    let game = game;
+                    do game.run();
+                    do game.dispose();
+                    return;
+                }
+                function void more() {
+                    var boolean b;
+                    if (b) { } else { }
+                    return;
+                }
+            }",
+        );
+
+        assert_eq!(got, [Feature::MethodCalls]);
+    }
+
+    #[test]
+    fn full_square_uses_most_features() {
+        let got = features(
+            "class Square {
+                field int x, y, size;
+                constructor Square new(int Ax, int Ay, int Asize) {
+                    let x = Ax; let y = Ay; let size = Asize;
+                    do draw();
+                    return this;
+                }
+                method void draw() {
+                    do Screen.setColor(true);
+                    do Screen.drawRectangle(x, y, x + size, y + size);
+                    return;
+                }
+                method void moveUp() {
+                    if (y > 1) {
+                        do Screen.setColor(false);
+                        let y = y - 2;
+                    }
+                    if (~(size = 0)) { do Output.printString(\"moved\"); }
+                    return;
+                }
+            }",
+        );
+
+        assert_eq!(
+            got,
+            [
+                Feature::StringConstants,
+                Feature::UnaryOps,
+                Feature::CompoundExpressions,
+                Feature::MethodCalls,
+                Feature::Constructors,
+            ]
+        );
+    }
+
+    #[test]
+    fn function_calls_are_not_method_calls() {
+        let got = features(
+            "class Main {
+                function void main() {
+                    do Output.printInt(Main.double(1));
+                    do double(2);
+                    return;
+                }
+                function int double(int n) { return Math.multiply(n, 2); }
+            }",
+        );
+        assert_eq!(got, []);
+
+        let got = features(
+            "class Main {
+                field Array items;
+                method void f() { do g(); return; }
+                method void g() { return; }
+            }",
+        );
+        assert_eq!(got, [Feature::MethodCalls]);
+
+        let got = features(
+            "class Main {
+                static Array items;
+                function void f() { do items.dispose(); return; }
+            }",
+        );
+        assert_eq!(got, [Feature::MethodCalls]);
+    }
+
+    #[test]
+    fn json_list() {
+        let got = Features::of(
+            &Parser::from_source("class A { function void f() { let a[0] = -1; return; } }")
+                .parse_class()
+                .unwrap(),
+        );
+
+        assert_eq!(got.0, 0b101);
+        assert_eq!(got.to_json(), "[\"array_indexing\", \"unary_ops\"]");
+    }
+}
@@ -0,0 +1,248 @@
+use crate::{
+    ast::{
+        Class, ClassVarKind, Expression, ExpressionKind, Statement, SubroutineCall, SubroutineDec,
+        SubroutineKind,
+    },
+    lexical_elements::Symbols,
+};
+
+const INDENT: &str = "    ";
+
+// writes a parsed class back out as jack source in one canonical layout.
+// comments aren't part of the tree, so they don't survive
+pub fn format_class(class: &Class) -> String {
+    let mut formatter = Formatter {
+        out: String::new(),
+        depth: 0,
+    };
+    formatter.class(class);
+    formatter.out
+}
+
+struct Formatter {
+    out: String,
+    depth: usize,
+}
+
+impl Formatter {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn class(&mut self, class: &Class) {
+        self.line(&format!("class {} {{", class.name.name));
+        self.depth += 1;
+
+        for dec in &class.class_vars {
+            let kind = match dec.kind {
+                ClassVarKind::Static => "static",
+                ClassVarKind::Field => "field",
+            };
+            let names: Vec<&str> = dec.names.iter().map(|n| n.name.as_str()).collect();
+            self.line(&format!("{} {} {};", kind, dec.var_type, names.join(", ")));
+        }
+        for (i, subroutine) in class.subroutines.iter().enumerate() {
+            if i > 0 || !class.class_vars.is_empty() {
+                self.out.push('\n');
+            }
+            self.subroutine(subroutine);
+        }
+
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn subroutine(&mut self, subroutine: &SubroutineDec) {
+        let kind = match subroutine.kind {
+            SubroutineKind::Constructor => "constructor",
+            SubroutineKind::Function => "function",
+            SubroutineKind::Method => "method",
+        };
+        let params: Vec<String> = subroutine
+            .params
+            .iter()
+            .map(|p| format!("{} {}", p.var_type, p.name.name))
+            .collect();
+        self.line(&format!(
+            "{} {} {}({}) {{",
+            kind,
+            subroutine.return_type,
+            subroutine.name.name,
+            params.join(", ")
+        ));
+        self.depth += 1;
+
+        for dec in &subroutine.locals {
+            let names: Vec<&str> = dec.names.iter().map(|n| n.name.as_str()).collect();
+            self.line(&format!("var {} {};", dec.var_type, names.join(", ")));
+        }
+        if !subroutine.locals.is_empty() && !subroutine.statements.is_empty() {
+            self.out.push('\n');
+        }
+        self.statements(&subroutine.statements);
+
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn block(&mut self, statements: &[Statement]) {
+        self.depth += 1;
+        self.statements(statements);
+        self.depth -= 1;
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let {
+                target,
+                index,
+                value,
+                ..
+            } => {
+                let index = match index {
+                    Some(index) => format!("[{}]", expression(index)),
+                    None => String::new(),
+                };
+                self.line(&format!(
+                    "let {}{} = {};",
+                    target.name,
+                    index,
+                    expression(value)
+                ));
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.line(&format!("if ({}) {{", expression(condition)));
+                self.block(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        self.line("} else {");
+                        self.block(else_branch);
+                        self.line("}");
+                    }
+                    None => self.line("}"),
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                self.line(&format!("while ({}) {{", expression(condition)));
+                self.block(body);
+                self.line("}");
+            }
+            Statement::Do { call, .. } => self.line(&format!("do {};", subroutine_call(call))),
+            Statement::Return { value, .. } => match value {
+                Some(value) => self.line(&format!("return {};", expression(value))),
+                None => self.line("return;"),
+            },
+        }
+    }
+}
+
+fn expression(expr: &Expression) -> String {
+    match &expr.kind {
+        ExpressionKind::IntConst(i) => i.to_string(),
+        ExpressionKind::StringConst(s) => format!("\"{}\"", s),
+        ExpressionKind::KeywordConst(keyword) => keyword.to_string(),
+        ExpressionKind::Var(ident) => ident.name.clone(),
+        ExpressionKind::Index(ident, index) => format!("{}[{}]", ident.name, expression(index)),
+        ExpressionKind::Call(call) => subroutine_call(call),
+        ExpressionKind::Paren(inner) => format!("({})", expression(inner)),
+        ExpressionKind::Unary(op, operand) => format!("{}{}", operator(op), expression(operand)),
+        ExpressionKind::Binary(op, lhs, rhs) => {
+            format!("{} {} {}", expression(lhs), operator(op), expression(rhs))
+        }
+    }
+}
+
+fn subroutine_call(call: &SubroutineCall) -> String {
+    let args: Vec<String> = call.args.iter().map(expression).collect();
+    match &call.receiver {
+        Some(receiver) => format!("{}.{}({})", receiver.name, call.name.name, args.join(", ")),
+        None => format!("{}({})", call.name.name, args.join(", ")),
+    }
+}
+
+// the symbols as written in source, their display form is escaped for xml
+pub(crate) fn operator(op: &Symbols) -> &'static str {
+    match op {
+        Symbols::Plus => "+",
+        Symbols::Minus => "-",
+        Symbols::Asterik => "*",
+        Symbols::BackSlash => "/",
+        Symbols::Ampersand => "&",
+        Symbols::VerticalBar => "|",
+        Symbols::LessThan => "<",
+        Symbols::GreaterThan => ">",
+        Symbols::Tilde => "~",
+        _ => "=",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Parser;
+
+    use super::format_class;
+
+    fn format(src: &str) -> String {
+        format_class(&Parser::from_source(src).parse_class().unwrap())
+    }
+
+    #[test]
+    fn canonical_layout() {
+        let got = format(
+            "class Main { field int x,y; function void main() { var int a; let a=-x+(2*y);
+            if (a<1) { do Output.printInt(a, 1); } else { while (~(a = 0)) { let a = a - 1; } }
+            return; } method int get() { return x; } }",
+        );
+
+        assert_eq!(
+            got,
+            "class Main {
+    field int x, y;
+
+    function void main() {
+        var int a;
+
+        let a = -x + (2 * y);
+        if (a < 1) {
+            do Output.printInt(a, 1);
+        } else {
+            while (~(a = 0)) {
+                let a = a - 1;
+            }
+        }
+        return;
+    }
+
+    method int get() {
+        return x;
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn formatting_is_stable() {
+        let once = format(
+            "class A { static boolean b; constructor A new() { let b = true; return this; } }",
+        );
+        assert_eq!(format(&once), once);
+    }
+}
@@ -0,0 +1,397 @@
+// the jack grammar the compilation engine implements, as data. kept by hand,
+// the tests check it against the tags the engine writes
+
+use Production::*;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Production {
+    // a keyword or symbol as written in source
+    Literal(&'static str),
+    // one of the token classes, `identifier`, `integerConstant` or
+    // `stringConstant`
+    Token(&'static str),
+    NonTerminal(&'static str),
+    Sequence(&'static [Production]),
+    Choice(&'static [Production]),
+    Optional(&'static Production),
+    Repeat(&'static Production),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rule {
+    pub name: &'static str,
+    // whether the engine wraps what the rule matches in a tag of its name,
+    // rules like `type` or `statement` only exist in the grammar
+    pub tagged: bool,
+    pub production: Production,
+}
+
+const fn rule(name: &'static str, tagged: bool, production: Production) -> Rule {
+    Rule {
+        name,
+        tagged,
+        production,
+    }
+}
+
+pub static GRAMMAR: &[Rule] = &[
+    rule(
+        "class",
+        true,
+        Sequence(&[
+            Literal("class"),
+            NonTerminal("className"),
+            Literal("{"),
+            Repeat(&NonTerminal("classVarDec")),
+            Repeat(&NonTerminal("subroutineDec")),
+            Literal("}"),
+        ]),
+    ),
+    rule(
+        "classVarDec",
+        true,
+        Sequence(&[
+            Choice(&[Literal("static"), Literal("field")]),
+            NonTerminal("type"),
+            NonTerminal("varName"),
+            Repeat(&Sequence(&[Literal(","), NonTerminal("varName")])),
+            Literal(";"),
+        ]),
+    ),
+    rule(
+        "type",
+        false,
+        Choice(&[
+            Literal("int"),
+            Literal("char"),
+            Literal("boolean"),
+            NonTerminal("className"),
+        ]),
+    ),
+    rule(
+        "subroutineDec",
+        true,
+        Sequence(&[
+            Choice(&[
+                Literal("constructor"),
+                Literal("function"),
+                Literal("method"),
+            ]),
+            Choice(&[Literal("void"), NonTerminal("type")]),
+            NonTerminal("subroutineName"),
+            Literal("("),
+            NonTerminal("parameterList"),
+            Literal(")"),
+            NonTerminal("subroutineBody"),
+        ]),
+    ),
+    rule(
+        "parameterList",
+        true,
+        Optional(&Sequence(&[
+            NonTerminal("type"),
+            NonTerminal("varName"),
+            Repeat(&Sequence(&[
+                Literal(","),
+                NonTerminal("type"),
+                NonTerminal("varName"),
+            ])),
+        ])),
+    ),
+    rule(
+        "subroutineBody",
+        true,
+        Sequence(&[
+            Literal("{"),
+            Repeat(&NonTerminal("varDec")),
+            NonTerminal("statements"),
+            Literal("}"),
+        ]),
+    ),
+    rule(
+        "varDec",
+        true,
+        Sequence(&[
+            Literal("var"),
+            NonTerminal("type"),
+            NonTerminal("varName"),
+            Repeat(&Sequence(&[Literal(","), NonTerminal("varName")])),
+            Literal(";"),
+        ]),
+    ),
+    rule("className", false, Token("identifier")),
+    rule("subroutineName", false, Token("identifier")),
+    rule("varName", false, Token("identifier")),
+    rule("statements", true, Repeat(&NonTerminal("statement"))),
+    rule(
+        "statement",
+        false,
+        Choice(&[
+            NonTerminal("letStatement"),
+            NonTerminal("ifStatement"),
+            NonTerminal("whileStatement"),
+            NonTerminal("doStatement"),
+            NonTerminal("returnStatement"),
+        ]),
+    ),
+    rule(
+        "letStatement",
+        true,
+        Sequence(&[
+            Literal("let"),
+            NonTerminal("varName"),
+            Optional(&Sequence(&[
+                Literal("["),
+                NonTerminal("expression"),
+                Literal("]"),
+            ])),
+            Literal("="),
+            NonTerminal("expression"),
+            Literal(";"),
+        ]),
+    ),
+    rule(
+        "ifStatement",
+        true,
+        Sequence(&[
+            Literal("if"),
+            Literal("("),
+            NonTerminal("expression"),
+            Literal(")"),
+            Literal("{"),
+            NonTerminal("statements"),
+            Literal("}"),
+            Optional(&Sequence(&[
+                Literal("else"),
+                Literal("{"),
+                NonTerminal("statements"),
+                Literal("}"),
+            ])),
+        ]),
+    ),
+    rule(
+        "whileStatement",
+        true,
+        Sequence(&[
+            Literal("while"),
+            Literal("("),
+            NonTerminal("expression"),
+            Literal(")"),
+            Literal("{"),
+            NonTerminal("statements"),
+            Literal("}"),
+        ]),
+    ),
+    rule(
+        "doStatement",
+        true,
+        Sequence(&[Literal("do"), NonTerminal("subroutineCall"), Literal(";")]),
+    ),
+    rule(
+        "returnStatement",
+        true,
+        Sequence(&[
+            Literal("return"),
+            Optional(&NonTerminal("expression")),
+            Literal(";"),
+        ]),
+    ),
+    rule(
+        "expression",
+        true,
+        Sequence(&[
+            NonTerminal("term"),
+            Repeat(&Sequence(&[NonTerminal("op"), NonTerminal("term")])),
+        ]),
+    ),
+    rule(
+        "term",
+        true,
+        Choice(&[
+            Token("integerConstant"),
+            Token("stringConstant"),
+            NonTerminal("keywordConstant"),
+            NonTerminal("varName"),
+            Sequence(&[
+                NonTerminal("varName"),
+                Literal("["),
+                NonTerminal("expression"),
+                Literal("]"),
+            ]),
+            NonTerminal("subroutineCall"),
+            Sequence(&[Literal("("), NonTerminal("expression"), Literal(")")]),
+            Sequence(&[NonTerminal("unaryOp"), NonTerminal("term")]),
+        ]),
+    ),
+    rule(
+        "subroutineCall",
+        false,
+        Sequence(&[
+            Optional(&Sequence(&[
+                Choice(&[NonTerminal("className"), NonTerminal("varName")]),
+                Literal("."),
+            ])),
+            NonTerminal("subroutineName"),
+            Literal("("),
+            NonTerminal("expressionList"),
+            Literal(")"),
+        ]),
+    ),
+    rule(
+        "expressionList",
+        true,
+        Optional(&Sequence(&[
+            NonTerminal("expression"),
+            Repeat(&Sequence(&[Literal(","), NonTerminal("expression")])),
+        ])),
+    ),
+    rule(
+        "op",
+        false,
+        Choice(&[
+            Literal("+"),
+            Literal("-"),
+            Literal("*"),
+            Literal("/"),
+            Literal("&"),
+            Literal("|"),
+            Literal("<"),
+            Literal(">"),
+            Literal("="),
+        ]),
+    ),
+    rule("unaryOp", false, Choice(&[Literal("-"), Literal("~")])),
+    rule(
+        "keywordConstant",
+        false,
+        Choice(&[
+            Literal("true"),
+            Literal("false"),
+            Literal("null"),
+            Literal("this"),
+        ]),
+    ),
+];
+
+// the grammar as ebnf, one `name ::= ...` line per rule
+pub fn grammar_to_ebnf() -> String {
+    let mut out = String::new();
+    for rule in GRAMMAR {
+        out.push_str(&format!("{} ::= {}\n", rule.name, ebnf(&rule.production)));
+    }
+    out
+}
+
+fn ebnf(production: &Production) -> String {
+    match production {
+        Literal(text) => format!("'{}'", text),
+        Token(name) | NonTerminal(name) => name.to_string(),
+        Sequence(parts) => parts.iter().map(grouped).collect::<Vec<_>>().join(" "),
+        Choice(options) => options.iter().map(grouped).collect::<Vec<_>>().join(" | "),
+        Optional(inner) => format!("{}?", grouped(inner)),
+        Repeat(inner) => format!("{}*", grouped(inner)),
+    }
+}
+
+// a production inside another one, in parentheses unless it is a single item
+fn grouped(production: &Production) -> String {
+    match production {
+        Sequence(_) | Choice(_) => format!("({})", ebnf(production)),
+        _ => ebnf(production),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use crate::{CompilationEngine, StreamTokenizer};
+
+    use super::{grammar_to_ebnf, Production, GRAMMAR};
+
+    const TOKEN_TAGS: [&str; 5] = [
+        "keyword",
+        "symbol",
+        "identifier",
+        "integerConstant",
+        "stringConstant",
+    ];
+
+    // uses every construct of the grammar
+    const EVERYTHING: &str = "class Main {
+        static int a; field Point p, q;
+        constructor Main new(int x, char y) { var Array b; let b[x] = -y; return this; }
+        function void f() {
+            if (~(a < 1)) { do Output.printString(\"a\"); } else { while (true) { do g(a, 2); } }
+            return;
+        }
+        method boolean g() { return null | (a + 1 = h()); }
+    }";
+
+    fn emitted_tags() -> BTreeSet<String> {
+        let mut tokenizer = StreamTokenizer::from_source(EVERYTHING);
+        let mut out = Vec::new();
+        CompilationEngine::new(&mut out, &mut tokenizer)
+            .compile()
+            .unwrap();
+        let xml = String::from_utf8(out).unwrap();
+
+        xml.split('<')
+            .skip(1)
+            .filter(|tag| !tag.starts_with('/'))
+            .map(|tag| tag[..tag.find('>').unwrap()].to_owned())
+            .collect()
+    }
+
+    fn references(production: &Production, names: &mut Vec<&'static str>) {
+        match production {
+            Production::NonTerminal(name) => names.push(name),
+            Production::Sequence(parts) | Production::Choice(parts) => {
+                parts.iter().for_each(|p| references(p, names))
+            }
+            Production::Optional(inner) | Production::Repeat(inner) => references(inner, names),
+            Production::Literal(_) | Production::Token(_) => {}
+        }
+    }
+
+    #[test]
+    fn tagged_rules_match_the_engine_tags() {
+        let tagged: BTreeSet<String> = GRAMMAR
+            .iter()
+            .filter(|rule| rule.tagged)
+            .map(|rule| rule.name.to_owned())
+            .collect();
+        let emitted: BTreeSet<String> = emitted_tags()
+            .into_iter()
+            .filter(|tag| !TOKEN_TAGS.contains(&tag.as_str()))
+            .collect();
+
+        assert_eq!(tagged, emitted);
+    }
+
+    #[test]
+    fn every_reference_is_defined_once() {
+        let defined: Vec<&str> = GRAMMAR.iter().map(|rule| rule.name).collect();
+        let unique: BTreeSet<&str> = defined.iter().copied().collect();
+        assert_eq!(unique.len(), defined.len());
+
+        let mut referenced = Vec::new();
+        for rule in GRAMMAR {
+            references(&rule.production, &mut referenced);
+        }
+        for name in referenced {
+            assert!(unique.contains(name), "`{}` is not defined", name);
+        }
+    }
+
+    #[test]
+    fn renders_ebnf() {
+        let ebnf = grammar_to_ebnf();
+
+        assert_eq!(ebnf.lines().count(), GRAMMAR.len());
+        assert!(
+            ebnf.contains("classVarDec ::= ('static' | 'field') type varName (',' varName)* ';'\n")
+        );
+        assert!(ebnf.contains("returnStatement ::= 'return' expression? ';'\n"));
+    }
+}
@@ -0,0 +1,72 @@
+// reading archived `.jack.gz` sources, gzip (rfc 1952) decoded by flate2.
+// every member of a file is read, one after the other, as `gzip -d` does
+
+use std::io::Read;
+
+use anyhow::{ensure, Result};
+use flate2::read::MultiGzDecoder;
+
+// the most a source is decompressed to. a few kilobytes of gzip can inflate
+// to gigabytes, anything past this is an error rather than read into memory
+pub const MAX_DECOMPRESSED_SIZE: u64 = 16 * 1024 * 1024;
+
+// the contents of every member of a gzip file
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    decompress_at_most(data, MAX_DECOMPRESSED_SIZE)
+}
+
+fn decompress_at_most(data: &[u8], limit: u64) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    // one byte past the limit is read to tell a source of exactly `limit`
+    // bytes from a larger one
+    MultiGzDecoder::new(data)
+        .take(limit + 1)
+        .read_to_end(&mut out)?;
+    ensure!(
+        out.len() as u64 <= limit,
+        "gzip data decompresses to more than {} bytes",
+        limit
+    );
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompress, decompress_at_most};
+
+    // written by `gzip -9 -n`
+    const SQUARE: &[u8] = include_bytes!("../tests/fixtures/Square.jack");
+    const SQUARE_GZ: &[u8] = include_bytes!("../tests/fixtures/Square.jack.gz");
+
+    #[test]
+    fn reads_what_gzip_wrote() {
+        assert_eq!(decompress(SQUARE_GZ).unwrap(), SQUARE);
+    }
+
+    #[test]
+    fn concatenated_members() {
+        let data = [SQUARE_GZ, SQUARE_GZ].concat();
+        assert_eq!(decompress(&data).unwrap(), [SQUARE, SQUARE].concat());
+    }
+
+    #[test]
+    fn corrupt_data_is_an_error() {
+        let mut data = SQUARE_GZ.to_vec();
+        data[100] ^= 0x10;
+        assert!(decompress(&data).is_err());
+
+        assert!(decompress(&SQUARE_GZ[..150]).is_err());
+        assert!(decompress(b"class Main { }").is_err());
+    }
+
+    #[test]
+    fn size_is_capped() {
+        let size = SQUARE.len() as u64;
+        assert!(decompress_at_most(SQUARE_GZ, size).is_ok());
+        let err = decompress_at_most(SQUARE_GZ, size - 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("gzip data decompresses to more than {} bytes", size - 1)
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+// the file in a source directory listing what discovery leaves out
+pub(crate) const IGNORE_FILE: &str = ".jackignore";
+
+// gitignore style patterns over the paths under a source directory. a
+// pattern is matched against the path relative to it with `/` between the
+// parts, whatever the platform: `*` and `?` stay within a part, `**` crosses
+// them, a pattern with a `/` before its end is matched from the root and any
+// other against the name of every file and directory, a trailing `/` only
+// matches directories and a leading `!` brings back what an earlier pattern
+// left out. the last pattern matching decides, and nothing under a directory
+// left out comes back
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Ignore {
+    root: PathBuf,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    // as written, for the verbose output
+    pattern: String,
+    glob: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, glob) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, glob) = match glob.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, glob),
+        };
+        let anchored = glob.contains('/');
+        Some(Self {
+            pattern: line.to_owned(),
+            glob: glob.trim_start_matches('/').to_owned(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let subject = match self.anchored {
+            true => relative,
+            false => relative.rsplit('/').next().unwrap_or(relative),
+        };
+        glob_matches(self.glob.as_bytes(), subject.as_bytes())
+    }
+}
+
+impl Ignore {
+    // the patterns of `root`'s ignore file, if it has one, then `excludes`
+    pub fn load(root: &Path, excludes: &[String]) -> io::Result<Self> {
+        let mut ignore = Self {
+            root: root.to_path_buf(),
+            rules: Vec::new(),
+        };
+        match std::fs::read_to_string(root.join(IGNORE_FILE)) {
+            Ok(text) => ignore.rules.extend(text.lines().filter_map(Rule::parse)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        ignore
+            .rules
+            .extend(excludes.iter().filter_map(|line| Rule::parse(line)));
+        Ok(ignore)
+    }
+
+    // the pattern leaving out `path`, a file or directory under the root
+    pub fn excluded(&self, path: &Path, is_dir: bool) -> Option<&str> {
+        if self.rules.is_empty() {
+            return None;
+        }
+        let parts: Vec<String> = path
+            .strip_prefix(&self.root)
+            .unwrap_or(path)
+            .components()
+            .filter_map(|part| match part {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+
+        // the directories on the way down first, nothing under an excluded
+        // one is taken
+        for end in 1..=parts.len() {
+            let relative = parts[..end].join("/");
+            let is_dir = end < parts.len() || is_dir;
+            let decided = self
+                .rules
+                .iter()
+                .rev()
+                .find(|rule| rule.matches(&relative, is_dir));
+            if let Some(rule) = decided.filter(|rule| !rule.negated) {
+                return Some(&rule.pattern);
+            }
+        }
+        None
+    }
+}
+
+fn glob_matches(glob: &[u8], text: &[u8]) -> bool {
+    match glob {
+        [] => text.is_empty(),
+        // `**/` is no directory or any number of them, what follows starts a
+        // part
+        [b'*', b'*', b'/', rest @ ..] => {
+            let parts = text.iter().enumerate().filter(|(_, &c)| c == b'/');
+            std::iter::once(0)
+                .chain(parts.map(|(i, _)| i + 1))
+                .any(|i| glob_matches(rest, &text[i..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| glob_matches(rest, &text[i..])),
+        [b'*', rest @ ..] => {
+            let part = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=part).any(|i| glob_matches(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => {
+            matches!(text.first(), Some(c) if *c != b'/') && glob_matches(rest, &text[1..])
+        }
+        [c, rest @ ..] => text.first() == Some(c) && glob_matches(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Ignore, Rule};
+
+    fn ignore(patterns: &[&str]) -> Ignore {
+        Ignore {
+            root: "root".into(),
+            rules: patterns.iter().filter_map(|p| Rule::parse(p)).collect(),
+        }
+    }
+
+    fn excluded<'a>(ignore: &'a Ignore, path: &str) -> Option<&'a str> {
+        ignore.excluded(&Path::new("root").join(path), false)
+    }
+
+    #[test]
+    fn globs_match_names_at_any_depth() {
+        let ignore = ignore(&["Old_*.jack", "# a comment", "lib/Te?t.jack"]);
+
+        assert_eq!(excluded(&ignore, "Old_Main.jack"), Some("Old_*.jack"));
+        assert_eq!(excluded(&ignore, "a/b/Old_.jack"), Some("Old_*.jack"));
+        assert_eq!(excluded(&ignore, "Main.jack"), None);
+        assert_eq!(excluded(&ignore, "lib/Test.jack"), Some("lib/Te?t.jack"));
+        assert_eq!(excluded(&ignore, "src/lib/Test.jack"), None);
+    }
+
+    #[test]
+    fn directories_leave_out_everything_under_them() {
+        let ignore = ignore(&["vendor/", "**/scratch/*.jack", "!Keep.jack"]);
+
+        assert_eq!(excluded(&ignore, "vendor/Main.jack"), Some("vendor/"));
+        assert_eq!(excluded(&ignore, "a/vendor/b/Keep.jack"), Some("vendor/"));
+        assert_eq!(excluded(&ignore, "vendor"), None);
+        assert_eq!(
+            excluded(&ignore, "x/scratch/A.jack"),
+            Some("**/scratch/*.jack")
+        );
+        assert_eq!(excluded(&ignore, "x/scratch/Keep.jack"), None);
+    }
+
+    #[test]
+    fn double_star_slash_only_matches_whole_parts() {
+        let ignore = ignore(&["**/test/*.jack", "a/**/b.jack", "build/**"]);
+
+        assert_eq!(excluded(&ignore, "test/A.jack"), Some("**/test/*.jack"));
+        assert_eq!(excluded(&ignore, "x/y/test/A.jack"), Some("**/test/*.jack"));
+        assert_eq!(excluded(&ignore, "mytest/A.jack"), None);
+        assert_eq!(excluded(&ignore, "x/mytest/A.jack"), None);
+        assert_eq!(excluded(&ignore, "a/b.jack"), Some("a/**/b.jack"));
+        assert_eq!(excluded(&ignore, "a/x/y/b.jack"), Some("a/**/b.jack"));
+        assert_eq!(excluded(&ignore, "a/xb.jack"), None);
+        assert_eq!(excluded(&ignore, "build/x/A.jack"), Some("build/**"));
+    }
+
+    #[test]
+    fn negation_brings_back_a_file() {
+        let ignore = ignore(&["*.jack", "!Main.jack"]);
+
+        assert_eq!(excluded(&ignore, "Screen.jack"), Some("*.jack"));
+        assert_eq!(excluded(&ignore, "Main.jack"), None);
+        assert_eq!(excluded(&ignore, "game/Main.jack"), None);
+    }
+}
@@ -1,17 +1,140 @@
+//! A compiler for the Jack language of nand2tetris: tokenizers, the xml
+//! parse tree engine, an ast parser with semantic checks, and vm code
+//! generation.
+//!
+//! The modules are private and everything public is re-exported from the
+//! crate root, so the paths below stay put when the code behind them moves.
+//! [`prelude`] has the types most users need. Items hidden from the docs are
+//! used by the binary and may change without notice.
+
+pub mod prelude;
+
 mod naive_tokenizer;
 pub use naive_tokenizer::NaiveTokenizer;
 
 mod engine;
-pub use engine::CompilationEngine;
+pub use engine::{
+    compile_batch, compile_fragment_source, compile_source, CompilationEngine, CompileOptions,
+    CompiledClassInfo, CompiledSubroutine, CompiledVar, ElementSpan, FailedSubroutines, Fragment,
+};
+
+mod listener;
+pub use listener::{ParseEvent, ParseListener, SexpListener, TokensListener};
 
 mod analyzer;
-pub use analyzer::Analyzer;
+pub use analyzer::{
+    analyze_source, Analyzer, AnalyzerOptions, DiagnosticFormat, DiagnosticReport, DiagnosticSink,
+    Emit, ParseFormat, ParseOutput, PhaseTimes, Report, Transform,
+};
 
 mod token;
-pub use token::Token;
+pub use token::{tokens_to_source, Span, SpannedToken, Token, TokenKind};
 
 mod elements;
 pub use elements::lexical_elements;
 
 mod stream_tokenizer;
-pub use stream_tokenizer::StreamTokenizer;
+pub use stream_tokenizer::{StreamTokenizer, TokenizerStats};
+
+mod bufread_tokenizer;
+pub use bufread_tokenizer::{BufReadSpannedTokens, BufReadTokenizer};
+
+mod token_source;
+pub use token_source::TokenSource;
+
+pub mod ast;
+
+mod parser;
+pub use parser::{Expected, Parser};
+
+mod diagnostic;
+pub use diagnostic::{utf16_column, CompileError, Diagnostic, Severity, TabPolicy};
+
+mod symbol_table;
+pub use symbol_table::{Symbol, SymbolKind, SymbolTable};
+
+mod balance;
+pub use balance::check_balance;
+#[doc(hidden)]
+pub use balance::BracketTracker;
+
+mod checker;
+pub use checker::Checker;
+
+mod check;
+pub use check::{check_source, CheckOptions};
+
+mod brace_style;
+pub use brace_style::{check_brace_style, BraceStyle};
+
+pub mod testing;
+
+mod format;
+pub use format::format_class;
+
+mod dot;
+pub use dot::ast_to_dot;
+
+mod records;
+pub use records::RecordsListener;
+
+pub mod grammar;
+
+mod rpn;
+pub use rpn::expr_to_rpn;
+
+mod comments;
+
+mod marker_line;
+
+mod features;
+pub use features::{Feature, Features};
+
+mod cfg;
+pub use cfg::{BasicBlock, ControlFlowGraph, EdgeKind};
+
+mod completion;
+mod json;
+
+pub use completion::{completions, expected_at_end, CompletionItem, CompletionKind};
+
+mod rename;
+pub use rename::{minify_names, rename, SourceSet, SymbolRef, TextEdit};
+
+mod resolve;
+
+mod symbol_index;
+pub use symbol_index::{Definition, DefinitionKind, Reference, Resolution, SymbolIndex};
+
+mod dead_code;
+pub use dead_code::{dead_code, DeadCodeReport, DeadItem, DeadKind};
+
+mod diff;
+pub use diff::{unified_diff, DiffStatus, OutputDiff};
+
+mod compilation_unit;
+pub use compilation_unit::{CompilationUnit, Signature};
+
+mod compilation_report;
+pub use compilation_report::{CompilationReport, FileReport};
+
+mod project;
+pub use project::{ParsedTree, Project};
+
+mod build_cache;
+#[cfg(feature = "gzip")]
+mod gzip;
+mod ignore;
+mod manifest;
+mod sha256;
+#[doc(hidden)]
+pub use manifest::{HashingWriter, Manifest};
+
+mod newline;
+pub use newline::{Newline, NewlineWriter};
+
+mod codegen;
+pub use codegen::{CodeGenerator, CodegenOptions, LabelStyle};
+
+#[cfg(test)]
+mod vm_interp;
@@ -0,0 +1,169 @@
+use std::io::{self, Write};
+
+use crate::{
+    engine::{escape_text, XML_DECLARATION},
+    lexical_elements::Symbols,
+    Token,
+};
+
+// what the engine compiled, in the order it wrote it. every terminal is a
+// token, a subroutine left out by error recovery isn't in it
+#[derive(Debug, Clone)]
+pub enum ParseEvent {
+    Open(&'static str),
+    Token(Token),
+    Close(&'static str),
+}
+
+// an output written from the events of an engine run, next to the xml the
+// engine writes itself, so more outputs don't need more parses. a listener
+// that fails is given no more events, the others carry on
+pub trait ParseListener {
+    fn event(&mut self, event: &ParseEvent) -> io::Result<()>;
+
+    // after the last event
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// the tag and the xml text of a token as in the course's `T.xml` files,
+// nothing for the end of the source. well formed text escapes every `&`, `<`
+// and `>`, the course's only those of symbols other than `&`
+pub(crate) fn token_xml(token: &Token, well_formed: bool) -> Option<(&'static str, String)> {
+    let tagged = match token {
+        Token::Symbol(Symbols::Ampersand) if well_formed => ("symbol", String::from("&amp;")),
+        Token::StringConst(s) if well_formed => ("stringConstant", escape_text(s)),
+        Token::Error(c) if well_formed => ("error", escape_text(&c.to_string())),
+        Token::Keyword(k) => ("keyword", k.to_string()),
+        Token::Symbol(s) => ("symbol", s.to_string()),
+        Token::Identifier(id) => ("identifier", id.clone()),
+        Token::IntConst(i) => ("integerConstant", (*i as u16).to_string()),
+        Token::StringConst(s) => ("stringConstant", s.clone()),
+        Token::Error(c) => ("error", c.to_string()),
+        Token::Eof => return None,
+    };
+    Some(tagged)
+}
+
+// the tokens compiled, as a `T.xml` file
+pub struct TokensListener<W: Write> {
+    out: W,
+    started: bool,
+    // start with a declaration and escape the text fully, see token_xml
+    well_formed: bool,
+}
+
+impl<W: Write> TokensListener<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            started: false,
+            well_formed: false,
+        }
+    }
+
+    pub fn well_formed(mut self, well_formed: bool) -> Self {
+        self.well_formed = well_formed;
+        self
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            if self.well_formed {
+                writeln!(self.out, "{}", XML_DECLARATION)?;
+            }
+            self.out.write_all(b"<tokens>\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> ParseListener for TokensListener<W> {
+    fn event(&mut self, event: &ParseEvent) -> io::Result<()> {
+        self.start()?;
+        match event {
+            ParseEvent::Token(token) => match token_xml(token, self.well_formed) {
+                Some((tag, value)) => writeln!(self.out, "<{}> {} </{}>", tag, value, tag),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.start()?;
+        self.out.write_all(b"</tokens>\n")?;
+        self.out.flush()
+    }
+}
+
+// the tree as an s-expression, a `(tag ...)` for every non-terminal and a
+// `(kind "text")` for every token, each on a line indented by its depth
+pub struct SexpListener<W: Write> {
+    out: W,
+    depth: usize,
+    has_written: bool,
+}
+
+impl<W: Write> SexpListener<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            depth: 0,
+            has_written: false,
+        }
+    }
+
+    fn start_line(&mut self) -> io::Result<()> {
+        if self.has_written {
+            write!(self.out, "\n{}", "  ".repeat(self.depth))?;
+        }
+        self.has_written = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> ParseListener for SexpListener<W> {
+    fn event(&mut self, event: &ParseEvent) -> io::Result<()> {
+        match event {
+            ParseEvent::Open(tag) => {
+                self.start_line()?;
+                self.depth += 1;
+                write!(self.out, "({}", tag)
+            }
+            ParseEvent::Token(token) => {
+                let Some((kind, _)) = token_xml(token, false) else {
+                    return Ok(());
+                };
+                self.start_line()?;
+                write!(self.out, "({} {})", kind, quoted(&raw_text(token)))
+            }
+            ParseEvent::Close(_) => {
+                self.depth = self.depth.saturating_sub(1);
+                write!(self.out, ")")
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.has_written {
+            self.out.write_all(b"\n")?;
+        }
+        self.out.flush()
+    }
+}
+
+// the text of a token as in the source, the xml text escapes `<` and `>`
+pub(crate) fn raw_text(token: &Token) -> String {
+    match token {
+        Token::Symbol(Symbols::LessThan) => String::from("<"),
+        Token::Symbol(Symbols::GreaterThan) => String::from(">"),
+        token => token_xml(token, false).map_or_else(String::new, |(_, text)| text),
+    }
+}
+
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
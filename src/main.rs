@@ -1,18 +1,668 @@
-use std::path::PathBuf;
+use std::{
+    io::{BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
 
 use anyhow::Result;
-use clap::Parser;
-use jack_compiler::Analyzer;
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand};
+use jack_compiler::{
+    compile_fragment_source, grammar, Analyzer, AnalyzerOptions, BraceStyle, BracketTracker,
+    CompilationReport, Diagnostic, DiagnosticFormat, DiagnosticReport, DiagnosticSink, Emit,
+    Newline, ParseFormat, ParseListener, ParseOutput, Report, Severity, SexpListener,
+    StreamTokenizer, TabPolicy, Token, Transform,
+};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // the flags from before there were subcommands, a bare `-s` is `parse`
+    #[command(flatten)]
+    legacy: LegacyArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write the xml parse tree of every source to `<name>.xml`
+    Parse {
+        #[command(flatten)]
+        paths: Paths,
+
+        /// How the parse trees are written, records go to `<name>.tsv` and dot
+        /// to `<name>.dot`
+        #[arg(long, value_enum, default_value_t = ParseFormat::Xml)]
+        format: ParseFormat,
+
+        /// Start the xml with a declaration and escape every `&`, `<` and `>`,
+        /// for xml tools that want a proper document
+        #[arg(long)]
+        well_formed_xml: bool,
+
+        /// Write every tag on a line of its own with no blank lines between
+        /// them, instead of the spacing of the reference files
+        #[arg(long)]
+        normalize: bool,
+
+        /// Print the time spent tokenizing and in the engine for each file
+        #[arg(long)]
+        profile: bool,
+
+        /// Keep the hashes of sources and outputs in this file and skip the
+        /// sources unchanged since their output was written
+        #[arg(long, value_name = "PATH")]
+        cache: Option<PathBuf>,
+
+        /// Write these outputs of every source from a single parse instead,
+        /// comma separated, as `<name>.xml`, `<name>T.xml` and `<name>.sexp`
+        #[arg(long, value_enum, value_delimiter = ',', value_name = "OUTPUTS")]
+        emit: Vec<ParseOutput>,
+
+        /// Stop at the first output of `--emit` that can't be written, rather
+        /// than write the others and fail at the end
+        #[arg(long, requires = "emit")]
+        fail_fast: bool,
+    },
+    /// Print the tokens of the sources as xml
+    Tokenize {
+        #[command(flatten)]
+        paths: Paths,
+
+        /// Start the xml with a declaration and escape every `&`, `<` and `>`,
+        /// for xml tools that want a proper document
+        #[arg(long)]
+        well_formed_xml: bool,
+    },
+    /// Compile the sources to vm code, one `.vm` file per class
+    Compile {
+        #[command(flatten)]
+        paths: Paths,
+
+        /// Concatenate all classes into a single file instead
+        #[arg(long, value_name = "PATH")]
+        single: Option<PathBuf>,
+
+        /// Also compile the subroutines of each class on several threads
+        #[arg(long)]
+        parallel_subroutines: bool,
+
+        /// Put a `// <file>:<line>` comment before every function and
+        /// statement, for the vm emulator's debugger
+        #[arg(long)]
+        vm_debug: bool,
+
+        /// The class whose `multiply` and `divide` do `*` and `/`
+        #[arg(long, value_name = "CLASS", default_value = "Math")]
+        math_class: String,
+    },
+    /// Only parse and run the semantic checks and lints, without writing output
+    Check {
+        #[command(flatten)]
+        paths: Paths,
+
+        /// Print every definition and reference of the sources as one JSON
+        /// index when they have no errors
+        #[arg(long)]
+        symbols: bool,
+    },
+    /// Print the sources in the canonical layout, comments are dropped
+    Fmt {
+        #[command(flatten)]
+        paths: Paths,
+
+        /// Rewrite the sources this way first, looking across all of them
+        #[arg(long, value_enum)]
+        transform: Option<Transform>,
+    },
+    /// Print token, declaration and statement counts and the language features
+    /// each source uses
+    Stats(Paths),
+    /// Read expressions, statements and classes from stdin and print the tree
+    /// of each, input with a delimiter left open continues on the next line
+    Repl {
+        /// Print the trees as s-expressions instead of xml
+        #[arg(long)]
+        sexp: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+struct Paths {
+    /// Source files or directories of `.jack` files
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    #[command(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Args, Debug)]
+struct CommonArgs {
+    /// Write outputs into this directory
+    #[arg(short, long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+
+    /// Also read sources from subdirectories
     #[arg(short, long)]
-    source: String,
+    recursive: bool,
+
+    /// Only warn when a directory holds no sources, rather than fail
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// The extensions of the sources read from directories, comma separated
+    /// and matched ignoring case
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "jack",
+        value_name = "EXTENSIONS"
+    )]
+    extensions: Vec<String>,
+
+    /// Leave the sources matching a gitignore style pattern out of
+    /// directories, after the patterns of their .jackignore. Can be repeated
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Print the files skipped while looking for sources, and how many were
+    /// looked at
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Number of files handled at the same time
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    /// How diagnostics are printed
+    #[arg(long, visible_alias = "error-format", value_enum, default_value_t = DiagnosticFormat::Human)]
+    diagnostics_format: DiagnosticFormat,
+
+    /// Expand tabs to stops this many columns apart in the source lines of
+    /// diagnostics, otherwise a tab is one column
+    #[arg(long, value_name = "N")]
+    tab_stop: Option<usize>,
+
+    /// Stop error recovery and printing errors after this many, 0 for no limit
+    #[arg(long, default_value_t = 20)]
+    max_errors: usize,
+
+    /// Write a manifest.json listing every output with its size and SHA-256
+    #[arg(long)]
+    manifest: bool,
+
+    /// Leave the generation time out of the manifest
+    #[arg(long)]
+    manifest_no_timestamp: bool,
+
+    /// Report unbalanced braces, parentheses and brackets before parsing
+    #[arg(long)]
+    check_balance: bool,
+
+    /// Also report method calls and indexing on int, char and boolean
+    /// variables, and reads of undeclared variables
+    #[arg(long)]
+    strict: bool,
+
+    /// Also check that calls into the other classes of the program go to a
+    /// class and subroutine that exist, of the kind the call needs and with
+    /// the right number of arguments
+    #[arg(long)]
+    check_calls: bool,
+
+    /// Warn about variables declared with this name, like an os class name
+    #[arg(long = "reserved", value_name = "NAME")]
+    reserved: Vec<String>,
+
+    /// Fail on a first line starting with `#!` or `<?xml` instead of skipping
+    /// it with a warning
+    #[arg(long)]
+    reject_marker_line: bool,
+
+    /// Leave out the warnings with this code, like J108 for shadowed class
+    /// variables
+    #[arg(long, value_name = "CODE")]
+    allow: Vec<String>,
+
+    /// Warn about opening braces not on the same line as what they open, or
+    /// not on the next one
+    #[arg(long, value_enum, value_name = "STYLE")]
+    brace_style: Option<BraceStyle>,
+
+    /// Add the source byte range of every non-terminal to the xml as `start`
+    /// and `end` attributes
+    #[arg(long)]
+    spans: bool,
+
+    /// Accept `this.f()` as the call `f()` on the current object instead of
+    /// reporting it
+    #[arg(long)]
+    allow_this_receiver: bool,
+
+    /// Write the size, token count, phase times and peak engine buffer of
+    /// every source to this file as json, whatever else is output
+    #[arg(long, value_name = "PATH")]
+    report_file: Option<PathBuf>,
+
+    /// Print how every output would change instead of writing it
+    #[arg(long)]
+    diff: bool,
+
+    /// The line ending of the files written
+    #[arg(long, value_enum, default_value_t = Newline::Lf)]
+    newline: Newline,
+}
+
+impl CommonArgs {
+    fn options(&self) -> AnalyzerOptions {
+        AnalyzerOptions {
+            max_errors: self.max_errors,
+            manifest: self.manifest,
+            manifest_timestamp: !self.manifest_no_timestamp,
+            check_balance: self.check_balance,
+            strict: self.strict,
+            check_calls: self.check_calls,
+            reserved_identifiers: self.reserved.iter().cloned().collect(),
+            skip_marker_line: !self.reject_marker_line,
+            allow: self.allow.clone(),
+            brace_style: self.brace_style,
+            spans: self.spans,
+            out_dir: self.out_dir.clone(),
+            recursive: self.recursive,
+            allow_empty: self.allow_empty,
+            newline: self.newline,
+            jobs: self.jobs,
+            parallel_subroutines: false,
+            vm_debug: false,
+            math_class: String::from("Math"),
+            diff: self.diff,
+            format: ParseFormat::Xml,
+            well_formed_xml: false,
+            normalize: false,
+            shared_output: false,
+            profile: false,
+            diagnostics: self.diagnostics_format,
+            tabs: self.tab_stop.map_or(TabPolicy::One, TabPolicy::Stop),
+            cache: None,
+            report_file: self.report_file.clone(),
+            fail_fast: false,
+            allow_this_receiver: self.allow_this_receiver,
+            extensions: self.extensions.clone(),
+            exclude: self.exclude.clone(),
+            verbose: self.verbose,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+struct LegacyArgs {
+    #[arg(short, long)]
+    source: Option<String>,
+
+    /// Only parse and run the semantic checks and lints, without writing output
+    #[arg(long)]
+    check: bool,
+
+    /// Print another representation of the sources instead of writing xml
+    #[arg(long, value_enum)]
+    emit: Option<Emit>,
+
+    /// Print a project wide report instead of writing xml
+    #[arg(long, value_enum)]
+    report: Option<Report>,
+
+    /// Leave constructors out of the dead code report
+    #[arg(long)]
+    ignore_constructors: bool,
+
+    /// Rename a class, member or local (`Class`, `Class.member` or
+    /// `Class.subroutine.local`) across the sources, as `target=new_name`
+    #[arg(long, value_name = "TARGET=NEW")]
+    rename: Option<String>,
+
+    /// Compile to vm code, one `.vm` file next to every source
+    #[arg(long)]
+    vm: bool,
+
+    /// Compile to vm code, all classes concatenated into a single file
+    #[arg(long, value_name = "PATH")]
+    single_vm: Option<PathBuf>,
+
+    /// Stop error recovery and printing errors after this many, 0 for no limit
+    #[arg(long, default_value_t = 20)]
+    max_errors: usize,
+
+    /// Write a manifest.json listing every output with its size and SHA-256
+    #[arg(long)]
+    manifest: bool,
+
+    /// Leave the generation time out of the manifest
+    #[arg(long)]
+    manifest_no_timestamp: bool,
+
+    /// Report unbalanced braces, parentheses and brackets before parsing
+    #[arg(long)]
+    check_balance: bool,
+
+    /// Only warn when the source directory holds no sources, rather than fail
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// The extensions of the sources read from the source directory, comma
+    /// separated and matched ignoring case
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "jack",
+        value_name = "EXTENSIONS"
+    )]
+    extensions: Vec<String>,
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
-    Analyzer::analyze(&PathBuf::from(args.source))?;
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(command) => run(command),
+        None => match &cli.legacy.source {
+            Some(source) => run_legacy(PathBuf::from(source), &cli.legacy),
+            None if cli.legacy.emit == Some(Emit::Grammar) => {
+                print!("{}", grammar::grammar_to_ebnf());
+                Ok(())
+            }
+            None => Cli::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "either a subcommand or `--source` is required",
+                )
+                .exit(),
+        },
+    }
+}
+
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Parse {
+            paths,
+            format,
+            well_formed_xml,
+            normalize,
+            profile,
+            cache,
+            emit,
+            fail_fast,
+        } => {
+            let options = AnalyzerOptions {
+                format,
+                well_formed_xml,
+                normalize,
+                profile,
+                cache,
+                fail_fast,
+                ..paths.common.options()
+            };
+            if !emit.is_empty() {
+                let mut failed = false;
+                for path in &paths.paths {
+                    for (_, e) in Analyzer::parse_outputs(path, &emit, &options)? {
+                        eprintln!("error: {:#}", e);
+                        failed = true;
+                    }
+                }
+                write_report(&paths, &options)?;
+                if failed {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+            let mut report = CompilationReport::default();
+            for path in &paths.paths {
+                report.extend(Analyzer::analyze(path, &options)?);
+            }
+            if let Some(path) = &options.report_file {
+                report.write(path)?;
+            }
+        }
+        Command::Tokenize {
+            paths,
+            well_formed_xml,
+        } => {
+            let options = AnalyzerOptions {
+                well_formed_xml,
+                ..paths.common.options()
+            };
+            for path in &paths.paths {
+                let outputs = Analyzer::tokenize(path, &options)?;
+                Analyzer::write_outputs(&outputs, "T.xml", &options)?;
+            }
+            write_report(&paths, &options)?;
+        }
+        Command::Compile {
+            paths,
+            single,
+            parallel_subroutines,
+            vm_debug,
+            math_class,
+        } => {
+            let options = AnalyzerOptions {
+                parallel_subroutines,
+                vm_debug,
+                math_class,
+                ..paths.common.options()
+            };
+            for path in &paths.paths {
+                Analyzer::write_vm(path, single.as_ref(), &options)?;
+            }
+            write_report(&paths, &options)?;
+        }
+        Command::Check { paths, symbols } => {
+            let options = paths.common.options();
+            let mut failed = false;
+            for path in &paths.paths {
+                let errors = check(path, &options)?;
+                if symbols && !errors {
+                    print!("{}", Analyzer::emit(path, Emit::Symbols, &options)?);
+                }
+                failed |= errors;
+            }
+            write_report(&paths, &options)?;
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Command::Fmt { paths, transform } => {
+            let options = paths.common.options();
+            for path in &paths.paths {
+                let outputs = match transform {
+                    Some(transform) => Analyzer::transform(path, transform, &options)?,
+                    None => Analyzer::format(path, &options)?,
+                };
+                Analyzer::write_outputs(&outputs, ".jack", &options)?;
+            }
+            write_report(&paths, &options)?;
+        }
+        Command::Stats(paths) => {
+            let options = paths.common.options();
+            for path in &paths.paths {
+                print!("{}", Analyzer::stats(path, &options)?);
+            }
+            write_report(&paths, &options)?;
+        }
+        Command::Repl { sexp } => repl(sexp)?,
+    }
+
+    Ok(())
+}
+
+// the report of `--report-file` for the subcommands that don't parse, from
+// a parse of their sources of its own
+fn write_report(paths: &Paths, options: &AnalyzerOptions) -> Result<()> {
+    let Some(file) = &options.report_file else {
+        return Ok(());
+    };
+    let mut report = CompilationReport::default();
+    for path in &paths.paths {
+        report.extend(Analyzer::measure(path, options)?);
+    }
+    report.write(file)
+}
+
+// the prompts are only shown to a terminal, so piped input gives only the
+// trees and errors
+fn repl(sexp: bool) -> Result<()> {
+    let interactive = std::io::stdin().is_terminal();
+    let mut lines = std::io::stdin().lock().lines();
+    let mut input = String::new();
+    loop {
+        if interactive {
+            print!("{}", if input.is_empty() { "jack> " } else { "...   " });
+            std::io::stdout().flush()?;
+        }
+        let Some(line) = lines.next().transpose()? else {
+            // what is left is unfinished, its error says what is missing
+            if !input.trim().is_empty() {
+                evaluate(&input, sexp);
+            }
+            if interactive {
+                println!();
+            }
+            return Ok(());
+        };
+        input.push_str(&line);
+        input.push('\n');
+        if input.trim().is_empty() {
+            input.clear();
+            continue;
+        }
+        if open_delimiters(&input) > 0 {
+            continue;
+        }
+        evaluate(&input, sexp);
+        input.clear();
+    }
+}
+
+fn open_delimiters(input: &str) -> usize {
+    let mut brackets = BracketTracker::new();
+    for token in StreamTokenizer::from_source(input).into_spanned() {
+        let Ok(token) = token else {
+            break;
+        };
+        if let Token::Symbol(symbol) = &token.token {
+            brackets.symbol(symbol, token.span);
+        }
+    }
+    brackets.depth()
+}
+
+fn evaluate(input: &str, sexp: bool) {
+    let mut tree = Vec::new();
+    let mut listener = SexpListener::new(&mut tree);
+    let listener = sexp.then_some(&mut listener as &mut dyn ParseListener);
+    match compile_fragment_source(input, listener) {
+        Ok(_) if sexp => print!("{}", String::from_utf8_lossy(&tree)),
+        Ok(xml) => println!("{}", xml.trim()),
+        Err(e) => match e.downcast_ref::<Diagnostic>() {
+            Some(diagnostic) => eprintln!(
+                "{}",
+                DiagnosticFormat::Human.render(
+                    diagnostic,
+                    Path::new("<repl>"),
+                    input,
+                    TabPolicy::One
+                )
+            ),
+            None => eprintln!("error: {:#}", e),
+        },
+    }
+}
+
+// prints the diagnostics of `--check`, true when any of them is an error.
+// errors and warnings together are printed grouped, with a count of them
+fn check(source: &PathBuf, options: &AnalyzerOptions) -> Result<bool> {
+    let diagnostics = Analyzer::check(source, options)?;
+    let has = |severity| diagnostics.iter().any(|(_, d)| d.severity == severity);
+    if has(Severity::Error) && has(Severity::Warning) {
+        let mut report = DiagnosticReport::new(options);
+        let mut text = None;
+        for (file, diagnostic) in diagnostics {
+            if text.as_ref().is_none_or(|(path, _)| *path != file) {
+                let source = Analyzer::read_source(&file)?;
+                text = Some((file.clone(), source));
+            }
+            let (_, source) = text.as_ref().unwrap();
+            report.add(&file, source, diagnostic);
+        }
+        report.sort_by_position();
+        eprintln!("{}", report.render());
+        return Ok(true);
+    }
+
+    let mut sink = DiagnosticSink::new(options);
+    let mut text = None;
+    for (file, diagnostic) in &diagnostics {
+        // the diagnostics of a file come together
+        if text.as_ref().is_none_or(|(path, _)| *path != file) {
+            text = Some((file, Analyzer::read_source(file)?));
+        }
+        let (_, source) = text.as_ref().unwrap();
+        if let Some(rendered) = sink.emit(diagnostic, file, source) {
+            eprintln!("{}", rendered);
+        }
+    }
+    if let Some(more) = sink.finish() {
+        eprintln!("{}", more);
+    }
+
+    Ok(diagnostics
+        .iter()
+        .any(|(_, d)| d.severity == Severity::Error))
+}
+
+fn run_legacy(source: PathBuf, args: &LegacyArgs) -> Result<()> {
+    let options = AnalyzerOptions {
+        max_errors: args.max_errors,
+        manifest: args.manifest,
+        manifest_timestamp: !args.manifest_no_timestamp,
+        check_balance: args.check_balance,
+        allow_empty: args.allow_empty,
+        extensions: args.extensions.clone(),
+        shared_output: true,
+        ..Default::default()
+    };
+
+    if args.check {
+        if check(&source, &options)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(report) = args.report {
+        print!(
+            "{}",
+            Analyzer::report(&source, report, args.ignore_constructors, &options)?
+        );
+        return Ok(());
+    }
+
+    if let Some(spec) = &args.rename {
+        for file in Analyzer::rename(&source, spec, &options)? {
+            println!("{}", file.display());
+        }
+        return Ok(());
+    }
+
+    if args.vm || args.single_vm.is_some() {
+        return Analyzer::write_vm(&source, args.single_vm.as_ref(), &options);
+    }
+
+    if let Some(emit) = args.emit {
+        print!("{}", Analyzer::emit(&source, emit, &options)?);
+        return Ok(());
+    }
+
+    Analyzer::analyze(&source, &options)?;
     Ok(())
 }
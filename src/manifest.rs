@@ -0,0 +1,129 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{json, sha256::Sha256};
+
+// passes everything through to `inner` while hashing and counting it, so the
+// manifest never has to read an artifact back
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    // flushes the inner writer and returns the byte count and hex digest
+    pub fn finish(mut self) -> io::Result<(u64, String)> {
+        self.inner.flush()?;
+        Ok((self.bytes, self.hasher.finish()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct Artifact {
+    bytes: u64,
+    sha256: String,
+    source: PathBuf,
+}
+
+// `manifest.json`, every artifact written by one run together with the
+// options used. keys and entries are sorted so equal runs give equal files
+#[derive(Default)]
+pub struct Manifest {
+    // keyed by path, an artifact written twice keeps the last contents
+    artifacts: BTreeMap<PathBuf, Artifact>,
+    // already encoded json values
+    options: BTreeMap<&'static str, String>,
+    timestamp: bool,
+}
+
+impl Manifest {
+    pub fn new(timestamp: bool) -> Self {
+        Self {
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    pub fn option(&mut self, name: &'static str, value: impl ToString) {
+        self.options.insert(name, value.to_string());
+    }
+
+    pub fn record<W: Write>(
+        &mut self,
+        path: &Path,
+        source: &Path,
+        writer: HashingWriter<W>,
+    ) -> io::Result<()> {
+        let (bytes, sha256) = writer.finish()?;
+        self.artifacts.insert(
+            path.to_path_buf(),
+            Artifact {
+                bytes,
+                sha256,
+                source: source.to_path_buf(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        let artifacts: Vec<String> = self
+            .artifacts
+            .iter()
+            .map(|(path, artifact)| {
+                format!(
+                    "{{\"bytes\":{},\"path\":{},\"sha256\":\"{}\",\"source\":{}}}",
+                    artifact.bytes,
+                    json::string(&path.display().to_string()),
+                    artifact.sha256,
+                    json::string(&artifact.source.display().to_string())
+                )
+            })
+            .collect();
+        let options: Vec<String> = self
+            .options
+            .iter()
+            .map(|(name, value)| format!("{}:{}", json::string(name), value))
+            .collect();
+
+        let mut out = format!("{{\"artifacts\":[{}]", artifacts.join(","));
+        if self.timestamp {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            out.push_str(&format!(",\"generated_at\":{}", now));
+        }
+        out.push_str(&format!(",\"options\":{{{}}}}}\n", options.join(",")));
+        out
+    }
+
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        std::fs::write(dir.join("manifest.json"), self.to_json())
+    }
+}
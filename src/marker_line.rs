@@ -0,0 +1,55 @@
+use std::borrow::Cow;
+
+use crate::{Diagnostic, Span};
+
+pub const NON_JACK_FIRST_LINE: &str = "J117";
+
+// a first line some tooling puts in front of the jack, a `#!` line or an xml
+// prolog. it is blanked with spaces rather than cut so the offsets, lines and
+// columns of everything after stay those of the file, with a warning at it
+pub fn skip_marker_line(source: &str) -> (Cow<'_, str>, Option<Diagnostic>) {
+    if !source.starts_with("#!") && !source.starts_with("<?xml") {
+        return (Cow::Borrowed(source), None);
+    }
+
+    let end = source.find('\n').unwrap_or(source.len());
+    let line = source[..end].trim_end_matches('\r');
+    let warning = Diagnostic::warning(
+        NON_JACK_FIRST_LINE,
+        "ignoring non-Jack first line",
+        Span {
+            start: 0,
+            end: line.len(),
+            line: 1,
+            column: 1,
+        },
+    );
+    let blanked = " ".repeat(line.len()) + &source[line.len()..];
+    (Cow::Owned(blanked), Some(warning))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::skip_marker_line;
+
+    #[test]
+    fn only_marker_lines_are_blanked() {
+        let (text, warning) = skip_marker_line("#!/usr/bin/env jack\r\nclass A { }");
+        assert_eq!(text, "                   \r\nclass A { }");
+        assert_eq!(warning.unwrap().span.end, 19);
+
+        let (text, warning) = skip_marker_line("<?xml version=\"1.0\"?>");
+        assert_eq!(text.trim(), "");
+        assert!(warning.is_some());
+
+        for source in [
+            "/** A */\nclass A { }",
+            "class A { } #!",
+            " #!\nclass A { }",
+        ] {
+            let (text, warning) = skip_marker_line(source);
+            assert_eq!(text, source);
+            assert!(warning.is_none());
+        }
+    }
+}
@@ -1,120 +1,139 @@
 use crate::{
     lexical_elements::{Keywords, Symbols},
-    Token,
+    token::Span,
+    StreamTokenizer, Token,
 };
-use std::{fs::File, io::BufRead, io::BufReader, path::PathBuf, str::FromStr};
+use anyhow::{anyhow, bail, Result};
+use std::{iter::FusedIterator, str::FromStr};
 
 pub struct NaiveTokenizer {
-    tokens: Vec<Token>,
-    i: usize,
+    tokens: std::vec::IntoIter<Result<Token>>,
 }
 
 impl NaiveTokenizer {
-    pub fn new(source: &PathBuf) -> Self {
-        let mut tokens = Vec::new();
-
-        // read line by line
-        let file = File::open(source).unwrap();
-        let file_buffer = BufReader::new(file);
-
-        // method 1 - loop through line - whenever we encounter a symbol we add space both sides
-        // then we split on space
+    // reads the file at `source`, line by line
+    pub fn new(source: &std::path::PathBuf) -> std::io::Result<Self> {
+        use std::io::{BufRead, BufReader};
+
+        let file = std::fs::File::open(source)?;
+        let lines = BufReader::new(file)
+            .lines()
+            .collect::<std::io::Result<Vec<_>>>()?;
+        Ok(Self::from_lines(lines.into_iter()))
+    }
 
-        for line in file_buffer.lines() {
-            // trim whitespace and comments
-            let line = Self::strip_comments(line.unwrap().trim());
+    pub fn from_source(source: &str) -> Self {
+        Self::from_lines(source.lines().map(str::to_owned))
+    }
 
-            // if line remains an empty string goto next line
-            if line.is_empty() {
-                continue;
+    fn from_lines(lines: impl Iterator<Item = String>) -> Self {
+        let mut tokens = Vec::new();
+        // a block comment can run over several lines
+        let mut in_comment = false;
+        let mut comment_line = 0;
+
+        for (n, line) in lines.enumerate() {
+            let was_in_comment = in_comment;
+            if let Err(e) = Self::tokenize_line(&line, &mut in_comment, &mut tokens) {
+                // like the stream tokenizer, nothing after an error is read
+                tokens.push(Err(e.context(format!("at line {}", n + 1))));
+                in_comment = false;
+                break;
             }
-
-            let mut processed_line = String::new();
-            let mut string_const = Vec::new();
-            for c in line.chars() {
-                if c == '"' {
-                    if string_const.is_empty() {
-                        string_const.push('"'.to_string());
-                        continue;
-                    } else {
-                        processed_line.push_str(&string_const.join("").replace(" ", "_"));
-                        string_const.clear();
-                        continue;
-                    }
-                }
-
-                if !string_const.is_empty() {
-                    string_const.push(c.to_string());
-                    continue;
-                }
-
-                if Symbols::from_str(&c.to_string()).is_ok() {
-                    processed_line.push_str(&format!(" {} ", c));
-                    continue;
-                } else {
-                    processed_line.push(c);
-                }
+            if in_comment && !was_in_comment {
+                comment_line = n + 1;
             }
+        }
+        if in_comment {
+            let e = StreamTokenizer::unexpected_eof("block comment", Span::default());
+            tokens.push(Err(e.context(format!("at line {}", comment_line))));
+        }
 
-            let line = processed_line;
-
-            // else read each char on line and match with language lexicon
-            for t in line.split(" ") {
-                let t = t.trim();
-                if t.is_empty() {
-                    continue;
-                }
-
-                let token = Keywords::from_str(t);
-                if token.is_ok() {
-                    tokens.push(Token::Keyword(token.unwrap()));
-                    continue;
-                }
+        Self {
+            tokens: tokens.into_iter(),
+        }
+    }
 
-                let token = Symbols::from_str(t);
-                if token.is_ok() {
-                    tokens.push(Token::Symbol(token.unwrap()));
-                    continue;
+    fn tokenize_line(
+        line: &str,
+        in_comment: &mut bool,
+        tokens: &mut Vec<Result<Token>>,
+    ) -> Result<()> {
+        let mut rest = line;
+
+        loop {
+            if *in_comment {
+                match rest.find("*/") {
+                    Some(end) => {
+                        rest = &rest[end + 2..];
+                        *in_comment = false;
+                    }
+                    None => return Ok(()),
                 }
+            }
 
-                let token = t.parse::<i16>();
-                if token.is_ok() {
-                    tokens.push(Token::IntConst(token.unwrap()));
-                    continue;
-                }
+            rest = rest.trim_start();
+            if rest.is_empty() || rest.starts_with("//") {
+                return Ok(());
+            }
+            if let Some(comment) = rest.strip_prefix("/*") {
+                rest = comment;
+                *in_comment = true;
+                continue;
+            }
 
-                if t.chars().next() == Some('"') {
-                    let token = t.to_string().replace("_", " ");
-                    tokens.push(Token::StringConst(token.replace("\"", "")));
-                    continue;
+            let c = rest.chars().next().unwrap();
+            let length = if let Ok(symbol) = Symbols::from_str(&c.to_string()) {
+                tokens.push(Ok(Token::Symbol(symbol)));
+                1
+            } else if c == '"' {
+                // string constants end on the line they start on
+                let end = rest[1..]
+                    .find('"')
+                    .ok_or_else(|| anyhow!("unterminated string constant"))?;
+                tokens.push(Ok(Token::StringConst(rest[1..end + 1].to_owned())));
+                end + 2
+            } else if c.is_ascii_digit() {
+                let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+                let after_minus = matches!(tokens.last(), Some(Ok(Token::Symbol(Symbols::Minus))));
+                let i = match digits.parse::<i16>() {
+                    Ok(i) => i,
+                    // -32768, see StreamTokenizer::tokenize_following
+                    Err(_) if after_minus && digits == "32768" => i16::MIN,
+                    Err(_) => bail!("integer constant `{}` is out of range", digits),
+                };
+                tokens.push(Ok(Token::IntConst(i)));
+                digits.len()
+            } else if c == '_' || c.is_alphabetic() {
+                let word = Self::word(rest);
+                match Keywords::from_str(word) {
+                    Ok(keyword) => tokens.push(Ok(Token::Keyword(keyword))),
+                    Err(_) => tokens.push(Ok(Token::Identifier(word.to_owned()))),
                 }
+                word.len()
+            } else {
+                bail!("unknown character `{}`", c);
+            };
 
-                tokens.push(Token::Identifier(t.to_string()));
-            }
+            rest = &rest[length..];
         }
-
-        Self { tokens, i: 0 }
     }
 
-    fn strip_comments(str: &str) -> String {
-        str.splitn(2, "//")
-            .next()
-            .unwrap()
-            .trim()
-            .splitn(2, "/*")
-            .next()
-            .unwrap()
-            .trim()
-            .to_owned()
+    // the identifier or keyword at the start of `s`
+    fn word(s: &str) -> &str {
+        let end = s
+            .find(|c: char| !(c == '_' || c.is_alphanumeric()))
+            .unwrap_or(s.len());
+        &s[..end]
     }
 }
 
 impl Iterator for NaiveTokenizer {
-    type Item = Token;
+    type Item = Result<Token>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let item = self.tokens.iter().nth(self.i);
-        self.i += 1;
-        item.map(|i| i.clone())
+        self.tokens.next()
     }
 }
+
+impl FusedIterator for NaiveTokenizer {}
@@ -0,0 +1,111 @@
+use std::io::{self, Write};
+
+// the line ending of the files written
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum Newline {
+    /// `\n`, the same bytes on every platform
+    #[default]
+    Lf,
+    /// `\r\n`
+    Crlf,
+    /// `\r\n` on windows, `\n` elsewhere
+    Native,
+}
+
+impl Newline {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Lf => "lf",
+            Self::Crlf => "crlf",
+            Self::Native => "native",
+        }
+    }
+
+    fn is_crlf(&self) -> bool {
+        match self {
+            Self::Lf => false,
+            Self::Crlf => true,
+            Self::Native => cfg!(windows),
+        }
+    }
+
+    // the bytes `text` is written as
+    pub fn apply(&self, text: &str) -> Vec<u8> {
+        let mut writer = NewlineWriter::new(Vec::new(), *self);
+        writer.write_all(text.as_bytes()).unwrap();
+        writer.into_inner()
+    }
+}
+
+// passes everything through to `inner` with every `\n` written as the line
+// ending asked for. a `\r\n` already there is left as it is
+pub struct NewlineWriter<W: Write> {
+    inner: W,
+    crlf: bool,
+    // whether the last byte written was a `\r`, a `\n` starting the next
+    // write completes it
+    after_cr: bool,
+}
+
+impl<W: Write> NewlineWriter<W> {
+    pub fn new(inner: W, newline: Newline) -> Self {
+        Self {
+            inner,
+            crlf: newline.is_crlf(),
+            after_cr: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for NewlineWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.crlf {
+            return self.inner.write(buf);
+        }
+
+        let mut start = 0;
+        for (i, byte) in buf.iter().enumerate() {
+            let after_cr = match i {
+                0 => self.after_cr,
+                _ => buf[i - 1] == b'\r',
+            };
+            if *byte == b'\n' && !after_cr {
+                self.inner.write_all(&buf[start..i])?;
+                self.inner.write_all(b"\r")?;
+                start = i;
+            }
+        }
+        self.inner.write_all(&buf[start..])?;
+        if let Some(last) = buf.last() {
+            self.after_cr = *last == b'\r';
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{Newline, NewlineWriter};
+
+    #[test]
+    fn line_feeds_become_the_style_asked_for() {
+        assert_eq!(Newline::Lf.apply("a\nb\r\n"), b"a\nb\r\n");
+        assert_eq!(Newline::Crlf.apply("a\nb\r\n\n"), b"a\r\nb\r\n\r\n");
+
+        // a `\r\n` split between two writes is still left alone
+        let mut writer = NewlineWriter::new(Vec::new(), Newline::Crlf);
+        writer.write_all(b"a\r").unwrap();
+        writer.write_all(b"\nb\n").unwrap();
+        assert_eq!(writer.into_inner(), b"a\r\nb\r\n");
+    }
+}
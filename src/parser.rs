@@ -0,0 +1,876 @@
+use anyhow::Result;
+use peekmore::{PeekMore, PeekMoreIterator};
+
+use crate::{
+    ast::{
+        Class, ClassVarDec, ClassVarKind, Expression, ExpressionKind, Ident, JackType, Parameter,
+        Statement, SubroutineCall, SubroutineDec, SubroutineKind, VarDec,
+    },
+    balance::BracketTracker,
+    diagnostic::Diagnostic,
+    lexical_elements::{Keywords, Symbols},
+    stream_tokenizer::SpannedTokens,
+    symbol_table::{SymbolKind, SymbolTable},
+    token::{Span, SpannedToken},
+    StreamTokenizer, Token,
+};
+
+pub const UNEXPECTED_TOKEN: &str = "J001";
+pub const UNEXPECTED_EOF: &str = "J002";
+pub const INTEGER_OUT_OF_RANGE: &str = "J006";
+
+// a token the parser would have accepted at the current position
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    Keyword(Keywords),
+    Symbol(Symbols),
+    // a new name being declared
+    Identifier,
+    // a type or the receiver of a call
+    ClassName,
+    // a variable, subroutine or class name used in a statement or term
+    VarName,
+    IntConst,
+    StringConst,
+}
+
+// builds an ast out of a stream of spanned tokens
+pub struct Parser<I: Iterator<Item = Result<SpannedToken>>> {
+    tokens: PeekMoreIterator<I>,
+    last_span: Span,
+    // everything tried since the last consumed token
+    expected: Vec<Expected>,
+    // declarations seen so far, kept for the completion provider
+    symbols: SymbolTable,
+    class_name: Option<String>,
+    subroutine_names: Vec<String>,
+    // accept `this.f()` as the call `f()`, otherwise it is an error
+    // suggesting the bare call
+    allow_this_receiver: bool,
+}
+
+impl Parser<SpannedTokens> {
+    pub fn from_source(source: &str) -> Self {
+        Self::new(StreamTokenizer::from_source(source).into_spanned())
+    }
+}
+
+impl<I: Iterator<Item = Result<SpannedToken>>> Parser<I> {
+    pub fn new(tokens: I) -> Self {
+        Self {
+            tokens: tokens.peekmore(),
+            last_span: Span::default(),
+            expected: Vec::new(),
+            symbols: SymbolTable::new(),
+            class_name: None,
+            subroutine_names: Vec::new(),
+            allow_this_receiver: false,
+        }
+    }
+
+    pub fn allow_this_receiver(mut self, allow: bool) -> Self {
+        self.allow_this_receiver = allow;
+        self
+    }
+
+    pub fn expected(&self) -> &[Expected] {
+        &self.expected
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn class_name(&self) -> Option<&str> {
+        self.class_name.as_deref()
+    }
+
+    pub fn subroutine_names(&self) -> &[String] {
+        &self.subroutine_names
+    }
+
+    pub fn parse_class(&mut self) -> Result<Class> {
+        self.parse_class_with(false)
+    }
+
+    // the class with only its variables and the signatures of its
+    // subroutines, for outlines. a body is skipped to the `}` closing it
+    // without parsing its statements, so the subroutines have no locals or
+    // statements and what is wrong inside a body isn't reported
+    pub fn parse_outline(&mut self) -> Result<Class> {
+        self.parse_class_with(true)
+    }
+
+    fn parse_class_with(&mut self, outline: bool) -> Result<Class> {
+        let start = self.expect_keyword(Keywords::Class)?;
+        let name = self.expect_identifier()?;
+        self.class_name = Some(name.name.clone());
+        self.expect_symbol(Symbols::OpenCurlyBrace)?;
+
+        let mut class_vars = Vec::new();
+        while self.peek_keyword_in(&[Keywords::Static, Keywords::Field]) {
+            class_vars.push(self.parse_class_var_dec()?);
+        }
+
+        let mut subroutines = Vec::new();
+        while self.peek_keyword_in(&[Keywords::Constructor, Keywords::Function, Keywords::Method]) {
+            subroutines.push(self.parse_subroutine_dec(outline)?);
+        }
+
+        let end = self.expect_symbol(Symbols::CloseCurlyBrace)?;
+        self.expect_class_end()?;
+        Ok(Class {
+            name,
+            class_vars,
+            subroutines,
+            span: start.to(&end),
+        })
+    }
+
+    fn parse_class_var_dec(&mut self) -> Result<ClassVarDec> {
+        let token = self.next()?;
+        let kind = match token.token {
+            Token::Keyword(Keywords::Static) => ClassVarKind::Static,
+            _ => ClassVarKind::Field,
+        };
+        let (var_type, type_span) = self.parse_type()?;
+        let names = self.parse_name_list()?;
+        for name in &names {
+            // a duplicate is left for the checker to report, like parameters
+            let _ = self
+                .symbols
+                .define(&name.name, var_type.clone(), kind.into(), name.span);
+        }
+        let end = self.expect_symbol(Symbols::SemiColon)?;
+
+        Ok(ClassVarDec {
+            kind,
+            var_type,
+            type_span,
+            names,
+            span: token.span.to(&end),
+        })
+    }
+
+    fn parse_subroutine_dec(&mut self, outline: bool) -> Result<SubroutineDec> {
+        let token = self.next()?;
+        let kind = match token.token {
+            Token::Keyword(Keywords::Constructor) => SubroutineKind::Constructor,
+            Token::Keyword(Keywords::Function) => SubroutineKind::Function,
+            _ => SubroutineKind::Method,
+        };
+
+        let (return_type, return_type_span) = if self.peek_keyword_in(&[Keywords::Void]) {
+            (JackType::Void, self.next()?.span)
+        } else {
+            self.parse_type()?
+        };
+
+        let name = self.expect_identifier()?;
+        self.subroutine_names.push(name.name.clone());
+        self.symbols.start_subroutine();
+        self.expect_symbol(Symbols::OpenBrace)?;
+        let params = self.parse_parameter_list()?;
+        self.expect_symbol(Symbols::CloseBrace)?;
+
+        let open = self.expect_symbol(Symbols::OpenCurlyBrace)?;
+        let mut locals = Vec::new();
+        let mut statements = Vec::new();
+        let end = match outline {
+            true => self.skip_body(open)?,
+            false => {
+                while self.peek_keyword_in(&[Keywords::Var]) {
+                    locals.push(self.parse_var_dec()?);
+                }
+                statements = self.parse_statements()?;
+                self.expect_symbol(Symbols::CloseCurlyBrace)?
+            }
+        };
+
+        Ok(SubroutineDec {
+            kind,
+            return_type,
+            return_type_span,
+            name,
+            params,
+            locals,
+            statements,
+            span: token.span.to(&end),
+        })
+    }
+
+    // reads up to the `}` that closes the body opened at `open`, the span
+    // of that `}`
+    fn skip_body(&mut self, open: Span) -> Result<Span> {
+        let mut brackets = BracketTracker::new();
+        brackets.symbol(&Symbols::OpenCurlyBrace, open);
+        loop {
+            let token = self.next()?;
+            if let Token::Symbol(symbol) = &token.token {
+                brackets.symbol(symbol, token.span);
+                if brackets.depth() == 0 {
+                    return Ok(token.span);
+                }
+            }
+        }
+    }
+
+    fn parse_parameter_list(&mut self) -> Result<Vec<Parameter>> {
+        let mut params = Vec::new();
+        if self.peek_symbol(Symbols::CloseBrace) {
+            return Ok(params);
+        }
+
+        loop {
+            let (var_type, type_span) = self.parse_type()?;
+            let name = self.expect_identifier()?;
+            // a duplicate parameter is left for the checker to report
+            let _ = self
+                .symbols
+                .define(&name.name, var_type.clone(), SymbolKind::Arg, name.span);
+            params.push(Parameter {
+                var_type,
+                type_span,
+                name,
+            });
+
+            if !self.peek_symbol(Symbols::Comma) {
+                break;
+            }
+            self.next()?;
+        }
+
+        Ok(params)
+    }
+
+    fn parse_var_dec(&mut self) -> Result<VarDec> {
+        let start = self.expect_keyword(Keywords::Var)?;
+        let (var_type, type_span) = self.parse_type()?;
+        let names = self.parse_name_list()?;
+        for name in &names {
+            let _ = self
+                .symbols
+                .define(&name.name, var_type.clone(), SymbolKind::Var, name.span);
+        }
+        let end = self.expect_symbol(Symbols::SemiColon)?;
+
+        Ok(VarDec {
+            var_type,
+            type_span,
+            names,
+            span: start.to(&end),
+        })
+    }
+
+    fn parse_name_list(&mut self) -> Result<Vec<Ident>> {
+        let mut names = vec![self.expect_identifier()?];
+        while self.peek_symbol(Symbols::Comma) {
+            self.next()?;
+            names.push(self.expect_identifier()?);
+        }
+
+        Ok(names)
+    }
+
+    fn parse_type(&mut self) -> Result<(JackType, Span)> {
+        self.expected.extend([
+            Expected::Keyword(Keywords::Int),
+            Expected::Keyword(Keywords::Char),
+            Expected::Keyword(Keywords::Boolean),
+            Expected::ClassName,
+        ]);
+        let token = self.next()?;
+        let var_type = match token.token {
+            Token::Keyword(Keywords::Int) => JackType::Int,
+            Token::Keyword(Keywords::Char) => JackType::Char,
+            Token::Keyword(Keywords::Boolean) => JackType::Boolean,
+            Token::Identifier(name) => JackType::ClassName(name),
+            other => return Err(Self::unexpected(&other, "a type", token.span)),
+        };
+
+        Ok((var_type, token.span))
+    }
+
+    pub fn parse_statements(&mut self) -> Result<Vec<Statement>> {
+        let mut statements = Vec::new();
+        while self.peek_keyword_in(&[
+            Keywords::Let,
+            Keywords::If,
+            Keywords::While,
+            Keywords::Do,
+            Keywords::Return,
+        ]) {
+            statements.push(self.parse_statement()?);
+        }
+
+        Ok(statements)
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement> {
+        let token = self.next()?;
+        let start = token.span;
+        let statement = match token.token {
+            Token::Keyword(Keywords::Let) => {
+                self.expected.push(Expected::VarName);
+                let target = self.expect_identifier()?;
+                let index = if self.peek_symbol(Symbols::OpenSquareBrace) {
+                    self.next()?;
+                    let index = self.parse_expression()?;
+                    self.expect_symbol(Symbols::CloseSquareBrace)?;
+                    Some(index)
+                } else {
+                    None
+                };
+                self.expect_symbol(Symbols::Equal)?;
+                let value = self.parse_expression()?;
+                let end = self.expect_symbol(Symbols::SemiColon)?;
+
+                Statement::Let {
+                    target,
+                    index,
+                    value,
+                    span: start.to(&end),
+                }
+            }
+            Token::Keyword(Keywords::If) => {
+                let condition = self.parse_condition()?;
+                let (then_branch, mut end) = self.parse_block()?;
+                let else_branch = if self.peek_keyword_in(&[Keywords::Else]) {
+                    self.next()?;
+                    let (else_branch, else_end) = self.parse_block()?;
+                    end = else_end;
+                    Some(else_branch)
+                } else {
+                    None
+                };
+
+                Statement::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                    span: start.to(&end),
+                }
+            }
+            Token::Keyword(Keywords::While) => {
+                let condition = self.parse_condition()?;
+                let (body, end) = self.parse_block()?;
+
+                Statement::While {
+                    condition,
+                    body,
+                    span: start.to(&end),
+                }
+            }
+            Token::Keyword(Keywords::Do) => {
+                self.skip_this_receiver()?;
+                self.expected.push(Expected::VarName);
+                let name = self.expect_identifier()?;
+                let call = self.parse_subroutine_call(name)?;
+                let end = self.expect_symbol(Symbols::SemiColon)?;
+
+                Statement::Do {
+                    call,
+                    span: start.to(&end),
+                }
+            }
+            _ => {
+                let value = if self.peek_symbol(Symbols::SemiColon) {
+                    None
+                } else {
+                    Some(self.parse_expression()?)
+                };
+                let end = self.expect_symbol(Symbols::SemiColon)?;
+
+                Statement::Return {
+                    value,
+                    span: start.to(&end),
+                }
+            }
+        };
+
+        Ok(statement)
+    }
+
+    fn parse_condition(&mut self) -> Result<Expression> {
+        self.expect_symbol(Symbols::OpenBrace)?;
+        let condition = self.parse_expression()?;
+        self.expect_symbol(Symbols::CloseBrace)?;
+
+        Ok(condition)
+    }
+
+    fn parse_block(&mut self) -> Result<(Vec<Statement>, Span)> {
+        self.expect_symbol(Symbols::OpenCurlyBrace)?;
+        let statements = self.parse_statements()?;
+        let end = self.expect_symbol(Symbols::CloseCurlyBrace)?;
+
+        Ok((statements, end))
+    }
+
+    pub fn parse_expression(&mut self) -> Result<Expression> {
+        let mut lhs = self.parse_term()?;
+
+        while let Some(op) = self.peek_operator() {
+            self.next()?;
+            let rhs = self.parse_term()?;
+            let span = lhs.span.to(&rhs.span);
+            lhs = Expression {
+                kind: ExpressionKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+                span,
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    // fails unless every token was consumed, for inputs that are a single
+    // expression rather than a class
+    pub fn expect_end(&mut self) -> Result<()> {
+        match self.tokens.next() {
+            Some(Ok(token)) => Err(Self::unexpected(
+                &token.token,
+                "the end of input",
+                token.span,
+            )),
+            Some(Err(e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // a file is exactly one class, anything after it is an error
+    fn expect_class_end(&mut self) -> Result<()> {
+        match self.tokens.next() {
+            Some(Ok(token)) => Err(Diagnostic::error(
+                UNEXPECTED_TOKEN,
+                format!("unexpected token after class: `{}`", token.token.lexeme()),
+                token.span,
+            )
+            .into()),
+            Some(Err(e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Expression> {
+        self.expected.extend([
+            Expected::IntConst,
+            Expected::StringConst,
+            Expected::Keyword(Keywords::True),
+            Expected::Keyword(Keywords::False),
+            Expected::Keyword(Keywords::Null),
+            Expected::Keyword(Keywords::This),
+            Expected::Symbol(Symbols::OpenBrace),
+            Expected::Symbol(Symbols::Minus),
+            Expected::Symbol(Symbols::Tilde),
+            Expected::VarName,
+        ]);
+        if self.skip_this_receiver()? {
+            let name = self.expect_identifier()?;
+            let call = self.parse_subroutine_call(name)?;
+            let span = call.span;
+            return Ok(Expression {
+                kind: ExpressionKind::Call(call),
+                span,
+            });
+        }
+        let token = self.next()?;
+        let start = token.span;
+        let (kind, end) = match token.token {
+            Token::IntConst(i16::MIN) => {
+                return Err(Diagnostic::error(
+                    INTEGER_OUT_OF_RANGE,
+                    "integer constant 32768 is out of range, only -32768 is allowed",
+                    start,
+                )
+                .into())
+            }
+            Token::IntConst(i) => (ExpressionKind::IntConst(i), start),
+            Token::StringConst(s) => (ExpressionKind::StringConst(s), start),
+            Token::Keyword(
+                k @ (Keywords::True | Keywords::False | Keywords::Null | Keywords::This),
+            ) => (ExpressionKind::KeywordConst(k), start),
+            Token::Symbol(Symbols::OpenBrace) => {
+                let inner = self.parse_expression()?;
+                let end = self.expect_symbol(Symbols::CloseBrace)?;
+                (ExpressionKind::Paren(Box::new(inner)), end)
+            }
+            // -32768 is a constant rather than the negation of one, 32768
+            // doesn't fit
+            Token::Symbol(Symbols::Minus)
+                if matches!(self.peek(), Some(Token::IntConst(i16::MIN))) =>
+            {
+                let end = self.next()?.span;
+                (ExpressionKind::IntConst(i16::MIN), end)
+            }
+            Token::Symbol(op @ (Symbols::Minus | Symbols::Tilde)) => {
+                let operand = self.parse_term()?;
+                let end = operand.span;
+                (ExpressionKind::Unary(op, Box::new(operand)), end)
+            }
+            Token::Identifier(name) => {
+                let ident = Ident { name, span: start };
+                if self.peek_symbol(Symbols::OpenSquareBrace) {
+                    self.next()?;
+                    let index = self.parse_expression()?;
+                    let end = self.expect_symbol(Symbols::CloseSquareBrace)?;
+                    (ExpressionKind::Index(ident, Box::new(index)), end)
+                } else if self.peek_symbol(Symbols::OpenBrace) || self.peek_symbol(Symbols::Dot) {
+                    let call = self.parse_subroutine_call(ident)?;
+                    let end = call.span;
+                    (ExpressionKind::Call(call), end)
+                } else {
+                    (ExpressionKind::Var(ident), start)
+                }
+            }
+            other => return Err(Self::unexpected(&other, "a term", start)),
+        };
+
+        Ok(Expression {
+            kind,
+            span: start.to(&end),
+        })
+    }
+
+    // `this.` starting a call, which the grammar doesn't allow. it is read
+    // and true when the parser allows it, otherwise an error suggesting the
+    // bare call
+    fn skip_this_receiver(&mut self) -> Result<bool> {
+        let this = match self.tokens.peek_nth(0) {
+            Some(Ok(token)) if token.token == Token::Keyword(Keywords::This) => token.span,
+            _ => return Ok(false),
+        };
+        if !matches!(self.tokens.peek_nth(1), Some(Ok(token)) if token.token == Token::Symbol(Symbols::Dot))
+        {
+            return Ok(false);
+        }
+        if !self.allow_this_receiver {
+            let name = match self.tokens.peek_nth(2) {
+                Some(Ok(SpannedToken {
+                    token: Token::Identifier(name),
+                    ..
+                })) => name.clone(),
+                _ => String::from("f"),
+            };
+            let message = format!(
+                "`this` can't be the receiver of a call, methods of the current class are called bare, as `{}(...)`",
+                name
+            );
+            return Err(Diagnostic::error(UNEXPECTED_TOKEN, message, this).into());
+        }
+        self.next()?;
+        self.next()?;
+        Ok(true)
+    }
+
+    // `first` is the already consumed subroutine, class or variable name
+    fn parse_subroutine_call(&mut self, first: Ident) -> Result<SubroutineCall> {
+        let start = first.span;
+        let (receiver, name) = if self.peek_symbol(Symbols::Dot) {
+            self.next()?;
+            (Some(first), self.expect_identifier()?)
+        } else {
+            (None, first)
+        };
+
+        self.expect_symbol(Symbols::OpenBrace)?;
+        let mut args = Vec::new();
+        if !self.peek_symbol(Symbols::CloseBrace) {
+            args.push(self.parse_expression()?);
+            while self.peek_symbol(Symbols::Comma) {
+                self.next()?;
+                args.push(self.parse_expression()?);
+            }
+        }
+        let end = self.expect_symbol(Symbols::CloseBrace)?;
+
+        Ok(SubroutineCall {
+            receiver,
+            name,
+            args,
+            span: start.to(&end),
+        })
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        match self.tokens.peek() {
+            Some(Ok(spanned)) => Some(&spanned.token),
+            _ => None,
+        }
+    }
+
+    fn peek_symbol(&mut self, symbol: Symbols) -> bool {
+        self.expected.push(Expected::Symbol(symbol.clone()));
+        matches!(self.peek(), Some(Token::Symbol(s)) if s == &symbol)
+    }
+
+    fn peek_keyword_in(&mut self, keywords: &[Keywords]) -> bool {
+        self.expected
+            .extend(keywords.iter().cloned().map(Expected::Keyword));
+        matches!(self.peek(), Some(Token::Keyword(k)) if keywords.contains(k))
+    }
+
+    fn peek_operator(&mut self) -> Option<Symbols> {
+        self.expected.extend(
+            [
+                Symbols::Plus,
+                Symbols::Minus,
+                Symbols::Asterik,
+                Symbols::BackSlash,
+                Symbols::Ampersand,
+                Symbols::VerticalBar,
+                Symbols::LessThan,
+                Symbols::GreaterThan,
+                Symbols::Equal,
+            ]
+            .map(Expected::Symbol),
+        );
+        match self.peek() {
+            Some(Token::Symbol(
+                s @ (Symbols::Plus
+                | Symbols::Minus
+                | Symbols::Asterik
+                | Symbols::BackSlash
+                | Symbols::Ampersand
+                | Symbols::VerticalBar
+                | Symbols::LessThan
+                | Symbols::GreaterThan
+                | Symbols::Equal),
+            )) => Some(s.clone()),
+            _ => None,
+        }
+    }
+
+    fn next(&mut self) -> Result<SpannedToken> {
+        match self.tokens.next() {
+            Some(Ok(token)) => {
+                self.last_span = token.span;
+                self.expected.clear();
+                Ok(token)
+            }
+            Some(Err(e)) => Err(e),
+            None => {
+                let span = Span {
+                    start: self.last_span.end,
+                    ..self.last_span
+                };
+                Err(Diagnostic::error(UNEXPECTED_EOF, "unexpected end of input", span).into())
+            }
+        }
+    }
+
+    fn expect_identifier(&mut self) -> Result<Ident> {
+        self.expected.push(Expected::Identifier);
+        let token = self.next()?;
+        match token.token {
+            Token::Identifier(name) => Ok(Ident {
+                name,
+                span: token.span,
+            }),
+            other => Err(Self::unexpected(&other, "an identifier", token.span)),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: Keywords) -> Result<Span> {
+        self.expected.push(Expected::Keyword(keyword.clone()));
+        let token = self.next()?;
+        match &token.token {
+            Token::Keyword(k) if k == &keyword => Ok(token.span),
+            other => Err(Self::unexpected(
+                other,
+                &format!("`{}`", keyword),
+                token.span,
+            )),
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: Symbols) -> Result<Span> {
+        self.expected.push(Expected::Symbol(symbol.clone()));
+        let token = self.next()?;
+        match &token.token {
+            Token::Symbol(s) if s == &symbol => Ok(token.span),
+            other => Err(Self::unexpected(
+                other,
+                &format!("`{}`", symbol),
+                token.span,
+            )),
+        }
+    }
+
+    fn unexpected(token: &Token, expected: &str, span: Span) -> anyhow::Error {
+        Diagnostic::error(
+            UNEXPECTED_TOKEN,
+            format!("expected {}, found `{}`", expected, token),
+            span,
+        )
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{ExpressionKind, JackType, Statement};
+    use crate::lexical_elements::Symbols;
+
+    use super::Parser;
+
+    #[test]
+    fn outlines_skip_subroutine_bodies() {
+        let src = "class Main { field int x;
+            constructor Main new() { let x = (1 + [2); return this; }
+            method void run(int n) { if (n) { while (n) { goto; } } }
+            function int twice(int a) { return a + a; } }";
+
+        // the bodies don't parse, the outline doesn't look inside them
+        assert!(Parser::from_source(src).parse_class().is_err());
+        let class = Parser::from_source(src).parse_outline().unwrap();
+        let names: Vec<&str> = class
+            .subroutines
+            .iter()
+            .map(|s| s.name.name.as_str())
+            .collect();
+        assert_eq!(names, ["new", "run", "twice"]);
+        assert_eq!(class.class_vars.len(), 1);
+        assert_eq!(class.subroutines[1].params[0].name.name, "n");
+        assert!(class
+            .subroutines
+            .iter()
+            .all(|s| s.locals.is_empty() && s.statements.is_empty()));
+
+        // the spans are those of the whole parse
+        let valid = "class A { function void f() { var int i; let i = 1; return; }\n }";
+        let outline = Parser::from_source(valid).parse_outline().unwrap();
+        let full = Parser::from_source(valid).parse_class().unwrap();
+        assert_eq!(outline.subroutines[0].span, full.subroutines[0].span);
+        assert_eq!(outline.span, full.span);
+    }
+
+    #[test]
+    fn parse_class_with_declarations() {
+        let src = "class Main { field int x, y; static Array a;
+            method void run(int n, Main other) { var char c; return; } }";
+
+        let class = Parser::from_source(src).parse_class().unwrap();
+        assert_eq!(class.name.name, "Main");
+        assert_eq!(class.class_vars.len(), 2);
+        assert_eq!(class.class_vars[0].names.len(), 2);
+        assert_eq!(
+            class.class_vars[1].var_type,
+            JackType::ClassName("Array".to_string())
+        );
+        assert_eq!(class.subroutines[0].params.len(), 2);
+        assert_eq!(class.subroutines[0].return_type, JackType::Void);
+        assert_eq!(class.subroutines[0].locals[0].var_type, JackType::Char);
+    }
+
+    #[test]
+    fn parse_expression_is_left_associative() {
+        let src = "class Main { function int f() { return 1 + 2 * 3; } }";
+
+        let class = Parser::from_source(src).parse_class().unwrap();
+        let value = match &class.subroutines[0].statements[0] {
+            Statement::Return { value, .. } => value.clone().unwrap(),
+            other => panic!("expected a return statement, got {:?}", other),
+        };
+
+        match value.kind {
+            ExpressionKind::Binary(op, lhs, _) => {
+                assert_eq!(op, Symbols::Asterik);
+                assert!(matches!(
+                    lhs.kind,
+                    ExpressionKind::Binary(Symbols::Plus, _, _)
+                ));
+            }
+            other => panic!("expected a binary expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_error_reports_position() {
+        let src = "class Main {\n  function void f() { let = 1; } }";
+
+        let err = Parser::from_source(src).parse_class().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "2:27: error[J001]: expected an identifier, found `Symbol(=)`"
+        );
+    }
+
+    #[test]
+    fn variables_declared_twice_still_parse() {
+        // the checker reports them, with the rest of what is wrong
+        for src in [
+            "class Main { function void f() { var int x; var boolean x; return; } }",
+            "class Main { field int x; static int x; }",
+        ] {
+            let class = Parser::from_source(src).parse_class().unwrap();
+            assert_eq!(class.name.name, "Main");
+        }
+    }
+
+    fn return_value(src: &str) -> anyhow::Result<ExpressionKind> {
+        let class = Parser::from_source(src).parse_class()?;
+        match &class.subroutines[0].statements[0] {
+            Statement::Return { value, .. } => Ok(value.clone().unwrap().kind),
+            other => panic!("expected a return statement, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn smallest_int_constant() {
+        for src in [
+            "class Main { function int f() { return -32768; } }",
+            "class Main { function int f() { return - 32768; } }",
+        ] {
+            let got = return_value(src).unwrap();
+            assert!(matches!(got, ExpressionKind::IntConst(-32768)), "{:?}", got);
+        }
+
+        let err = return_value("class Main { function int f() { return -32769; } }").unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("number too large"),
+            "{:#}",
+            err
+        );
+        let err = return_value("class Main { function int f() { return 1-32768; } }").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:42: error[J006]: integer constant 32768 is out of range, only -32768 is allowed"
+        );
+    }
+
+    #[test]
+    fn a_second_class_is_an_error() {
+        let err = Parser::from_source("class A { } class B { }")
+            .parse_class()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:13: error[J001]: unexpected token after class: `class`"
+        );
+    }
+
+    #[test]
+    fn this_receiver_is_an_error_unless_allowed() {
+        let src = "class Main { method void f() { do this.draw(); let x = this.size(); return; } }";
+
+        let err = Parser::from_source(src).parse_class().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:35: error[J001]: `this` can't be the receiver of a call, methods of the current class are called bare, as `draw(...)`"
+        );
+
+        let class = Parser::from_source(src)
+            .allow_this_receiver(true)
+            .parse_class()
+            .unwrap();
+        let statements = &class.subroutines[0].statements;
+        assert!(matches!(
+            &statements[0],
+            Statement::Do { call, .. } if call.receiver.is_none() && call.name.name == "draw"
+        ));
+        assert!(matches!(
+            &statements[1],
+            Statement::Let { value, .. } if matches!(&value.kind, ExpressionKind::Call(call) if call.receiver.is_none())
+        ));
+    }
+}
@@ -0,0 +1,17 @@
+//! The types most users of the crate need, for a glob import.
+//!
+//! ```
+//! use jack_compiler::prelude::*;
+//!
+//! let class = Parser::from_source("class Main { }").parse_class().unwrap();
+//! assert!(Checker::check(&class).is_empty());
+//! ```
+//!
+//! Items are only added here, never removed or renamed within a major
+//! version.
+
+pub use crate::{
+    ast, compile_batch, compile_source, lexical_elements, Analyzer, AnalyzerOptions, Checker,
+    CodeGenerator, CompilationEngine, CompileError, CompileOptions, Diagnostic, Emit, Parser,
+    Project, Severity, Span, SpannedToken, StreamTokenizer, Token, TokenKind,
+};
@@ -0,0 +1,580 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    analyzer::{source_stem, ParseFormat, PhaseTimes},
+    ast::Class,
+    ast_to_dot,
+    balance::unbalanced,
+    check_brace_style,
+    engine::check_fully_read,
+    listener::ParseListener,
+    marker_line::skip_marker_line,
+    records::RecordsListener,
+    AnalyzerOptions, Checker, CodeGenerator, CodegenOptions, CompilationEngine, CompilationUnit,
+    CompileOptions, Diagnostic, Parser, Severity, StreamTokenizer,
+};
+
+// the sources of a program held in memory by file name, everything Analyzer
+// does with files without touching the filesystem. outputs are keyed by the
+// name the file would get next to its source
+#[derive(Debug, Clone, Default)]
+pub struct Project {
+    sources: BTreeMap<String, String>,
+    options: AnalyzerOptions,
+}
+
+// the parse tree of one source, in the format of the options, and the errors
+// recovered from while writing it. there is no tree when the balance check
+// failed first
+#[derive(Debug, Clone, Default)]
+pub struct ParsedTree {
+    pub tree: Option<String>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub times: PhaseTimes,
+    // the tokens the engine was given, the ones it compiled and how far into
+    // the source the tokenizer read. a tree from fewer than all of them is
+    // an internal error
+    pub tokens: usize,
+    pub tokens_consumed: usize,
+    pub bytes_scanned: usize,
+    // the most output the engine held back
+    pub peak_buffer: usize,
+}
+
+impl Project {
+    pub fn from_sources(sources: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            sources: sources.into_iter().collect(),
+            options: AnalyzerOptions::default(),
+        }
+    }
+
+    // only the options about checking and compiling are used, the ones
+    // about writing files are left to Analyzer
+    pub fn with_options(mut self, options: AnalyzerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn sources(&self) -> &BTreeMap<String, String> {
+        &self.sources
+    }
+
+    pub fn source(&self, name: &str) -> Option<&str> {
+        self.sources.get(name).map(String::as_str)
+    }
+
+    // the class of every source, the first that doesn't parse is the error
+    pub fn parse_all(&self) -> Result<BTreeMap<String, Class>> {
+        let classes = self.map_sources(|_, text| {
+            let (text, _) = Self::compiled_text(text, &self.options);
+            Self::parse_class(&text, &self.options)
+        })?;
+        Ok(self.sources.keys().cloned().zip(classes).collect())
+    }
+
+    // the semantic checks and lints of every source, then the brace style
+    // ones, then with check_calls the calls between classes. a syntax error
+    // is the only diagnostic of its source besides a skipped first
+    // line, and leaves it out of the call
+    // checks. warnings with an allowed code are left out
+    pub fn check(&self) -> Result<Vec<(String, Diagnostic)>> {
+        let options = &self.options;
+        let per_source =
+            self.map_sources(|_, text| -> Result<(Option<Class>, Vec<Diagnostic>)> {
+                let (text, skipped) = Self::compiled_text(text, options);
+                let mut diagnostics: Vec<Diagnostic> = skipped.into_iter().collect();
+                let class = match Self::parse_class(&text, options) {
+                    Ok(class) => class,
+                    Err(e) => {
+                        diagnostics.push(e.downcast::<Diagnostic>()?);
+                        return Ok((None, diagnostics));
+                    }
+                };
+                diagnostics.extend(Self::check_class(&class, &text, options));
+                Ok((Some(class), diagnostics))
+            })?;
+
+        let (classes, mut per_source): (Vec<_>, Vec<_>) = per_source.into_iter().unzip();
+        if options.check_calls {
+            let names: Vec<&String> = self.sources.keys().collect();
+            let unit = CompilationUnit::new(
+                names
+                    .iter()
+                    .zip(classes)
+                    .filter_map(|(name, class)| Some(((*name).clone(), class?))),
+            );
+            // kept with the other diagnostics of their source
+            for (name, diagnostic) in unit.check_calls() {
+                let i = names.iter().position(|n| **n == name).unwrap();
+                per_source[i].push(diagnostic);
+            }
+        }
+
+        Ok(self
+            .sources
+            .keys()
+            .zip(per_source)
+            .flat_map(|(name, diagnostics)| diagnostics.into_iter().map(|d| (name.clone(), d)))
+            .collect())
+    }
+
+    // the semantic checks and lints of the source called `name`, none when it
+    // doesn't parse
+    pub(crate) fn check_source(&self, name: &str) -> Vec<Diagnostic> {
+        let text = self.sources.get(name).map_or("", String::as_str);
+        let (text, _) = Self::compiled_text(text, &self.options);
+        match Self::parse_class(&text, &self.options) {
+            Ok(class) => Self::check_class(&class, &text, &self.options),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // the diagnostics of the checker and the brace style lint, without the
+    // warnings with an allowed code
+    fn check_class(class: &Class, text: &str, options: &AnalyzerOptions) -> Vec<Diagnostic> {
+        let mut diagnostics =
+            Checker::check_reserving(class, options.strict, &options.reserved_identifiers);
+        if let Some(style) = options.brace_style {
+            diagnostics.extend(check_brace_style(text, style));
+        }
+        diagnostics
+            .into_iter()
+            .filter(|d| {
+                d.severity != Severity::Warning || !options.allow.iter().any(|code| code == d.code)
+            })
+            .collect()
+    }
+
+    // the vm code of every class, keyed by `<stem>.vm`
+    pub fn emit_vm(&self) -> Result<BTreeMap<String, String>> {
+        Ok(self
+            .sources
+            .keys()
+            .map(|name| output_name(name, "vm"))
+            .zip(self.compile_vm()?.into_iter().map(|(vm, _)| vm))
+            .collect())
+    }
+
+    // the xml parse tree of every source, keyed by `<stem>.xml`. fails on the
+    // first source with a syntax error
+    pub fn emit_xml(&self) -> Result<BTreeMap<String, String>> {
+        let options = AnalyzerOptions {
+            format: ParseFormat::Xml,
+            check_balance: false,
+            ..self.options.clone()
+        };
+        let mut outputs = BTreeMap::new();
+        for (name, text) in &self.sources {
+            let (parsed, _) = Self::parse_tree(text, &options, Vec::new())?;
+            if let Some(error) = parsed
+                .diagnostics
+                .into_iter()
+                .find(|d| d.severity == Severity::Error)
+            {
+                return Err(anyhow::Error::new(error).context(name.clone()));
+            }
+            outputs.insert(output_name(name, "xml"), parsed.tree.unwrap_or_default());
+        }
+        Ok(outputs)
+    }
+
+    // the vm code of every class, in the order of the sources, with the
+    // warning about a marker first line that was skipped
+    pub(crate) fn compile_vm(&self) -> Result<Vec<(String, Option<Diagnostic>)>> {
+        let threads = match self.options.parallel_subroutines {
+            true => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            false => 1,
+        };
+        self.map_sources(|name, text| {
+            let (text, skipped) = Self::compiled_text(text, &self.options);
+            let class = Self::parse_class(&text, &self.options)?;
+            let file = Path::new(name).file_name().and_then(|f| f.to_str());
+            let options = CodegenOptions {
+                jobs: threads,
+                source: self.options.vm_debug.then(|| file.unwrap_or(name)),
+                math_class: &self.options.math_class,
+                ..Default::default()
+            };
+            let vm = CodeGenerator::generate_with(&class, &options)?;
+            Ok((vm, skipped))
+        })
+    }
+
+    // the parse tree of the source called `name`, with the errors the engine
+    // recovered from
+    pub fn tree(&self, name: &str) -> Result<ParsedTree> {
+        let text = self
+            .source(name)
+            .with_context(|| format!("no source named `{}`", name))?;
+        let (parsed, _) =
+            Self::parse_tree(text, &self.options, Vec::new()).with_context(|| name.to_owned())?;
+        Ok(parsed)
+    }
+
+    // the xml tree of the source called `name` like tree, with every listener
+    // given the events of the same parse. the errors the listeners failed
+    // with are in the order they were given
+    pub fn tree_with<'l>(
+        &self,
+        name: &str,
+        listeners: Vec<Box<dyn ParseListener + 'l>>,
+    ) -> Result<(ParsedTree, Vec<Option<std::io::Error>>)> {
+        let text = self
+            .source(name)
+            .with_context(|| format!("no source named `{}`", name))?;
+        let options = AnalyzerOptions {
+            format: ParseFormat::Xml,
+            ..self.options.clone()
+        };
+        Self::parse_tree(text, &options, listeners).with_context(|| name.to_owned())
+    }
+
+    fn parse_tree<'l>(
+        text: &str,
+        options: &AnalyzerOptions,
+        listeners: Vec<Box<dyn ParseListener + 'l>>,
+    ) -> Result<(ParsedTree, Vec<Option<std::io::Error>>)> {
+        let (text, skipped) = Self::compiled_text(text, options);
+        let mut diagnostics: Vec<Diagnostic> = skipped.into_iter().collect();
+        let mut xml = Vec::new();
+        let mut records = Vec::new();
+        let mut listeners: Vec<Box<dyn ParseListener + '_>> = listeners;
+        // records are written from the events of the same run, after the
+        // caller's listeners
+        let records_listener = options.format == ParseFormat::Records;
+        if records_listener {
+            listeners.push(Box::new(RecordsListener::new(&mut records)));
+        }
+        let (mut parsed, failed) = Self::write_xml(&text, &mut xml, options, listeners);
+        diagnostics.append(&mut parsed.diagnostics);
+        parsed.diagnostics = diagnostics;
+        // the balance check stopped it before the engine ran
+        let Some(mut failed) = failed else {
+            return Ok((parsed, Vec::new()));
+        };
+        if records_listener {
+            failed.pop();
+        }
+        // the syntax tree of a source with errors is left empty, the engine
+        // has reported them
+        let tree = match options.format {
+            ParseFormat::Xml => xml,
+            ParseFormat::Records => records,
+            ParseFormat::Dot => Self::parse_class(&text, options)
+                .map(|class| ast_to_dot(&class).into_bytes())
+                .unwrap_or_default(),
+        };
+
+        parsed.tree = Some(String::from_utf8_lossy(&tree).into_owned());
+        Ok((parsed, failed))
+    }
+
+    // the file is tokenized whole before the engine runs so the two can be
+    // timed apart. all but the tree, that is in `xml`, and the listener
+    // errors unless an imbalance was found before compiling
+    fn write_xml<'l>(
+        text: &str,
+        xml: &mut Vec<u8>,
+        options: &AnalyzerOptions,
+        mut listeners: Vec<Box<dyn ParseListener + 'l>>,
+    ) -> (ParsedTree, Option<Vec<Option<std::io::Error>>>) {
+        // when more than one error is wanted, unknown characters are
+        // reported like any other syntax error so the rest of the file is
+        // still checked
+        let recover = options.max_errors != 1;
+        let start = Instant::now();
+        let mut source_tokens = StreamTokenizer::from_source(text)
+            .recover_from_lex_errors(recover)
+            .into_spanned();
+        let spanned: Vec<_> = source_tokens.by_ref().collect();
+        let tokenize = start.elapsed();
+        let count = spanned.len();
+        if options.check_balance {
+            // over the tokens the engine would be given
+            let diagnostics: Vec<_> = unbalanced(&spanned).into_iter().take(1).collect();
+            if !diagnostics.is_empty() {
+                let parsed = ParsedTree {
+                    diagnostics,
+                    times: PhaseTimes {
+                        tokenize,
+                        ..Default::default()
+                    },
+                    tokens: count,
+                    ..Default::default()
+                };
+                return (parsed, None);
+            }
+        }
+
+        let start = Instant::now();
+        // the spans give the diagnostics about delimiters their positions
+        let spans = spanned
+            .iter()
+            .map_while(|token| token.as_ref().ok())
+            .map(|token| token.span)
+            .collect();
+        let mut tokens = spanned
+            .into_iter()
+            .map(|token| token.map(|spanned| spanned.token));
+        let mut engine = CompilationEngine::new(xml, &mut tokens)
+            .with_token_spans(spans)
+            .with_options(CompileOptions {
+                span_attributes: options.spans,
+                well_formed_xml: options.well_formed_xml && options.format == ParseFormat::Xml,
+                normalize: options.normalize && options.format == ParseFormat::Xml,
+                allow_this_receiver: options.allow_this_receiver,
+                ..Default::default()
+            });
+        for listener in &mut listeners {
+            engine = engine.with_listener(listener.as_mut());
+        }
+        let mut diagnostics = engine.compile_collecting(options.max_errors);
+        let consumed = engine.tokens_consumed();
+        if !diagnostics.iter().any(|d| d.severity == Severity::Error) {
+            diagnostics.extend(check_fully_read(source_tokens.tokenizer(), consumed));
+        }
+        let parsed = ParsedTree {
+            tree: None,
+            diagnostics,
+            times: PhaseTimes {
+                tokenize,
+                engine: start.elapsed(),
+                ..Default::default()
+            },
+            tokens: count,
+            tokens_consumed: consumed,
+            bytes_scanned: source_tokens.tokenizer().bytes_scanned(),
+            peak_buffer: engine.peak_buffer(),
+        };
+        (parsed, Some(engine.listener_errors()))
+    }
+
+    // the class of a source, read the way the options allow
+    pub(crate) fn parse_class(text: &str, options: &AnalyzerOptions) -> Result<Class> {
+        Parser::from_source(text)
+            .allow_this_receiver(options.allow_this_receiver)
+            .parse_class()
+    }
+
+    // the text of a source as it is compiled, with a marker first line
+    // blanked when the options skip it, and the warning about that
+    fn compiled_text<'a>(
+        text: &'a str,
+        options: &AnalyzerOptions,
+    ) -> (Cow<'a, str>, Option<Diagnostic>) {
+        match options.skip_marker_line {
+            true => skip_marker_line(text),
+            false => (Cow::Borrowed(text), None),
+        }
+    }
+
+    // runs `f` over every source on up to `jobs` threads of the options, the
+    // results are in the order of the sources. errors name their source
+    fn map_sources<R: Send>(&self, f: impl Fn(&str, &str) -> Result<R> + Sync) -> Result<Vec<R>> {
+        let sources: Vec<(&String, &String)> = self.sources.iter().collect();
+        map_parallel(&sources, self.options.jobs, |(name, text)| {
+            f(name, text).with_context(|| (*name).clone())
+        })
+    }
+}
+
+// `name` with its `.jack` or `.jack.gz` replaced by `.<extension>`
+fn output_name(name: &str, extension: &str) -> String {
+    let path = Path::new(name);
+    path.with_file_name(format!("{}.{}", source_stem(path), extension))
+        .display()
+        .to_string()
+}
+
+// runs `f` over every item on up to `jobs` threads, the results are in the
+// order of `items`
+pub(crate) fn map_parallel<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    f: impl Fn(&T) -> Result<R> + Sync,
+) -> Result<Vec<R>> {
+    let jobs = jobs.clamp(1, items.len().max(1));
+    if jobs == 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<Result<R>>> = items.iter().map(|_| None).collect();
+    std::thread::scope(|scope| {
+        let (f, next) = (&f, &next);
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(move || {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break done;
+                        };
+                        done.push((i, f(item)));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            for (i, result) in worker.join().expect("worker thread panicked") {
+                results[i] = Some(result);
+            }
+        }
+    });
+
+    results.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Project;
+    use crate::{AnalyzerOptions, Severity};
+
+    fn project(files: &[(&str, &str)]) -> Project {
+        Project::from_sources(
+            files
+                .iter()
+                .map(|(name, text)| (name.to_string(), text.to_string())),
+        )
+    }
+
+    #[test]
+    fn two_classes_check_and_compile_in_memory() {
+        let project = project(&[
+            (
+                "Main.jack",
+                "class Main { function void main() { var Point p;
+                    let p = Point.new(3); do Output.printInt(p.getX()); return; } }",
+            ),
+            (
+                "Point.jack",
+                "class Point { field int x;
+                    constructor Point new(int ax) { let x = ax; return this; }
+                    method int getX() { return x; } }",
+            ),
+        ]);
+
+        assert!(project.check().unwrap().is_empty());
+        let classes = project.parse_all().unwrap();
+        assert_eq!(classes["Point.jack"].name.name, "Point");
+
+        let vm = project.emit_vm().unwrap();
+        assert_eq!(
+            vm.keys().collect::<Vec<_>>(),
+            ["Main.vm", "Point.vm"].iter().collect::<Vec<_>>()
+        );
+        assert!(vm["Main.vm"].starts_with("function Main.main 1\n"));
+        assert!(vm["Main.vm"].contains("call Point.new 1\n"));
+        assert!(vm["Point.vm"].contains("function Point.getX 0\n"));
+
+        let xml = project.emit_xml().unwrap();
+        assert!(
+            xml["Point.xml"].starts_with("<class>"),
+            "{}",
+            xml["Point.xml"]
+        );
+    }
+
+    #[test]
+    fn check_calls_reports_calls_into_other_sources() {
+        let files = [
+            (
+                "Foo.jack",
+                "class Foo { function void main() { do Bar.missing(); return; } }",
+            ),
+            ("Bar.jack", "class Bar { function void f() { return; } }"),
+        ];
+        assert!(project(&files).check().unwrap().is_empty());
+
+        let project = project(&files).with_options(AnalyzerOptions {
+            check_calls: true,
+            ..Default::default()
+        });
+        let diagnostics = project.check().unwrap();
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        assert_eq!(diagnostics[0].0, "Foo.jack");
+        assert_eq!(diagnostics[0].1.code, "J112");
+    }
+
+    #[test]
+    fn errors_name_their_source() {
+        let project = project(&[
+            ("lib/Good.jack", "class Good { }"),
+            (
+                "lib/Bad.jack",
+                "class Bad { function void f() { let = 1; return; } }",
+            ),
+        ]);
+
+        let diagnostics = project.check().unwrap();
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        assert_eq!(diagnostics[0].0, "lib/Bad.jack");
+
+        let err = project.emit_vm().unwrap_err();
+        assert!(
+            format!("{:#}", err).starts_with("lib/Bad.jack: "),
+            "{:#}",
+            err
+        );
+        let err = project.emit_xml().unwrap_err();
+        assert!(
+            format!("{:#}", err).starts_with("lib/Bad.jack: "),
+            "{:#}",
+            err
+        );
+    }
+
+    // the first line of each is skipped, the undeclared `x` on line 3 is
+    // still reported there
+    const SHEBANG: &str =
+        "#!/usr/bin/env jack\nclass Main {\n    function void main() { let x = 1; return; }\n}\n";
+    const PROLOG: &str = "<?xml version=\"1.0\"?>\nclass Main {\n    function void main() { let x = 1; return; }\n}\n";
+    const DOC: &str =
+        "/** Main.jack */\nclass Main {\n    function void main() { let x = 1; return; }\n}\n";
+
+    fn lines(project: &Project) -> Vec<(&'static str, usize)> {
+        let diagnostics = project.check().unwrap();
+        diagnostics
+            .into_iter()
+            .map(|(_, d)| (d.code, d.span.line))
+            .collect()
+    }
+
+    #[test]
+    fn marker_first_lines_are_skipped_with_a_warning() {
+        for source in [SHEBANG, PROLOG] {
+            let project = project(&[("Main.jack", source)]);
+            assert_eq!(lines(&project), [("J117", 1), ("J103", 3)], "{}", source);
+            let tree = project.tree("Main.jack").unwrap();
+            assert!(tree.tree.is_some());
+            assert_eq!(tree.diagnostics.len(), 1, "{:?}", tree.diagnostics);
+            assert_eq!(project.source("Main.jack"), Some(source));
+
+            let strict = project.with_options(AnalyzerOptions {
+                skip_marker_line: false,
+                ..Default::default()
+            });
+            // an unknown character or an unexpected `<`, both at 1:1
+            let failed = strict.check().map_or(true, |diagnostics| {
+                diagnostics
+                    .iter()
+                    .any(|(_, d)| d.severity == Severity::Error && d.span.line == 1)
+            });
+            assert!(failed, "{}", source);
+        }
+
+        let project = project(&[("Main.jack", DOC)]);
+        assert_eq!(lines(&project), [("J103", 3)]);
+        assert!(project.tree("Main.jack").unwrap().diagnostics.is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::{self, Write};
+
+use crate::{
+    listener::{raw_text, token_xml},
+    ParseEvent, ParseListener,
+};
+
+// the parse tree as one `id\tparent\tkind\tvalue` row per node. ids count
+// from 1 in pre-order and the root's parent is 0. a non-terminal has an empty
+// value, a token its lexeme with tabs, line ends and backslashes escaped
+pub struct RecordsListener<W: Write> {
+    out: W,
+    ids: usize,
+    // the ids of the non-terminals not closed yet
+    open: Vec<usize>,
+}
+
+impl<W: Write> RecordsListener<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            ids: 0,
+            open: Vec::new(),
+        }
+    }
+
+    fn row(&mut self, kind: &str, value: &str) -> io::Result<()> {
+        self.ids += 1;
+        let parent = self.open.last().copied().unwrap_or(0);
+        writeln!(
+            self.out,
+            "{}\t{}\t{}\t{}",
+            self.ids,
+            parent,
+            kind,
+            escape(value)
+        )
+    }
+}
+
+impl<W: Write> ParseListener for RecordsListener<W> {
+    fn event(&mut self, event: &ParseEvent) -> io::Result<()> {
+        match event {
+            ParseEvent::Open(tag) => {
+                self.row(tag, "")?;
+                self.open.push(self.ids);
+                Ok(())
+            }
+            ParseEvent::Token(token) => match token_xml(token, false) {
+                Some((kind, _)) => self.row(kind, &raw_text(token)),
+                None => Ok(()),
+            },
+            ParseEvent::Close(_) => {
+                self.open.pop();
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CompilationEngine, StreamTokenizer};
+
+    use super::RecordsListener;
+
+    fn records(src: &str) -> Vec<(usize, usize, String, String)> {
+        let mut tokenizer = StreamTokenizer::from_source(src);
+        let mut out = Vec::new();
+        let mut listener = RecordsListener::new(&mut out);
+        CompilationEngine::new(&mut Vec::new(), &mut tokenizer)
+            .with_listener(&mut listener)
+            .compile()
+            .unwrap();
+
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                assert_eq!(fields.len(), 4, "{:?}", line);
+                (
+                    fields[0].parse().unwrap(),
+                    fields[1].parse().unwrap(),
+                    fields[2].to_owned(),
+                    fields[3].to_owned(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parents_reflect_nesting() {
+        let got = records("class A { function void f() { return 1 < 2; } }");
+        let rows: Vec<String> = got
+            .iter()
+            .map(|(id, parent, kind, value)| format!("{} {} {} {}", id, parent, kind, value))
+            .collect();
+
+        assert_eq!(
+            rows,
+            [
+                "1 0 class ",
+                "2 1 keyword class",
+                "3 1 identifier A",
+                "4 1 symbol {",
+                "5 1 subroutineDec ",
+                "6 5 keyword function",
+                "7 5 keyword void",
+                "8 5 identifier f",
+                "9 5 symbol (",
+                "10 5 parameterList ",
+                "11 5 symbol )",
+                "12 5 subroutineBody ",
+                "13 12 symbol {",
+                "14 12 statements ",
+                "15 14 returnStatement ",
+                "16 15 keyword return",
+                "17 15 expression ",
+                "18 17 term ",
+                "19 18 integerConstant 1",
+                "20 17 symbol <",
+                "21 17 term ",
+                "22 21 integerConstant 2",
+                "23 15 symbol ;",
+                "24 12 symbol }",
+                "25 1 symbol }",
+            ]
+        );
+    }
+
+    #[test]
+    fn values_are_escaped() {
+        let got = records("class A { function void f() { do g(\"a\tb\\\"); return; } }");
+        let string = got.iter().find(|row| row.2 == "stringConstant").unwrap();
+
+        assert_eq!(string.3, "a\\tb\\\\");
+        // a parent is always seen before its children
+        assert!(got.iter().all(|(id, parent, _, _)| parent < id));
+    }
+}
@@ -0,0 +1,703 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::{
+    ast::{Class, JackType, Statement, SubroutineCall, SubroutineDec},
+    checker::OS_CLASSES,
+    lexical_elements::Keywords,
+    marker_line::skip_marker_line,
+    resolve::{call_target, class_var_type, declared_names, local_type, uses, var_type, Use},
+    token::Span,
+    Analyzer, AnalyzerOptions, Parser, StreamTokenizer, Token,
+};
+
+// the sources of a whole project, renames look across all of them
+#[derive(Debug, Clone, Default)]
+pub struct SourceSet {
+    files: Vec<(PathBuf, String)>,
+    // parse a `#!` or xml prolog first line as jack rather than blank it
+    keep_marker_lines: bool,
+}
+
+impl SourceSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // every source under `source` as the options find them, or just `source`
+    // when it is a file
+    pub fn read(source: &PathBuf, options: &AnalyzerOptions) -> Result<Self> {
+        let mut set = Self::new();
+        for file in Analyzer::read_source_files(source, options)? {
+            // archived sources can't be edited in place
+            if Analyzer::is_gzipped(&file) {
+                continue;
+            }
+            let text =
+                std::fs::read_to_string(&file).with_context(|| format!("{}", file.display()))?;
+            set.add(file, text);
+        }
+        Ok(set)
+    }
+
+    pub fn keep_marker_lines(mut self, keep: bool) -> Self {
+        self.keep_marker_lines = keep;
+        self
+    }
+
+    pub fn add(&mut self, file: impl Into<PathBuf>, text: impl Into<String>) {
+        self.files.push((file.into(), text.into()));
+    }
+
+    pub fn files(&self) -> &[(PathBuf, String)] {
+        &self.files
+    }
+
+    // resolves `Class`, `Class.member` or `Class.subroutine.local` to the
+    // declaration it names
+    pub fn resolve(&self, path: &str) -> Result<SymbolRef> {
+        let classes = self.parse()?;
+        let parts: Vec<&str> = path.split('.').collect();
+        let class = classes
+            .iter()
+            .map(|(_, class)| class)
+            .find(|class| class.name.name == parts[0])
+            .ok_or_else(|| anyhow!("no class named `{}`", parts[0]))?;
+
+        let target = match parts[..] {
+            [_] => SymbolRef::Class(parts[0].to_owned()),
+            [_, member] => {
+                if class.subroutines.iter().any(|s| s.name.name == member) {
+                    SymbolRef::Subroutine {
+                        class: parts[0].to_owned(),
+                        name: member.to_owned(),
+                    }
+                } else if class_var_type(class, member).is_some() {
+                    SymbolRef::Field {
+                        class: parts[0].to_owned(),
+                        name: member.to_owned(),
+                    }
+                } else {
+                    bail!(
+                        "`{}` declares no subroutine or field `{}`",
+                        parts[0],
+                        member
+                    )
+                }
+            }
+            [_, subroutine, local] => {
+                let dec = class
+                    .subroutines
+                    .iter()
+                    .find(|s| s.name.name == subroutine)
+                    .ok_or_else(|| {
+                        anyhow!("`{}` declares no subroutine `{}`", parts[0], subroutine)
+                    })?;
+                if local_type(dec, local).is_none() {
+                    bail!(
+                        "`{}.{}` declares no variable `{}`",
+                        parts[0],
+                        subroutine,
+                        local
+                    );
+                }
+                SymbolRef::Local {
+                    class: parts[0].to_owned(),
+                    subroutine: subroutine.to_owned(),
+                    name: local.to_owned(),
+                }
+            }
+            _ => bail!("`{}` is not a class, member or local name", path),
+        };
+
+        Ok(target)
+    }
+
+    // new contents of every file touched by `edits`
+    pub fn apply(&self, edits: &[TextEdit]) -> Vec<(PathBuf, String)> {
+        let mut changed = Vec::new();
+        for (file, text) in &self.files {
+            let mut file_edits: Vec<&TextEdit> = edits.iter().filter(|e| &e.file == file).collect();
+            if file_edits.is_empty() {
+                continue;
+            }
+
+            // back to front so earlier offsets stay valid
+            file_edits.sort_by_key(|e| e.span.start);
+            let mut text = text.clone();
+            for edit in file_edits.iter().rev() {
+                text.replace_range(edit.span.start..edit.span.end, &edit.replacement);
+            }
+            changed.push((file.clone(), text));
+        }
+        changed
+    }
+
+    pub(crate) fn parse(&self) -> Result<Vec<(&Path, Class)>> {
+        self.files
+            .iter()
+            .map(|(file, text)| {
+                // blanking keeps the offsets, edits still apply to `text`
+                let text = match self.keep_marker_lines {
+                    true => Cow::Borrowed(text.as_str()),
+                    false => skip_marker_line(text).0,
+                };
+                let class = Parser::from_source(&text)
+                    .parse_class()
+                    .with_context(|| format!("{}", file.display()))?;
+                Ok((file.as_path(), class))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolRef {
+    Class(String),
+    Subroutine {
+        class: String,
+        name: String,
+    },
+    // a static or field variable
+    Field {
+        class: String,
+        name: String,
+    },
+    // a parameter or local variable
+    Local {
+        class: String,
+        subroutine: String,
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub file: PathBuf,
+    pub span: Span,
+    pub replacement: String,
+}
+
+// the edits renaming `target` and every occurrence bound to it. fails without
+// any edits when `new_name` is not a usable identifier or would clash with a
+// name already visible where the target is
+pub fn rename(project: &SourceSet, target: &SymbolRef, new_name: &str) -> Result<Vec<TextEdit>> {
+    check_identifier(new_name)?;
+    let classes = project.parse()?;
+
+    let mut renamer = Renamer {
+        new_name,
+        edits: Vec::new(),
+    };
+    match target {
+        SymbolRef::Class(name) => renamer.class(&classes, name)?,
+        SymbolRef::Subroutine { class, name } => renamer.subroutine(&classes, class, name)?,
+        SymbolRef::Field { class, name } => {
+            let (file, class) = find_class(&classes, class)?;
+            renamer.field(file, class, name)?
+        }
+        SymbolRef::Local {
+            class,
+            subroutine,
+            name,
+        } => {
+            let (file, class) = find_class(&classes, class)?;
+            let dec = class
+                .subroutines
+                .iter()
+                .find(|s| &s.name.name == subroutine)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "`{}` declares no subroutine `{}`",
+                        class.name.name,
+                        subroutine
+                    )
+                })?;
+            renamer.local(file, class, dec, name)?
+        }
+    }
+
+    let mut edits = renamer.edits;
+    edits.sort_by(|a, b| (&a.file, a.span.start).cmp(&(&b.file, b.span.start)));
+    edits.dedup();
+    Ok(edits)
+}
+
+// the edits renaming every variable, and every subroutine only called from
+// its own class, to short generated names, `v0`, `v1`, ... and `f0`, `f1`,
+// ... counted per class. a local's number comes after the class variables'.
+// class names, the os and `Main.main` keep their names, as does a subroutine
+// whose name is called on something of a builtin type, which may be it. no
+// generated name is an identifier the sources already use
+pub fn minify_names(project: &SourceSet) -> Result<Vec<TextEdit>> {
+    let classes = project.parse()?;
+    let mut taken: HashSet<String> = OS_CLASSES.iter().map(|name| name.to_string()).collect();
+    for (_, text) in project.files() {
+        taken.extend(
+            StreamTokenizer::from_source(text)
+                .map_while(Result::ok)
+                .filter_map(|token| match token {
+                    Token::Identifier(name) => Some(name),
+                    _ => None,
+                }),
+        );
+    }
+
+    let mut calls = Vec::new();
+    for (_, class) in &classes {
+        for subroutine in &class.subroutines {
+            for found in uses(&subroutine.statements) {
+                if let Use::Call(call) = found {
+                    let target = call_target(class, subroutine, call);
+                    calls.push((class.name.name.as_str(), target, call.name.name.as_str()));
+                }
+            }
+        }
+    }
+    let is_private = |class: &str, name: &str| {
+        calls
+            .iter()
+            .filter(|(_, _, called)| *called == name)
+            .all(|(from, target, _)| target.as_deref() == Some(class) && *from == class)
+    };
+
+    let mut edits = Vec::new();
+    let mut rename_to = |new_name: &str, rename: &dyn Fn(&mut Renamer) -> Result<()>| {
+        let mut renamer = Renamer {
+            new_name,
+            edits: Vec::new(),
+        };
+        rename(&mut renamer)?;
+        edits.append(&mut renamer.edits);
+        anyhow::Ok(())
+    };
+    for (file, class) in &classes {
+        let mut names = NameGenerator::new(&taken);
+        let class_vars: Vec<&str> = class
+            .class_vars
+            .iter()
+            .flat_map(|dec| dec.names.iter())
+            .map(|ident| ident.name.as_str())
+            .collect();
+        for name in &class_vars {
+            let new_name = names.next("v");
+            rename_to(&new_name, &|r| r.field(file, class, name))?;
+        }
+
+        let after_class_vars = names.clone();
+        let mut subroutines = NameGenerator::new(&taken);
+        for subroutine in &class.subroutines {
+            let mut names = after_class_vars.clone();
+            let locals: Vec<&str> = declared_names(subroutine)
+                .map(|ident| ident.name.as_str())
+                .collect();
+            for name in locals {
+                let new_name = names.next("v");
+                rename_to(&new_name, &|r| r.local(file, class, subroutine, name))?;
+            }
+
+            let name = subroutine.name.name.as_str();
+            let is_entry = class.name.name == "Main" && name == "main";
+            if !is_entry && is_private(&class.name.name, name) {
+                let new_name = subroutines.next("f");
+                rename_to(&new_name, &|r| {
+                    r.subroutine(&classes, &class.name.name, name)
+                })?;
+            }
+        }
+    }
+
+    edits.sort_by(|a, b| (&a.file, a.span.start).cmp(&(&b.file, b.span.start)));
+    edits.dedup();
+    Ok(edits)
+}
+
+// `v0`, `v1`, ... leaving out the names taken
+#[derive(Clone)]
+struct NameGenerator<'a> {
+    taken: &'a HashSet<String>,
+    next: usize,
+}
+
+impl<'a> NameGenerator<'a> {
+    fn new(taken: &'a HashSet<String>) -> Self {
+        Self { taken, next: 0 }
+    }
+
+    fn next(&mut self, prefix: &str) -> String {
+        loop {
+            let name = format!("{}{}", prefix, self.next);
+            self.next += 1;
+            if !self.taken.contains(&name) && Keywords::from_str(&name).is_err() {
+                return name;
+            }
+        }
+    }
+}
+
+pub(crate) fn check_identifier(name: &str) -> Result<()> {
+    if Keywords::from_str(name).is_ok() {
+        bail!("`{}` is a keyword", name);
+    }
+
+    let mut chars = name.chars();
+    let valid = match chars.next() {
+        Some(c) => {
+            (c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    };
+    if !valid {
+        bail!("`{}` is not a valid identifier", name);
+    }
+
+    Ok(())
+}
+
+fn find_class<'a>(classes: &'a [(&'a Path, Class)], name: &str) -> Result<(&'a Path, &'a Class)> {
+    classes
+        .iter()
+        .find(|(_, class)| class.name.name == name)
+        .map(|(file, class)| (*file, class))
+        .ok_or_else(|| anyhow!("no class named `{}`", name))
+}
+
+struct Renamer<'a> {
+    new_name: &'a str,
+    edits: Vec<TextEdit>,
+}
+
+impl Renamer<'_> {
+    fn edit(&mut self, file: &Path, span: Span) {
+        self.edits.push(TextEdit {
+            file: file.to_path_buf(),
+            span,
+            replacement: self.new_name.to_owned(),
+        });
+    }
+
+    fn class(&mut self, classes: &[(&Path, Class)], name: &str) -> Result<()> {
+        if classes.iter().any(|(_, c)| c.name.name == self.new_name)
+            || OS_CLASSES.contains(&self.new_name)
+        {
+            bail!("a class named `{}` already exists", self.new_name);
+        }
+        let (file, class) = find_class(classes, name)?;
+        self.edit(file, class.name.span);
+
+        let is_class =
+            |var_type: &JackType| matches!(var_type, JackType::ClassName(n) if n == name);
+        for (file, class) in classes {
+            for dec in &class.class_vars {
+                if is_class(&dec.var_type) {
+                    self.edit(file, dec.type_span);
+                }
+            }
+
+            for subroutine in &class.subroutines {
+                if is_class(&subroutine.return_type) {
+                    self.edit(file, subroutine.return_type_span);
+                }
+                for param in &subroutine.params {
+                    if is_class(&param.var_type) {
+                        self.edit(file, param.type_span);
+                    }
+                }
+                for dec in &subroutine.locals {
+                    if is_class(&dec.var_type) {
+                        self.edit(file, dec.type_span);
+                    }
+                }
+
+                for found in uses(&subroutine.statements) {
+                    if let Use::Call(SubroutineCall {
+                        receiver: Some(receiver),
+                        ..
+                    }) = found
+                    {
+                        // `Name.f()` names the class unless a variable hides it
+                        if receiver.name == name && var_type(class, subroutine, name).is_none() {
+                            self.edit(file, receiver.span);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn subroutine(
+        &mut self,
+        classes: &[(&Path, Class)],
+        class_name: &str,
+        name: &str,
+    ) -> Result<()> {
+        let (file, class) = find_class(classes, class_name)?;
+        if class
+            .subroutines
+            .iter()
+            .any(|s| s.name.name == self.new_name)
+        {
+            bail!(
+                "`{}` already declares a subroutine `{}`",
+                class_name,
+                self.new_name
+            );
+        }
+        let dec = class
+            .subroutines
+            .iter()
+            .find(|s| s.name.name == name)
+            .ok_or_else(|| anyhow!("`{}` declares no subroutine `{}`", class_name, name))?;
+        self.edit(file, dec.name.span);
+
+        for (file, class) in classes {
+            for subroutine in &class.subroutines {
+                for found in uses(&subroutine.statements) {
+                    if let Use::Call(call) = found {
+                        if call.name.name == name
+                            && call_target(class, subroutine, call).as_deref() == Some(class_name)
+                        {
+                            self.edit(file, call.name.span);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn field(&mut self, file: &Path, class: &Class, name: &str) -> Result<()> {
+        if class_var_type(class, self.new_name).is_some() {
+            bail!(
+                "`{}` already declares a variable `{}`",
+                class.name.name,
+                self.new_name
+            );
+        }
+        let declaration = class
+            .class_vars
+            .iter()
+            .flat_map(|dec| dec.names.iter())
+            .find(|n| n.name == name)
+            .ok_or_else(|| anyhow!("`{}` declares no field `{}`", class.name.name, name))?;
+        self.edit(file, declaration.span);
+
+        for subroutine in &class.subroutines {
+            // the field is hidden in subroutines declaring the same name
+            if local_type(subroutine, name).is_some() {
+                continue;
+            }
+
+            let before = self.edits.len();
+            self.var_uses(file, &subroutine.statements, name);
+            if self.edits.len() > before && local_type(subroutine, self.new_name).is_some() {
+                bail!(
+                    "`{}` is already declared in `{}` and would hide the renamed field",
+                    self.new_name,
+                    subroutine.name.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn local(
+        &mut self,
+        file: &Path,
+        class: &Class,
+        subroutine: &SubroutineDec,
+        name: &str,
+    ) -> Result<()> {
+        if local_type(subroutine, self.new_name).is_some() {
+            bail!(
+                "`{}` is already declared in `{}`",
+                self.new_name,
+                subroutine.name.name
+            );
+        }
+        let declaration = declared_names(subroutine)
+            .find(|n| n.name == name)
+            .ok_or_else(|| anyhow!("`{}` declares no variable `{}`", subroutine.name.name, name))?;
+        self.edit(file, declaration.span);
+
+        // a field of the new name used here would now resolve to the local
+        if class_var_type(class, self.new_name).is_some()
+            && uses(&subroutine.statements)
+                .iter()
+                .any(|u| matches!(u, Use::Var(ident) if ident.name == self.new_name))
+        {
+            bail!(
+                "`{}` is a field used in `{}`, the renamed variable would hide it",
+                self.new_name,
+                subroutine.name.name
+            );
+        }
+
+        self.var_uses(file, &subroutine.statements, name);
+        Ok(())
+    }
+
+    fn var_uses(&mut self, file: &Path, statements: &[Statement], name: &str) {
+        for found in uses(statements) {
+            let ident = match found {
+                Use::Var(ident) => ident,
+                Use::Call(SubroutineCall {
+                    receiver: Some(receiver),
+                    ..
+                }) => receiver,
+                Use::Call(_) => continue,
+            };
+            if ident.name == name {
+                self.edit(file, ident.span);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{minify_names, rename, SourceSet, SymbolRef};
+    use crate::{CodeGenerator, Parser};
+
+    fn project(files: &[(&str, &str)]) -> SourceSet {
+        let mut set = SourceSet::new();
+        for (file, text) in files {
+            set.add(*file, *text);
+        }
+        set
+    }
+
+    fn renamed(set: &SourceSet, target: &SymbolRef, new_name: &str) -> Vec<String> {
+        let edits = rename(set, target, new_name).unwrap();
+        set.apply(&edits)
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect()
+    }
+
+    #[test]
+    fn local_shadowing_a_field_is_left_alone() {
+        let set = project(&[(
+            "Main.jack",
+            "class Main { field int x;
+            method void a() { let x = x + 1; return; }
+            method void b() { var int x; let x = 2; return; } }",
+        )]);
+
+        let field = set.resolve("Main.x").unwrap();
+        assert_eq!(
+            renamed(&set, &field, "count"),
+            ["class Main { field int count;
+            method void a() { let count = count + 1; return; }
+            method void b() { var int x; let x = 2; return; } }"]
+        );
+
+        let local = set.resolve("Main.b.x").unwrap();
+        assert_eq!(
+            renamed(&set, &local, "y"),
+            ["class Main { field int x;
+            method void a() { let x = x + 1; return; }
+            method void b() { var int y; let y = 2; return; } }"]
+        );
+    }
+
+    #[test]
+    fn class_rename_touches_every_file() {
+        let set = project(&[
+            (
+                "Point.jack",
+                "class Point { field int x; constructor Point new() { return this; } }",
+            ),
+            (
+                "Main.jack",
+                "class Main { function void main() { var Point p; let p = Point.new(); return; } }",
+            ),
+            (
+                "Line.jack",
+                "class Line { field Point a; method Point start(Point b) { return a; } }",
+            ),
+        ]);
+
+        let got = renamed(&set, &SymbolRef::Class("Point".to_owned()), "Vec");
+        assert_eq!(
+            got,
+            [
+                "class Vec { field int x; constructor Vec new() { return this; } }",
+                "class Main { function void main() { var Vec p; let p = Vec.new(); return; } }",
+                "class Line { field Vec a; method Vec start(Vec b) { return a; } }",
+            ]
+        );
+    }
+
+    #[test]
+    fn conflicting_names_are_rejected() {
+        let set = project(&[(
+            "Main.jack",
+            "class Main { field int x, y; method void a() { var int z; let x = z; return; } }",
+        )]);
+
+        let field = set.resolve("Main.x").unwrap();
+        assert!(rename(&set, &field, "y").is_err());
+        assert!(rename(&set, &field, "z").is_err());
+        assert!(rename(&set, &field, "while").is_err());
+        assert!(rename(&set, &field, "1x").is_err());
+    }
+
+    fn vm(text: &str) -> String {
+        CodeGenerator::generate(&Parser::from_source(text).parse_class().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn minified_names_compile_to_the_same_vm() {
+        let set = project(&[
+            (
+                "Main.jack",
+                "class Main {
+                static Counter counter;
+                function void main() { var int v0, total;
+                    let counter = Counter.new(3);
+                    let total = Main.twice(counter.get()) + v0;
+                    do Output.printInt(total); return; }
+                function int twice(int n) { return n + n; } }",
+            ),
+            (
+                "Counter.jack",
+                "class Counter { field int count;
+                constructor Counter new(int start) { let count = start; return this; }
+                method int get() { do bump(); return count; }
+                method void bump() { let count = count + 1; return; } }",
+            ),
+        ]);
+
+        let edits = minify_names(&set).unwrap();
+        let minified: Vec<String> = set
+            .apply(&edits)
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect();
+        assert!(minified[0].contains("var int v2, v3;"), "{}", minified[0]);
+        assert!(minified[0].contains("Counter.new(3)"), "{}", minified[0]);
+        assert!(minified[0].contains("v1.get()"), "{}", minified[0]);
+
+        // private subroutines keep their place, so their vm differs only in name
+        let renamed = [("Main.f0", "Main.twice"), ("Counter.f0", "Counter.bump")];
+        for ((_, original), minified) in set.files().iter().zip(&minified) {
+            let mut got = vm(minified);
+            for (new, old) in renamed {
+                got = got.replace(&format!("{} ", new), &format!("{} ", old));
+            }
+            assert_eq!(got, vm(original));
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// name resolution helpers shared by the project wide analyses
+
+use crate::ast::{
+    Class, Expression, ExpressionKind, Ident, JackType, Statement, SubroutineCall, SubroutineDec,
+};
+
+pub(crate) fn class_var_type<'a>(class: &'a Class, name: &str) -> Option<&'a JackType> {
+    class
+        .class_vars
+        .iter()
+        .find(|dec| dec.names.iter().any(|n| n.name == name))
+        .map(|dec| &dec.var_type)
+}
+
+pub(crate) fn local_type<'a>(subroutine: &'a SubroutineDec, name: &str) -> Option<&'a JackType> {
+    subroutine
+        .params
+        .iter()
+        .find(|p| p.name.name == name)
+        .map(|p| &p.var_type)
+        .or_else(|| {
+            subroutine
+                .locals
+                .iter()
+                .find(|dec| dec.names.iter().any(|n| n.name == name))
+                .map(|dec| &dec.var_type)
+        })
+}
+
+pub(crate) fn declared_names(subroutine: &SubroutineDec) -> impl Iterator<Item = &Ident> {
+    subroutine
+        .params
+        .iter()
+        .map(|p| &p.name)
+        .chain(subroutine.locals.iter().flat_map(|dec| dec.names.iter()))
+}
+
+// an identifier in a subroutine body, either used as a variable or as part of
+// a call
+pub(crate) enum Use<'a> {
+    Var(&'a Ident),
+    Call(&'a SubroutineCall),
+}
+
+pub(crate) fn uses(statements: &[Statement]) -> Vec<Use<'_>> {
+    let mut out = Vec::new();
+    for statement in statements {
+        statement_uses(statement, &mut out);
+    }
+    out
+}
+
+fn statement_uses<'a>(statement: &'a Statement, out: &mut Vec<Use<'a>>) {
+    match statement {
+        Statement::Let {
+            target,
+            index,
+            value,
+            ..
+        } => {
+            out.push(Use::Var(target));
+            if let Some(index) = index {
+                expression_uses(index, out);
+            }
+            expression_uses(value, out);
+        }
+        Statement::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            expression_uses(condition, out);
+            for statement in then_branch.iter().chain(else_branch.iter().flatten()) {
+                statement_uses(statement, out);
+            }
+        }
+        Statement::While {
+            condition, body, ..
+        } => {
+            expression_uses(condition, out);
+            for statement in body {
+                statement_uses(statement, out);
+            }
+        }
+        Statement::Do { call, .. } => call_uses(call, out),
+        Statement::Return { value, .. } => {
+            if let Some(value) = value {
+                expression_uses(value, out);
+            }
+        }
+    }
+}
+
+fn expression_uses<'a>(expression: &'a Expression, out: &mut Vec<Use<'a>>) {
+    match &expression.kind {
+        ExpressionKind::Var(ident) => out.push(Use::Var(ident)),
+        ExpressionKind::Index(ident, index) => {
+            out.push(Use::Var(ident));
+            expression_uses(index, out);
+        }
+        ExpressionKind::Call(call) => call_uses(call, out),
+        ExpressionKind::Paren(inner) | ExpressionKind::Unary(_, inner) => {
+            expression_uses(inner, out)
+        }
+        ExpressionKind::Binary(_, lhs, rhs) => {
+            expression_uses(lhs, out);
+            expression_uses(rhs, out);
+        }
+        ExpressionKind::IntConst(_)
+        | ExpressionKind::StringConst(_)
+        | ExpressionKind::KeywordConst(_) => {}
+    }
+}
+
+fn call_uses<'a>(call: &'a SubroutineCall, out: &mut Vec<Use<'a>>) {
+    out.push(Use::Call(call));
+    for arg in &call.args {
+        expression_uses(arg, out);
+    }
+}
+
+// the variable `name` refers to inside `subroutine`, locals shadow class vars
+pub(crate) fn var_type<'a>(
+    class: &'a Class,
+    subroutine: &'a SubroutineDec,
+    name: &str,
+) -> Option<&'a JackType> {
+    local_type(subroutine, name).or_else(|| class_var_type(class, name))
+}
+
+// the class a call goes to, `None` when the receiver is a variable of a
+// builtin type
+pub(crate) fn call_target(
+    class: &Class,
+    subroutine: &SubroutineDec,
+    call: &SubroutineCall,
+) -> Option<String> {
+    match &call.receiver {
+        None => Some(class.name.name.clone()),
+        Some(receiver) => match var_type(class, subroutine, &receiver.name) {
+            Some(JackType::ClassName(name)) => Some(name.clone()),
+            Some(_) => None,
+            None => Some(receiver.name.clone()),
+        },
+    }
+}
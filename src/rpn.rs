@@ -0,0 +1,87 @@
+use anyhow::Result;
+
+use crate::{
+    ast::{Expression, ExpressionKind, SubroutineCall},
+    format::operator,
+    lexical_elements::Symbols,
+    Parser,
+};
+
+// an expression in postfix order, the order the vm evaluates it in. like the
+// vm code, operators apply left to right since jack has no precedence, so
+// `a + b * c` is `a b + c *`. unary minus is written `neg`, a call as its
+// arguments followed by `name/arity` and an array access as `a i []`
+pub fn expr_to_rpn(src: &str) -> Result<String> {
+    let mut parser = Parser::from_source(src);
+    let expression = parser.parse_expression()?;
+    parser.expect_end()?;
+
+    let mut out = Vec::new();
+    postfix(&expression, &mut out);
+    Ok(out.join(" "))
+}
+
+fn postfix(expression: &Expression, out: &mut Vec<String>) {
+    match &expression.kind {
+        ExpressionKind::IntConst(i) => out.push(i.to_string()),
+        ExpressionKind::StringConst(s) => out.push(format!("\"{}\"", s)),
+        ExpressionKind::KeywordConst(keyword) => out.push(keyword.to_string()),
+        ExpressionKind::Var(ident) => out.push(ident.name.clone()),
+        ExpressionKind::Index(ident, index) => {
+            out.push(ident.name.clone());
+            postfix(index, out);
+            out.push("[]".to_owned());
+        }
+        ExpressionKind::Call(call) => postfix_call(call, out),
+        ExpressionKind::Paren(inner) => postfix(inner, out),
+        ExpressionKind::Unary(op, operand) => {
+            postfix(operand, out);
+            out.push(match op {
+                Symbols::Minus => "neg".to_owned(),
+                op => operator(op).to_owned(),
+            });
+        }
+        ExpressionKind::Binary(op, lhs, rhs) => {
+            postfix(lhs, out);
+            postfix(rhs, out);
+            out.push(operator(op).to_owned());
+        }
+    }
+}
+
+fn postfix_call(call: &SubroutineCall, out: &mut Vec<String>) {
+    for arg in &call.args {
+        postfix(arg, out);
+    }
+
+    let name = match &call.receiver {
+        Some(receiver) => format!("{}.{}", receiver.name, call.name.name),
+        None => call.name.name.clone(),
+    };
+    out.push(format!("{}/{}", name, call.args.len()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expr_to_rpn;
+
+    #[test]
+    fn operators_apply_left_to_right() {
+        assert_eq!(expr_to_rpn("a + b * c").unwrap(), "a b + c *");
+        assert_eq!(expr_to_rpn("a + (b * c)").unwrap(), "a b c * +");
+    }
+
+    #[test]
+    fn unary_calls_and_arrays() {
+        assert_eq!(expr_to_rpn("-a + b").unwrap(), "a neg b +");
+        assert_eq!(
+            expr_to_rpn("Math.max(x[1], ~y) < 2").unwrap(),
+            "x 1 [] y ~ Math.max/2 2 <"
+        );
+    }
+
+    #[test]
+    fn trailing_tokens_are_an_error() {
+        assert!(expr_to_rpn("a + b c").is_err());
+    }
+}
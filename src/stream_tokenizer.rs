@@ -1,46 +1,388 @@
-use std::{fs::File, io::Read, path::PathBuf, str::FromStr};
+use std::{iter::FusedIterator, str::FromStr};
 
 use anyhow::{anyhow, bail, Context, Result};
 
 use crate::{
-    lexical_elements::{Keywords, Symbols},
-    Token,
+    comments::{CommentKind, Layout},
+    engine::INTERNAL_ERROR,
+    lexical_elements::{KeywordSet, Symbols},
+    parser::UNEXPECTED_EOF,
+    token::{Span, SpannedToken, TokenKind},
+    Diagnostic, TabPolicy, Token,
 };
 
+// what a tokenizer read, counted when it was asked to collect them. a token
+// that failed to read or an unknown character isn't counted as any kind
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenizerStats {
+    pub keywords: usize,
+    pub symbols: usize,
+    pub identifiers: usize,
+    pub int_consts: usize,
+    pub string_consts: usize,
+    pub line_comments: usize,
+    pub block_comments: usize,
+    // block comments opened with `/**`
+    pub doc_comments: usize,
+    // lines with only whitespace on them
+    pub blank_lines: usize,
+    // the lines read, a last one without a line end included
+    pub lines: usize,
+}
+
+impl TokenizerStats {
+    pub fn count(&self, kind: TokenKind) -> usize {
+        match kind {
+            TokenKind::Keyword => self.keywords,
+            TokenKind::Symbol => self.symbols,
+            TokenKind::Identifier => self.identifiers,
+            TokenKind::IntConst => self.int_consts,
+            TokenKind::StringConst => self.string_consts,
+            TokenKind::Error | TokenKind::Eof => 0,
+        }
+    }
+
+    pub fn tokens(&self) -> usize {
+        self.keywords + self.symbols + self.identifiers + self.int_consts + self.string_consts
+    }
+
+    fn add(&mut self, kind: TokenKind) {
+        match kind {
+            TokenKind::Keyword => self.keywords += 1,
+            TokenKind::Symbol => self.symbols += 1,
+            TokenKind::Identifier => self.identifiers += 1,
+            TokenKind::IntConst => self.int_consts += 1,
+            TokenKind::StringConst => self.string_consts += 1,
+            TokenKind::Error | TokenKind::Eof => {}
+        }
+    }
+}
+
 pub struct StreamTokenizer {
     remaining_text: String,
     current_index: usize,
+    line: usize,
+    column: usize,
     iter_times: usize,
+    recover_from_lex_errors: bool,
+    // without any comment in the source the gaps between tokens are only
+    // whitespace and `skip` doesn't need to look for them
+    has_comments: bool,
+    // where a tab advances the column to, to match the editor the source is
+    // shown in
+    tabs: TabPolicy,
+    after_minus: bool,
+    keywords: KeywordSet,
+    // end with a `Token::Eof` before the iterator runs out
+    emit_eof: bool,
+    eof_emitted: bool,
+    // nothing is read after an error
+    failed: bool,
+    // count the blank lines before every token
+    track_layout: bool,
+    // the blank lines the last skip went over
+    blank_lines: usize,
+    // the tokens read so far, not counting `Token::Eof`
+    produced: usize,
+    // only kept when asked for, counting is otherwise skipped
+    stats: Option<TokenizerStats>,
+    // tests make the tokenizer stop early after this many tokens, to see
+    // that truncated input is an error
+    #[cfg(test)]
+    stop_after: Option<usize>,
+    // the gaps between tokens looked through for comments
+    #[cfg(test)]
+    comment_scans: usize,
 }
 
 impl StreamTokenizer {
-    pub fn new(source: &PathBuf) -> Self {
-        let mut text = String::new();
-        let _ = File::open(source).unwrap().read_to_string(&mut text);
+    // reads the file at `source`
+    pub fn new(source: &std::path::PathBuf) -> std::io::Result<Self> {
+        Ok(Self::from_source(&std::fs::read_to_string(source)?))
+    }
+
+    pub fn from_source(source: &str) -> Self {
+        // editors on windows like to start files with a byte order mark, it is
+        // skipped but still counted so offsets keep pointing into `source`
+        let bom = if source.starts_with('\u{FEFF}') {
+            '\u{FEFF}'.len_utf8()
+        } else {
+            0
+        };
 
         Self {
-            remaining_text: text,
-            current_index: 0,
+            remaining_text: source[bom..].to_owned(),
+            current_index: bom,
+            line: 1,
+            column: 1,
             iter_times: 0,
+            recover_from_lex_errors: false,
+            has_comments: source.contains("//") || source.contains("/*"),
+            tabs: TabPolicy::One,
+            after_minus: false,
+            keywords: KeywordSet::base(),
+            emit_eof: false,
+            eof_emitted: false,
+            failed: false,
+            track_layout: false,
+            blank_lines: 0,
+            produced: 0,
+            stats: None,
+            #[cfg(test)]
+            stop_after: None,
+            #[cfg(test)]
+            comment_scans: 0,
+        }
+    }
+
+    // for error recovery, unknown characters become `Token::Error` instead of
+    // stopping the tokenizer so the tokens after them are still seen
+    pub fn recover_from_lex_errors(mut self, recover: bool) -> Self {
+        self.recover_from_lex_errors = recover;
+        self
+    }
+
+    pub fn tabs(mut self, tabs: TabPolicy) -> Self {
+        self.tabs = tabs;
+        self
+    }
+
+    pub fn keywords(mut self, keywords: KeywordSet) -> Self {
+        self.keywords = keywords;
+        self
+    }
+
+    pub fn emit_eof(mut self, emit: bool) -> Self {
+        self.emit_eof = emit;
+        self
+    }
+
+    // fill in `blank_lines_before` of the spanned tokens, for a formatter
+    // keeping the blank lines of the source
+    pub fn track_layout(mut self, track: bool) -> Self {
+        self.track_layout = track;
+        self
+    }
+
+    // count the tokens of each kind, the comments, and the blank and other
+    // lines read, for stats
+    pub fn collect_stats(mut self, collect: bool) -> Self {
+        self.stats = collect.then(TokenizerStats::default);
+        self
+    }
+
+    // the counts of what was read so far, when collected
+    pub fn stats(&self) -> Option<TokenizerStats> {
+        let mut stats = self.stats.clone()?;
+        stats.lines = self.line - (self.column == 1) as usize;
+        Some(stats)
+    }
+
+    pub fn into_spanned(self) -> SpannedTokens {
+        SpannedTokens(self)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn stop_after(mut self, tokens: usize) -> Self {
+        self.stop_after = Some(tokens);
+        self
+    }
+
+    pub fn tokens_produced(&self) -> usize {
+        self.produced
+    }
+
+    // the byte offset reading got to, the length of the source once it was
+    // read to the end
+    pub fn bytes_scanned(&self) -> usize {
+        self.current_index
+    }
+
+    // what is left of the source after the bytes scanned
+    pub fn unscanned(&self) -> &str {
+        &self.remaining_text
+    }
+
+    // the internal error of a tokenizer that stopped with more than
+    // whitespace left, which would otherwise pass for the end of the source
+    pub(crate) fn truncation(&self) -> Option<Diagnostic> {
+        let unscanned = self.remaining_text.trim_start();
+        if unscanned.is_empty() {
+            return None;
         }
+        let left: String = unscanned.chars().take(20).collect();
+        let message = format!(
+            "internal error: the tokenizer stopped after {} tokens at byte {} of {}, before `{}`",
+            self.produced,
+            self.current_index,
+            self.current_index + self.remaining_text.len(),
+            left.split_whitespace().collect::<Vec<_>>().join(" ")
+        );
+        Some(Diagnostic::error(INTERNAL_ERROR, message, Span::default()))
+    }
+
+    // only tests stop a tokenizer before the end of its source
+    #[cfg(test)]
+    fn stopped_early(&self) -> bool {
+        self.stop_after.is_some_and(|n| self.produced >= n)
+    }
+
+    #[cfg(not(test))]
+    fn stopped_early(&self) -> bool {
+        false
     }
 
     fn next_token(&mut self) -> Result<Token> {
-        let (tok, bytes_read) = Self::tokenize_single_token(&self.remaining_text)?;
+        let (start, line, column) = (self.current_index, self.line, self.column);
+        let token = Self::tokenize_following(
+            &self.remaining_text,
+            self.recover_from_lex_errors,
+            self.after_minus,
+            &self.keywords,
+        );
+        let (tok, bytes_read) = match token {
+            Ok(token) => token,
+            Err(_) if Self::runs_to_end(&self.remaining_text) => {
+                let end = start + self.remaining_text.len();
+                return Err(Self::unexpected_eof(
+                    "string constant",
+                    Span {
+                        start,
+                        end,
+                        line,
+                        column,
+                    },
+                ));
+            }
+            Err(e) => return Err(e.context(format!("at {}:{}", line, column))),
+        };
         self.chomp(bytes_read);
+        self.after_minus = matches!(tok, Token::Symbol(Symbols::Minus));
+        if let Some(stats) = &mut self.stats {
+            stats.add(tok.kind());
+        }
 
         Ok(tok)
     }
 
+    // `None` is a clean end of input. input ending inside a string constant
+    // or a block comment is an UNEXPECTED_EOF diagnostic, and like any other
+    // error it is the last item
+    fn next_spanned(&mut self) -> Option<Result<SpannedToken>> {
+        if self.failed {
+            return None;
+        }
+        let token = self.read_spanned();
+        if matches!(token, Some(Err(_))) {
+            self.failed = true;
+        }
+        token
+    }
+
+    fn read_spanned(&mut self) -> Option<Result<SpannedToken>> {
+        if let Err(e) = self.skip_whitespace() {
+            return Some(Err(e));
+        }
+
+        self.iter_times += 1;
+        if self.stopped_early() {
+            if let Some(error) = self.truncation() {
+                return Some(Err(error.into()));
+            }
+        }
+        if self.remaining_text.is_empty() {
+            if !self.emit_eof || self.eof_emitted {
+                return None;
+            }
+            self.eof_emitted = true;
+            let (start, line, column) = (self.current_index, self.line, self.column);
+            return Some(Ok(SpannedToken {
+                token: Token::Eof,
+                span: Span {
+                    start,
+                    end: start,
+                    line,
+                    column,
+                },
+                blank_lines_before: self.blank_lines,
+            }));
+        }
+
+        self.produced += 1;
+        let (start, line, column) = (self.current_index, self.line, self.column);
+        let token = self.next_token().map(|token| SpannedToken {
+            token,
+            span: Span {
+                start,
+                end: self.current_index,
+                line,
+                column,
+            },
+            blank_lines_before: self.blank_lines,
+        });
+
+        Some(token)
+    }
+
+    // whether the token at the start of `data` failed only because the input
+    // ended, a string constant without its closing quote or a line end
+    pub(crate) fn runs_to_end(data: &str) -> bool {
+        data.strip_prefix('"')
+            .is_some_and(|body| !body.contains(['"', '\n']))
+    }
+
+    pub(crate) fn unexpected_eof(what: &str, span: Span) -> anyhow::Error {
+        let message = format!("{} is never closed, reached end of input", what);
+        Diagnostic::error(UNEXPECTED_EOF, message, span).into()
+    }
+
     fn chomp(&mut self, num_bytes: usize) {
+        for ch in self.remaining_text[..num_bytes].chars() {
+            match ch {
+                '\n' => {
+                    self.line += 1;
+                    self.column = 1;
+                }
+                '\t' => self.column = self.tabs.advance(self.column - 1, '\t') + 1,
+                _ => self.column += 1,
+            }
+        }
+
         self.remaining_text = self.remaining_text[num_bytes..].to_owned();
         self.current_index += num_bytes;
     }
 
-    fn tokenize_single_token(data: &str) -> Result<(Token, usize)> {
+    // 32768 is out of range on its own, but after a `-` it makes -32768, the
+    // smallest int. it is kept as i16::MIN, which is 32768 read unsigned
+    pub(crate) fn tokenize_following(
+        data: &str,
+        recover: bool,
+        after_minus: bool,
+        keywords: &KeywordSet,
+    ) -> Result<(Token, usize)> {
+        if let Some(rest) = data.strip_prefix("32768") {
+            if after_minus && !rest.starts_with(|c: char| c.is_ascii_digit()) {
+                return Ok((Token::IntConst(i16::MIN), 5));
+            }
+        }
+        Self::tokenize_single_token(data, recover, keywords)
+    }
+
+    fn tokenize_single_token(
+        data: &str,
+        recover: bool,
+        keywords: &KeywordSet,
+    ) -> Result<(Token, usize)> {
         let next = match data.chars().next() {
             Some(c) => c,
-            None => bail!("EOF"),
+            None => {
+                return Err(Diagnostic::error(
+                    UNEXPECTED_EOF,
+                    "no token left, reached end of input",
+                    Span::default(),
+                )
+                .into())
+            }
         };
 
         let (tok, length) = match next {
@@ -52,33 +394,119 @@ impl StreamTokenizer {
             '"' => {
                 Self::tokenize_string_literal(data).context("couldnt tokenize string literal")?
             }
-            c @ '_' | c if c.is_alphabetic() => Self::tokenize_ident_or_keyword(data)
-                .context("couldnt tokenize an identifier/keyword")?,
+            c if c == '_' || c.is_alphabetic() => {
+                Self::tokenize_ident_or_keyword(data, keywords)
+                    .context("couldnt tokenize an identifier/keyword")?
+            }
+            c if recover => (Token::Error(c), c.len_utf8()),
             _ => bail!("unknown character"),
         };
 
         Ok((tok, length))
     }
 
-    fn skip_whitespace(&mut self) {
-        let skipped = Self::skip(&self.remaining_text);
-        self.chomp(skipped);
+    fn skip_whitespace(&mut self) -> Result<()> {
+        if !self.has_comments {
+            let skipped = Self::leading_whitespace(&self.remaining_text);
+            self.count_blank_lines(skipped);
+            self.count_trivia(skipped);
+            self.chomp(skipped);
+            return Ok(());
+        }
+
+        #[cfg(test)]
+        {
+            self.comment_scans += 1;
+        }
+        let (skipped, unclosed) = Self::skip_trivia(&self.remaining_text);
+        let Some(comment) = unclosed else {
+            self.count_blank_lines(skipped);
+            self.count_trivia(skipped);
+            self.chomp(skipped);
+            return Ok(());
+        };
+        self.chomp(comment);
+        let span = Span {
+            start: self.current_index,
+            end: self.current_index + self.remaining_text.len(),
+            line: self.line,
+            column: self.column,
+        };
+        self.chomp(self.remaining_text.len());
+        Err(Self::unexpected_eof("block comment", span))
+    }
+
+    fn count_blank_lines(&mut self, skipped: usize) {
+        if self.track_layout {
+            let at_start = self.iter_times == 0;
+            self.blank_lines = Self::blank_lines(&self.remaining_text[..skipped], at_start);
+        }
     }
 
-    fn skip_comments(src: &str) -> usize {
-        let pairs = [("//", "\n"), ("/*", "*/")];
+    // the comments and blank lines of the next `skipped` bytes, for the stats
+    fn count_trivia(&mut self, skipped: usize) {
+        let Some(stats) = &mut self.stats else {
+            return;
+        };
+        let trivia = &self.remaining_text[..skipped];
+        stats.blank_lines += Self::blank_lines(trivia, self.iter_times == 0);
+
+        let mut comments = Vec::new();
+        Layout::scan(trivia, 0..trivia.len(), &mut comments);
+        for comment in comments {
+            match comment.kind {
+                CommentKind::Line => stats.line_comments += 1,
+                CommentKind::Block => stats.block_comments += 1,
+                CommentKind::Doc => stats.doc_comments += 1,
+            }
+        }
+    }
 
-        for &(pattern, matcher) in &pairs {
-            if src.starts_with(pattern) {
-                let leftovers = Self::skip_until(src, matcher);
-                return src.len() - leftovers.len();
+    // the lines of `trivia` with only whitespace on them. its first line is
+    // the one of the token before it unless `at_start`, a line with a comment
+    // on it isn't blank
+    fn blank_lines(trivia: &str, at_start: bool) -> usize {
+        let mut blank = 0;
+        let mut line_empty = at_start;
+        let mut rest = trivia;
+        loop {
+            let ws = Self::leading_whitespace(rest);
+            for _ in rest[..ws].matches('\n') {
+                blank += line_empty as usize;
+                line_empty = true;
             }
+            rest = &rest[ws..];
+
+            let (comment, _) = Self::skip_comments(rest);
+            if comment == 0 {
+                return blank;
+            }
+            // a line comment ends with the line it is on
+            line_empty = rest[..comment].ends_with('\n');
+            rest = &rest[comment..];
         }
+    }
 
-        0
+    // the length of the comment at the start of `src`, and whether it is a
+    // block comment the input ends in
+    pub(crate) fn skip_comments(src: &str) -> (usize, bool) {
+        if src.starts_with("//") {
+            // a line comment may end the input
+            let rest = Self::skip_until(src, "\n").unwrap_or("");
+            return (src.len() - rest.len(), false);
+        }
+        if let Some(body) = src.strip_prefix("/*") {
+            return match Self::skip_until(body, "*/") {
+                Some(rest) => (src.len() - rest.len(), false),
+                None => (src.len(), true),
+            };
+        }
+
+        (0, false)
     }
 
-    fn skip_until<'a>(mut src: &'a str, pattern: &str) -> &'a str {
+    // what follows the first `pattern` in `src`, if there is one
+    fn skip_until<'a>(mut src: &'a str, pattern: &str) -> Option<&'a str> {
         while !src.is_empty() && !src.starts_with(pattern) {
             let next_char_size = src
                 .chars()
@@ -88,68 +516,68 @@ impl StreamTokenizer {
             src = &src[next_char_size..];
         }
 
-        &src[pattern.len()..]
+        src.get(pattern.len()..)
     }
 
-    fn skip(src: &str) -> usize {
+    // the whitespace and comments at the start of `src`, in bytes, and where
+    // a block comment that is never closed starts
+    pub(crate) fn skip_trivia(src: &str) -> (usize, Option<usize>) {
         let mut remaining = src;
 
         loop {
-            let ws = Self::_skip_whitespace(remaining);
+            let ws = Self::leading_whitespace(remaining);
             remaining = &remaining[ws..];
-            let comments = Self::skip_comments(remaining);
+            let start = src.len() - remaining.len();
+            let (comments, unclosed) = Self::skip_comments(remaining);
             remaining = &remaining[comments..];
 
+            if unclosed {
+                return (src.len(), Some(start));
+            }
             if ws + comments == 0 {
-                return src.len() - remaining.len();
+                return (src.len() - remaining.len(), None);
             }
         }
     }
 
-    fn tokenize_ident_or_keyword(data: &str) -> Result<(Token, usize)> {
+    fn tokenize_ident_or_keyword(data: &str, keywords: &KeywordSet) -> Result<(Token, usize)> {
         match data.chars().next() {
-            Some(ch) if ch.is_digit(10) => bail!("Identifiers can't start with a number"),
+            Some(ch) if ch.is_ascii_digit() => bail!("Identifiers can't start with a number"),
             None => bail!("EOF"),
             _ => {}
         }
 
         let (got, bytes_read) = Self::take_while(data, |ch| ch == '_' || ch.is_alphanumeric())?;
 
-        let token = match got {
-            s if Keywords::from_str(s).is_ok() => Token::Keyword(Keywords::from_str(s)?),
-            _ => Token::Identifier(got.to_string()),
+        let token = match keywords.lookup(got) {
+            Some(keyword) => Token::Keyword(keyword),
+            None => Token::Identifier(got.to_string()),
         };
 
         Ok((token, bytes_read))
     }
 
     fn tokenize_digit(data: &str) -> Result<(Token, usize)> {
-        let (digit, bytes_read) =
-            Self::take_while(data, |c| if c.is_digit(10) { true } else { false })?;
+        let (digit, bytes_read) = Self::take_while(data, |c| c.is_ascii_digit())?;
 
         let n: i16 = digit.parse()?;
         Ok((Token::IntConst(n), bytes_read))
     }
 
     fn tokenize_string_literal(data: &str) -> Result<(Token, usize)> {
-        if data.chars().next() != Some('"') {
+        if !data.starts_with('"') {
             return Err(anyhow!("Invalid string literal"));
         }
 
-        let mut quotes = 0;
-        let (got, bytes_read) = Self::take_while(data, |ch| {
-            if quotes >= 2 {
-                return false;
-            }
-
-            if ch == '"' {
-                quotes += 1;
-            }
-
-            true
-        })?;
+        // string constants can't contain a quote or run over a line
+        let body = &data[1..];
+        let end = match body.find(['"', '\n']) {
+            Some(end) if body[end..].starts_with('"') => end,
+            _ => bail!("unterminated string constant"),
+        };
 
-        let token = Token::StringConst(got.replace(r#"""#, ""));
+        let token = Token::StringConst(body[..end].to_owned());
+        let bytes_read = end + 2;
 
         Ok((token, bytes_read))
     }
@@ -177,7 +605,7 @@ impl StreamTokenizer {
         }
     }
 
-    fn _skip_whitespace(data: &str) -> usize {
+    pub(crate) fn leading_whitespace(data: &str) -> usize {
         match Self::take_while(data, |ch| ch.is_whitespace()) {
             Ok((_, bytes_skipped)) => bytes_skipped,
             _ => 0,
@@ -189,15 +617,29 @@ impl Iterator for StreamTokenizer {
     type Item = Result<Token>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.skip_whitespace();
+        self.next_spanned()
+            .map(|token| token.map(|spanned| spanned.token))
+    }
+}
 
-        self.iter_times += 1;
-        if self.remaining_text.is_empty() || self.iter_times >= 1000 {
-            None
-        } else {
-            let token = self.next_token();
-            Some(token)
-        }
+impl FusedIterator for StreamTokenizer {}
+
+// yields every token together with the span of source it was read from
+pub struct SpannedTokens(StreamTokenizer);
+
+impl FusedIterator for SpannedTokens {}
+
+impl SpannedTokens {
+    pub fn tokenizer(&self) -> &StreamTokenizer {
+        &self.0
+    }
+}
+
+impl Iterator for SpannedTokens {
+    type Item = Result<SpannedToken>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_spanned()
     }
 }
 
@@ -205,14 +647,72 @@ impl Iterator for StreamTokenizer {
 mod tests {
     use std::str::FromStr;
 
-    use crate::{lexical_elements::Keywords, StreamTokenizer, Token};
+    use crate::{
+        engine::INTERNAL_ERROR,
+        lexical_elements::{KeywordSet, Keywords, Symbols},
+        parser::UNEXPECTED_EOF,
+        token::TokenKind,
+        Diagnostic, StreamTokenizer, TabPolicy, Token,
+    };
+
+    use super::TokenizerStats;
+
+    #[test]
+    fn a_missing_file_is_an_error() {
+        let missing = std::path::PathBuf::from("no/such/Main.jack");
+        let err = StreamTokenizer::new(&missing).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn stats_count_what_was_read() {
+        let src = "/** Main */\nclass Main {\n\n  // entry\n  function void main() {\n    \
+                   /* a */ do Output.printString(\"hi\");\n    \n    return 1 + 2;\n  }\n}";
+        let mut tokenizer = StreamTokenizer::from_source(src).collect_stats(true);
+        assert!(tokenizer.by_ref().all(|t| t.is_ok()));
+
+        let stats = tokenizer.stats().unwrap();
+        assert_eq!(
+            stats,
+            TokenizerStats {
+                keywords: 5,
+                symbols: 12,
+                identifiers: 4,
+                int_consts: 2,
+                string_consts: 1,
+                line_comments: 1,
+                block_comments: 1,
+                doc_comments: 1,
+                blank_lines: 2,
+                lines: 10,
+            }
+        );
+        assert_eq!(stats.count(TokenKind::Symbol), 12);
+        assert_eq!(stats.tokens(), 24);
+        assert_eq!(StreamTokenizer::from_source(src).stats(), None);
+    }
+
+    #[test]
+    fn stats_leave_out_characters_that_failed() {
+        let mut tokenizer = StreamTokenizer::from_source("let x = 1 # 2;\n$")
+            .recover_from_lex_errors(true)
+            .collect_stats(true);
+        assert_eq!(tokenizer.by_ref().count(), 8);
+        let stats = tokenizer.stats().unwrap();
+        assert_eq!((stats.tokens(), stats.lines), (6, 2));
+
+        let mut tokenizer = StreamTokenizer::from_source("let x = \"1;").collect_stats(true);
+        assert!(tokenizer.by_ref().last().unwrap().is_err());
+        assert_eq!(tokenizer.stats().unwrap().tokens(), 3);
+    }
 
     #[test]
     fn tokenize_a_single_letter() {
         let src = "F";
         let should_be = Token::Identifier(src.to_string());
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_ident_or_keyword(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_ident_or_keyword(src, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -221,7 +721,8 @@ mod tests {
         let src = "Foo";
         let should_be = Token::Identifier(src.to_string());
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_ident_or_keyword(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_ident_or_keyword(src, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -230,7 +731,8 @@ mod tests {
         let src = "Foo_bar";
         let should_be = Token::Identifier(src.to_string());
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_ident_or_keyword(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_ident_or_keyword(src, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -239,7 +741,8 @@ mod tests {
         let src = "class";
         let should_be = Token::Keyword(Keywords::from_str(src).unwrap());
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_ident_or_keyword(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_ident_or_keyword(src, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -248,7 +751,8 @@ mod tests {
         let src = "classifier";
         let should_be = Token::Identifier(src.to_string());
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_ident_or_keyword(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_ident_or_keyword(src, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -256,7 +760,7 @@ mod tests {
     fn tokenize_ident_cant_start_with_number() {
         let src = "7Foo_bar";
 
-        let got = StreamTokenizer::tokenize_ident_or_keyword(src);
+        let got = StreamTokenizer::tokenize_ident_or_keyword(src, &KeywordSet::base());
         assert!(got.is_err(), "{:?} should be an error", got);
     }
 
@@ -264,7 +768,7 @@ mod tests {
     fn tokenize_ident_cant_start_with_dot() {
         let src = ".Foo_bar";
 
-        let got = StreamTokenizer::tokenize_ident_or_keyword(src);
+        let got = StreamTokenizer::tokenize_ident_or_keyword(src, &KeywordSet::base());
         assert!(got.is_err(), "{:?} should be an error", got);
     }
 
@@ -291,7 +795,7 @@ mod tests {
         let src = " \t\n\r123";
         let should_be = 4;
 
-        let num_skipped = StreamTokenizer::_skip_whitespace(src);
+        let num_skipped = StreamTokenizer::leading_whitespace(src);
         assert_eq!(num_skipped, should_be);
     }
 
@@ -300,36 +804,103 @@ mod tests {
         let src = "Hello World";
         let should_be = 0;
 
-        let num_skipped = StreamTokenizer::_skip_whitespace(src);
+        let num_skipped = StreamTokenizer::leading_whitespace(src);
         assert_eq!(num_skipped, should_be);
     }
 
+    #[test]
+    fn comment_free_sources_skip_only_whitespace() {
+        let src = "class Main {\n    function void main() {\n        do Output.printInt(1 + 2);\n        return;\n    }\n}\n".repeat(20);
+        let fast = StreamTokenizer::from_source(&src);
+        let mut slow = StreamTokenizer::from_source(&src);
+        assert!(!fast.has_comments);
+        slow.has_comments = true;
+
+        // the spans, and how many gaps were looked through for comments
+        let read = |tokenizer: StreamTokenizer| -> (Vec<String>, usize) {
+            let mut tokens = tokenizer.into_spanned();
+            let spans = tokens
+                .by_ref()
+                .map(|t| {
+                    let t = t.unwrap();
+                    format!("{} {}..{}", t.token, t.span.start, t.span.end)
+                })
+                .collect();
+            (spans, tokens.tokenizer().comment_scans)
+        };
+        let (fast_spans, fast_scans) = read(fast);
+        let (slow_spans, slow_scans) = read(slow);
+        assert_eq!(fast_spans, slow_spans);
+        assert_eq!(fast_scans, 0);
+        assert!(slow_scans >= fast_spans.len(), "{}", slow_scans);
+    }
+
+    #[test]
+    fn tabs_advance_the_column_to_the_next_stop() {
+        let src = "a\n\t  #";
+        let error = |mut tokenizer: StreamTokenizer| {
+            let err = tokenizer.find_map(Result::err).unwrap();
+            format!("{:#}", err)
+        };
+
+        assert!(error(StreamTokenizer::from_source(src)).starts_with("at 2:4:"));
+        let stops = StreamTokenizer::from_source(src).tabs(TabPolicy::Stop(4));
+        assert!(error(stops).starts_with("at 2:7:"));
+
+        // to the next stop, not four more columns
+        let src = "a\nab\t#";
+        let stops = StreamTokenizer::from_source(src).tabs(TabPolicy::Stop(4));
+        assert!(error(stops).starts_with("at 2:5:"));
+    }
+
+    #[test]
+    fn extension_keywords_depend_on_the_keyword_set() {
+        let tokens = |keywords: KeywordSet, src: &str| -> Vec<String> {
+            StreamTokenizer::from_source(src)
+                .keywords(keywords)
+                .map(|t| t.unwrap().to_string())
+                .collect()
+        };
+        let for_loops = KeywordSet::base().for_loops(true);
+
+        let src = "let for = 1;";
+        assert_eq!(tokens(KeywordSet::base(), src)[1], "Identifier(for)");
+        assert_eq!(tokens(for_loops.clone(), src)[1], "Keyword(for)");
+
+        let src = "while (i < 10) { let forward = i; }";
+        assert_eq!(
+            tokens(KeywordSet::base(), src),
+            tokens(for_loops, src),
+            "base programs are tokenized the same"
+        );
+    }
+
     #[test]
     fn slash_slash_skips_to_end_of_line() {
         let src = "// foo bar { baz }\n 1234";
         let got = StreamTokenizer::skip_comments(src);
-        assert_eq!(got, 19)
+        assert_eq!(got, (19, false))
     }
 
     #[test]
     fn comment_skip_multi_line_comment() {
         let src = "/** foo bar { baz } */ 1234";
         let got = StreamTokenizer::skip_comments(src);
-        assert_eq!(got, 22)
+        assert_eq!(got, (22, false))
     }
 
     #[test]
     fn comment_skip_ignores_alphanumeric() {
         let src = "123 hello world";
         let got = StreamTokenizer::skip_comments(src);
-        assert_eq!(got, 0)
+        assert_eq!(got, (0, false))
     }
 
     #[test]
     fn comment_skip_ignores_whitespace() {
         let src = "   /* */ 123 hello world";
         let got = StreamTokenizer::skip_comments(src);
-        assert_eq!(got, 0)
+        assert_eq!(got, (0, false))
     }
 
     #[test]
@@ -337,7 +908,8 @@ mod tests {
         let src = "1234";
         let should_be = Token::IntConst(1234);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -346,7 +918,8 @@ mod tests {
         let src = "{";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::OpenCurlyBrace);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -355,7 +928,8 @@ mod tests {
         let src = "}";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::CloseCurlyBrace);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -364,7 +938,8 @@ mod tests {
         let src = "(";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::OpenBrace);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -373,7 +948,8 @@ mod tests {
         let src = ")";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::CloseBrace);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -382,7 +958,8 @@ mod tests {
         let src = "[";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::OpenSquareBrace);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -391,7 +968,8 @@ mod tests {
         let src = "]";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::CloseSquareBrace);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -400,7 +978,8 @@ mod tests {
         let src = ".";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::Dot);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -409,7 +988,8 @@ mod tests {
         let src = ",";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::Comma);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -418,7 +998,8 @@ mod tests {
         let src = ";";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::SemiColon);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -427,7 +1008,8 @@ mod tests {
         let src = "+";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::Plus);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -436,7 +1018,8 @@ mod tests {
         let src = "-";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::Minus);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -445,7 +1028,8 @@ mod tests {
         let src = "*";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::Asterik);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -454,7 +1038,8 @@ mod tests {
         let src = "/";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::BackSlash);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -463,7 +1048,8 @@ mod tests {
         let src = "&";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::Ampersand);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -472,7 +1058,8 @@ mod tests {
         let src = "|";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::VerticalBar);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -481,7 +1068,8 @@ mod tests {
         let src = "<";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::LessThan);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -490,7 +1078,8 @@ mod tests {
         let src = ">";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::GreaterThan);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -499,7 +1088,8 @@ mod tests {
         let src = "=";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::Equal);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
 
@@ -508,7 +1098,159 @@ mod tests {
         let src = "~";
         let should_be = Token::Symbol(crate::lexical_elements::Symbols::Tilde);
 
-        let (got, _bytes_read) = StreamTokenizer::tokenize_single_token(src).unwrap();
+        let (got, _bytes_read) =
+            StreamTokenizer::tokenize_single_token(src, false, &KeywordSet::base()).unwrap();
         assert_eq!(got, should_be, "Input was {:?}", src);
     }
+
+    #[test]
+    fn unknown_character_becomes_error_token_when_recovering() {
+        let src = "a # b";
+        let got: Vec<_> = StreamTokenizer::from_source(src)
+            .recover_from_lex_errors(true)
+            .into_spanned()
+            .map(|t| t.unwrap())
+            .collect();
+
+        let tokens: Vec<_> = got.iter().map(|t| t.token.clone()).collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Identifier("a".to_string()),
+                Token::Error('#'),
+                Token::Identifier("b".to_string()),
+            ],
+            "Input was {:?}",
+            src
+        );
+        assert_eq!((got[1].span.start, got[1].span.end), (2, 3));
+        assert!(StreamTokenizer::from_source(src).nth(1).unwrap().is_err());
+    }
+
+    #[test]
+    fn leading_byte_order_mark_is_skipped() {
+        let src = "\u{FEFF}class Main {}";
+        let first = StreamTokenizer::from_source(src)
+            .into_spanned()
+            .next()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            first.token,
+            Token::Keyword(Keywords::Class),
+            "Input was {:?}",
+            src
+        );
+        assert_eq!(&src[first.span.start..first.span.end], "class");
+        assert_eq!(first.span.column, 1);
+    }
+
+    #[test]
+    fn eof_follows_the_last_token_once() {
+        let src = "class Main {} // done\n";
+        let mut tokens = StreamTokenizer::from_source(src)
+            .emit_eof(true)
+            .into_spanned();
+        let got: Vec<_> = tokens.by_ref().map(|t| t.unwrap()).collect();
+
+        let names: Vec<String> = got.iter().map(|t| t.token.to_string()).collect();
+        assert_eq!(
+            names,
+            [
+                "Keyword(class)",
+                "Identifier(Main)",
+                "Symbol({)",
+                "Symbol(})",
+                "<EOF>"
+            ]
+        );
+        let eof = got.last().unwrap().span;
+        assert_eq!((eof.start, eof.end, eof.line), (src.len(), src.len(), 2));
+        assert!(tokens.next().is_none());
+
+        // off by default
+        let last = StreamTokenizer::from_source(src).last().unwrap().unwrap();
+        assert_eq!(last, Token::Symbol(Symbols::CloseCurlyBrace));
+    }
+
+    #[test]
+    fn no_eof_after_input_cut_off() {
+        let src = "class A { } class B { }";
+        let got: Vec<_> = StreamTokenizer::from_source(src)
+            .stop_after(4)
+            .emit_eof(true)
+            .collect();
+
+        assert_eq!(got.len(), 5);
+        assert!(got[..4]
+            .iter()
+            .all(|t| !matches!(t, Ok(Token::Eof) | Err(_))));
+        let err = got[4].as_ref().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Diagnostic>().unwrap().code,
+            INTERNAL_ERROR
+        );
+    }
+
+    #[test]
+    fn input_ending_inside_a_token_is_one_error() {
+        for (src, start) in [("a \"open", 2), ("a /* open\n", 2), ("a /* /* */ /*", 11)] {
+            let mut tokens = StreamTokenizer::from_source(src).into_spanned();
+            assert!(tokens.next().unwrap().is_ok());
+
+            let e = tokens.next().unwrap().unwrap_err();
+            let diagnostic = e.downcast::<Diagnostic>().unwrap();
+            assert_eq!(diagnostic.code, UNEXPECTED_EOF, "{:?}", src);
+            assert_eq!(
+                (diagnostic.span.start, diagnostic.span.end),
+                (start, src.len())
+            );
+            for _ in 0..3 {
+                assert!(tokens.next().is_none(), "{:?}", src);
+            }
+        }
+
+        // a string ended by a line is an ordinary error, also only once
+        let mut tokens = StreamTokenizer::from_source("\"open\n\"");
+        let e = tokens.next().unwrap().unwrap_err();
+        assert!(e.downcast_ref::<Diagnostic>().is_none(), "{:#}", e);
+        for _ in 0..3 {
+            assert!(tokens.next().is_none());
+        }
+    }
+
+    #[test]
+    fn blank_lines_before_tokens() {
+        let src = "\nclass Main {
+    method void a() { return; }
+
+
+    method void b() { return; }
+    // a comment line isn't blank
+
+    /* nor is a block
+    comment */ method void c() { return; }
+}";
+        let blank_lines = |track| -> Vec<(String, usize)> {
+            StreamTokenizer::from_source(src)
+                .track_layout(track)
+                .into_spanned()
+                .map(Result::unwrap)
+                .filter(|t| t.blank_lines_before > 0 || t.token == Token::Keyword(Keywords::Method))
+                .map(|t| (t.token.lexeme(), t.blank_lines_before))
+                .collect()
+        };
+
+        assert_eq!(
+            blank_lines(true),
+            [
+                ("class".to_owned(), 1),
+                ("method".to_owned(), 0),
+                ("method".to_owned(), 2),
+                ("method".to_owned(), 1),
+            ]
+        );
+        assert!(blank_lines(false).iter().all(|(_, blank)| *blank == 0));
+    }
 }
@@ -0,0 +1,454 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{
+    ast::{Class, Ident, JackType, SubroutineCall, SubroutineDec, SubroutineKind},
+    checker::OS_CLASSES,
+    json,
+    resolve::{call_target, uses, Use},
+    token::Span,
+    SourceSet, SymbolKind, SymbolTable,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefinitionKind {
+    Class,
+    Subroutine(SubroutineKind),
+    Variable(SymbolKind),
+}
+
+impl DefinitionKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Class => "class",
+            Self::Subroutine(SubroutineKind::Constructor) => "constructor",
+            Self::Subroutine(SubroutineKind::Function) => "function",
+            Self::Subroutine(SubroutineKind::Method) => "method",
+            Self::Variable(SymbolKind::Static) => "static",
+            Self::Variable(SymbolKind::Field) => "field",
+            Self::Variable(SymbolKind::Arg) => "argument",
+            Self::Variable(SymbolKind::Var) => "local",
+        }
+    }
+}
+
+// a declared name. `container` is the class of a member and `Class.sub` of a
+// parameter or local, a class has none
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub kind: DefinitionKind,
+    pub name: String,
+    pub container: Option<String>,
+    pub file: PathBuf,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution {
+    // an index into the definitions
+    Definition(usize),
+    // a class of the os or one of its subroutines, named `Class` or
+    // `Class.subroutine`
+    Os(String),
+    // nothing in the project or the os declares it, like a call on an int or
+    // a misspelled name
+    Unresolved(String),
+}
+
+// a use of a name, as a variable, a call, a call's receiver or a type
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    pub file: PathBuf,
+    pub span: Span,
+    pub resolution: Resolution,
+}
+
+// every declaration of a project and every reference to one, for going to
+// definitions and finding references across files
+#[derive(Debug, Clone, Default)]
+pub struct SymbolIndex {
+    pub definitions: Vec<Definition>,
+    pub references: Vec<Reference>,
+}
+
+impl SymbolIndex {
+    // the declarations of every class come first so references in any file
+    // can point at them. variables are resolved with the symbol table of the
+    // subroutine they are used in, calls by the class of their receiver
+    pub fn build(project: &SourceSet) -> Result<Self> {
+        let classes = project.parse()?;
+        let mut index = Self::default();
+        let mut by_class = HashMap::new();
+        let mut by_subroutine = HashMap::new();
+        for (file, class) in &classes {
+            by_class
+                .entry(class.name.name.clone())
+                .or_insert(index.definitions.len());
+            index.define(DefinitionKind::Class, None, file, &class.name);
+            for subroutine in &class.subroutines {
+                by_subroutine
+                    .entry((class.name.name.clone(), subroutine.name.name.clone()))
+                    .or_insert(index.definitions.len());
+                index.define(
+                    DefinitionKind::Subroutine(subroutine.kind),
+                    Some(&class.name.name),
+                    file,
+                    &subroutine.name,
+                );
+            }
+        }
+
+        let mut resolver = Resolver {
+            by_class,
+            by_subroutine,
+            by_span: HashMap::new(),
+            index,
+        };
+        for (file, class) in &classes {
+            resolver.class(file, class);
+        }
+
+        let mut index = resolver.index;
+        index
+            .references
+            .sort_by(|a, b| (&a.file, a.span.start).cmp(&(&b.file, b.span.start)));
+        Ok(index)
+    }
+
+    fn define(&mut self, kind: DefinitionKind, container: Option<&str>, file: &Path, name: &Ident) {
+        self.definitions.push(Definition {
+            kind,
+            name: name.name.clone(),
+            container: container.map(str::to_owned),
+            file: file.to_path_buf(),
+            span: name.span,
+        });
+    }
+
+    // the definition the name at byte `offset` of `file` declares or refers
+    // to, `None` for a name of the os or one that isn't resolved
+    pub fn definition_at(&self, file: &Path, offset: usize) -> Option<&Definition> {
+        let contains =
+            |f: &Path, span: &Span| f == file && (span.start..span.end).contains(&offset);
+        if let Some(definition) = self.definitions.iter().find(|d| contains(&d.file, &d.span)) {
+            return Some(definition);
+        }
+
+        let reference = self
+            .references
+            .iter()
+            .find(|r| contains(&r.file, &r.span))?;
+        match reference.resolution {
+            Resolution::Definition(id) => self.definitions.get(id),
+            _ => None,
+        }
+    }
+
+    // the references resolved to `definitions[id]`, in file order
+    pub fn references_to(&self, id: usize) -> impl Iterator<Item = &Reference> {
+        self.references
+            .iter()
+            .filter(move |r| r.resolution == Resolution::Definition(id))
+    }
+
+    pub fn to_json(&self) -> String {
+        let location = |file: &Path, span: &Span| {
+            format!(
+                "\"file\":{},\"line\":{},\"column\":{},\"start\":{},\"end\":{}",
+                json::string(&file.display().to_string()),
+                span.line,
+                span.column,
+                span.start,
+                span.end
+            )
+        };
+
+        let definitions: Vec<String> = self
+            .definitions
+            .iter()
+            .enumerate()
+            .map(|(id, d)| {
+                format!(
+                    "{{\"id\":{},\"kind\":\"{}\",\"name\":{},\"container\":{},{}}}",
+                    id,
+                    d.kind.label(),
+                    json::string(&d.name),
+                    d.container
+                        .as_deref()
+                        .map_or("null".to_owned(), json::string),
+                    location(&d.file, &d.span)
+                )
+            })
+            .collect();
+
+        let references: Vec<String> = self
+            .references
+            .iter()
+            .map(|r| {
+                let resolution = match &r.resolution {
+                    Resolution::Definition(id) => format!("\"definition\":{}", id),
+                    Resolution::Os(name) => format!("\"os\":{}", json::string(name)),
+                    Resolution::Unresolved(name) => {
+                        format!("\"unresolved\":{}", json::string(name))
+                    }
+                };
+                format!(
+                    "{{\"name\":{},{},{}}}",
+                    json::string(&r.name),
+                    location(&r.file, &r.span),
+                    resolution
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"definitions\":[{}],\"references\":[{}]}}\n",
+            definitions.join(","),
+            references.join(",")
+        )
+    }
+}
+
+struct Resolver {
+    by_class: HashMap<String, usize>,
+    by_subroutine: HashMap<(String, String), usize>,
+    // variables by the file and offset of their declaration, where the
+    // symbol table's spans point
+    by_span: HashMap<(PathBuf, usize), usize>,
+    index: SymbolIndex,
+}
+
+impl Resolver {
+    fn class(&mut self, file: &Path, class: &Class) {
+        let mut table = SymbolTable::new();
+        for dec in &class.class_vars {
+            self.type_reference(file, &dec.var_type, dec.type_span);
+            for name in &dec.names {
+                let kind = SymbolKind::from(dec.kind);
+                self.variable(kind, &class.name.name, file, name);
+                // only parameters clash
+                let _ = table.define(&name.name, dec.var_type.clone(), kind, name.span);
+            }
+        }
+
+        for subroutine in &class.subroutines {
+            self.subroutine(file, class, subroutine, &mut table);
+        }
+    }
+
+    fn subroutine(
+        &mut self,
+        file: &Path,
+        class: &Class,
+        subroutine: &SubroutineDec,
+        table: &mut SymbolTable,
+    ) {
+        let container = format!("{}.{}", class.name.name, subroutine.name.name);
+        table.start_subroutine();
+        self.type_reference(file, &subroutine.return_type, subroutine.return_type_span);
+        for param in &subroutine.params {
+            self.type_reference(file, &param.var_type, param.type_span);
+            self.variable(SymbolKind::Arg, &container, file, &param.name);
+            // a duplicate parameter resolves to the first one
+            let _ = table.define(
+                &param.name.name,
+                param.var_type.clone(),
+                SymbolKind::Arg,
+                param.name.span,
+            );
+        }
+        for dec in &subroutine.locals {
+            self.type_reference(file, &dec.var_type, dec.type_span);
+            for name in &dec.names {
+                self.variable(SymbolKind::Var, &container, file, name);
+                let _ = table.define(&name.name, dec.var_type.clone(), SymbolKind::Var, name.span);
+            }
+        }
+
+        for found in uses(&subroutine.statements) {
+            match found {
+                Use::Var(ident) => self.var_reference(file, table, ident),
+                Use::Call(call) => self.call(file, class, subroutine, table, call),
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        file: &Path,
+        class: &Class,
+        subroutine: &SubroutineDec,
+        table: &SymbolTable,
+        call: &SubroutineCall,
+    ) {
+        if let Some(receiver) = &call.receiver {
+            match table.lookup(&receiver.name) {
+                Some(_) => self.var_reference(file, table, receiver),
+                None => self.class_reference(file, &receiver.name, receiver.span),
+            }
+        }
+
+        let name = &call.name.name;
+        let resolution = match call_target(class, subroutine, call) {
+            Some(target) => match self.by_subroutine.get(&(target.clone(), name.clone())) {
+                Some(&id) => Resolution::Definition(id),
+                None if OS_CLASSES.contains(&target.as_str()) => {
+                    Resolution::Os(format!("{}.{}", target, name))
+                }
+                None => Resolution::Unresolved(format!("{}.{}", target, name)),
+            },
+            // a call on an int, char or boolean
+            None => Resolution::Unresolved(name.clone()),
+        };
+        self.reference(file, &call.name.name, call.name.span, resolution);
+    }
+
+    fn variable(&mut self, kind: SymbolKind, container: &str, file: &Path, name: &Ident) {
+        self.by_span.insert(
+            (file.to_path_buf(), name.span.start),
+            self.index.definitions.len(),
+        );
+        self.index
+            .define(DefinitionKind::Variable(kind), Some(container), file, name);
+    }
+
+    fn var_reference(&mut self, file: &Path, table: &SymbolTable, ident: &Ident) {
+        let resolution = table
+            .lookup(&ident.name)
+            .and_then(|symbol| self.by_span.get(&(file.to_path_buf(), symbol.span.start)))
+            .map_or(Resolution::Unresolved(ident.name.clone()), |&id| {
+                Resolution::Definition(id)
+            });
+        self.reference(file, &ident.name, ident.span, resolution);
+    }
+
+    fn type_reference(&mut self, file: &Path, var_type: &JackType, span: Span) {
+        if let JackType::ClassName(name) = var_type {
+            self.class_reference(file, name, span);
+        }
+    }
+
+    fn class_reference(&mut self, file: &Path, name: &str, span: Span) {
+        let resolution = match self.by_class.get(name) {
+            Some(&id) => Resolution::Definition(id),
+            None if OS_CLASSES.contains(&name) => Resolution::Os(name.to_owned()),
+            None => Resolution::Unresolved(name.to_owned()),
+        };
+        self.reference(file, name, span, resolution);
+    }
+
+    fn reference(&mut self, file: &Path, name: &str, span: Span, resolution: Resolution) {
+        self.index.references.push(Reference {
+            name: name.to_owned(),
+            file: file.to_path_buf(),
+            span,
+            resolution,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::SourceSet;
+
+    use super::{DefinitionKind, Resolution, SymbolIndex};
+
+    const POINT: &str = "class Point { field int x;
+    constructor Point new(int ax) { let x = ax; return this; }
+    method int getX() { return x; } }";
+
+    const MAIN: &str = "class Main { function void main() { var Point p;
+    let p = Point.new(3); do Output.printInt(p.getX()); do p.getY(); return; } }";
+
+    fn index() -> SymbolIndex {
+        let mut set = SourceSet::new();
+        set.add("Point.jack", POINT);
+        set.add("Main.jack", MAIN);
+        SymbolIndex::build(&set).unwrap()
+    }
+
+    #[test]
+    fn method_call_links_to_its_definition_in_another_file() {
+        let index = index();
+        let (id, get_x) = index
+            .definitions
+            .iter()
+            .enumerate()
+            .find(|(_, d)| d.name == "getX")
+            .unwrap();
+        assert_eq!(get_x.kind.label(), "method");
+        assert_eq!(get_x.file, Path::new("Point.jack"));
+        assert_eq!(&POINT[get_x.span.start..get_x.span.end], "getX");
+
+        let calls: Vec<_> = index.references_to(id).collect();
+        assert_eq!(calls.len(), 1, "{:?}", calls);
+        assert_eq!(calls[0].file, Path::new("Main.jack"));
+        assert_eq!(&MAIN[calls[0].span.start..calls[0].span.end], "getX");
+
+        let call = MAIN.find("getX").unwrap();
+        assert_eq!(
+            index.definition_at(Path::new("Main.jack"), call + 2),
+            Some(get_x)
+        );
+    }
+
+    #[test]
+    fn variables_os_calls_and_unresolved_names() {
+        let index = index();
+        let resolution = |file: &str, text: &str, name: &str| {
+            let at = text.find(name).unwrap();
+            index
+                .references
+                .iter()
+                .find(|r| {
+                    r.file == Path::new(file)
+                        && r.span.start >= at
+                        && r.name == name.trim_end_matches(';')
+                })
+                .unwrap()
+                .resolution
+                .clone()
+        };
+
+        // `ax` in `let x = ax` is the parameter, `x` the field
+        let Resolution::Definition(id) = resolution("Point.jack", POINT, "ax;") else {
+            panic!("`ax` is unresolved");
+        };
+        let ax = &index.definitions[id];
+        assert_eq!(
+            (ax.kind.label(), ax.container.as_deref()),
+            ("argument", Some("Point.new"))
+        );
+        let Resolution::Definition(id) = resolution("Point.jack", POINT, "x") else {
+            panic!("`x` is unresolved");
+        };
+        assert_eq!(
+            index.definitions[id].kind,
+            DefinitionKind::Variable(crate::SymbolKind::Field)
+        );
+
+        assert_eq!(
+            resolution("Main.jack", MAIN, "printInt"),
+            Resolution::Os("Output.printInt".to_owned())
+        );
+        assert_eq!(
+            resolution("Main.jack", MAIN, "Output"),
+            Resolution::Os("Output".to_owned())
+        );
+        assert_eq!(
+            resolution("Main.jack", MAIN, "getY"),
+            Resolution::Unresolved("Point.getY".to_owned())
+        );
+
+        let json = index.to_json();
+        assert!(json.contains("\"os\":\"Output.printInt\""), "{}", json);
+        assert!(json.contains("\"unresolved\":\"Point.getY\""), "{}", json);
+    }
+}
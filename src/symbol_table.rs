@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{ClassVarKind, JackType},
+    diagnostic::Diagnostic,
+    token::Span,
+};
+
+pub const DUPLICATE_PARAMETER: &str = "J107";
+pub const DUPLICATE_VARIABLE: &str = "J119";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Static,
+    Field,
+    Arg,
+    Var,
+}
+
+impl From<ClassVarKind> for SymbolKind {
+    fn from(kind: ClassVarKind) -> Self {
+        match kind {
+            ClassVarKind::Static => Self::Static,
+            ClassVarKind::Field => Self::Field,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub var_type: JackType,
+    pub kind: SymbolKind,
+    pub index: u16,
+    pub span: Span,
+}
+
+// class and subroutine scoped symbols, as described in the nand2tetris course
+#[derive(Default, Clone)]
+pub struct SymbolTable {
+    class_scope: HashMap<String, Symbol>,
+    subroutine_scope: HashMap<String, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // clears the subroutine scope, class scope is kept
+    pub fn start_subroutine(&mut self) {
+        self.subroutine_scope.clear();
+    }
+
+    // a second declaration of a name in the same scope is an error and isn't
+    // defined, the first one stays. a subroutine's variables may hide the
+    // class's
+    pub fn define(
+        &mut self,
+        name: &str,
+        var_type: JackType,
+        kind: SymbolKind,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let scope = match kind {
+            SymbolKind::Static | SymbolKind::Field => &self.class_scope,
+            SymbolKind::Arg | SymbolKind::Var => &self.subroutine_scope,
+        };
+        if let Some(first) = scope.get(name) {
+            if kind == SymbolKind::Arg && first.kind == SymbolKind::Arg {
+                return Err(Diagnostic::error(
+                    DUPLICATE_PARAMETER,
+                    format!(
+                        "parameter `{}` is declared twice, first at {}",
+                        name, first.span
+                    ),
+                    span,
+                ));
+            }
+            return Err(Diagnostic::error(
+                DUPLICATE_VARIABLE,
+                format!("variable '{}' already declared in this scope", name),
+                span,
+            ));
+        }
+
+        let index = self.var_count(kind);
+        let symbol = Symbol {
+            name: name.to_owned(),
+            var_type,
+            kind,
+            index,
+            span,
+        };
+
+        match kind {
+            SymbolKind::Static | SymbolKind::Field => {
+                self.class_scope.insert(name.to_owned(), symbol)
+            }
+            SymbolKind::Arg | SymbolKind::Var => {
+                self.subroutine_scope.insert(name.to_owned(), symbol)
+            }
+        };
+        Ok(())
+    }
+
+    pub fn var_count(&self, kind: SymbolKind) -> u16 {
+        let scope = match kind {
+            SymbolKind::Static | SymbolKind::Field => &self.class_scope,
+            SymbolKind::Arg | SymbolKind::Var => &self.subroutine_scope,
+        };
+
+        scope.values().filter(|s| s.kind == kind).count() as u16
+    }
+
+    // every symbol `lookup` can currently reach, subroutine scope first
+    pub fn visible(&self) -> impl Iterator<Item = &Symbol> {
+        self.subroutine_scope.values().chain(
+            self.class_scope
+                .values()
+                .filter(|s| !self.subroutine_scope.contains_key(&s.name)),
+        )
+    }
+
+    // subroutine scope shadows class scope
+    pub fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.subroutine_scope
+            .get(name)
+            .or_else(|| self.class_scope.get(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ast::JackType, token::Span};
+
+    use super::{SymbolKind, SymbolTable, DUPLICATE_PARAMETER, DUPLICATE_VARIABLE};
+
+    fn span(column: usize) -> Span {
+        Span {
+            start: column - 1,
+            end: column,
+            line: 1,
+            column,
+        }
+    }
+
+    #[test]
+    fn define_assigns_running_index_per_kind() {
+        let mut table = SymbolTable::new();
+        for (name, kind) in [
+            ("x", SymbolKind::Field),
+            ("y", SymbolKind::Field),
+            ("s", SymbolKind::Static),
+        ] {
+            table
+                .define(name, JackType::Int, kind, Span::default())
+                .unwrap();
+        }
+
+        assert_eq!(table.lookup("y").unwrap().index, 1);
+        assert_eq!(table.lookup("s").unwrap().index, 0);
+        assert_eq!(table.var_count(SymbolKind::Field), 2);
+    }
+
+    #[test]
+    fn subroutine_scope_shadows_class_scope_until_reset() {
+        let mut table = SymbolTable::new();
+        table
+            .define("x", JackType::Int, SymbolKind::Field, Span::default())
+            .unwrap();
+        table
+            .define("x", JackType::Boolean, SymbolKind::Var, Span::default())
+            .unwrap();
+        assert_eq!(table.lookup("x").unwrap().kind, SymbolKind::Var);
+
+        table.start_subroutine();
+        assert_eq!(table.lookup("x").unwrap().kind, SymbolKind::Field);
+    }
+
+    #[test]
+    fn duplicate_parameters_are_rejected() {
+        let mut table = SymbolTable::new();
+        table
+            .define("x", JackType::Int, SymbolKind::Arg, span(20))
+            .unwrap();
+        let err = table
+            .define("x", JackType::Boolean, SymbolKind::Arg, span(27))
+            .unwrap_err();
+
+        assert_eq!(err.code, DUPLICATE_PARAMETER);
+        assert_eq!(err.span.column, 27);
+        assert_eq!(
+            err.to_string(),
+            "1:27: error[J107]: parameter `x` is declared twice, first at 1:20"
+        );
+        let first = table.lookup("x").unwrap();
+        assert_eq!((first.var_type.clone(), first.index), (JackType::Int, 0));
+        assert_eq!(table.var_count(SymbolKind::Arg), 1);
+    }
+
+    #[test]
+    fn names_declared_twice_in_a_scope_are_rejected() {
+        let mut table = SymbolTable::new();
+        for (kind, other) in [
+            (SymbolKind::Field, SymbolKind::Static),
+            (SymbolKind::Var, SymbolKind::Var),
+            (SymbolKind::Arg, SymbolKind::Var),
+        ] {
+            table.start_subroutine();
+            table.define("x", JackType::Int, kind, span(5)).unwrap();
+            let err = table
+                .define("x", JackType::Int, other, span(9))
+                .unwrap_err();
+
+            assert_eq!(err.code, DUPLICATE_VARIABLE);
+            assert_eq!(
+                err.to_string(),
+                "1:9: error[J119]: variable 'x' already declared in this scope"
+            );
+            assert_eq!(table.lookup("x").unwrap().kind, kind);
+        }
+    }
+}
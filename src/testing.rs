@@ -0,0 +1,44 @@
+// helpers for tests that compile jack source, in this crate or in code that
+// generates jack
+
+use crate::{check_source, CheckOptions, Severity};
+
+// panics with every diagnostic rendered when checking `source` finds an
+// error. warnings don't fail the check
+#[track_caller]
+pub fn assert_compiles(source: &str) {
+    let diagnostics = check_source(source, &CheckOptions::default());
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        let rendered: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        panic!(
+            "source does not compile:\n{}\n\n{}",
+            rendered.join("\n"),
+            source
+        );
+    }
+}
+
+// the same as assert_compiles, for any expression that derefs to `str`
+#[macro_export]
+macro_rules! assert_compiles {
+    ($source:expr) => {
+        $crate::testing::assert_compiles(&$source)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assert_compiles;
+
+    #[test]
+    fn compiling_source_passes() {
+        assert_compiles("class Main { function void main() { return; } }");
+        crate::assert_compiles!(format!("class {} {{ }}", "Main"));
+    }
+
+    #[test]
+    #[should_panic(expected = "1:41: error[J103]: cannot assign to `x`, it is not declared")]
+    fn errors_panic_with_the_diagnostics() {
+        assert_compiles("class Main { function void main() { let x = 1; return; } }");
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use crate::lexical_elements::{Keywords, Symbols};
 
 #[derive(Debug, Clone)]
@@ -5,24 +7,208 @@ pub enum Token {
     Keyword(Keywords),
     Symbol(Symbols),
     Identifier(String),
+    // the literal, only ever negative as i16::MIN for the 32768 of `-32768`
     IntConst(i16),
     StringConst(String),
+    // an unknown character, only produced when recovering from lex errors
+    Error(char),
+    // the end of the input, only produced by tokenizers asked to emit it
+    Eof,
+}
+
+// what a token is, without its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword,
+    Symbol,
+    Identifier,
+    IntConst,
+    StringConst,
+    Error,
+    Eof,
 }
 
-impl ToString for Token {
-    fn to_string(&self) -> String {
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Keyword(k) => format!("Keyword({})", k.to_string()),
-            Self::Symbol(s) => format!("Symbol({})", s.to_string()),
-            Self::Identifier(id) => format!("Identifier({})", id.to_string()),
-            Self::IntConst(i) => format!("IntConst({})", i),
-            Self::StringConst(s) => format!("StringConst({})", s),
+            Self::Keyword(k) => write!(f, "Keyword({})", k),
+            Self::Symbol(s) => write!(f, "Symbol({})", s),
+            Self::Identifier(id) => write!(f, "Identifier({})", id),
+            Self::IntConst(i) => write!(f, "IntConst({})", *i as u16),
+            Self::StringConst(s) => write!(f, "StringConst({})", s),
+            Self::Error(c) => write!(f, "Error({})", c),
+            Self::Eof => write!(f, "<EOF>"),
         }
     }
 }
 
+impl Token {
+    pub fn kind(&self) -> TokenKind {
+        match self {
+            Self::Keyword(_) => TokenKind::Keyword,
+            Self::Symbol(_) => TokenKind::Symbol,
+            Self::Identifier(_) => TokenKind::Identifier,
+            Self::IntConst(_) => TokenKind::IntConst,
+            Self::StringConst(_) => TokenKind::StringConst,
+            Self::Error(_) => TokenKind::Error,
+            Self::Eof => TokenKind::Eof,
+        }
+    }
+
+    pub fn keyword(&self) -> Option<&Keywords> {
+        match self {
+            Self::Keyword(k) => Some(k),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(&self) -> Option<&Symbols> {
+        match self {
+            Self::Symbol(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    // the token as it is written in a source file, strings with their quotes.
+    // the display form of `<` and `>` is escaped for xml
+    pub fn lexeme(&self) -> String {
+        match self {
+            Self::Keyword(k) => k.to_string(),
+            Self::Symbol(Symbols::LessThan) => String::from("<"),
+            Self::Symbol(Symbols::GreaterThan) => String::from(">"),
+            Self::Symbol(s) => s.to_string(),
+            Self::Identifier(id) => id.clone(),
+            Self::IntConst(i) => (*i as u16).to_string(),
+            Self::StringConst(s) => format!("\"{}\"", s),
+            Self::Error(c) => c.to_string(),
+            Self::Eof => String::new(),
+        }
+    }
+}
+
+// the lexemes of `tokens` with a space between each, a quick look at a token
+// stream rather than a formatting of it
+pub fn tokens_to_source(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .filter(|token| !matches!(token, Token::Eof))
+        .map(Token::lexeme)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 impl PartialEq for Token {
     fn eq(&self, other: &Self) -> bool {
         self.to_string() == other.to_string()
     }
 }
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    // span starting where `self` starts and ending where `other` ends
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            end: other.end,
+            ..*self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+    // the lines holding nothing but whitespace between this token and the
+    // one before it, or the start of input. only counted by a StreamTokenizer
+    // tracking the layout, 0 otherwise
+    pub blank_lines_before: usize,
+}
+
+// tokens compare by their lexical content only, two identical tokens read
+// from different positions are equal
+impl PartialEq for SpannedToken {
+    fn eq(&self, other: &Self) -> bool {
+        self.token == other.token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        lexical_elements::{Keywords, Symbols},
+        StreamTokenizer, Token,
+    };
+
+    use super::tokens_to_source;
+
+    #[test]
+    fn spanned_tokens_compare_without_span() {
+        let src = "class  class while";
+        let tokens: Vec<_> = StreamTokenizer::from_source(src)
+            .into_spanned()
+            .map(|t| t.unwrap())
+            .collect();
+
+        assert_ne!(tokens[0].span, tokens[1].span);
+        assert_eq!(tokens[0], tokens[1], "Input was {:?}", src);
+        assert_ne!(tokens[1], tokens[2], "Input was {:?}", src);
+    }
+
+    #[test]
+    fn accessors_only_match_their_variant() {
+        assert_eq!(
+            Token::Keyword(Keywords::Let).keyword(),
+            Some(&Keywords::Let)
+        );
+        assert_eq!(Token::Symbol(Symbols::Dot).symbol(), Some(&Symbols::Dot));
+
+        assert_eq!(Token::Symbol(Symbols::Dot).keyword(), None);
+        assert_eq!(Token::Identifier("let".to_owned()).keyword(), None);
+        assert_eq!(Token::Keyword(Keywords::Let).symbol(), None);
+        assert_eq!(Token::StringConst(".".to_owned()).symbol(), None);
+    }
+
+    #[test]
+    fn tokens_read_back_with_single_spaces() {
+        let src = "class Main {\n  function void main() {\n    do Output.printString(\"hi there\");\n    return -32768; // done\n  }\n}\n";
+        let tokens: Vec<Token> = StreamTokenizer::from_source(src)
+            .emit_eof(true)
+            .map(|t| t.unwrap())
+            .collect();
+
+        assert_eq!(
+            tokens_to_source(&tokens),
+            "class Main { function void main ( ) { do Output . printString ( \"hi there\" ) ; \
+             return - 32768 ; } }"
+        );
+        assert_eq!(tokens_to_source(&[]), "");
+    }
+
+    #[test]
+    fn comparison_and_logic_symbols_read_back_as_source() {
+        let src = "if (a < b) { let c = (a > b) & ~d; }";
+        let tokens: Vec<Token> = StreamTokenizer::from_source(src)
+            .map(|t| t.unwrap())
+            .collect();
+
+        let rebuilt = tokens_to_source(&tokens);
+        assert_eq!(rebuilt, "if ( a < b ) { let c = ( a > b ) & ~ d ; }");
+        let again: Vec<Token> = StreamTokenizer::from_source(&rebuilt)
+            .map(|t| t.unwrap())
+            .collect();
+        assert_eq!(again, tokens);
+    }
+}
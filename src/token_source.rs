@@ -0,0 +1,233 @@
+use anyhow::Result;
+
+use crate::{NaiveTokenizer, StreamTokenizer, Token, TokenKind};
+
+// a tokenizer the compilation engine can be fed from
+pub trait TokenSource: Iterator<Item = Result<Token>> + Sized {
+    // used to tell implementations apart in test failures
+    const NAME: &'static str;
+
+    fn from_source(source: &str) -> Self;
+
+    // the tokens other than those of `kind`, errors are kept
+    fn without(self, kind: TokenKind) -> impl Iterator<Item = Result<Token>> {
+        self.filter(move |token| !matches!(token, Ok(token) if token.kind() == kind))
+    }
+}
+
+impl TokenSource for StreamTokenizer {
+    const NAME: &'static str = "StreamTokenizer";
+
+    fn from_source(source: &str) -> Self {
+        StreamTokenizer::from_source(source)
+    }
+}
+
+impl TokenSource for NaiveTokenizer {
+    const NAME: &'static str = "NaiveTokenizer";
+
+    fn from_source(source: &str) -> Self {
+        NaiveTokenizer::from_source(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{BufReadTokenizer, NaiveTokenizer, StreamTokenizer, TokenKind};
+
+    use super::TokenSource;
+
+    const ERROR: &str = "<error>";
+
+    // (source, tokens as displayed), a tokenizer error ends the expected list
+    const CASES: &[(&str, &[&str])] = &[
+        (
+            "{}()[].,;+-*/&|<>=~",
+            &[
+                "Symbol({)",
+                "Symbol(})",
+                "Symbol(()",
+                "Symbol())",
+                "Symbol([)",
+                "Symbol(])",
+                "Symbol(.)",
+                "Symbol(,)",
+                "Symbol(;)",
+                "Symbol(+)",
+                "Symbol(-)",
+                "Symbol(*)",
+                "Symbol(/)",
+                "Symbol(&)",
+                "Symbol(|)",
+                "Symbol(&lt;)",
+                "Symbol(&gt;)",
+                "Symbol(=)",
+                "Symbol(~)",
+            ],
+        ),
+        (
+            "class classy Class _x x1 while whiles",
+            &[
+                "Keyword(class)",
+                "Identifier(classy)",
+                "Identifier(Class)",
+                "Identifier(_x)",
+                "Identifier(x1)",
+                "Keyword(while)",
+                "Identifier(whiles)",
+            ],
+        ),
+        (
+            "a[i]=b;",
+            &[
+                "Identifier(a)",
+                "Symbol([)",
+                "Identifier(i)",
+                "Symbol(])",
+                "Symbol(=)",
+                "Identifier(b)",
+                "Symbol(;)",
+            ],
+        ),
+        (
+            "x=-1;",
+            &[
+                "Identifier(x)",
+                "Symbol(=)",
+                "Symbol(-)",
+                "IntConst(1)",
+                "Symbol(;)",
+            ],
+        ),
+        (
+            "\"a; b, (c) // not_a comment\" x",
+            &["StringConst(a; b, (c) // not_a comment)", "Identifier(x)"],
+        ),
+        ("\"\"", &["StringConst()"]),
+        ("\"never closed", &[ERROR]),
+        ("a \"never closed\nb", &["Identifier(a)", ERROR]),
+        ("a /* never closed", &["Identifier(a)", ERROR]),
+        ("a /*/ b", &["Identifier(a)", ERROR]),
+        ("a/*c*/b", &["Identifier(a)", "Identifier(b)"]),
+        (
+            "a/**/b /** doc */ c",
+            &["Identifier(a)", "Identifier(b)", "Identifier(c)"],
+        ),
+        ("a // rest\nb", &["Identifier(a)", "Identifier(b)"]),
+        (
+            "a /* over\nseveral\nlines */ b",
+            &["Identifier(a)", "Identifier(b)"],
+        ),
+        ("x // comment at the end", &["Identifier(x)"]),
+        ("a / b", &["Identifier(a)", "Symbol(/)", "Identifier(b)"]),
+        (
+            "a/b//c\nd",
+            &[
+                "Identifier(a)",
+                "Symbol(/)",
+                "Identifier(b)",
+                "Identifier(d)",
+            ],
+        ),
+        (
+            "a/b/*c*/d",
+            &[
+                "Identifier(a)",
+                "Symbol(/)",
+                "Identifier(b)",
+                "Identifier(d)",
+            ],
+        ),
+        ("x//comment\ny/**/", &["Identifier(x)", "Identifier(y)"]),
+        ("/**/x", &["Identifier(x)"]),
+        ("5/*five*/+1", &["IntConst(5)", "Symbol(+)", "IntConst(1)"]),
+        (
+            "0 32767 007",
+            &["IntConst(0)", "IntConst(32767)", "IntConst(7)"],
+        ),
+        ("1 32768", &["IntConst(1)", ERROR]),
+        ("-32768", &["Symbol(-)", "IntConst(32768)"]),
+        ("- /* */ 32768", &["Symbol(-)", "IntConst(32768)"]),
+        ("-32769", &["Symbol(-)", ERROR]),
+        ("-327680", &["Symbol(-)", ERROR]),
+        ("123abc", &["IntConst(123)", "Identifier(abc)"]),
+        ("a # b", &["Identifier(a)", ERROR]),
+    ];
+
+    fn tokens(tokens: impl Iterator<Item = anyhow::Result<String>>) -> Vec<String> {
+        let mut got = Vec::new();
+        for token in tokens {
+            match token {
+                Ok(token) => got.push(token),
+                Err(_) => {
+                    got.push(ERROR.to_owned());
+                    break;
+                }
+            }
+        }
+        got
+    }
+
+    fn check(name: &str, got: impl Fn(&str) -> Vec<String>) {
+        for (src, expected) in CASES {
+            assert_eq!(&got(src), expected, "{} on {:?}", name, src);
+        }
+    }
+
+    fn conformance<T: TokenSource>() {
+        check(T::NAME, |src| {
+            tokens(T::from_source(src).map(|t| t.map(|t| t.to_string())))
+        });
+    }
+
+    #[test]
+    fn stream_tokenizer_conformance() {
+        conformance::<StreamTokenizer>();
+    }
+
+    #[test]
+    fn bufread_tokenizer_conformance() {
+        conformance::<BufReadTokenizer<Cursor<Vec<u8>>>>();
+    }
+
+    #[test]
+    fn naive_tokenizer_conformance() {
+        conformance::<NaiveTokenizer>();
+    }
+
+    #[test]
+    fn without_drops_one_kind() {
+        let got = tokens(
+            StreamTokenizer::from_source("let a[1] = 2 + \"3\"; #")
+                .without(TokenKind::IntConst)
+                .map(|t| t.map(|t| t.to_string())),
+        );
+        assert_eq!(
+            got,
+            [
+                "Keyword(let)",
+                "Identifier(a)",
+                "Symbol([)",
+                "Symbol(])",
+                "Symbol(=)",
+                "Symbol(+)",
+                "StringConst(3)",
+                "Symbol(;)",
+                ERROR,
+            ]
+        );
+    }
+
+    #[test]
+    fn spanned_stream_conformance() {
+        check("SpannedTokens", |src| {
+            tokens(
+                StreamTokenizer::from_source(src)
+                    .into_spanned()
+                    .map(|t| t.map(|t| t.token.to_string())),
+            )
+        });
+    }
+}
@@ -0,0 +1,661 @@
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+// the fixed part of the hack memory map
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+const TEMP: usize = 5;
+const STATIC: usize = 16;
+const STACK: usize = 256;
+const HEAP: usize = 2048;
+const RAM_SIZE: usize = 32768;
+
+// a program looping forever fails instead of hanging the test
+const MAX_STEPS: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Segment {
+    Constant,
+    Local,
+    Argument,
+    This,
+    That,
+    Pointer,
+    Temp,
+    // resolved to its address when the program is read
+    Static,
+}
+
+impl FromStr for Segment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let v = match s {
+            "constant" => Self::Constant,
+            "local" => Self::Local,
+            "argument" => Self::Argument,
+            "this" => Self::This,
+            "that" => Self::That,
+            "pointer" => Self::Pointer,
+            "temp" => Self::Temp,
+            "static" => Self::Static,
+            _ => bail!("unsupported segment `{}`", s),
+        };
+
+        Ok(v)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ArithmeticOp {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+#[derive(Debug, Clone)]
+enum Instruction {
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    Arithmetic(ArithmeticOp),
+    // label targets, resolved to instruction indices
+    Label,
+    Goto(usize),
+    IfGoto(usize),
+    Function(String, u16),
+    Call(String, u16),
+    Return,
+}
+
+// what a run left behind
+#[derive(Debug)]
+pub struct Execution {
+    // the value the entry function returned, none when it called Sys.halt
+    pub returned: Option<i16>,
+    // everything printed through the Output stubs
+    pub output: String,
+    pub ram: Vec<i16>,
+}
+
+// a tiny hack vm used by the tests to execute generated vm code. the os is
+// stubbed as far as arithmetic programs need it, any other os call fails
+pub struct VmInterpreter {
+    instructions: Vec<Instruction>,
+    functions: HashMap<String, usize>,
+}
+
+impl VmInterpreter {
+    pub fn new(source: &str) -> Result<Self> {
+        let mut instructions = Vec::new();
+        let mut functions = HashMap::new();
+        // labels are local to the function they are in
+        let mut labels = HashMap::new();
+        let mut jumps = Vec::new();
+        let mut statics = HashMap::new();
+        let mut function = String::new();
+
+        for (i, line) in source.lines().enumerate() {
+            // strip comments and surrounding whitespace
+            let line = line.split("//").next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let context = || format!("line {}: `{}`", i + 1, line);
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let mut instruction = Self::parse_instruction(&parts).with_context(context)?;
+
+            match &mut instruction {
+                Instruction::Function(name, _) => {
+                    functions.insert(name.clone(), instructions.len());
+                    function = name.clone();
+                }
+                Instruction::Label => {
+                    let label = (function.clone(), Self::name(&parts)?);
+                    if labels.insert(label, instructions.len()).is_some() {
+                        return Err(anyhow!("label defined twice")).with_context(context);
+                    }
+                }
+                Instruction::Goto(_) | Instruction::IfGoto(_) => {
+                    jumps.push((instructions.len(), function.clone(), Self::name(&parts)?));
+                }
+                // every class has its own statics, named after the function
+                Instruction::Push(Segment::Static, index)
+                | Instruction::Pop(Segment::Static, index) => {
+                    let class = function.split('.').next().unwrap_or("").to_owned();
+                    let count = statics.len();
+                    let address = *statics.entry((class, *index)).or_insert(STATIC + count);
+                    if address >= STACK {
+                        return Err(anyhow!("too many static variables")).with_context(context);
+                    }
+                    *index = address as u16;
+                }
+                _ => {}
+            }
+            instructions.push(instruction);
+        }
+
+        for (at, function, label) in jumps {
+            let target = labels
+                .get(&(function, label.clone()))
+                .copied()
+                .ok_or_else(|| anyhow!("unknown label `{}`", label))?;
+            match &mut instructions[at] {
+                Instruction::Goto(to) | Instruction::IfGoto(to) => *to = target,
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(Self {
+            instructions,
+            functions,
+        })
+    }
+
+    fn parse_instruction(parts: &[&str]) -> Result<Instruction> {
+        let index = |i: usize| -> Result<u16> {
+            let part = parts.get(i).ok_or(anyhow!("missing operand"))?;
+            Ok(part.parse::<u16>()?)
+        };
+
+        let instruction = match parts[0] {
+            "push" => Instruction::Push(Segment::from_str(parts.get(1).unwrap_or(&""))?, index(2)?),
+            "pop" => {
+                let segment = Segment::from_str(parts.get(1).unwrap_or(&""))?;
+                if segment == Segment::Constant {
+                    bail!("cannot pop into the constant segment");
+                }
+                Instruction::Pop(segment, index(2)?)
+            }
+            "add" => Instruction::Arithmetic(ArithmeticOp::Add),
+            "sub" => Instruction::Arithmetic(ArithmeticOp::Sub),
+            "neg" => Instruction::Arithmetic(ArithmeticOp::Neg),
+            "eq" => Instruction::Arithmetic(ArithmeticOp::Eq),
+            "gt" => Instruction::Arithmetic(ArithmeticOp::Gt),
+            "lt" => Instruction::Arithmetic(ArithmeticOp::Lt),
+            "and" => Instruction::Arithmetic(ArithmeticOp::And),
+            "or" => Instruction::Arithmetic(ArithmeticOp::Or),
+            "not" => Instruction::Arithmetic(ArithmeticOp::Not),
+            "label" => Instruction::Label,
+            "goto" => Instruction::Goto(usize::MAX),
+            "if-goto" => Instruction::IfGoto(usize::MAX),
+            "function" => Instruction::Function(Self::name(parts)?, index(2)?),
+            "call" => Instruction::Call(Self::name(parts)?, index(2)?),
+            "return" => Instruction::Return,
+            command => bail!("unsupported command `{}`", command),
+        };
+
+        Ok(instruction)
+    }
+
+    fn name(parts: &[&str]) -> Result<String> {
+        parts
+            .get(1)
+            .map(|s| s.to_string())
+            .ok_or(anyhow!("missing name"))
+    }
+
+    // calls `entry` with no arguments and returns the value it returns
+    pub fn run(&self, entry: &str) -> Result<i16> {
+        self.execute(entry)?
+            .returned
+            .ok_or(anyhow!("`{}` halted instead of returning", entry))
+    }
+
+    // calls `entry` with no arguments and keeps what it printed and the
+    // memory it ran in
+    pub fn execute(&self, entry: &str) -> Result<Execution> {
+        let mut machine = Machine {
+            ram: vec![0; RAM_SIZE],
+            return_pcs: Vec::new(),
+            heap: HEAP,
+            output: String::new(),
+        };
+        machine.ram[SP] = STACK as i16;
+        let returned = machine
+            .run(self, entry)
+            .with_context(|| format!("running `{}`", entry))?;
+
+        Ok(Execution {
+            returned,
+            output: machine.output,
+            ram: machine.ram,
+        })
+    }
+
+    fn function_address(&self, name: &str) -> Result<usize> {
+        self.functions
+            .get(name)
+            .copied()
+            .ok_or(anyhow!("unknown function `{}`", name))
+    }
+}
+
+struct Machine {
+    ram: Vec<i16>,
+    // kept out of ram, instruction indices don't fit a word
+    return_pcs: Vec<usize>,
+    // the next free word of the bump allocator behind Memory.alloc
+    heap: usize,
+    output: String,
+}
+
+impl Machine {
+    fn run(&mut self, program: &VmInterpreter, entry: &str) -> Result<Option<i16>> {
+        let mut pc = program.function_address(entry)?;
+        self.call(usize::MAX, 0)?;
+
+        for _ in 0..MAX_STEPS {
+            let instruction = program
+                .instructions
+                .get(pc)
+                .ok_or(anyhow!("ran past the end of the program"))?;
+            pc += 1;
+
+            match instruction {
+                Instruction::Push(segment, i) => {
+                    let value = match segment {
+                        Segment::Constant => *i as i16,
+                        _ => self.ram[self.address(*segment, *i)?],
+                    };
+                    self.push(value)?;
+                }
+                Instruction::Pop(segment, i) => {
+                    let value = self.pop()?;
+                    let address = self.address(*segment, *i)?;
+                    self.ram[address] = value;
+                }
+                Instruction::Arithmetic(op) => self.apply(*op)?,
+                Instruction::Label => {}
+                Instruction::Goto(to) => pc = *to,
+                Instruction::IfGoto(to) => {
+                    if self.pop()? != 0 {
+                        pc = *to;
+                    }
+                }
+                Instruction::Function(_, locals) => {
+                    for _ in 0..*locals {
+                        self.push(0)?;
+                    }
+                }
+                Instruction::Call(name, args) => match program.functions.get(name) {
+                    Some(&address) => {
+                        self.call(pc, *args as usize)?;
+                        pc = address;
+                    }
+                    None => {
+                        let args = *args as usize;
+                        let sp = self.word(SP);
+                        if sp < STACK + args {
+                            bail!("not enough arguments on the stack to call `{}`", name);
+                        }
+                        let values = self.ram[sp - args..sp].to_vec();
+                        self.ram[SP] = (sp - args) as i16;
+                        match self.os_call(name, &values)? {
+                            Some(value) => self.push(value)?,
+                            None => return Ok(None),
+                        }
+                    }
+                },
+                Instruction::Return => {
+                    let value = self.pop()?;
+                    let frame = self.word(LCL);
+                    let arg = self.word(ARG);
+                    self.ram[arg] = value;
+                    self.ram[SP] = (arg + 1) as i16;
+                    for (i, pointer) in [THAT, THIS, ARG, LCL].into_iter().enumerate() {
+                        self.ram[pointer] = self.ram[frame - 1 - i];
+                    }
+                    pc = self.return_pcs.pop().expect("a frame for every return");
+                    if pc == usize::MAX {
+                        return Ok(Some(value));
+                    }
+                }
+            }
+        }
+
+        bail!("still running after {} instructions", MAX_STEPS)
+    }
+
+    // saves the caller's frame the way the vm translator does, the return
+    // address slot is left 0
+    fn call(&mut self, return_pc: usize, args: usize) -> Result<()> {
+        let sp = self.word(SP);
+        if sp < STACK + args {
+            bail!("not enough arguments on the stack");
+        }
+        self.push(0)?;
+        for pointer in [LCL, ARG, THIS, THAT] {
+            self.push(self.ram[pointer])?;
+        }
+        self.ram[ARG] = (sp - args) as i16;
+        self.ram[LCL] = self.ram[SP];
+        self.return_pcs.push(return_pc);
+        Ok(())
+    }
+
+    // the os functions arithmetic programs need, none when the program
+    // halts
+    fn os_call(&mut self, name: &str, args: &[i16]) -> Result<Option<i16>> {
+        let arg = |i: usize| -> Result<i16> {
+            args.get(i)
+                .copied()
+                .ok_or_else(|| anyhow!("`{}` is missing argument {}", name, i))
+        };
+        let value = match name {
+            "Math.multiply" => arg(0)?.wrapping_mul(arg(1)?),
+            "Math.divide" => match arg(1)? {
+                0 => bail!("division by zero"),
+                y => arg(0)?.wrapping_div(y),
+            },
+            "Math.abs" => arg(0)?.wrapping_abs(),
+            "Math.min" => arg(0)?.min(arg(1)?),
+            "Math.max" => arg(0)?.max(arg(1)?),
+            "Memory.alloc" | "Array.new" => self.alloc(arg(0)?)?,
+            "Memory.deAlloc" | "Array.dispose" | "String.dispose" => 0,
+            "Memory.peek" => self.ram[arg(0)? as u16 as usize % RAM_SIZE],
+            "Memory.poke" => {
+                self.ram[arg(0)? as u16 as usize % RAM_SIZE] = arg(1)?;
+                0
+            }
+            // a string is its length followed by its characters
+            "String.new" => {
+                let string = self.alloc(arg(0)?.wrapping_add(1))?;
+                self.ram[string as usize] = 0;
+                string
+            }
+            "String.appendChar" => {
+                let string = arg(0)? as usize;
+                let length = self.ram[string] as usize;
+                self.ram[string + 1 + length] = arg(1)?;
+                self.ram[string] += 1;
+                string as i16
+            }
+            "String.length" => self.ram[arg(0)? as usize],
+            "String.charAt" => self.ram[arg(0)? as usize + 1 + arg(1)? as usize],
+            "Output.printInt" => {
+                self.output.push_str(&arg(0)?.to_string());
+                0
+            }
+            "Output.printChar" => {
+                self.output.push(char::from(arg(0)? as u8));
+                0
+            }
+            "Output.printString" => {
+                let string = arg(0)? as usize;
+                let length = self.ram[string] as usize;
+                for &c in &self.ram[string + 1..string + 1 + length] {
+                    self.output.push(char::from(c as u8));
+                }
+                0
+            }
+            "Output.println" => {
+                self.output.push('\n');
+                0
+            }
+            "Sys.halt" => return Ok(None),
+            "Sys.error" => bail!("Sys.error({})", arg(0)?),
+            _ => bail!("unsupported os call `{}`", name),
+        };
+        Ok(Some(value))
+    }
+
+    fn alloc(&mut self, size: i16) -> Result<i16> {
+        if size < 0 {
+            bail!("cannot allocate {} words", size);
+        }
+        let block = self.heap;
+        self.heap += size as usize;
+        if self.heap > RAM_SIZE / 2 {
+            bail!("heap exhausted");
+        }
+        Ok(block as i16)
+    }
+
+    // a pointer register read as an address
+    fn word(&self, address: usize) -> usize {
+        self.ram[address] as u16 as usize
+    }
+
+    fn address(&self, segment: Segment, i: u16) -> Result<usize> {
+        let i = i as usize;
+        let address = match segment {
+            Segment::Local => self.word(LCL) + i,
+            Segment::Argument => self.word(ARG) + i,
+            Segment::This => self.word(THIS) + i,
+            Segment::That => self.word(THAT) + i,
+            Segment::Pointer if i < 2 => THIS + i,
+            Segment::Temp if i < 8 => TEMP + i,
+            Segment::Static => i,
+            _ => bail!("index {} is out of the {:?} segment", i, segment),
+        };
+        if address >= RAM_SIZE {
+            bail!("address {} is out of memory", address);
+        }
+        Ok(address)
+    }
+
+    fn push(&mut self, value: i16) -> Result<()> {
+        let sp = self.word(SP);
+        if sp >= HEAP {
+            bail!("stack overflow");
+        }
+        self.ram[sp] = value;
+        self.ram[SP] += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i16> {
+        let sp = self.word(SP);
+        if sp <= STACK {
+            bail!("stack underflow");
+        }
+        self.ram[SP] -= 1;
+        Ok(self.ram[sp - 1])
+    }
+
+    fn apply(&mut self, op: ArithmeticOp) -> Result<()> {
+        let result = match op {
+            ArithmeticOp::Neg => self.pop()?.wrapping_neg(),
+            ArithmeticOp::Not => !self.pop()?,
+            _ => {
+                let y = self.pop()?;
+                let x = self.pop()?;
+                match op {
+                    ArithmeticOp::Add => x.wrapping_add(y),
+                    ArithmeticOp::Sub => x.wrapping_sub(y),
+                    ArithmeticOp::Eq => -((x == y) as i16),
+                    ArithmeticOp::Gt => -((x > y) as i16),
+                    ArithmeticOp::Lt => -((x < y) as i16),
+                    ArithmeticOp::And => x & y,
+                    ArithmeticOp::Or => x | y,
+                    _ => unreachable!(),
+                }
+            }
+        };
+
+        self.push(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CodeGenerator, Parser};
+
+    use super::{Execution, VmInterpreter, SP, STACK};
+
+    fn compile_and_run(sources: &[&str]) -> anyhow::Result<Execution> {
+        let mut vm = String::new();
+        for src in sources {
+            let class = Parser::from_source(src).parse_class()?;
+            vm.push_str(&CodeGenerator::generate(&class)?);
+        }
+        VmInterpreter::new(&vm)?.execute("Main.main")
+    }
+
+    #[test]
+    fn run_function_adding_two_constants() {
+        let src = "function Main.main 0\npush constant 2\npush constant 3\nadd\nreturn";
+
+        let got = VmInterpreter::new(src).unwrap().run("Main.main").unwrap();
+        assert_eq!(got, 5, "Input was {:?}", src);
+    }
+
+    #[test]
+    fn run_call_with_arguments_and_locals() {
+        let src = "
+            function Main.main 0
+            push constant 7
+            push constant 3
+            call Main.sub 2
+            return
+            // argument 0 - argument 1, via a local
+            function Main.sub 1
+            push argument 0
+            push argument 1
+            sub
+            pop local 0
+            push local 0
+            return
+        ";
+
+        let got = VmInterpreter::new(src).unwrap().run("Main.main").unwrap();
+        assert_eq!(got, 4, "Input was {:?}", src);
+    }
+
+    #[test]
+    fn unknown_function_is_an_error() {
+        let src = "function Main.main 0\ncall Foo.bar 0\nreturn";
+
+        let got = VmInterpreter::new(src).unwrap().run("Main.main");
+        assert!(got.is_err(), "{:?} should be an error", got);
+    }
+
+    #[test]
+    fn labels_are_local_to_their_function() {
+        let src = "
+            function Main.main 0
+            call Main.f 0
+            goto END
+            label END
+            return
+            function Main.f 0
+            label END
+            push constant 1
+            return
+        ";
+        assert_eq!(
+            VmInterpreter::new(src).unwrap().run("Main.main").unwrap(),
+            1
+        );
+
+        let src = "function Main.main 0\ngoto ELSEWHERE\nfunction Main.f 0\nlabel ELSEWHERE";
+        assert!(VmInterpreter::new(src).is_err());
+    }
+
+    // project 11's first test program
+    #[test]
+    fn seven() {
+        let got = compile_and_run(&["
+            class Main {
+                function void main() {
+                    do Output.printInt(1 + (2 * 3));
+                    return;
+                }
+            }"])
+        .unwrap();
+
+        assert_eq!(got.output, "7");
+        assert_eq!(got.returned, Some(0));
+
+        // returned instead, it is left at the bottom of the stack
+        let got = compile_and_run(&["
+            class Main { function int main() { return 1 + (2 * 3); } }"])
+        .unwrap();
+        assert_eq!((got.ram[SP], got.ram[STACK]), (STACK as i16 + 1, 7));
+    }
+
+    #[test]
+    fn arithmetic_program() {
+        let got = compile_and_run(&[
+            "class Main {
+                static int calls;
+                function int main() {
+                    var Counter c; var Array squares; var int i, sum;
+                    let c = Counter.new(10);
+                    let squares = Array.new(5);
+                    while (i < 5) {
+                        let squares[i] = i * i;
+                        let i = i + 1;
+                    }
+                    let i = 0;
+                    while (~(i = 5)) {
+                        let sum = sum + squares[i];
+                        do c.add(-1);
+                        let i = i + 1;
+                    }
+                    if ((sum > 29) & (sum < 31)) {
+                        do Output.printString(\"sum \");
+                        do Output.printInt(sum / 3);
+                    } else {
+                        do Output.printInt(-sum);
+                    }
+                    return c.get() * 100 + Main.calls();
+                }
+                function int calls() { let calls = calls + 1; return calls; }
+            }",
+            "class Counter {
+                field int n;
+                constructor Counter new(int start) { let n = start; return this; }
+                method void add(int d) { let n = n + d; return; }
+                method int get() { return n; }
+            }",
+        ])
+        .unwrap();
+
+        assert_eq!(got.output, "sum 10");
+        assert_eq!(got.returned, Some(501));
+    }
+
+    #[test]
+    fn unsupported_os_calls_fail() {
+        let err = compile_and_run(&["
+            class Main {
+                function void main() { do Screen.drawPixel(1, 2); return; }
+            }"])
+        .unwrap_err();
+
+        assert!(
+            format!("{:#}", err).contains("unsupported os call `Screen.drawPixel`"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn halting_and_runaway_programs() {
+        let got = compile_and_run(&["
+            class Main {
+                function void main() { do Sys.halt(); do Output.printInt(1); return; }
+            }"])
+        .unwrap();
+        assert_eq!((got.returned, got.output.as_str()), (None, ""));
+
+        let err = compile_and_run(&["
+            class Main {
+                function void main() { while (true) { } return; }
+            }"])
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("still running"), "{:#}", err);
+    }
+}
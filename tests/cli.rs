@@ -0,0 +1,695 @@
+// runs the built binary the way scripts do
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+const MAIN: &str = "class Main { function void main() { do Output.printInt(1); return; } }";
+const BROKEN: &str = "class Bad { function void f() { let = 1; return; } }";
+
+// a fresh directory with `files` in it, removed again when dropped
+struct Project(PathBuf);
+
+impl Project {
+    fn new(name: &str, files: &[(&str, &str)]) -> Self {
+        let dir = std::env::temp_dir().join(format!("jack_cli_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (file, text) in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        Self(dir)
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_jack_compiler"))
+            .args(args)
+            .current_dir(&self.0)
+            .output()
+            .unwrap()
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for Project {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn is_parse_tree(path: &Path) -> bool {
+    let xml = std::fs::read_to_string(path).unwrap();
+    xml.starts_with("<class>") && xml.contains("<identifier> Main </identifier>")
+}
+
+#[test]
+fn parse_writes_into_the_output_directory() {
+    let project = Project::new("parse", &[("Main.jack", MAIN)]);
+
+    let output = project.run(&["parse", "Main.jack", "--out-dir", "out"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(is_parse_tree(&project.path("out/Main.xml")));
+    assert!(!project.path("Main.xml").exists());
+}
+
+#[test]
+fn parse_writes_a_tree_per_source() {
+    let other = MAIN.replace("class Main", "class Other");
+    let project = Project::new(
+        "per_source",
+        &[("Main.jack", MAIN), ("lib/Other.jack", &other)],
+    );
+
+    let output = project.run(&["parse", "Main.jack", "lib"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(is_parse_tree(&project.path("Main.xml")));
+    let other = std::fs::read_to_string(project.path("lib/Other.xml")).unwrap();
+    assert!(
+        other.contains("<identifier> Other </identifier>"),
+        "{}",
+        other
+    );
+    assert!(!project.path("Output.xml").exists());
+}
+
+#[test]
+fn parse_profile_prints_a_row_per_file_and_the_totals() {
+    let other = MAIN.replace("class Main", "class Other");
+    let project = Project::new(
+        "profile",
+        &[("src/Main.jack", MAIN), ("src/Other.jack", &other)],
+    );
+
+    let output = project.run(&["parse", "--profile", "src"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let report = stderr(&output);
+    let rows: Vec<Vec<&str>> = report
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(rows.len(), 4, "{}", report);
+    assert_eq!(rows[0], ["file", "tokenize", "ms", "engine", "ms"]);
+    for (row, name) in rows[1..]
+        .iter()
+        .zip(["src/Main.jack", "src/Other.jack", "total"])
+    {
+        assert_eq!(row.len(), 3, "{}", report);
+        assert_eq!(row[0], name);
+        assert!(
+            row[1..].iter().all(|ms| ms.parse::<f64>().is_ok()),
+            "{}",
+            report
+        );
+    }
+    assert!(is_parse_tree(&project.path("src/Main.xml")));
+}
+
+#[test]
+fn parse_writes_the_syntax_tree_as_dot() {
+    let project = Project::new("dot", &[("Main.jack", MAIN), ("Bad.jack", BROKEN)]);
+
+    let output = project.run(&["parse", "--format", "dot", "Main.jack", "-o", "out"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let dot = std::fs::read_to_string(project.path("out/Main.dot")).unwrap();
+    assert!(dot.starts_with("digraph \"Main\" {\n"), "{}", dot);
+    assert!(dot.contains("label=\"subroutineDec\""), "{}", dot);
+    assert!(!project.path("out/Main.xml").exists());
+
+    // errors are reported as for the xml, and a source with them has an
+    // empty graph
+    let errors = |format: &str| {
+        let output = project.run(&[
+            "parse",
+            "--format",
+            format,
+            "--error-format",
+            "short",
+            "Bad.jack",
+        ]);
+        stderr(&output)
+    };
+    assert!(
+        errors("dot").starts_with("Bad.jack:1:37: error["),
+        "{}",
+        errors("dot")
+    );
+    assert_eq!(errors("dot"), errors("xml"));
+    assert_eq!(
+        std::fs::read_to_string(project.path("Bad.dot")).unwrap(),
+        ""
+    );
+}
+
+#[test]
+fn legacy_source_flag_still_parses() {
+    let project = Project::new("legacy", &[("Main.jack", MAIN)]);
+
+    let output = project.run(&["-s", "Main.jack"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(is_parse_tree(&project.path("Output.xml")));
+}
+
+#[test]
+fn check_fails_on_errors() {
+    let project = Project::new("check", &[("Main.jack", MAIN), ("Bad.jack", BROKEN)]);
+
+    let output = project.run(&["check", "Main.jack"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let output = project.run(&["check", "--diagnostics-format", "json", "Bad.jack"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output).trim_end(),
+        "{\"file\":\"Bad.jack\",\"line\":1,\"column\":37,\"column_utf16\":37,\
+         \"offset\":36,\"end_offset\":37,\"severity\":\"error\",\
+         \"code\":\"J001\",\"message\":\"expected an identifier, found `Symbol(=)`\"}"
+    );
+}
+
+#[test]
+fn short_errors_stop_at_the_limit() {
+    let lets: String = ["a", "b", "c", "d"]
+        .iter()
+        .map(|name| format!("let {} = 1; ", name))
+        .collect();
+    let source = format!("class Many {{ function void f() {{ {}return; }} }}", lets);
+    let project = Project::new("short", &[("Many.jack", &source)]);
+
+    let output = project.run(&[
+        "check",
+        "--error-format",
+        "short",
+        "--max-errors",
+        "3",
+        "Many.jack",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "Many.jack:1:38: error[J103]: cannot assign to `a`, it is not declared\n\
+         Many.jack:1:49: error[J103]: cannot assign to `b`, it is not declared\n\
+         Many.jack:1:60: error[J103]: cannot assign to `c`, it is not declared\n\
+         and 1 more error\n"
+    );
+}
+
+#[test]
+fn errors_and_warnings_are_grouped_with_a_count() {
+    // a field shadowed by a local is a warning
+    let source = "class Mixed { field int x;
+        function void f() { let b = 1; return; }
+        method void g() { var int x; let a = 1; return; } }";
+    let project = Project::new("grouped", &[("Mixed.jack", source)]);
+
+    let output = project.run(&["check", "--error-format", "short", "Mixed.jack"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "Mixed.jack:2:33: error[J103]: cannot assign to `b`, it is not declared\n\
+         Mixed.jack:3:42: error[J103]: cannot assign to `a`, it is not declared\n\
+         \n\
+         Mixed.jack:3:35: warning[J108]: local `x` shadows the field `x` declared at 1:25\n\
+         \n\
+         2 errors, 1 warning\n"
+    );
+}
+
+#[test]
+fn parse_emits_several_outputs_from_one_run() {
+    let project = Project::new("emit", &[("Main.jack", MAIN)]);
+    let read = |name: &str| std::fs::read_to_string(project.path(name)).unwrap();
+
+    let output = project.run(&["parse", "--emit", "xml,tokens,sexp", "Main.jack"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let tokens = project.run(&["tokenize", "Main.jack"]);
+    assert_eq!(read("MainT.xml"), String::from_utf8_lossy(&tokens.stdout));
+    let sexp = read("Main.sexp");
+    assert!(sexp.starts_with("(class\n  (keyword \"class\")\n  (identifier \"Main\")"));
+    let emitted = read("Main.xml");
+    std::fs::remove_file(project.path("Main.xml")).unwrap();
+    assert!(project.run(&["parse", "Main.jack"]).status.success());
+    assert_eq!(read("Main.xml"), emitted);
+
+    // an output that can't be created doesn't keep the others from being
+    // written
+    std::fs::remove_file(project.path("Main.sexp")).unwrap();
+    std::fs::remove_file(project.path("Main.xml")).unwrap();
+    std::fs::create_dir(project.path("Main.sexp")).unwrap();
+    let output = project.run(&["parse", "--emit", "sexp,xml", "Main.jack"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).starts_with("error: Main.sexp: "),
+        "{}",
+        stderr(&output)
+    );
+    assert!(project.path("Main.xml").exists());
+
+    std::fs::remove_file(project.path("Main.xml")).unwrap();
+    let output = project.run(&["parse", "--emit", "sexp,xml", "--fail-fast", "Main.jack"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!project.path("Main.xml").exists());
+}
+
+#[test]
+fn a_second_class_in_a_file_fails_every_subcommand() {
+    let project = Project::new("two_classes", &[("Two.jack", "class A { }\nclass B { }\n")]);
+
+    for subcommand in ["check", "compile"] {
+        let output = project.run(&[subcommand, "Two.jack", "-o", "out"]);
+        assert!(!output.status.success(), "{} succeeded", subcommand);
+        assert!(
+            stderr(&output).contains("2:1: error[J001]: unexpected token after class: `class`"),
+            "{}: {}",
+            subcommand,
+            stderr(&output)
+        );
+    }
+    assert!(!project.path("out/A.vm").exists());
+}
+
+#[test]
+fn every_subcommand_fails_on_a_directory_without_sources() {
+    let project = Project::new("no_sources", &[("sub/M.jack", MAIN)]);
+
+    for subcommand in ["parse", "compile", "check", "tokenize", "fmt", "stats"] {
+        let output = project.run(&[subcommand, ".", "-o", "out"]);
+        assert!(!output.status.success(), "{} succeeded", subcommand);
+        assert!(
+            stderr(&output).contains("(searched non-recursively; try --recursive)"),
+            "{}: {}",
+            subcommand,
+            stderr(&output)
+        );
+
+        let output = project.run(&[subcommand, ".", "-o", "out", "--allow-empty"]);
+        assert!(
+            output.status.success(),
+            "{}: {}",
+            subcommand,
+            stderr(&output)
+        );
+        assert!(stderr(&output).starts_with("warning: no .jack files found under"));
+    }
+}
+
+#[test]
+fn strict_check_reports_every_undeclared_read() {
+    let source = "class Main {\n    function void main() {\n        var int y;\n        let y = z + 1;\n        let y = w;\n        return;\n    }\n}\n";
+    let project = Project::new("undeclared", &[("Main.jack", source)]);
+
+    let output = project.run(&["check", "Main.jack"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let output = project.run(&["check", "--strict", "Main.jack"]);
+    assert!(!output.status.success());
+    let errors = stderr(&output);
+    assert!(errors.contains("Main.jack:4:17: error[J201]: use of undeclared variable `z`"));
+    assert!(errors.contains("Main.jack:5:17: error[J201]: use of undeclared variable `w`"));
+}
+
+#[test]
+fn this_receiver_is_reported_unless_allowed() {
+    let source = "class Main {\n    method void main() {\n        do this.draw();\n        return;\n    }\n    method void draw() {\n        return;\n    }\n}\n";
+    let project = Project::new("this_receiver", &[("Main.jack", source)]);
+
+    for subcommand in ["check", "compile", "parse"] {
+        let output = project.run(&[subcommand, "Main.jack", "-o", "out"]);
+        let errors = stderr(&output);
+        assert!(
+            errors.contains("`this` can't be the receiver of a call, methods of the current class are called bare, as `draw(...)`"),
+            "{}: {}",
+            subcommand,
+            errors
+        );
+
+        let output = project.run(&[
+            subcommand,
+            "Main.jack",
+            "-o",
+            "out",
+            "--allow-this-receiver",
+        ]);
+        assert!(
+            output.status.success(),
+            "{}: {}",
+            subcommand,
+            stderr(&output)
+        );
+        assert!(
+            stderr(&output).is_empty(),
+            "{}: {}",
+            subcommand,
+            stderr(&output)
+        );
+    }
+
+    let output = project.run(&["check", "Main.jack"]);
+    assert!(stderr(&output).contains("Main.jack:3:12: error[J001]: `this` can't be"));
+}
+
+#[test]
+fn every_subcommand_skips_a_marker_first_line_with_a_warning() {
+    let source = format!("#!/usr/bin/env jack\n{}\n", MAIN);
+    let project = Project::new("marker", &[("Main.jack", &source)]);
+
+    let runs: [&[&str]; 7] = [
+        &["parse", "Main.jack", "-o", "out"],
+        &["compile", "Main.jack", "-o", "out"],
+        &["check", "Main.jack"],
+        &["tokenize", "Main.jack", "-o", "out"],
+        &["fmt", "Main.jack", "-o", "out"],
+        &["stats", "Main.jack"],
+        &["-s", "Main.jack", "--emit", "cfg"],
+    ];
+    for args in runs {
+        let output = project.run(args);
+        assert!(output.status.success(), "{:?}: {}", args, stderr(&output));
+        assert!(
+            stderr(&output)
+                .starts_with("Main.jack:1:1: warning[J117]: ignoring non-Jack first line"),
+            "{:?}: {}",
+            args,
+            stderr(&output)
+        );
+    }
+}
+
+#[test]
+fn legacy_emit_and_rename_read_the_extensions_given() {
+    let project = Project::new(
+        "legacy_extensions",
+        &[
+            (
+                "Main.jck",
+                "class Main { function void main() { return; } }",
+            ),
+            ("Old.jack", "class Old { }"),
+        ],
+    );
+
+    let output = project.run(&["-s", ".", "--extensions", "jck", "--emit", "symbols"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let symbols = String::from_utf8_lossy(&output.stdout);
+    assert!(symbols.contains("Main.jck"), "{}", symbols);
+    assert!(!symbols.contains("Old.jack"), "{}", symbols);
+
+    let output = project.run(&[
+        "-s",
+        ".",
+        "--extensions",
+        "jck",
+        "--rename",
+        "Main.main=run",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let renamed = std::fs::read_to_string(project.path("Main.jck")).unwrap();
+    assert!(renamed.contains("function void run()"), "{}", renamed);
+
+    // the file of a renamed class keeps its extension
+    let output = project.run(&["-s", ".", "--extensions", "jck", "--rename", "Main=Game"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(project.path("Game.jck").exists());
+}
+
+#[test]
+fn compile_reads_subdirectories_when_recursive() {
+    let project = Project::new(
+        "recursive",
+        &[
+            ("Main.jack", MAIN),
+            ("lib/A.jack", "class A { }"),
+            ("lib/B.jack", "class B { }"),
+        ],
+    );
+
+    let output = project.run(&["compile", ".", "-r", "-j", "2", "-o", "out"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    for vm in ["Main.vm", "A.vm", "B.vm"] {
+        assert!(project.path("out").join(vm).exists(), "{} is missing", vm);
+    }
+
+    let output = project.run(&["compile", ".", "-o", "flat"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!project.path("flat/A.vm").exists());
+}
+
+#[test]
+fn fmt_writes_minified_sources_into_the_output_directory() {
+    let project = Project::new(
+        "minify",
+        &[(
+            "Main.jack",
+            "class Main { function void main() { var int total; let total = 1; return; } }",
+        )],
+    );
+
+    let output = project.run(&["fmt", ".", "--transform", "minify-names", "-o", "out"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = std::fs::read_to_string(project.path("out/Main.jack")).unwrap();
+    assert!(text.contains("var int v0;"), "{}", text);
+    assert!(!text.contains("total"), "{}", text);
+}
+
+#[test]
+fn repl_prints_a_tree_for_every_input() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jack_compiler"))
+        .args(["repl", "--sexp"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"1 + 2 * x\nlet a[i] = b;\n1 + ;\nif (x) {\n  do f();\n}\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let trees: Vec<&str> = stdout
+        .lines()
+        .filter(|line| !line.starts_with(' '))
+        .collect();
+    assert_eq!(trees, ["(expression", "(statements", "(statements"]);
+    assert!(stdout.contains("(ifStatement"), "{}", stdout);
+    assert_eq!(
+        stderr(&output),
+        "<repl>:1:5: error[J001]: expected a term, found `Symbol(;)`\n  |\n1 | 1 + ;\n  |     ^\n"
+    );
+}
+
+// whether `text` is one json value and nothing else
+fn is_json(text: &str) -> bool {
+    fn value(s: &[u8], i: &mut usize) -> bool {
+        skip_ws(s, i);
+        let ok = match s.get(*i) {
+            Some(b'{') => list(s, i, b'}', |s, i| {
+                string(s, i) && {
+                    skip_ws(s, i);
+                    s.get(*i) == Some(&b':') && {
+                        *i += 1;
+                        value(s, i)
+                    }
+                }
+            }),
+            Some(b'[') => list(s, i, b']', value),
+            Some(b'"') => string(s, i),
+            Some(b'-' | b'0'..=b'9') => {
+                let start = *i;
+                *i += 1;
+                while matches!(
+                    s.get(*i),
+                    Some(b'0'..=b'9' | b'.' | b'e' | b'E' | b'+' | b'-')
+                ) {
+                    *i += 1;
+                }
+                std::str::from_utf8(&s[start..*i])
+                    .unwrap()
+                    .parse::<f64>()
+                    .is_ok()
+            }
+            _ => ["true", "false", "null"].iter().any(|word| {
+                let found = s[*i..].starts_with(word.as_bytes());
+                if found {
+                    *i += word.len();
+                }
+                found
+            }),
+        };
+        skip_ws(s, i);
+        ok
+    }
+    fn list(s: &[u8], i: &mut usize, close: u8, item: fn(&[u8], &mut usize) -> bool) -> bool {
+        *i += 1;
+        skip_ws(s, i);
+        if s.get(*i) == Some(&close) {
+            *i += 1;
+            return true;
+        }
+        loop {
+            skip_ws(s, i);
+            if !item(s, i) {
+                return false;
+            }
+            skip_ws(s, i);
+            match s.get(*i) {
+                Some(b',') => *i += 1,
+                Some(&c) if c == close => {
+                    *i += 1;
+                    return true;
+                }
+                _ => return false,
+            }
+        }
+    }
+    fn string(s: &[u8], i: &mut usize) -> bool {
+        if s.get(*i) != Some(&b'"') {
+            return false;
+        }
+        *i += 1;
+        while let Some(&c) = s.get(*i) {
+            *i += 1;
+            match c {
+                b'"' => return true,
+                b'\\' => *i += 1,
+                c if c < 0x20 => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+    fn skip_ws(s: &[u8], i: &mut usize) {
+        while matches!(s.get(*i), Some(b' ' | b'\n' | b'\r' | b'\t')) {
+            *i += 1;
+        }
+    }
+
+    let mut i = 0;
+    value(text.as_bytes(), &mut i) && i == text.len()
+}
+
+#[test]
+fn report_file_is_json() {
+    let project = Project::new("report", &[("Main.jack", MAIN), ("lib/Other.jack", MAIN)]);
+
+    let output = project.run(&[
+        "parse",
+        "Main.jack",
+        "lib",
+        "--diff",
+        "--report-file",
+        "report.json",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let report = std::fs::read_to_string(project.path("report.json")).unwrap();
+    assert!(is_json(&report), "{}", report);
+    assert!(!is_json(&report[1..]));
+    // one entry per source, across both paths
+    assert_eq!(report.matches("\"semantic_ns\":").count(), 2, "{}", report);
+}
+
+#[test]
+fn check_prints_the_symbol_index() {
+    let game = "class Game { function void run() { do Main.main(); return; } }";
+    let project = Project::new(
+        "check_symbols",
+        &[
+            ("src/Main.jck", MAIN),
+            ("src/Game.jck", game),
+            ("src/Old.jack", "class Old { }"),
+        ],
+    );
+
+    let output = project.run(&["check", "--symbols", "--extensions", "jck", "src"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let index = String::from_utf8_lossy(&output.stdout);
+    assert!(is_json(index.trim_end()), "{}", index);
+    assert!(
+        index.contains("\"kind\":\"function\",\"name\":\"run\""),
+        "{}",
+        index
+    );
+    assert!(index.contains("src/Main.jck"), "{}", index);
+    assert!(!index.contains("Old"), "{}", index);
+
+    // nothing is printed for sources with errors
+    let project = Project::new("check_symbols_errors", &[("Bad.jack", BROKEN)]);
+    let output = project.run(&["check", "--symbols", "Bad.jack"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn every_subcommand_writes_the_report_file() {
+    let project = Project::new("report_everywhere", &[("Main.jack", MAIN)]);
+
+    let runs: [&[&str]; 6] = [
+        &["parse", "Main.jack", "-o", "out", "--emit", "tokens"],
+        &["compile", "Main.jack", "-o", "out"],
+        &["check", "Main.jack"],
+        &["tokenize", "Main.jack", "-o", "out"],
+        &["fmt", "Main.jack", "-o", "out"],
+        &["stats", "Main.jack"],
+    ];
+    for args in runs {
+        let _ = std::fs::remove_file(project.path("report.json"));
+        let output = project.run(&[args, &["--report-file", "report.json"]].concat());
+        assert!(output.status.success(), "{:?}: {}", args, stderr(&output));
+        let report = std::fs::read_to_string(project.path("report.json")).unwrap();
+        assert!(is_json(&report), "{:?}: {}", args, report);
+        assert_eq!(report.matches("\"semantic_ns\":").count(), 1, "{}", report);
+    }
+}
+
+#[test]
+fn report_file_does_not_change_what_parse_prints() {
+    // a syntax error in one subroutine and an undeclared variable in another
+    let source = "class Main {\n    function void main() {\n        let y = 1;\n        return;\n    }\n    \
+                  function void f() {\n        let = 1;\n        return;\n    }\n}\n";
+    let project = Project::new("report_checks", &[("Main.jack", source)]);
+
+    let output = project.run(&["parse", "Main.jack", "-o", "out"]);
+    let plain = stderr(&output);
+    assert!(plain.contains("Main.jack:7:13: error["), "{}", plain);
+
+    let output = project.run(&["parse", "Main.jack", "-o", "out", "--report-file", "r.json"]);
+    assert_eq!(stderr(&output), plain);
+    assert!(!stderr(&output).contains("J103"), "{}", stderr(&output));
+    let report = std::fs::read_to_string(project.path("r.json")).unwrap();
+    assert!(report.contains("\"semantic_ns\":"), "{}", report);
+}
+
+#[test]
+fn tokenize_writes_well_formed_xml() {
+    let source = "class Main {\n    function void main() {\n        do Output.printString(\"<&>\");\n        return;\n    }\n}\n";
+    let project = Project::new("tokens_well_formed", &[("Main.jack", source)]);
+
+    let output = project.run(&["tokenize", "--well-formed-xml", "Main.jack", "-o", "out"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let tokens = std::fs::read_to_string(project.path("out/MainT.xml")).unwrap();
+    assert!(
+        tokens.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<tokens>\n"),
+        "{}",
+        tokens
+    );
+    assert!(tokens.contains("<stringConstant> &lt;&amp;&gt; </stringConstant>"));
+
+    let output = project.run(&["tokenize", "Main.jack", "-o", "plain"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let tokens = std::fs::read_to_string(project.path("plain/MainT.xml")).unwrap();
+    assert!(tokens.starts_with("<tokens>\n"), "{}", tokens);
+    assert!(tokens.contains("<stringConstant> <&> </stringConstant>"));
+}
@@ -0,0 +1,40 @@
+// the public items of the crate root. removing or renaming one breaks the
+// code using it, so the imports below stop compiling when an item goes away
+
+#[allow(unused_imports)]
+use jack_compiler::{
+    analyze_source, ast, ast_to_dot, check_balance, check_brace_style, check_source, compile_batch,
+    compile_fragment_source, compile_source, completions, dead_code, expected_at_end, expr_to_rpn,
+    format_class, grammar, lexical_elements, minify_names, prelude, rename, testing,
+    tokens_to_source, unified_diff, utf16_column, Analyzer, AnalyzerOptions, BasicBlock,
+    BraceStyle, BracketTracker, BufReadSpannedTokens, BufReadTokenizer, CheckOptions, Checker,
+    CodeGenerator, CodegenOptions, CompilationEngine, CompilationReport, CompilationUnit,
+    CompileError, CompileOptions, CompiledClassInfo, CompiledSubroutine, CompiledVar,
+    CompletionItem, CompletionKind, ControlFlowGraph, DeadCodeReport, DeadItem, DeadKind,
+    Definition, DefinitionKind, Diagnostic, DiagnosticFormat, DiagnosticReport, DiagnosticSink,
+    DiffStatus, EdgeKind, ElementSpan, Emit, Expected, FailedSubroutines, Feature, Features,
+    FileReport, Fragment, HashingWriter, LabelStyle, Manifest, NaiveTokenizer, Newline,
+    NewlineWriter, OutputDiff, ParseEvent, ParseFormat, ParseListener, ParseOutput, ParsedTree,
+    Parser, PhaseTimes, Project, RecordsListener, Reference, Report, Resolution, Severity,
+    SexpListener, Signature, SourceSet, Span, SpannedToken, StreamTokenizer, Symbol, SymbolIndex,
+    SymbolKind, SymbolRef, SymbolTable, TabPolicy, TextEdit, Token, TokenKind, TokenSource,
+    TokenizerStats, TokensListener, Transform,
+};
+
+#[test]
+fn prelude_has_the_common_types() {
+    use jack_compiler::prelude::*;
+
+    let class = Parser::from_source("class Main { function void main() { return; } }")
+        .parse_class()
+        .unwrap();
+    assert!(Checker::check(&class).is_empty());
+    let vm = CodeGenerator::generate(&class).unwrap();
+    assert!(vm.starts_with("function Main.main 0\n"), "{}", vm);
+
+    let tokens: Vec<Token> = StreamTokenizer::from_source("let x = 1;")
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(tokens.len(), 5);
+    assert_eq!(tokens[0], Token::Keyword(lexical_elements::Keywords::Let));
+}
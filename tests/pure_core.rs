@@ -0,0 +1,12 @@
+// the tokenizer, engine, parser and code generator have to build for
+// wasm32-unknown-unknown, where there is no filesystem or processes. ci
+// builds the library for that target, this only checks that compiling needs
+// nothing but strings
+
+#[test]
+fn compile_source_needs_only_strings() {
+    let xml =
+        jack_compiler::compile_source("class Main { function void main() { return; } }").unwrap();
+    assert!(xml.starts_with("<class>"), "{}", xml);
+    assert!(xml.contains("<returnStatement>"), "{}", xml);
+}